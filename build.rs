@@ -0,0 +1,15 @@
+// Generates the gRPC service code when the `grpc` feature is enabled.
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/compiler.proto");
+        // Use the vendored protoc so no system installation is needed
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        // Maps are ordered, like those of the REST API, so requests serialize the same way every time
+        tonic_build::configure()
+            .btree_map(["."])
+            .compile_protos(&["proto/compiler.proto"], &["proto"])
+            .expect("failed to compile proto/compiler.proto");
+    }
+}
@@ -0,0 +1,54 @@
+/* C interface of the compiler. Generated by cbindgen from ffi/src/lib.rs; do not edit. */
+
+#ifndef COMPILER_H
+#define COMPILER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Compiles `source`, optimized at `opt_level` (0 to 2), and returns its
+ bytecode, one instruction per line, or NULL on error.
+
+ # Safety
+
+ `source` must be a NUL-terminated string.
+ */
+char *compiler_compile(const char *source, uint8_t opt_level);
+
+/*
+ Compiles and runs `source`, with `input` as the text read by read_line()
+ (NULL for none), and returns the output of the program, or NULL on error.
+
+ # Safety
+
+ `source` must be a NUL-terminated string, and `input` one or NULL.
+ */
+char *compiler_execute(const char *source, const char *input);
+
+/*
+ Releases a result of compiler_compile or compiler_execute. NULL is ignored.
+
+ # Safety
+
+ `result` must come from this library and not have been released yet.
+ */
+void compiler_free_result(char *result);
+
+/*
+ Message of the last call that failed on this thread, or NULL. It stays
+ valid until the next call on this thread.
+ */
+const char *compiler_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* COMPILER_H */
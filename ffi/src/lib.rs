@@ -0,0 +1,92 @@
+// C ABI of the compiler, so that hosts written in C, C#, Swift and other
+// languages can embed it. Strings cross the boundary as NUL-terminated UTF-8.
+// Results are allocated here and must be released with compiler_free_result;
+// a call that fails returns NULL and leaves its message for
+// compiler_last_error, on the calling thread.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+thread_local! {
+    // Message of the last failed call on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Compiles `source`, optimized at `opt_level` (0 to 2), and returns its
+/// bytecode, one instruction per line, or NULL on error.
+///
+/// # Safety
+///
+/// `source` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn compiler_compile(source: *const c_char, opt_level: u8) -> *mut c_char {
+    respond(|| {
+        let source = read(source)?;
+        compiler::embed::compile(&source, opt_level).map(|code| code.join("\n"))
+    })
+}
+
+/// Compiles and runs `source`, with `input` as the text read by read_line()
+/// (NULL for none), and returns the output of the program, or NULL on error.
+///
+/// # Safety
+///
+/// `source` must be a NUL-terminated string, and `input` one or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn compiler_execute(source: *const c_char, input: *const c_char) -> *mut c_char {
+    respond(|| {
+        let source = read(source)?;
+        let input = if input.is_null() { String::new() } else { read(input)? };
+        compiler::embed::execute(&source, &input)
+    })
+}
+
+/// Releases a result of compiler_compile or compiler_execute. NULL is ignored.
+///
+/// # Safety
+///
+/// `result` must come from this library and not have been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn compiler_free_result(result: *mut c_char) {
+    if !result.is_null() {
+        drop(CString::from_raw(result));
+    }
+}
+
+/// Message of the last call that failed on this thread, or NULL. It stays
+/// valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn compiler_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
+
+// Reads a string argument
+unsafe fn read(text: *const c_char) -> Result<String, String> {
+    if text.is_null() {
+        return Err("Unexpected NULL string".to_string());
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map(str::to_string)
+        .map_err(|e| format!("Invalid UTF-8: {}", e))
+}
+
+// Runs a call, returning its result as a C string or recording its error.
+// Panics are caught, as they must not unwind into the host.
+fn respond(call: impl FnOnce() -> Result<String, String>) -> *mut c_char {
+    let outcome = panic::catch_unwind(AssertUnwindSafe(call))
+        .unwrap_or_else(|_| Err("Internal compiler error: the compiler panicked".to_string()))
+        .and_then(|text| CString::new(text).map_err(|_| "The result contains a NUL character".to_string()));
+    match outcome {
+        Ok(text) => {
+            LAST_ERROR.with(|error| *error.borrow_mut() = None);
+            text.into_raw()
+        }
+        Err(message) => {
+            let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+            LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+            std::ptr::null_mut()
+        }
+    }
+}
@@ -0,0 +1,4 @@
+// Sets up linking against the Node-API symbols provided by node at load time.
+fn main() {
+    napi_build::setup();
+}
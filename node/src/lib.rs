@@ -0,0 +1,43 @@
+// Node.js addon (Node-API) running the compiler in process, so the web
+// frontend works offline, e.g. in an Electron app. The functions run on the
+// calling thread; a long program blocks the event loop until it ends.
+
+use napi::{Error, Result};
+use napi_derive::napi;
+
+/// A token of a program, as `tokenize` returns it.
+#[napi(object)]
+pub struct Token {
+    pub kind: String, // Type of the token, e.g. `Identifier("x")`
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Splits `source` into tokens. Throws on invalid characters or literals.
+#[napi]
+pub fn tokenize(source: String) -> Result<Vec<Token>> {
+    let tokens = compiler::embed::tokenize(&source).map_err(Error::from_reason)?;
+    Ok(tokens
+        .into_iter()
+        .map(|token| Token {
+            kind: token.kind,
+            line: u32::try_from(token.line).unwrap_or(u32::MAX),
+            column: u32::try_from(token.column).unwrap_or(u32::MAX),
+        })
+        .collect())
+}
+
+/// Compiles `source`, optimized at `optLevel` (0 to 2, default 0), and returns
+/// its bytecode, one instruction per entry. Throws when it does not compile.
+#[napi]
+pub fn compile(source: String, opt_level: Option<u32>) -> Result<Vec<String>> {
+    let opt_level = u8::try_from(opt_level.unwrap_or(0)).unwrap_or(u8::MAX);
+    compiler::embed::compile(&source, opt_level).map_err(Error::from_reason)
+}
+
+/// Compiles and runs `source`, with `stdin` as the input of read_line(), and
+/// returns its output as POST /compile does. Throws when the program fails.
+#[napi]
+pub fn run(source: String, stdin: Option<String>) -> Result<String> {
+    compiler::embed::execute(&source, &stdin.unwrap_or_default()).map_err(Error::from_reason)
+}
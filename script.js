@@ -170,7 +170,7 @@ triangleArea + rectangleArea;`,
         resultOutput.innerHTML = `<span class="success-icon">✅</span> Result: <span class="result-value">${data.result || "No output"}</span>`;
         
         // Format bytecode with syntax highlighting
-        bytecodeOutput.innerHTML = formatBytecode(data.bytecode);
+        bytecodeOutput.innerHTML = formatBytecode(data.bytecode, data.strings || []);
         
         // Animate result
         animateResult();
@@ -185,17 +185,21 @@ triangleArea + rectangleArea;`,
   }
 
   // Format bytecode with syntax highlighting
-  function formatBytecode(bytecode) {
+  function formatBytecode(bytecode, strings) {
     if (!bytecode || bytecode.length === 0) {
       return "<em>No bytecode generated</em>";
     }
 
     // Create HTML for bytecode with syntax highlighting
     return bytecode.map((line, index) => {
+      // Strings are listed once and referred to as #N
+      const resolved = line.replace(/#(\d+)/g, (reference, n) =>
+        n < strings.length ? JSON.stringify(strings[n]) : reference
+      );
       // Highlight different parts of the bytecode
       return `<div class="bytecode-line">
         <span class="bytecode-index">${index.toString().padStart(2, '0')}</span>
-        ${highlightBytecode(line)}
+        ${highlightBytecode(resolved)}
       </div>`;
     }).join("");
   }
@@ -205,7 +209,7 @@ triangleArea + rectangleArea;`,
     // Replace specific patterns with highlighted spans
     return instruction
       .replace(/Push\(([^)]+)\)/g, 'Push(<span class="bytecode-value">$1</span>)')
-      .replace(/(Add|Subtract|Multiply|Divide|Negate)/g, '<span class="bytecode-op">$1</span>')
+      .replace(/(Add|Subtract|Multiply|IntDivide|Divide|Negate)/g, '<span class="bytecode-op">$1</span>')
       .replace(/(Jump|JumpIfFalse)\((\d+)\)/g, '<span class="bytecode-flow">$1</span>(<span class="bytecode-number">$2</span>)')
       .replace(/(Load|Store)Variable\("([^"]+)"\)/g, '<span class="bytecode-var">$1Variable</span>("$2")');
   }
@@ -0,0 +1,188 @@
+// Endpoints for operators of the server, under /admin. They need the token
+// set in COMPILER_ADMIN_TOKEN, sent as `Authorization: Bearer <token>`; when
+// no token is configured every admin request is refused.
+
+use actix_web::{delete, get, web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::builtins;
+use crate::executions::{ExecutionInfo, ExecutionRegistry};
+use crate::history::{HistoryEntry, HistoryQuery};
+use crate::sessions::{SessionStore, SessionUsage};
+use crate::{ErrorDetails, ErrorKind, ServerConfig};
+
+// Response of GET /admin/sessions
+#[derive(Serialize, ToSchema)]
+pub struct SessionsOutput {
+    sessions: Vec<SessionUsage>, // Active sessions, by id
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for GET /admin/sessions
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "Active sessions and their resource usage", body = SessionsOutput),
+        (status = 401, description = "Missing or wrong admin token, or no token configured", body = SessionsOutput),
+    )
+)]
+#[get("/admin/sessions")]
+pub async fn list_sessions(
+    request: HttpRequest,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+) -> impl Responder {
+    match authorize(&request, &config) {
+        Ok(()) => HttpResponse::Ok().json(SessionsOutput {
+            sessions: sessions.usage(),
+            error_details: None,
+        }),
+        Err(details) => HttpResponse::build(details.kind.status()).json(SessionsOutput {
+            sessions: Vec::new(),
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Response of GET /admin/executions
+#[derive(Serialize, ToSchema)]
+pub struct ExecutionsOutput {
+    executions: Vec<ExecutionInfo>, // Running programs, oldest first
+    error_details: Option<ErrorDetails>,
+}
+
+// Response of DELETE /admin/executions/{id}
+#[derive(Serialize, ToSchema)]
+pub struct CancelOutput {
+    execution: Option<ExecutionInfo>, // The program asked to stop
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for GET /admin/executions
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, description = "Programs running on the server", body = ExecutionsOutput),
+        (status = 401, description = "Missing or wrong admin token, or no token configured", body = ExecutionsOutput),
+    )
+)]
+#[get("/admin/executions")]
+pub async fn list_executions(
+    request: HttpRequest,
+    config: web::Data<ServerConfig>,
+    executions: web::Data<ExecutionRegistry>,
+) -> impl Responder {
+    match authorize(&request, &config) {
+        Ok(()) => HttpResponse::Ok().json(ExecutionsOutput {
+            executions: executions.list(),
+            error_details: None,
+        }),
+        Err(details) => HttpResponse::build(details.kind.status()).json(ExecutionsOutput {
+            executions: Vec::new(),
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Route handler for DELETE /admin/executions/{id}
+#[utoipa::path(
+    tag = "admin",
+    params(("id" = u64, Path, description = "Id of the execution, from GET /admin/executions")),
+    responses(
+        (status = 200, description = "The program was asked to stop; its request fails with a runtime error", body = CancelOutput),
+        (status = 401, description = "Missing or wrong admin token, or no token configured", body = CancelOutput),
+        (status = 404, description = "No running program has this id", body = CancelOutput),
+    )
+)]
+#[delete("/admin/executions/{id}")]
+pub async fn cancel_execution(
+    request: HttpRequest,
+    id: web::Path<u64>,
+    config: web::Data<ServerConfig>,
+    executions: web::Data<ExecutionRegistry>,
+) -> impl Responder {
+    let cancelled = authorize(&request, &config).and_then(|()| {
+        executions.cancel(*id).ok_or_else(|| ErrorDetails {
+            kind: ErrorKind::NotFound,
+            code: None,
+            message: format!("No execution {} is running", id),
+            line: None,
+            column: None,
+            rendered: None,
+        })
+    });
+    match cancelled {
+        Ok(execution) => HttpResponse::Ok().json(CancelOutput {
+            execution: Some(execution),
+            error_details: None,
+        }),
+        Err(details) => HttpResponse::build(details.kind.status()).json(CancelOutput {
+            execution: None,
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Response of GET /admin/history
+#[derive(Serialize, ToSchema)]
+pub struct HistoryOutput {
+    runs: Vec<HistoryEntry>, // Finished runs matching the query, newest first
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for GET /admin/history
+#[utoipa::path(
+    tag = "admin",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "Finished runs matching the query, newest first", body = HistoryOutput),
+        (status = 401, description = "Missing or wrong admin token, or no token configured", body = HistoryOutput),
+    )
+)]
+#[get("/admin/history")]
+pub async fn list_history(
+    request: HttpRequest,
+    query: web::Query<HistoryQuery>,
+    config: web::Data<ServerConfig>,
+    executions: web::Data<ExecutionRegistry>,
+) -> impl Responder {
+    match authorize(&request, &config) {
+        Ok(()) => HttpResponse::Ok().json(HistoryOutput {
+            runs: executions.history().query(&query),
+            error_details: None,
+        }),
+        Err(details) => HttpResponse::build(details.kind.status()).json(HistoryOutput {
+            runs: Vec::new(),
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Checks the bearer token of an admin request
+pub fn authorize(request: &HttpRequest, config: &ServerConfig) -> Result<(), ErrorDetails> {
+    let unauthorized = |message: &str| ErrorDetails {
+        kind: ErrorKind::Unauthorized,
+        code: None,
+        message: message.to_string(),
+        line: None,
+        column: None,
+        rendered: None,
+    };
+    let Some(expected) = &config.admin_token else {
+        return Err(unauthorized("Admin endpoints are disabled: set COMPILER_ADMIN_TOKEN to enable them"));
+    };
+    let token = request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        // Compared in constant time, so the response time doesn't reveal how
+        // much of a guess was right
+        Some(token) if builtins::constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        Some(_) => Err(unauthorized("Wrong admin token")),
+        None => Err(unauthorized("Missing admin token: send 'Authorization: Bearer <token>'")),
+    }
+}
+
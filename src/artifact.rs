@@ -0,0 +1,764 @@
+// Binary bytecode artifacts written by `compiler build` and run by
+// `compiler run FILE.bc`, so a program can be compiled once and run many times.
+//
+// Layout (all integers little-endian):
+//
+//   magic       4 bytes  "CBC\0"
+//   version     u16      FORMAT_VERSION, see VERSIONS
+//   opt_level   u8
+//   limits      timeout_ms, max_sleep_ms: each a u8 presence flag + u64
+//   max_stack   u8 presence flag + u64: operand stack slots the code needs,
+//               absent when it has no static bound (since version 2; before
+//               version 10, also whenever the code has functions)
+//   strings     u32 count + that many strings: every string of the code,
+//               once (since version 5)
+//   functions   u32 count + that many names: the function table, whose
+//               entries the Function instructions mark in order (since version 4)
+//   count       u32      number of instructions
+//   code        count instructions: a u8 opcode followed by its operands
+//   debug       u8 presence flag, absent in stripped builds (since version 6),
+//               then: source hash u64; u32 count + that many pairs of the
+//               variable of a local slot and the name of its local; u32
+//               count + that many line entries, an instruction u64, a file
+//               and a line u32
+//
+// Strings are a u32 byte length followed by UTF-8 bytes; floats are f64 bits
+// and ints i64 (since version 9; before, every number was a float).
+// Arrays are a u32 element count followed by the elements (since version 8).
+// Since version 5, strings after the string table are a u32 index into it.
+
+use base64::Engine;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::bytecode::Module;
+use crate::strings::StringTable;
+use crate::verifier;
+use crate::vm::{Array, Instruction, Value};
+
+/// First bytes of every artifact.
+pub const MAGIC: &[u8; 4] = b"CBC\0";
+
+/// Version written into new artifacts: the layout above and the opcodes
+/// below. Bumped when either changes; older versions stay readable.
+pub const FORMAT_VERSION: u16 = 10;
+
+// How to decode each version that can still be read, oldest first
+struct Version {
+    number: u16,
+    has_max_stack: bool,   // Header records the stack depth
+    has_functions: bool,   // Header lists the functions; calls give their index, not a name
+    has_strings: bool,     // Header holds the string table the code refers to
+    has_debug: bool,       // Code is followed by the optional debug information
+    has_ints: bool,        // Constants may be ints
+    has_call_depth: bool,  // Stack depth is recorded for code with functions too
+    opcodes: &'static [u8], // Opcodes the version defines
+}
+
+const OPCODES_V1: &[u8] = &[
+    OP_PUSH, OP_POP, OP_DUPLICATE, OP_ADD, OP_SUBTRACT, OP_MULTIPLY, OP_DIVIDE, OP_NEGATE, OP_EQUAL,
+    OP_NOT_EQUAL, OP_GREATER_THAN, OP_LESS_THAN, OP_STORE_VARIABLE, OP_LOAD_VARIABLE, OP_JUMP,
+    OP_JUMP_IF_FALSE, OP_CALL, OP_CALL_NATIVE, OP_RETURN, OP_PRINT, OP_HALT,
+];
+
+// Version 3 adds integer division
+const OPCODES_V3: &[u8] = &[
+    OP_PUSH, OP_POP, OP_DUPLICATE, OP_ADD, OP_SUBTRACT, OP_MULTIPLY, OP_DIVIDE, OP_INT_DIVIDE, OP_NEGATE,
+    OP_EQUAL, OP_NOT_EQUAL, OP_GREATER_THAN, OP_LESS_THAN, OP_STORE_VARIABLE, OP_LOAD_VARIABLE, OP_JUMP,
+    OP_JUMP_IF_FALSE, OP_CALL, OP_CALL_NATIVE, OP_RETURN, OP_PRINT, OP_HALT,
+];
+
+// Version 4 adds the function table
+const OPCODES_V4: &[u8] = &[
+    OP_PUSH, OP_POP, OP_DUPLICATE, OP_ADD, OP_SUBTRACT, OP_MULTIPLY, OP_DIVIDE, OP_INT_DIVIDE, OP_NEGATE,
+    OP_EQUAL, OP_NOT_EQUAL, OP_GREATER_THAN, OP_LESS_THAN, OP_STORE_VARIABLE, OP_LOAD_VARIABLE, OP_JUMP,
+    OP_JUMP_IF_FALSE, OP_CALL, OP_CALL_NATIVE, OP_RETURN, OP_FUNCTION, OP_PRINT, OP_HALT,
+];
+
+// Version 7 adds <= and >=
+const OPCODES_V7: &[u8] = &[
+    OP_PUSH, OP_POP, OP_DUPLICATE, OP_ADD, OP_SUBTRACT, OP_MULTIPLY, OP_DIVIDE, OP_INT_DIVIDE, OP_NEGATE,
+    OP_EQUAL, OP_NOT_EQUAL, OP_GREATER_THAN, OP_LESS_THAN, OP_GREATER_EQUAL, OP_LESS_EQUAL, OP_STORE_VARIABLE,
+    OP_LOAD_VARIABLE, OP_JUMP, OP_JUMP_IF_FALSE, OP_CALL, OP_CALL_NATIVE, OP_RETURN, OP_FUNCTION, OP_PRINT, OP_HALT,
+];
+
+// Version 8 adds arrays
+const OPCODES_V8: &[u8] = &[
+    OP_PUSH, OP_POP, OP_DUPLICATE, OP_ADD, OP_SUBTRACT, OP_MULTIPLY, OP_DIVIDE, OP_INT_DIVIDE, OP_NEGATE,
+    OP_EQUAL, OP_NOT_EQUAL, OP_GREATER_THAN, OP_LESS_THAN, OP_GREATER_EQUAL, OP_LESS_EQUAL, OP_STORE_VARIABLE,
+    OP_LOAD_VARIABLE, OP_JUMP, OP_JUMP_IF_FALSE, OP_CALL, OP_CALL_NATIVE, OP_RETURN, OP_FUNCTION, OP_PRINT,
+    OP_MAKE_ARRAY, OP_INDEX_GET, OP_INDEX_SET, OP_HALT,
+];
+
+const VERSIONS: &[Version] = &[
+    Version { number: 1, has_max_stack: false, has_functions: false, has_strings: false, has_debug: false, has_ints: false, has_call_depth: false, opcodes: OPCODES_V1 },
+    Version { number: 2, has_max_stack: true, has_functions: false, has_strings: false, has_debug: false, has_ints: false, has_call_depth: false, opcodes: OPCODES_V1 },
+    Version { number: 3, has_max_stack: true, has_functions: false, has_strings: false, has_debug: false, has_ints: false, has_call_depth: false, opcodes: OPCODES_V3 },
+    Version { number: 4, has_max_stack: true, has_functions: true, has_strings: false, has_debug: false, has_ints: false, has_call_depth: false, opcodes: OPCODES_V4 },
+    // Version 5 adds the string table
+    Version { number: 5, has_max_stack: true, has_functions: true, has_strings: true, has_debug: false, has_ints: false, has_call_depth: false, opcodes: OPCODES_V4 },
+    // Version 6 adds the debug information
+    Version { number: 6, has_max_stack: true, has_functions: true, has_strings: true, has_debug: true, has_ints: false, has_call_depth: false, opcodes: OPCODES_V4 },
+    Version { number: 7, has_max_stack: true, has_functions: true, has_strings: true, has_debug: true, has_ints: false, has_call_depth: false, opcodes: OPCODES_V7 },
+    Version { number: 8, has_max_stack: true, has_functions: true, has_strings: true, has_debug: true, has_ints: false, has_call_depth: false, opcodes: OPCODES_V8 },
+    // Version 9 adds ints
+    Version { number: 9, has_max_stack: true, has_functions: true, has_strings: true, has_debug: true, has_ints: true, has_call_depth: false, opcodes: OPCODES_V8 },
+    // Version 10 bounds the stack of code with functions
+    Version { number: 10, has_max_stack: true, has_functions: true, has_strings: true, has_debug: true, has_ints: true, has_call_depth: true, opcodes: OPCODES_V8 },
+];
+
+/// Execution limits stored in an artifact and applied when it runs.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub timeout_ms: Option<u64>,   // Wall-clock limit for a run
+    pub max_sleep_ms: Option<u64>, // Total time the program may spend in sleep()
+}
+
+/// A compiled program with the settings it was built with.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub opt_level: u8,
+    pub limits: Limits,
+    pub max_stack: Option<usize>, // See `verifier::max_stack_depth`
+    pub instructions: Vec<Instruction>,
+    pub debug: Option<DebugInfo>, // None in stripped builds
+}
+
+/// What an artifact keeps of its source to explain runtime errors.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub source_hash: u64,              // See `source_hash`
+    pub locals: Vec<(String, String)>, // VM variable of each local slot, name of the first local in it
+    pub lines: Vec<LineEntry>,         // By instruction; empty when the code was optimized
+}
+
+/// Start of the code of a top-level statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineEntry {
+    pub instruction: usize,
+    pub file: String, // Empty for the source being run
+    pub line: usize,  // 0 when unknown
+}
+
+/// A runtime error in a statement of the source being run, whose line error
+/// details give along with the message.
+#[derive(Debug)]
+pub struct StatementError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for StatementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for StatementError {}
+
+impl DebugInfo {
+    /// The VM variable of each local slot of `module`, with the name of the
+    /// first local in it.
+    pub fn locals_of(module: &Module) -> Vec<(String, String)> {
+        module
+            .chunks
+            .iter()
+            .enumerate()
+            .flat_map(|(chunk, code)| {
+                code.locals.iter().enumerate().map(move |(slot, name)| (crate::local_variable(chunk, slot), name.clone()))
+            })
+            .collect()
+    }
+
+    /// The top-level statement whose code holds `instruction`, if known.
+    pub fn statement_at(&self, instruction: usize) -> Option<&LineEntry> {
+        let next = self.lines.partition_point(|entry| entry.instruction <= instruction);
+        next.checked_sub(1).map(|index| &self.lines[index]).filter(|entry| entry.line > 0)
+    }
+
+    /// The error with `message` at `instruction`, with locals called by
+    /// their names and the statement it happened in: a `StatementError` for
+    /// the source being run, otherwise named in the message.
+    pub fn describe_error(&self, message: &str, instruction: usize) -> Box<dyn Error> {
+        let mut message = message.to_string();
+        for (variable, name) in &self.locals {
+            message = message.replace(variable, name);
+        }
+        match self.statement_at(instruction) {
+            Some(entry) if entry.file.is_empty() => Box::new(StatementError { message, line: entry.line }),
+            Some(entry) => format!("{}, in the statement at line {} of {}", message, entry.line, entry.file).into(),
+            None => message.into(),
+        }
+    }
+}
+
+/// Hash identifying the sources an artifact was built from: FNV-1a over
+/// their contents, in order. Unlike the std hasher it is the same in
+/// every build of the compiler.
+pub fn source_hash<'a>(sources: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for source in sources {
+        for byte in source.bytes().chain([0]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Error for data that is not a valid artifact.
+#[derive(Debug)]
+pub struct ArtifactError {
+    message: String,
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid bytecode artifact: {}", self.message)
+    }
+}
+
+impl Error for ArtifactError {}
+
+impl ArtifactError {
+    fn new(message: impl Into<String>) -> Self {
+        ArtifactError { message: message.into() }
+    }
+}
+
+// Opcodes of the serialized instructions
+const OP_PUSH: u8 = 0x01;
+const OP_POP: u8 = 0x02;
+const OP_DUPLICATE: u8 = 0x03;
+const OP_ADD: u8 = 0x10;
+const OP_SUBTRACT: u8 = 0x11;
+const OP_MULTIPLY: u8 = 0x12;
+const OP_DIVIDE: u8 = 0x13;
+const OP_NEGATE: u8 = 0x14;
+const OP_INT_DIVIDE: u8 = 0x15;
+const OP_EQUAL: u8 = 0x20;
+const OP_NOT_EQUAL: u8 = 0x21;
+const OP_GREATER_THAN: u8 = 0x22;
+const OP_LESS_THAN: u8 = 0x23;
+const OP_GREATER_EQUAL: u8 = 0x24;
+const OP_LESS_EQUAL: u8 = 0x25;
+const OP_STORE_VARIABLE: u8 = 0x30;
+const OP_LOAD_VARIABLE: u8 = 0x31;
+const OP_JUMP: u8 = 0x40;
+const OP_JUMP_IF_FALSE: u8 = 0x41;
+const OP_CALL: u8 = 0x42;
+const OP_CALL_NATIVE: u8 = 0x43;
+const OP_RETURN: u8 = 0x44;
+const OP_FUNCTION: u8 = 0x45;
+const OP_PRINT: u8 = 0x50;
+const OP_MAKE_ARRAY: u8 = 0x60;
+const OP_INDEX_GET: u8 = 0x61;
+const OP_INDEX_SET: u8 = 0x62;
+const OP_HALT: u8 = 0xFF;
+
+// Tags of constant values
+const VALUE_NUMBER: u8 = 0;
+const VALUE_STRING: u8 = 1;
+const VALUE_BOOLEAN: u8 = 2;
+const VALUE_NULL: u8 = 3;
+const VALUE_ARRAY: u8 = 4;
+const VALUE_INT: u8 = 5;
+
+// Arrays nested deeper than this are refused, so decoding cannot exhaust the stack
+const MAX_VALUE_DEPTH: usize = 64;
+
+impl Artifact {
+    /// Whether `bytes` looks like an artifact rather than source code.
+    pub fn is_artifact(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Serializes the artifact.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.push(self.opt_level);
+        write_optional(&mut out, self.limits.timeout_ms);
+        write_optional(&mut out, self.limits.max_sleep_ms);
+        write_optional(&mut out, self.max_stack.map(|slots| slots as u64));
+
+        let mut strings = StringTable::of(&self.instructions);
+        out.extend_from_slice(&(strings.strings().len() as u32).to_le_bytes());
+        for string in strings.strings() {
+            write_string(&mut out, string);
+        }
+
+        let functions: Vec<&String> = self
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Function(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        out.extend_from_slice(&(functions.len() as u32).to_le_bytes());
+        for name in functions {
+            write_index(&mut out, &mut strings, name);
+        }
+
+        out.extend_from_slice(&(self.instructions.len() as u32).to_le_bytes());
+        for instruction in &self.instructions {
+            write_instruction(&mut out, &mut strings, instruction);
+        }
+
+        out.push(self.debug.is_some() as u8);
+        if let Some(debug) = &self.debug {
+            out.extend_from_slice(&debug.source_hash.to_le_bytes());
+            out.extend_from_slice(&(debug.locals.len() as u32).to_le_bytes());
+            for (variable, name) in &debug.locals {
+                write_string(&mut out, variable);
+                write_string(&mut out, name);
+            }
+            out.extend_from_slice(&(debug.lines.len() as u32).to_le_bytes());
+            for entry in &debug.lines {
+                out.extend_from_slice(&(entry.instruction as u64).to_le_bytes());
+                write_string(&mut out, &entry.file);
+                out.extend_from_slice(&(entry.line as u32).to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Decodes and verifies an artifact from an untrusted source.
+    pub fn load(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let artifact = Artifact::decode(bytes)?;
+        verifier::verify(&artifact.instructions)?;
+        let max_stack = verifier::max_stack_depth(&artifact.instructions)?;
+        if artifact.max_stack != max_stack {
+            return Err(Box::new(ArtifactError::new(format!(
+                "header declares a stack depth of {} but the code needs {}",
+                describe_depth(artifact.max_stack),
+                describe_depth(max_stack)
+            ))));
+        }
+        Ok(artifact)
+    }
+
+    /// Like `load`, for an artifact encoded as standard base64.
+    pub fn load_base64(text: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(text.trim())
+            .map_err(|e| ArtifactError::new(format!("not valid base64 ({})", e)))?;
+        Artifact::load(&bytes)
+    }
+
+    /// Deserializes an artifact, rejecting truncated, unknown or trailing data.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ArtifactError> {
+        let mut reader = Reader {
+            bytes,
+            position: 0,
+            strings: Vec::new(),
+        };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ArtifactError::new("missing header, not a compiled program"));
+        }
+        let number = reader.u16()?;
+        let version = VERSIONS.iter().find(|version| version.number == number).ok_or_else(|| {
+            let (oldest, newest) = (VERSIONS[0].number, FORMAT_VERSION);
+            let (age, hint) = if number > newest {
+                ("a newer", "upgrade the compiler or build it again")
+            } else {
+                ("an older", "build it again")
+            };
+            ArtifactError::new(format!(
+                "bytecode compiled with {} version of the compiler (bytecode version {}; this compiler reads versions {} to {}), {}",
+                age, number, oldest, newest, hint
+            ))
+        })?;
+        let opt_level = reader.u8()?;
+        let limits = Limits {
+            timeout_ms: reader.optional()?,
+            max_sleep_ms: reader.optional()?,
+        };
+        let mut max_stack = None;
+        if version.has_max_stack {
+            if let Some(slots) = reader.optional()? {
+                max_stack = Some(usize::try_from(slots).map_err(|_| ArtifactError::new("stack depth out of range"))?);
+            }
+        }
+
+        if version.has_strings {
+            let count = reader.u32()? as usize;
+            // Every string takes at least four bytes
+            if count > reader.remaining() / 4 {
+                return Err(ArtifactError::new("string count exceeds the data"));
+            }
+            for _ in 0..count {
+                let string = reader.string()?;
+                reader.strings.push(string);
+            }
+        }
+
+        let mut functions = Vec::new();
+        if version.has_functions {
+            let count = reader.u32()? as usize;
+            // Every name takes at least four bytes
+            if count > reader.remaining() / 4 {
+                return Err(ArtifactError::new("function count exceeds the data"));
+            }
+            for _ in 0..count {
+                functions.push(reader.name(version)?);
+            }
+        }
+
+        let count = reader.u32()? as usize;
+        // Every instruction takes at least one byte, which bounds the allocation
+        if count > reader.remaining() {
+            return Err(ArtifactError::new("instruction count exceeds the data"));
+        }
+        let mut instructions = Vec::with_capacity(count);
+        let mut names = functions.into_iter();
+        for _ in 0..count {
+            let instruction = match reader.instruction(version)? {
+                Instruction::Function(_) => Instruction::Function(
+                    names.next().ok_or_else(|| ArtifactError::new("more functions than the function table lists"))?,
+                ),
+                instruction => instruction,
+            };
+            instructions.push(instruction);
+        }
+        if names.next().is_some() {
+            return Err(ArtifactError::new("function table lists functions the code does not define"));
+        }
+        let debug = if version.has_debug { reader.debug()? } else { None };
+        if reader.remaining() > 0 {
+            return Err(ArtifactError::new("unexpected data after the last instruction"));
+        }
+        // Older headers don't record the depth, or not for code with functions;
+        // code that underflows fails verification
+        let defines_functions = instructions.iter().any(|instruction| matches!(instruction, Instruction::Function(_)));
+        if !version.has_max_stack || (!version.has_call_depth && defines_functions) {
+            max_stack = verifier::max_stack_depth(&instructions).unwrap_or(None);
+        }
+
+        Ok(Artifact {
+            opt_level,
+            limits,
+            max_stack,
+            instructions,
+            debug,
+        })
+    }
+}
+
+fn describe_depth(depth: Option<usize>) -> String {
+    depth.map_or("unbounded".to_string(), |slots| slots.to_string())
+}
+
+fn write_instruction(out: &mut Vec<u8>, strings: &mut StringTable, instruction: &Instruction) {
+    match instruction {
+        Instruction::Push(value) => {
+            out.push(OP_PUSH);
+            write_value(out, strings, value);
+        }
+        Instruction::Pop => out.push(OP_POP),
+        Instruction::Duplicate => out.push(OP_DUPLICATE),
+        Instruction::Add => out.push(OP_ADD),
+        Instruction::Subtract => out.push(OP_SUBTRACT),
+        Instruction::Multiply => out.push(OP_MULTIPLY),
+        Instruction::Divide => out.push(OP_DIVIDE),
+        Instruction::IntDivide => out.push(OP_INT_DIVIDE),
+        Instruction::Negate => out.push(OP_NEGATE),
+        Instruction::Equal => out.push(OP_EQUAL),
+        Instruction::NotEqual => out.push(OP_NOT_EQUAL),
+        Instruction::GreaterThan => out.push(OP_GREATER_THAN),
+        Instruction::LessThan => out.push(OP_LESS_THAN),
+        Instruction::GreaterEqual => out.push(OP_GREATER_EQUAL),
+        Instruction::LessEqual => out.push(OP_LESS_EQUAL),
+        Instruction::MakeArray(count) => {
+            out.push(OP_MAKE_ARRAY);
+            out.extend_from_slice(&(*count as u64).to_le_bytes());
+        }
+        Instruction::IndexGet => out.push(OP_INDEX_GET),
+        Instruction::IndexSet(count) => {
+            out.push(OP_INDEX_SET);
+            out.extend_from_slice(&(*count as u64).to_le_bytes());
+        }
+        Instruction::StoreVariable(name) => {
+            out.push(OP_STORE_VARIABLE);
+            write_index(out, strings, name);
+        }
+        Instruction::LoadVariable(name) => {
+            out.push(OP_LOAD_VARIABLE);
+            write_index(out, strings, name);
+        }
+        Instruction::Jump(target) => {
+            out.push(OP_JUMP);
+            out.extend_from_slice(&(*target as u64).to_le_bytes());
+        }
+        Instruction::JumpIfFalse(target) => {
+            out.push(OP_JUMP_IF_FALSE);
+            out.extend_from_slice(&(*target as u64).to_le_bytes());
+        }
+        Instruction::Call(function, arg_count) => {
+            out.push(OP_CALL);
+            out.extend_from_slice(&(*function as u64).to_le_bytes());
+            out.extend_from_slice(&(*arg_count as u64).to_le_bytes());
+        }
+        Instruction::CallNative(name, arg_count) => {
+            out.push(OP_CALL_NATIVE);
+            write_index(out, strings, name);
+            out.extend_from_slice(&(*arg_count as u64).to_le_bytes());
+        }
+        Instruction::Return => out.push(OP_RETURN),
+        // The name is in the function table of the header
+        Instruction::Function(_) => out.push(OP_FUNCTION),
+        Instruction::Print => out.push(OP_PRINT),
+        Instruction::Halt => out.push(OP_HALT),
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, strings: &mut StringTable, value: &Value) {
+    match value {
+        Value::Int(i) => {
+            out.push(VALUE_INT);
+            out.extend_from_slice(&i.to_le_bytes());
+        }
+        Value::Number(n) => {
+            out.push(VALUE_NUMBER);
+            out.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        Value::String(s) => {
+            out.push(VALUE_STRING);
+            write_index(out, strings, s);
+        }
+        Value::Boolean(b) => {
+            out.push(VALUE_BOOLEAN);
+            out.push(*b as u8);
+        }
+        Value::Null => out.push(VALUE_NULL),
+        Value::Array(elements) => {
+            out.push(VALUE_ARRAY);
+            out.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+            for element in elements.iter() {
+                write_value(out, strings, element);
+            }
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+// Writes a string as its index in the string table
+fn write_index(out: &mut Vec<u8>, strings: &mut StringTable, s: &str) {
+    out.extend_from_slice(&(strings.intern(s) as u32).to_le_bytes());
+}
+
+fn write_optional(out: &mut Vec<u8>, value: Option<u64>) {
+    out.push(value.is_some() as u8);
+    out.extend_from_slice(&value.unwrap_or(0).to_le_bytes());
+}
+
+// Cursor over the serialized bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    strings: Vec<String>, // String table, once read
+}
+
+impl Reader<'_> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], ArtifactError> {
+        if len > self.remaining() {
+            return Err(ArtifactError::new("unexpected end of data"));
+        }
+        let slice = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ArtifactError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, ArtifactError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ArtifactError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ArtifactError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, ArtifactError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, ArtifactError> {
+        usize::try_from(self.u64()?).map_err(|_| ArtifactError::new("operand out of range"))
+    }
+
+    fn optional(&mut self) -> Result<Option<u64>, ArtifactError> {
+        let present = self.u8()?;
+        let value = self.u64()?;
+        match present {
+            0 => Ok(None),
+            1 => Ok(Some(value)),
+            flag => Err(ArtifactError::new(format!("invalid presence flag {}", flag))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ArtifactError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ArtifactError::new("string is not valid UTF-8"))
+    }
+
+    fn debug(&mut self) -> Result<Option<DebugInfo>, ArtifactError> {
+        match self.u8()? {
+            0 => return Ok(None),
+            1 => {}
+            flag => return Err(ArtifactError::new(format!("invalid presence flag {}", flag))),
+        }
+        let source_hash = self.u64()?;
+        // Every local takes at least eight bytes, and every line entry sixteen
+        let count = self.u32()? as usize;
+        if count > self.remaining() / 8 {
+            return Err(ArtifactError::new("local count exceeds the data"));
+        }
+        let mut locals = Vec::with_capacity(count);
+        for _ in 0..count {
+            locals.push((self.string()?, self.string()?));
+        }
+        let count = self.u32()? as usize;
+        if count > self.remaining() / 16 {
+            return Err(ArtifactError::new("line count exceeds the data"));
+        }
+        let mut lines = Vec::with_capacity(count);
+        for _ in 0..count {
+            lines.push(LineEntry {
+                instruction: self.usize()?,
+                file: self.string()?,
+                line: self.u32()? as usize,
+            });
+        }
+        if lines.windows(2).any(|pair| pair[0].instruction > pair[1].instruction) {
+            return Err(ArtifactError::new("line entries are not in instruction order"));
+        }
+        Ok(Some(DebugInfo {
+            source_hash,
+            locals,
+            lines,
+        }))
+    }
+
+    // A string of the code: from the string table in versions that have one
+    fn name(&mut self, version: &Version) -> Result<String, ArtifactError> {
+        if !version.has_strings {
+            return self.string();
+        }
+        let index = self.u32()? as usize;
+        self.strings
+            .get(index)
+            .cloned()
+            .ok_or_else(|| ArtifactError::new(format!("string {} is not in the string table", index)))
+    }
+
+    fn value(&mut self, version: &Version) -> Result<Value, ArtifactError> {
+        self.nested_value(version, 0)
+    }
+
+    // A value inside `depth` arrays
+    fn nested_value(&mut self, version: &Version, depth: usize) -> Result<Value, ArtifactError> {
+        match self.u8()? {
+            VALUE_INT if version.has_ints => Ok(Value::Int(self.u64()? as i64)),
+            VALUE_NUMBER => Ok(Value::Number(f64::from_bits(self.u64()?))),
+            VALUE_STRING => Ok(Value::String(self.name(version)?)),
+            VALUE_BOOLEAN => match self.u8()? {
+                0 => Ok(Value::Boolean(false)),
+                1 => Ok(Value::Boolean(true)),
+                other => Err(ArtifactError::new(format!("invalid boolean {}", other))),
+            },
+            VALUE_NULL => Ok(Value::Null),
+            VALUE_ARRAY if version.opcodes.contains(&OP_MAKE_ARRAY) => {
+                if depth >= MAX_VALUE_DEPTH {
+                    return Err(ArtifactError::new(format!("arrays nested more than {} deep", MAX_VALUE_DEPTH)));
+                }
+                let count = self.u32()? as usize;
+                // Every element takes at least one byte
+                if count > self.remaining() {
+                    return Err(ArtifactError::new("array length exceeds the data"));
+                }
+                let mut elements = Vec::with_capacity(count);
+                for _ in 0..count {
+                    elements.push(self.nested_value(version, depth + 1)?);
+                }
+                Ok(Value::Array(Arc::new(Array::new(elements))))
+            }
+            tag => Err(ArtifactError::new(format!("unknown value tag {}", tag))),
+        }
+    }
+
+    fn instruction(&mut self, version: &Version) -> Result<Instruction, ArtifactError> {
+        let opcode = self.u8()?;
+        if !version.opcodes.contains(&opcode) {
+            return Err(ArtifactError::new(format!(
+                "unknown opcode 0x{:02x} for bytecode version {}",
+                opcode, version.number
+            )));
+        }
+        let instruction = match opcode {
+            OP_PUSH => Instruction::Push(self.value(version)?),
+            OP_POP => Instruction::Pop,
+            OP_DUPLICATE => Instruction::Duplicate,
+            OP_ADD => Instruction::Add,
+            OP_SUBTRACT => Instruction::Subtract,
+            OP_MULTIPLY => Instruction::Multiply,
+            OP_DIVIDE => Instruction::Divide,
+            OP_INT_DIVIDE => Instruction::IntDivide,
+            OP_NEGATE => Instruction::Negate,
+            OP_EQUAL => Instruction::Equal,
+            OP_NOT_EQUAL => Instruction::NotEqual,
+            OP_GREATER_THAN => Instruction::GreaterThan,
+            OP_LESS_THAN => Instruction::LessThan,
+            OP_GREATER_EQUAL => Instruction::GreaterEqual,
+            OP_LESS_EQUAL => Instruction::LessEqual,
+            OP_STORE_VARIABLE => Instruction::StoreVariable(self.name(version)?),
+            OP_LOAD_VARIABLE => Instruction::LoadVariable(self.name(version)?),
+            OP_JUMP => Instruction::Jump(self.usize()?),
+            OP_JUMP_IF_FALSE => Instruction::JumpIfFalse(self.usize()?),
+            OP_CALL if version.has_functions => Instruction::Call(self.usize()?, self.usize()?),
+            // Compilers of older versions never defined functions to call
+            OP_CALL => {
+                let name = self.string()?;
+                return Err(ArtifactError::new(format!(
+                    "call to function '{}', which bytecode version {} cannot define",
+                    name, version.number
+                )));
+            }
+            OP_CALL_NATIVE => Instruction::CallNative(self.name(version)?, self.usize()?),
+            OP_RETURN => Instruction::Return,
+            // Named from the function table once decoded
+            OP_FUNCTION => Instruction::Function(String::new()),
+            OP_PRINT => Instruction::Print,
+            OP_MAKE_ARRAY => Instruction::MakeArray(self.usize()?),
+            OP_INDEX_GET => Instruction::IndexGet,
+            OP_INDEX_SET => Instruction::IndexSet(self.usize()?),
+            OP_HALT => Instruction::Halt,
+            // In a version's table but not decoded above
+            opcode => return Err(ArtifactError::new(format!("unknown opcode 0x{:02x}", opcode))),
+        };
+        Ok(instruction)
+    }
+}
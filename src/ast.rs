@@ -0,0 +1,353 @@
+// Syntax trees stored in an arena: the nodes of a program live in one vector
+// and refer to their children by index instead of owning them in boxes. A
+// pass can hand out and keep node ids, which are plain numbers, so walking or
+// revisiting a subtree never copies it, as the code generator does for
+// deferred statements that run again at each `return` and `break`.
+//
+// The parser builds its nodes into an arena, which the code generator reads
+// as it is. `ASTNode` trees remain the form the other analyses and the parse
+// listings of the JSON endpoints use: `Ast::to_tree` converts a node into
+// one, and `Ast::add` moves a tree into an arena.
+
+use std::ops::{Index, IndexMut};
+
+use crate::lexer::TokenType;
+use crate::parser::ASTNode;
+
+/// Index of a node in its `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+/// A node of an `Ast`; each variant is the `ASTNode` of the same name with
+/// ids in place of its children.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Program(Vec<NodeId>),
+    VarDeclaration {
+        var_type: String,
+        name: String,
+        initializer: Option<NodeId>,
+        doc: Option<String>,
+    },
+    Block(Vec<NodeId>),
+    ExpressionStatement(NodeId),
+    IfStatement {
+        condition: NodeId,
+        then_branch: NodeId,
+        else_branch: Option<NodeId>,
+    },
+    WhileStatement {
+        condition: NodeId,
+        body: NodeId,
+        label: Option<String>,
+    },
+    BreakStatement(Option<String>),
+    ContinueStatement(Option<String>),
+    ReturnStatement(Option<NodeId>),
+    PrintStatement(NodeId),
+    DeferStatement(NodeId),
+    Import(String),
+    FunctionDeclaration {
+        name: String,
+        params: Vec<String>,
+        body: NodeId,
+        doc: Option<String>,
+    },
+    TestBlock {
+        name: String,
+        body: NodeId,
+    },
+    BinaryExpression {
+        left: NodeId,
+        operator: TokenType<'static>,
+        right: NodeId,
+    },
+    UnaryExpression {
+        operator: TokenType<'static>,
+        operand: NodeId,
+    },
+    CallExpression {
+        callee: NodeId,
+        arguments: Vec<NodeId>,
+    },
+    AssignmentExpression {
+        name: String,
+        value: NodeId,
+    },
+    IndexExpression {
+        array: NodeId,
+        index: NodeId,
+    },
+    IndexAssignment {
+        name: String,
+        indices: Vec<NodeId>,
+        value: NodeId,
+    },
+    BlockExpression {
+        statements: Vec<NodeId>,
+        value: Option<NodeId>,
+    },
+    IfExpression {
+        condition: NodeId,
+        then_branch: NodeId,
+        else_branch: NodeId,
+    },
+    IntLiteral(i64),
+    FloatLiteral(f64),
+    StringLiteral(String),
+    NullLiteral,
+    ArrayLiteral(Vec<NodeId>),
+    Identifier(String),
+}
+
+/// Arena holding the nodes of one or more syntax trees.
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+    nodes: Vec<Node>,
+}
+
+impl Index<NodeId> for Ast {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0 as usize]
+    }
+}
+
+impl IndexMut<NodeId> for Ast {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0 as usize]
+    }
+}
+
+impl Ast {
+    /// Adds `node`, whose children are already in the arena, and returns its id.
+    pub fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId(u32::try_from(self.nodes.len() - 1).expect("more syntax tree nodes than fit in a node id"))
+    }
+
+    /// Moves the tree `node` into the arena and returns the id of its root.
+    /// Children get their ids before their parent.
+    pub fn add(&mut self, node: ASTNode) -> NodeId {
+        let node = match node {
+            ASTNode::Program(statements) => Node::Program(self.add_all(statements)),
+            ASTNode::VarDeclaration { var_type, name, initializer, doc } => Node::VarDeclaration {
+                var_type,
+                name,
+                initializer: initializer.map(|initializer| self.add(*initializer)),
+                doc,
+            },
+            ASTNode::Block(statements) => Node::Block(self.add_all(statements)),
+            ASTNode::ExpressionStatement(expr) => Node::ExpressionStatement(self.add(*expr)),
+            ASTNode::IfStatement { condition, then_branch, else_branch } => Node::IfStatement {
+                condition: self.add(*condition),
+                then_branch: self.add(*then_branch),
+                else_branch: else_branch.map(|else_branch| self.add(*else_branch)),
+            },
+            ASTNode::WhileStatement { condition, body, label } => Node::WhileStatement {
+                condition: self.add(*condition),
+                body: self.add(*body),
+                label,
+            },
+            ASTNode::BreakStatement(label) => Node::BreakStatement(label),
+            ASTNode::ContinueStatement(label) => Node::ContinueStatement(label),
+            ASTNode::ReturnStatement(value) => Node::ReturnStatement(value.map(|value| self.add(*value))),
+            ASTNode::PrintStatement(value) => Node::PrintStatement(self.add(*value)),
+            ASTNode::DeferStatement(statement) => Node::DeferStatement(self.add(*statement)),
+            ASTNode::Import(path) => Node::Import(path),
+            ASTNode::FunctionDeclaration { name, params, body, doc } => Node::FunctionDeclaration {
+                name,
+                params,
+                body: self.add(*body),
+                doc,
+            },
+            ASTNode::TestBlock { name, body } => Node::TestBlock { name, body: self.add(*body) },
+            // A chain of operators, as in `a + b - c`, is added from its first
+            // operand up with a loop, so a long one doesn't exhaust the stack
+            ASTNode::BinaryExpression { left, operator, right } => {
+                let mut operations = vec![(operator, *right)];
+                let mut first = *left;
+                while let ASTNode::BinaryExpression { left, operator, right } = first {
+                    operations.push((operator, *right));
+                    first = *left;
+                }
+                let mut left = self.add(first);
+                for (operator, right) in operations.into_iter().rev() {
+                    let right = self.add(right);
+                    left = self.push(Node::BinaryExpression { left, operator, right });
+                }
+                return left;
+            }
+            ASTNode::UnaryExpression { operator, operand } => Node::UnaryExpression {
+                operator,
+                operand: self.add(*operand),
+            },
+            ASTNode::CallExpression { callee, arguments } => Node::CallExpression {
+                callee: self.add(*callee),
+                arguments: self.add_all(arguments),
+            },
+            ASTNode::AssignmentExpression { name, value } => Node::AssignmentExpression {
+                name,
+                value: self.add(*value),
+            },
+            ASTNode::IndexExpression { array, index } => Node::IndexExpression {
+                array: self.add(*array),
+                index: self.add(*index),
+            },
+            ASTNode::IndexAssignment { name, indices, value } => Node::IndexAssignment {
+                name,
+                indices: self.add_all(indices),
+                value: self.add(*value),
+            },
+            ASTNode::BlockExpression { statements, value } => Node::BlockExpression {
+                statements: self.add_all(statements),
+                value: value.map(|value| self.add(*value)),
+            },
+            ASTNode::IfExpression { condition, then_branch, else_branch } => Node::IfExpression {
+                condition: self.add(*condition),
+                then_branch: self.add(*then_branch),
+                else_branch: self.add(*else_branch),
+            },
+            ASTNode::IntLiteral(value) => Node::IntLiteral(value),
+            ASTNode::FloatLiteral(value) => Node::FloatLiteral(value),
+            ASTNode::StringLiteral(value) => Node::StringLiteral(value),
+            ASTNode::NullLiteral => Node::NullLiteral,
+            ASTNode::ArrayLiteral(elements) => Node::ArrayLiteral(self.add_all(elements)),
+            ASTNode::Identifier(name) => Node::Identifier(name),
+        };
+        self.push(node)
+    }
+
+    fn add_all(&mut self, nodes: Vec<ASTNode>) -> Vec<NodeId> {
+        nodes.into_iter().map(|node| self.add(node)).collect()
+    }
+
+    /// The nodes directly inside the node at `id`, in source order.
+    pub fn children(&self, id: NodeId) -> Vec<NodeId> {
+        match &self[id] {
+            Node::Program(nodes) | Node::Block(nodes) | Node::ArrayLiteral(nodes) => nodes.clone(),
+            Node::VarDeclaration { initializer: node, .. } | Node::ReturnStatement(node) => node.iter().copied().collect(),
+            Node::ExpressionStatement(node)
+            | Node::PrintStatement(node)
+            | Node::DeferStatement(node)
+            | Node::FunctionDeclaration { body: node, .. }
+            | Node::TestBlock { body: node, .. }
+            | Node::UnaryExpression { operand: node, .. }
+            | Node::AssignmentExpression { value: node, .. } => vec![*node],
+            Node::IfStatement { condition, then_branch, else_branch } => {
+                [*condition, *then_branch].into_iter().chain(*else_branch).collect()
+            }
+            Node::WhileStatement { condition: left, body: right, .. }
+            | Node::BinaryExpression { left, right, .. }
+            | Node::IndexExpression { array: left, index: right } => vec![*left, *right],
+            Node::CallExpression { callee, arguments } => std::iter::once(*callee).chain(arguments.iter().copied()).collect(),
+            Node::IndexAssignment { indices, value, .. } => indices.iter().copied().chain([*value]).collect(),
+            Node::BlockExpression { statements, value } => statements.iter().copied().chain(*value).collect(),
+            Node::IfExpression { condition, then_branch, else_branch } => vec![*condition, *then_branch, *else_branch],
+            Node::BreakStatement(_)
+            | Node::ContinueStatement(_)
+            | Node::Import(_)
+            | Node::IntLiteral(_)
+            | Node::FloatLiteral(_)
+            | Node::StringLiteral(_)
+            | Node::NullLiteral
+            | Node::Identifier(_) => Vec::new(),
+        }
+    }
+
+    /// The subtree at `id` as an `ASTNode` tree.
+    pub fn to_tree(&self, id: NodeId) -> ASTNode {
+        let boxed = |id: NodeId| Box::new(self.to_tree(id));
+        let trees = |ids: &[NodeId]| ids.iter().map(|&id| self.to_tree(id)).collect();
+        match &self[id] {
+            Node::Program(statements) => ASTNode::Program(trees(statements)),
+            Node::VarDeclaration { var_type, name, initializer, doc } => ASTNode::VarDeclaration {
+                var_type: var_type.clone(),
+                name: name.clone(),
+                initializer: initializer.map(boxed),
+                doc: doc.clone(),
+            },
+            Node::Block(statements) => ASTNode::Block(trees(statements)),
+            Node::ExpressionStatement(expr) => ASTNode::ExpressionStatement(boxed(*expr)),
+            Node::IfStatement { condition, then_branch, else_branch } => ASTNode::IfStatement {
+                condition: boxed(*condition),
+                then_branch: boxed(*then_branch),
+                else_branch: else_branch.map(boxed),
+            },
+            Node::WhileStatement { condition, body, label } => ASTNode::WhileStatement {
+                condition: boxed(*condition),
+                body: boxed(*body),
+                label: label.clone(),
+            },
+            Node::BreakStatement(label) => ASTNode::BreakStatement(label.clone()),
+            Node::ContinueStatement(label) => ASTNode::ContinueStatement(label.clone()),
+            Node::ReturnStatement(value) => ASTNode::ReturnStatement(value.map(boxed)),
+            Node::PrintStatement(value) => ASTNode::PrintStatement(boxed(*value)),
+            Node::DeferStatement(statement) => ASTNode::DeferStatement(boxed(*statement)),
+            Node::Import(path) => ASTNode::Import(path.clone()),
+            Node::FunctionDeclaration { name, params, body, doc } => ASTNode::FunctionDeclaration {
+                name: name.clone(),
+                params: params.clone(),
+                body: boxed(*body),
+                doc: doc.clone(),
+            },
+            Node::TestBlock { name, body } => ASTNode::TestBlock {
+                name: name.clone(),
+                body: boxed(*body),
+            },
+            // Built from the first operand of the chain up, as `add` adds it
+            Node::BinaryExpression { .. } => {
+                let mut operations = Vec::new();
+                let mut first = id;
+                while let Node::BinaryExpression { left, operator, right } = &self[first] {
+                    operations.push((operator, *right));
+                    first = *left;
+                }
+                operations.into_iter().rev().fold(self.to_tree(first), |left, (operator, right)| {
+                    ASTNode::BinaryExpression {
+                        left: Box::new(left),
+                        operator: operator.clone(),
+                        right: boxed(right),
+                    }
+                })
+            }
+            Node::UnaryExpression { operator, operand } => ASTNode::UnaryExpression {
+                operator: operator.clone(),
+                operand: boxed(*operand),
+            },
+            Node::CallExpression { callee, arguments } => ASTNode::CallExpression {
+                callee: boxed(*callee),
+                arguments: trees(arguments),
+            },
+            Node::AssignmentExpression { name, value } => ASTNode::AssignmentExpression {
+                name: name.clone(),
+                value: boxed(*value),
+            },
+            Node::IndexExpression { array, index } => ASTNode::IndexExpression {
+                array: boxed(*array),
+                index: boxed(*index),
+            },
+            Node::IndexAssignment { name, indices, value } => ASTNode::IndexAssignment {
+                name: name.clone(),
+                indices: trees(indices),
+                value: boxed(*value),
+            },
+            Node::BlockExpression { statements, value } => ASTNode::BlockExpression {
+                statements: trees(statements),
+                value: value.map(boxed),
+            },
+            Node::IfExpression { condition, then_branch, else_branch } => ASTNode::IfExpression {
+                condition: boxed(*condition),
+                then_branch: boxed(*then_branch),
+                else_branch: boxed(*else_branch),
+            },
+            Node::IntLiteral(value) => ASTNode::IntLiteral(*value),
+            Node::FloatLiteral(value) => ASTNode::FloatLiteral(*value),
+            Node::StringLiteral(value) => ASTNode::StringLiteral(value.clone()),
+            Node::NullLiteral => ASTNode::NullLiteral,
+            Node::ArrayLiteral(elements) => ASTNode::ArrayLiteral(trees(elements)),
+            Node::Identifier(name) => ASTNode::Identifier(name.clone()),
+        }
+    }
+}
@@ -0,0 +1,683 @@
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::sandbox::Capability;
+use crate::vm::{Array, ClockRead, Value, VirtualMachine};
+
+/// Signature shared by all native functions.
+pub type NativeFn = fn(&mut VirtualMachine, &[Value]) -> Result<Value, Box<dyn Error>>;
+
+/// A function implemented in Rust and callable from source code.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    pub variadic: bool,          // Whether more arguments than `arity` may follow
+    pub signature: &'static str, // Parameters and result type, shown by editor completions
+    pub function: NativeFn,
+    pub capability: Option<Capability>, // Permission the sandbox must grant before the call
+    pub constant_time: bool,     // Whether its running time cannot reveal its arguments, see `SandboxPolicy::constant_time`
+}
+
+impl Builtin {
+    /// Whether the builtin can be called with `count` arguments.
+    pub fn accepts(&self, count: usize) -> bool {
+        count == self.arity || (self.variadic && count > self.arity)
+    }
+
+    /// The number of arguments the builtin takes, as shown in errors.
+    pub fn arity_text(&self) -> String {
+        if self.variadic {
+            format!("at least {}", self.arity)
+        } else {
+            self.arity.to_string()
+        }
+    }
+}
+
+/// Every native function known to the compiler and the VM.
+const BUILTINS: &[Builtin] = &[
+    Builtin { name: "clock_ms", arity: 0, variadic: false, signature: "clock_ms() -> float", function: clock_ms, capability: Some(Capability::Time), constant_time: true },
+    Builtin { name: "now", arity: 0, variadic: false, signature: "now() -> string", function: now, capability: Some(Capability::Time), constant_time: true },
+    Builtin { name: "sleep", arity: 1, variadic: false, signature: "sleep(ms: int) -> null", function: sleep, capability: Some(Capability::Time), constant_time: false },
+    Builtin { name: "random", arity: 0, variadic: false, signature: "random() -> float", function: random, capability: Some(Capability::Random), constant_time: true },
+    Builtin { name: "rand_int", arity: 2, variadic: false, signature: "rand_int(lo: int, hi: int) -> int", function: rand_int, capability: Some(Capability::Random), constant_time: false },
+    Builtin { name: "read_line", arity: 0, variadic: false, signature: "read_line() -> string | null", function: read_line, capability: Some(Capability::Stdin), constant_time: true },
+    Builtin { name: "assert", arity: 1, variadic: true, signature: "assert(condition: bool, message...) -> null", function: assert, capability: None, constant_time: false },
+    Builtin { name: "secure_equals", arity: 2, variadic: false, signature: "secure_equals(a: string, b: string) -> bool", function: secure_equals, capability: None, constant_time: true },
+    Builtin { name: "exit", arity: 1, variadic: false, signature: "exit(code: int) -> null", function: exit, capability: None, constant_time: true },
+    Builtin { name: "getenv", arity: 1, variadic: false, signature: "getenv(name: string) -> string | null", function: getenv, capability: Some(Capability::Env), constant_time: false },
+    Builtin { name: "read_file", arity: 1, variadic: false, signature: "read_file(path: string) -> string | null", function: read_file, capability: Some(Capability::FileSystem), constant_time: false },
+    Builtin { name: "write_file", arity: 2, variadic: false, signature: "write_file(path: string, contents) -> null", function: write_file, capability: Some(Capability::FileSystem), constant_time: false },
+    Builtin { name: "append_file", arity: 2, variadic: false, signature: "append_file(path: string, contents) -> null", function: append_file, capability: Some(Capability::FileSystem), constant_time: false },
+    Builtin { name: "len", arity: 1, variadic: false, signature: "len(value: array | string) -> int", function: len, capability: None, constant_time: false },
+    Builtin { name: "push", arity: 2, variadic: false, signature: "push(array: array, value) -> array", function: push, capability: None, constant_time: false },
+    Builtin { name: "json_parse", arity: 1, variadic: false, signature: "json_parse(text: string) -> number | string | bool | array | null", function: json_parse, capability: None, constant_time: false },
+    Builtin { name: "json_string", arity: 1, variadic: false, signature: "json_string(value) -> string", function: json_string, capability: None, constant_time: false },
+    Builtin { name: "regex_match", arity: 2, variadic: false, signature: "regex_match(pattern: string, text: string) -> bool", function: regex_match, capability: None, constant_time: false },
+    Builtin { name: "regex_find_all", arity: 2, variadic: false, signature: "regex_find_all(pattern: string, text: string) -> array", function: regex_find_all, capability: None, constant_time: false },
+    Builtin { name: "regex_replace", arity: 3, variadic: false, signature: "regex_replace(pattern: string, text: string, replacement: string) -> string", function: regex_replace, capability: None, constant_time: false },
+    Builtin { name: "checked_add", arity: 2, variadic: false, signature: "checked_add(a, b) -> number | string | null", function: checked_add, capability: None, constant_time: false },
+    Builtin { name: "checked_div", arity: 2, variadic: false, signature: "checked_div(a: float, b: float) -> float | null", function: checked_div, capability: None, constant_time: false },
+    Builtin { name: "format", arity: 1, variadic: true, signature: "format(format: string, values...) -> string", function: format, capability: None, constant_time: false },
+    Builtin { name: "printf", arity: 1, variadic: true, signature: "printf(format: string, values...) -> null", function: printf, capability: None, constant_time: false },
+];
+
+/// Sleeps are split into slices so a cancelled run stops promptly.
+const SLEEP_SLICE_MS: u64 = 10;
+
+/// Most decimals a `{:.Nf}` placeholder may ask for.
+const MAX_DECIMALS: usize = 20;
+
+/// Longest pattern the regex builtins accept, in bytes.
+const MAX_PATTERN_LEN: usize = 1_000;
+
+/// Memory a compiled pattern may take. Matching is linear in the text, so
+/// bounding the pattern's size also bounds the time of each call.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Compiled patterns a run keeps for reuse.
+const REGEX_CACHE_SIZE: usize = 64;
+
+/// Looks up a native function by name.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+/// All native functions, in declaration order.
+pub fn all() -> &'static [Builtin] {
+    BUILTINS
+}
+
+/// clock_ms(): milliseconds elapsed since the program started running.
+fn clock_ms(vm: &mut VirtualMachine, _args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let elapsed = vm.read_clock(ClockRead::ElapsedMs(vm.elapsed().as_secs_f64() * 1000.0))?;
+    match elapsed {
+        ClockRead::ElapsedMs(ms) => Ok(Value::Number(ms)),
+        ClockRead::UnixSecs(_) => Err("Replay diverged: clock_ms() read where now() was recorded".into()),
+    }
+}
+
+/// now(): current UTC date and time as an ISO 8601 string.
+fn now(vm: &mut VirtualMachine, _args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    match vm.read_clock(ClockRead::UnixSecs(since_epoch.as_secs()))? {
+        ClockRead::UnixSecs(secs) => Ok(Value::String(format_utc(secs))),
+        ClockRead::ElapsedMs(_) => Err("Replay diverged: now() read where clock_ms() was recorded".into()),
+    }
+}
+
+/// sleep(ms): pauses execution, bounded by the VM's sleep budget.
+fn sleep(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let ms = match &args[0] {
+        Value::Int(n) if *n >= 0 => *n as u64,
+        Value::Number(n) if *n >= 0.0 => *n as u64,
+        other => return Err(format!("sleep expects a non-negative number, got {}", other.type_name()).into()),
+    };
+
+    let remaining = vm.config().max_sleep_ms.saturating_sub(vm.slept_ms);
+    if ms > remaining {
+        return Err(format!(
+            "sleep({}) exceeds the remaining sleep budget of {} ms",
+            ms, remaining
+        ).into());
+    }
+
+    // Replays don't wait: their clock readings are recorded
+    if vm.is_replaying() {
+        vm.slept_ms += ms;
+        return Ok(Value::Null);
+    }
+
+    let mut left = ms;
+    while left > 0 {
+        if vm.config().cancel_token.is_cancelled() {
+            return Err("Execution cancelled".into());
+        }
+        let slice = left.min(SLEEP_SLICE_MS);
+        thread::sleep(Duration::from_millis(slice));
+        vm.slept_ms += slice;
+        left -= slice;
+    }
+
+    Ok(Value::Null)
+}
+
+/// random(): uniformly distributed number in [0, 1).
+fn random(vm: &mut VirtualMachine, _args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    Ok(Value::Number(vm.rng.next_f64()))
+}
+
+/// rand_int(lo, hi): uniformly distributed integer in [lo, hi], both ends inclusive.
+fn rand_int(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let lo = integer_arg("rand_int", &args[0])?;
+    let hi = integer_arg("rand_int", &args[1])?;
+    if lo > hi {
+        return Err(format!("rand_int: lower bound {} is greater than upper bound {}", lo, hi).into());
+    }
+
+    let span = hi.wrapping_sub(lo) as u64;
+    let offset = if span == u64::MAX {
+        vm.rng.next_u64()
+    } else {
+        vm.rng.next_below(span + 1)
+    };
+    Ok(Value::Int(lo.wrapping_add(offset as i64)))
+}
+
+/// read_line(): next line of the program's input without its line ending,
+/// or null once the input is exhausted.
+fn read_line(vm: &mut VirtualMachine, _args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    Ok(match vm.next_stdin_line() {
+        Some(line) => Value::String(line),
+        None => Value::Null,
+    })
+}
+
+/// assert(condition, message?): fails the run when `condition` is false,
+/// with `message` in the error if given. Meant for test blocks, see `testing`.
+fn assert(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    match args {
+        [Value::Boolean(false)] => Err("Assertion failed".into()),
+        [Value::Boolean(false), message] => Err(format!("Assertion failed: {}", message).into()),
+        [_] | [_, _] => Ok(Value::Null),
+        _ => Err(format!("assert expects a condition and at most one message, got {} arguments", args.len()).into()),
+    }
+}
+
+/// secure_equals(a, b): whether two strings are equal, in time that only
+/// depends on their lengths, so comparing a guess with a secret doesn't tell
+/// how much of the guess was right.
+fn secure_equals(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    match (&args[0], &args[1]) {
+        (Value::String(a), Value::String(b)) => Ok(Value::Boolean(constant_time_eq(a.as_bytes(), b.as_bytes()))),
+        (a, b) => Err(format!("secure_equals expects two strings, got {} and {}", a.type_name(), b.type_name()).into()),
+    }
+}
+
+/// Whether `a` and `b` are equal, comparing every byte whatever the first
+/// difference, so the time taken only depends on the lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && std::hint::black_box(a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y))) == 0
+}
+
+/// exit(code): ends the program at once with an exit code from 0 to 255,
+/// reported to the caller with the output printed so far.
+fn exit(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let code = integer_arg("exit", &args[0])?;
+    if !(0..=255).contains(&code) {
+        return Err(format!("exit expects a code between 0 and 255, got {}", code).into());
+    }
+    vm.exit(code as i32);
+    Ok(Value::Null)
+}
+
+/// getenv(name): value of a variable of the request's `env`, or null when it
+/// has none by that name. The host's environment is never read.
+fn getenv(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let Value::String(name) = &args[0] else {
+        return Err(format!("getenv expects a string, got {}", args[0].type_name()).into());
+    };
+    Ok(match vm.config().env.get(name) {
+        Some(value) => Value::String(value.clone()),
+        None => Value::Null,
+    })
+}
+
+/// read_file(path): contents of a file, or null when there is none at `path`.
+fn read_file(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let path = path_arg("read_file", &args[0])?;
+    Ok(match vm.read_file(path)? {
+        Some(contents) => Value::String(contents),
+        None => Value::Null,
+    })
+}
+
+/// write_file(path, contents): replaces the file at `path` with `contents`,
+/// rendered as print shows it, creating the file if needed.
+fn write_file(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let path = path_arg("write_file", &args[0])?;
+    vm.write_file(path, &args[1].to_string(), false)?;
+    Ok(Value::Null)
+}
+
+/// append_file(path, contents): adds `contents` to the end of the file at
+/// `path`, creating the file if needed.
+fn append_file(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let path = path_arg("append_file", &args[0])?;
+    vm.write_file(path, &args[1].to_string(), true)?;
+    Ok(Value::Null)
+}
+
+/// len(value): the number of elements of an array, or of characters of a string.
+fn len(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    match &args[0] {
+        Value::Array(elements) => Ok(Value::Int(elements.len() as i64)),
+        Value::String(s) => Ok(Value::Int(s.chars().count() as i64)),
+        other => Err(format!("len expects an array or a string, got {}", other.type_name()).into()),
+    }
+}
+
+/// push(array, value): a copy of `array` with `value` added at its end; the
+/// array itself is unchanged, so the result is usually stored back into it.
+fn push(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let Value::Array(elements) = &args[0] else {
+        return Err(format!("push expects an array, got {}", args[0].type_name()).into());
+    };
+    let mut elements = elements.clone();
+    Arc::make_mut(&mut elements).push(args[1].clone());
+    Ok(Value::Array(elements))
+}
+
+/// json_parse(text): the value encoded by a JSON document. Objects are
+/// refused, since the language has no values to map them onto yet.
+fn json_parse(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let Value::String(text) = &args[0] else {
+        return Err(format!("json_parse expects a string, got {}", args[0].type_name()).into());
+    };
+    let parsed: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| format!("json_parse: invalid JSON: {}", e))?;
+    from_json(parsed).map_err(|e| format!("json_parse: {}", e).into())
+}
+
+/// The value of the language a JSON value maps onto: null, a boolean, an
+/// int for a number without a fraction or exponent that fits one, a float
+/// for any other number, a string or an array of those.
+pub fn from_json(value: serde_json::Value) -> Result<Value, String> {
+    match value {
+        serde_json::Value::Null => Ok(Value::Null),
+        serde_json::Value::Bool(b) => Ok(Value::Boolean(b)),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Ok(Value::Int(i)),
+            None => n.as_f64()
+                .map(Value::Number)
+                .ok_or_else(|| format!("{} is out of range", n)),
+        },
+        serde_json::Value::String(s) => Ok(Value::String(s)),
+        serde_json::Value::Array(values) => Ok(Value::Array(Arc::new(
+            values.into_iter().map(from_json).collect::<Result<Array, _>>()?,
+        ))),
+        serde_json::Value::Object(_) => Err("objects are not supported, the language has no map values".to_string()),
+    }
+}
+
+/// json_string(value): `value` encoded as JSON. Whole numbers are written
+/// without a fraction, as the language displays them.
+fn json_string(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let encoded = to_json(&args[0]).map_err(|e| format!("json_string: {}", e))?;
+    Ok(Value::String(encoded.to_string()))
+}
+
+/// The JSON value of `value`. Ints, and floats that are whole numbers, are
+/// written without a fraction; NaN and the infinities have no JSON encoding.
+pub fn to_json(value: &Value) -> Result<serde_json::Value, String> {
+    match value {
+        Value::Null => Ok(serde_json::Value::Null),
+        Value::Boolean(b) => Ok(serde_json::Value::Bool(*b)),
+        Value::Int(i) => Ok(serde_json::Value::from(*i)),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => Ok(serde_json::Value::from(*n as i64)),
+        Value::Number(n) => serde_json::Number::from_f64(*n)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| format!("{} has no JSON encoding", n)),
+        Value::String(s) => Ok(serde_json::Value::String(s.clone())),
+        Value::Array(elements) => elements.iter().map(to_json).collect::<Result<_, _>>().map(serde_json::Value::Array),
+    }
+}
+
+/// regex_match(pattern, text): whether `pattern` matches anywhere in `text`.
+fn regex_match(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let regex = vm.regexes.compile("regex_match", &args[0])?;
+    let text = string_arg("regex_match", &args[1])?;
+    Ok(Value::Boolean(regex.is_match(text)))
+}
+
+/// regex_find_all(pattern, text): an array of every non-overlapping match of
+/// `pattern` in `text`, in order.
+fn regex_find_all(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let regex = vm.regexes.compile("regex_find_all", &args[0])?;
+    let text = string_arg("regex_find_all", &args[1])?;
+    let matches: Array = regex.find_iter(text).map(|found| Value::String(found.as_str().to_string())).collect();
+    Ok(Value::Array(Arc::new(matches)))
+}
+
+/// regex_replace(pattern, text, replacement): `text` with every match of
+/// `pattern` replaced; `$1` or `${name}` in `replacement` insert a group.
+fn regex_replace(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let regex = vm.regexes.compile("regex_replace", &args[0])?;
+    let text = string_arg("regex_replace", &args[1])?;
+    let replacement = string_arg("regex_replace", &args[2])?;
+    Ok(Value::String(regex.replace_all(text, replacement).into_owned()))
+}
+
+/// checked_add(a, b): `a + b` as the operator computes it, or null where the
+/// operator fails or the sum overflows: operands it cannot add, ints whose
+/// sum is too large for an int, or floats whose sum is too large for a float.
+fn checked_add(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    match (&args[0], &args[1]) {
+        (Value::Int(a), Value::Int(b)) => Ok(a.checked_add(*b).map_or(Value::Null, Value::Int)),
+        (a, b) if a.as_number().is_some() && b.as_number().is_some() => {
+            let (a, b) = (a.as_number().unwrap_or_default(), b.as_number().unwrap_or_default());
+            Ok(finite_or_null(a + b, &[a, b]))
+        }
+        (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
+        _ => Ok(Value::Null),
+    }
+}
+
+/// checked_div(a, b): `a / b`, always as a float, or null where dividing
+/// fails: a divisor of zero, operands that are not numbers, or a quotient
+/// that overflows.
+fn checked_div(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    match (args[0].as_number(), args[1].as_number()) {
+        (Some(a), Some(b)) if b != 0.0 => Ok(finite_or_null(a / b, &[a, b])),
+        _ => Ok(Value::Null),
+    }
+}
+
+// `result` of a checked operation on `operands`, or null when it overflowed
+// to an infinity or NaN that the operands did not already hold
+fn finite_or_null(result: f64, operands: &[f64]) -> Value {
+    if result.is_finite() || operands.iter().any(|operand| !operand.is_finite()) {
+        Value::Number(result)
+    } else {
+        Value::Null
+    }
+}
+
+/// format(format, values...): `format` with each placeholder replaced by the
+/// next value, e.g. `format("x={} y={}", 1, 2)` is "x=1 y=2".
+fn format(_vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    Ok(Value::String(render_format("format", args)?))
+}
+
+/// printf(format, values...): prints what `format` returns for the same
+/// arguments, as a line.
+fn printf(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Box<dyn Error>> {
+    let text = render_format("printf", args)?;
+    vm.print_line(&text);
+    Ok(Value::Null)
+}
+
+/// A placeholder of a format string, by the values it accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    Any,                   // `{}`: any value, as it is displayed
+    Integer,               // `{:d}`: a whole number
+    Number(Option<usize>), // `{:f}`, or `{:.2f}` for a fixed number of decimals
+    Text,                  // `{:s}`: a string
+}
+
+impl Placeholder {
+    /// The placeholder as written in a format string.
+    pub fn spec(self) -> String {
+        match self {
+            Placeholder::Any => "{}".to_string(),
+            Placeholder::Integer => "{:d}".to_string(),
+            Placeholder::Number(None) => "{:f}".to_string(),
+            Placeholder::Number(Some(decimals)) => format!("{{:.{}f}}", decimals),
+            Placeholder::Text => "{:s}".to_string(),
+        }
+    }
+}
+
+/// A part of a parsed format string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatPiece {
+    Literal(String),
+    Slot(Placeholder),
+}
+
+/// Parses a format string: text with placeholders in braces, where `{{` and
+/// `}}` stand for literal braces.
+pub fn parse_format(format: &str) -> Result<Vec<FormatPiece>, String> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("Invalid format string: unclosed '{'".to_string()),
+                    }
+                }
+                let placeholder = match spec.as_str() {
+                    "" => Placeholder::Any,
+                    ":d" => Placeholder::Integer,
+                    ":f" => Placeholder::Number(None),
+                    ":s" => Placeholder::Text,
+                    _ => match spec.strip_prefix(":.").and_then(|rest| rest.strip_suffix('f')) {
+                        Some(decimals) => match decimals.parse() {
+                            Ok(decimals) if decimals <= MAX_DECIMALS => Placeholder::Number(Some(decimals)),
+                            _ => return Err(format!("Invalid format string: unknown placeholder '{{{}}}'", spec)),
+                        },
+                        None => return Err(format!("Invalid format string: unknown placeholder '{{{}}}'", spec)),
+                    },
+                };
+                if !literal.is_empty() {
+                    pieces.push(FormatPiece::Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(FormatPiece::Slot(placeholder));
+            }
+            '}' => return Err("Invalid format string: unmatched '}'".to_string()),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(FormatPiece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+/// The placeholders of a format string, in order.
+pub fn placeholders(format: &str) -> Result<Vec<Placeholder>, String> {
+    Ok(parse_format(format)?
+        .into_iter()
+        .filter_map(|piece| match piece {
+            FormatPiece::Slot(placeholder) => Some(placeholder),
+            FormatPiece::Literal(_) => None,
+        })
+        .collect())
+}
+
+// Fills in the format string that is the first of `args` with the others
+fn render_format(function: &str, args: &[Value]) -> Result<String, Box<dyn Error>> {
+    let Value::String(format) = &args[0] else {
+        return Err(format!("{} expects a string format, got {}", function, args[0].type_name()).into());
+    };
+    let pieces = parse_format(format).map_err(|e| format!("{}: {}", function, e))?;
+    let values = &args[1..];
+    let slots = pieces.iter().filter(|piece| matches!(piece, FormatPiece::Slot(_))).count();
+    if slots != values.len() {
+        return Err(format!("{} expects {} value(s) for its placeholders, got {}", function, slots, values.len()).into());
+    }
+
+    let mut text = String::new();
+    let mut values = values.iter().enumerate();
+    for piece in pieces {
+        let placeholder = match piece {
+            FormatPiece::Literal(literal) => {
+                text.push_str(&literal);
+                continue;
+            }
+            FormatPiece::Slot(placeholder) => placeholder,
+        };
+        let Some((index, value)) = values.next() else { break };
+        let rendered = match (placeholder, value) {
+            (Placeholder::Any, value) => Some(value.to_string()),
+            (Placeholder::Integer, Value::Int(_)) => Some(value.to_string()),
+            (Placeholder::Integer, Value::Number(n)) if n.fract() == 0.0 => Some(value.to_string()),
+            (Placeholder::Number(None), Value::Int(_) | Value::Number(_)) => Some(value.to_string()),
+            (Placeholder::Number(Some(decimals)), value) if value.as_number().is_some() => {
+                Some(format!("{:.*}", decimals, value.as_number().unwrap_or_default()))
+            }
+            (Placeholder::Text, Value::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        match rendered {
+            Some(rendered) => text.push_str(&rendered),
+            None => {
+                return Err(format!(
+                    "{} expects {} for placeholder {} ({}), got {}",
+                    function,
+                    match placeholder {
+                        Placeholder::Integer => "a whole number",
+                        Placeholder::Number(_) => "a number",
+                        _ => "a string",
+                    },
+                    index + 1,
+                    placeholder.spec(),
+                    match value {
+                        Value::Int(_) | Value::Number(_) => value.to_string(),
+                        _ => value.type_name().to_string(),
+                    },
+                ).into());
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Extracts a string argument.
+fn string_arg<'a>(function: &str, value: &'a Value) -> Result<&'a str, Box<dyn Error>> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(format!("{} expects string arguments, got {}", function, other.type_name()).into()),
+    }
+}
+
+/// Extracts the path argument of a file builtin.
+fn path_arg<'a>(function: &str, value: &'a Value) -> Result<&'a str, Box<dyn Error>> {
+    match value {
+        Value::String(path) => Ok(path),
+        other => Err(format!("{} expects a string path, got {}", function, other.type_name()).into()),
+    }
+}
+
+/// Extracts a whole number argument.
+fn integer_arg(function: &str, value: &Value) -> Result<i64, Box<dyn Error>> {
+    match value {
+        Value::Int(i) => Ok(*i),
+        Value::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+        other => Err(format!("{} expects integer arguments, got {}", function, other).into()),
+    }
+}
+
+/// Patterns compiled by the regex builtins during a run, so a pattern used
+/// in a loop is compiled once.
+#[derive(Debug, Clone, Default)]
+pub struct RegexCache {
+    compiled: HashMap<String, Regex>,
+}
+
+impl RegexCache {
+    /// The compiled form of the pattern argument of `function`. Patterns that
+    /// are too long or compile too large are refused.
+    fn compile(&mut self, function: &str, pattern: &Value) -> Result<Regex, Box<dyn Error>> {
+        let pattern = string_arg(function, pattern)?;
+        if let Some(regex) = self.compiled.get(pattern) {
+            return Ok(regex.clone());
+        }
+
+        if pattern.len() > MAX_PATTERN_LEN {
+            return Err(format!("{}: pattern is longer than {} bytes", function, MAX_PATTERN_LEN).into());
+        }
+        let regex = RegexBuilder::new(pattern)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| format!("{}: invalid pattern: {}", function, e))?;
+
+        if self.compiled.len() >= REGEX_CACHE_SIZE {
+            self.compiled.clear();
+        }
+        self.compiled.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+}
+
+/// Small deterministic PRNG (xorshift64*) so seeded runs are reproducible
+/// across platforms and crate upgrades.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed with SplitMix64 so small seeds still give good output;
+        // xorshift must never start from zero.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z } }
+    }
+
+    /// A seed taken from the system clock, for runs without one.
+    pub fn time_seed() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform float in [0, 1) built from the top 53 bits.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform integer in [0, bound), rejecting values that would bias the result.
+    pub fn next_below(&mut self, bound: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, time / 3_600, (time % 3_600) / 60, time % 60
+    )
+}
@@ -1,3 +1,5 @@
+use crate::ast::{Ast, Node, NodeId};
+use crate::builtins;
 use crate::lexer::TokenType;
 use crate::parser::ASTNode;
 use std::collections::HashMap;
@@ -7,11 +9,12 @@ use std::fmt;
 #[derive(Debug, Clone)]
 pub enum OpCode {
     // Stack operations
-    Constant(Value),
+    Constant(usize), // Index into the constants of the module
     Pop,
+    Duplicate,
 
     // Variables
-    GetLocal(usize),
+    GetLocal(usize), // Slot of the local in the chunk
     SetLocal(usize),
     GetGlobal(String),
     SetGlobal(String),
@@ -29,15 +32,26 @@ pub enum OpCode {
     NotEqual,
     LessThan,
     GreaterThan,
+    LessEqual,
+    GreaterEqual,
+
+    // Arrays
+    MakeArray(usize), // Element count: the elements are on the stack, the last on top
+    IndexGet,         // Array then index on the stack
+    IndexSet(usize),  // Index count: array, indices, then value; pushes the value, then the updated array
 
     // Control flow
     Jump(usize),
     JumpIfFalse(usize),
-    Call(usize), // argument count
-    Return,
+    Call(usize, usize), // function index, argument count
+    CallNative(String, usize), // builtin name, argument count
+    Return, // From a function, with its value on the stack
 
-    // Debug
+    // Output
     Print,
+
+    // End of program
+    Halt,
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +75,120 @@ impl fmt::Display for Value {
     }
 }
 
+/// Code of one function, or of the top-level code of a program.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub name: String,        // "<program>" for the top-level code
+    pub code: Vec<OpCode>,
+    pub locals: Vec<String>, // Name of the first local held by each slot
+    // Where the code of each top-level statement of a program begins, with
+    // the statement's index; deferred statements run from the end. A
+    // function has one entry, for the statement declaring it
+    pub statements: Vec<(usize, usize)>,
+    // Line table: where the code of each entry of `statements` begins, with
+    // the line its statement starts on. Empty unless the generator was given
+    // the lines of the statements, see `BytecodeGenerator::with_lines`
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    fn new(name: &str) -> Self {
+        Chunk {
+            name: name.to_string(),
+            ..Chunk::default()
+        }
+    }
+}
+
+/// A compiled program: the chunk of its top-level code, then one per
+/// function. Function `i` of the function table is chunk `i + 1`. The
+/// chunks share one table of constants, where equal constants are stored once.
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub chunks: Vec<Chunk>,
+    constants: Vec<Value>,
+    indexes: HashMap<ConstantKey, usize>, // Index of each constant in `constants`
+}
+
+// Identity of a constant: floats are compared by their bits
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Int(i64),
+    Float(u64),
+    String(String),
+    Bool(bool),
+    Null,
+}
+
+impl From<&Value> for ConstantKey {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Int(i) => ConstantKey::Int(*i),
+            Value::Float(f) => ConstantKey::Float(f.to_bits()),
+            Value::String(s) => ConstantKey::String(s.clone()),
+            Value::Bool(b) => ConstantKey::Bool(*b),
+            Value::Null => ConstantKey::Null,
+        }
+    }
+}
+
+impl Module {
+    /// The constant at `index`, which Constant instructions refer to.
+    pub fn constant(&self, index: usize) -> &Value {
+        &self.constants[index]
+    }
+
+    // Index of `value` in the constants, adding it unless an equal one is there
+    fn add_constant(&mut self, value: Value) -> usize {
+        let key = ConstantKey::from(&value);
+        if let Some(&index) = self.indexes.get(&key) {
+            return index;
+        }
+        self.constants.push(value);
+        self.indexes.insert(key, self.constants.len() - 1);
+        self.constants.len() - 1
+    }
+
+    /// Appends the top-level code of `other`, generated on its own, to the
+    /// top-level code of this module, moving its jumps past this code and
+    /// its constants into this table, and its functions after those of this
+    /// module. Locals keep their slots: separately generated code reuses
+    /// slots as a block ending does.
+    pub fn append(&mut self, other: &Module) {
+        let Some(code) = other.chunks.first() else {
+            return;
+        };
+        let constants: Vec<usize> = other.constants.iter().map(|value| self.add_constant(value.clone())).collect();
+        if self.chunks.is_empty() {
+            self.chunks.push(Chunk::new("<program>"));
+        }
+        let chunk = &mut self.chunks[0];
+        let offset = chunk.code.len();
+        chunk.code.extend(code.code.iter().map(|op| match op {
+            OpCode::Jump(target) => OpCode::Jump(target + offset),
+            OpCode::JumpIfFalse(target) => OpCode::JumpIfFalse(target + offset),
+            OpCode::Constant(index) => OpCode::Constant(constants[*index]),
+            op => op.clone(),
+        }));
+        chunk.statements.extend(code.statements.iter().map(|&(start, statement)| (start + offset, statement)));
+        chunk.lines.extend(code.lines.iter().map(|&(start, line)| (start + offset, line)));
+        if code.locals.len() > chunk.locals.len() {
+            let known = chunk.locals.len();
+            chunk.locals.extend(code.locals[known..].iter().cloned());
+        }
+        for function in &other.chunks[1..] {
+            let code = function.code.iter().map(|op| match op {
+                OpCode::Constant(index) => OpCode::Constant(constants[*index]),
+                op => op.clone(),
+            });
+            self.chunks.push(Chunk {
+                code: code.collect(),
+                ..function.clone()
+            });
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BytecodeGeneratorError {
     message: String,
@@ -79,81 +207,202 @@ struct LocalVariable {
     depth: usize,
 }
 
+// A loop being generated, which break and continue statements can leave
+struct LoopContext {
+    label: Option<String>,
+    start: usize,       // Address of the condition, where continue jumps
+    breaks: Vec<usize>, // Jumps of the break statements, patched once the loop ends
+    deferred: usize,    // Number of deferred lists around the loop; the others run when leaving it
+    block_expressions: usize, // Block expressions open around the loop
+}
+
 pub struct BytecodeGenerator {
-    code: Vec<OpCode>,
-    #[allow(dead_code)]
-    constants: Vec<Value>,
+    chunk: Chunk, // Chunk being generated
+    module: Module, // Constants, and the chunks of the functions generated so far
     locals: Vec<LocalVariable>,
     scope_depth: usize,
     #[allow(dead_code)]
     global_variables: HashMap<String, usize>,
+    outer_deferred: Option<Vec<ASTNode>>, // Given by `with_deferred`, moved into the arena by `generate_ast`
+    deferred: Vec<Vec<NodeId>>, // Deferred statements of the program and each open block
+    loops: Vec<LoopContext>, // Enclosing loops, innermost last
+    block_expressions: usize, // Block expressions being generated, whose values may be on the stack
+    functions: HashMap<String, (usize, usize)>, // Index in the function table and parameter count of each function
+    lines: Vec<usize>, // Line where each top-level statement begins, given by `with_lines`
+    in_function: bool, // Whether the code being generated is a function's
+}
+
+/// The function table of a program, given its top-level statements: the
+/// index and the number of parameters of each function it declares, in
+/// order of declaration.
+pub fn function_table(statements: &[ASTNode]) -> Result<HashMap<String, (usize, usize)>, Box<dyn Error>> {
+    declared_functions(statements.iter().filter_map(|statement| match statement {
+        ASTNode::FunctionDeclaration { name, params, .. } => Some((name, params)),
+        _ => None,
+    }))
+}
+
+// The function table of the functions declared with these names and
+// parameters, in order
+fn declared_functions<'a>(
+    declarations: impl Iterator<Item = (&'a String, &'a Vec<String>)>,
+) -> Result<HashMap<String, (usize, usize)>, Box<dyn Error>> {
+    let mut functions = HashMap::new();
+    for (name, params) in declarations {
+        let message = if builtins::lookup(name).is_some() {
+            format!("Function '{}' has the name of a builtin function", name)
+        } else if functions.contains_key(name) {
+            format!("Function '{}' is already declared", name)
+        } else {
+            functions.insert(name.clone(), (functions.len(), params.len()));
+            continue;
+        };
+        return Err(Box::new(BytecodeGeneratorError { message }));
+    }
+    Ok(functions)
 }
 
 impl BytecodeGenerator {
     pub fn new() -> Self {
         BytecodeGenerator {
-            code: Vec::new(),
-            constants: Vec::new(),
+            chunk: Chunk::new("<program>"),
+            module: Module::default(),
             locals: Vec::new(),
             scope_depth: 0,
             global_variables: HashMap::new(),
+            outer_deferred: None,
+            deferred: Vec::new(),
+            loops: Vec::new(),
+            block_expressions: 0,
+            functions: HashMap::new(),
+            lines: Vec::new(),
+            in_function: false,
         }
     }
 
-    pub fn generate(&mut self, ast: ASTNode) -> Result<Vec<OpCode>, Box<dyn Error>> {
-        match ast {
-            ASTNode::Program(statements) => {
-                for statement in statements {
-                    self.generate_statement(statement)?;
-                }
-            }
-            _ => self.generate_statement(ast)?,
+    /// Generator for one top-level item of a program with the function table
+    /// `functions`, see `function_table`.
+    pub fn with_functions(mut self, functions: HashMap<String, (usize, usize)>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    /// Generator for an item following the top-level `deferred` statements,
+    /// which a `return` in the item runs before ending the program.
+    pub fn with_deferred(mut self, deferred: Vec<ASTNode>) -> Self {
+        self.outer_deferred = Some(deferred);
+        self
+    }
+
+    /// Generator filling in the line table of each chunk, given the line
+    /// where each top-level statement of the program begins, as the parser
+    /// records them.
+    pub fn with_lines(mut self, lines: Vec<usize>) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    pub fn generate(&mut self, ast: ASTNode) -> Result<Module, Box<dyn Error>> {
+        let mut arena = Ast::default();
+        let root = arena.add(ast);
+        self.generate_ast(arena, root)
+    }
+
+    /// Generates the code of the node `root` of `ast`, as parsed.
+    pub fn generate_ast(&mut self, mut ast: Ast, root: NodeId) -> Result<Module, Box<dyn Error>> {
+        // Functions can be called before their declaration
+        if let Node::Program(statements) = &ast[root] {
+            self.functions = declared_functions(statements.iter().filter_map(|&statement| match &ast[statement] {
+                Node::FunctionDeclaration { name, params, .. } => Some((name, params)),
+                _ => None,
+            }))?;
+        }
+        if let Some(deferred) = self.outer_deferred.take() {
+            self.deferred = vec![deferred.into_iter().map(|statement| ast.add(statement)).collect()];
         }
+        self.generate_node(&ast, root)?;
 
-        Ok(self.code.clone())
+        let mut module = self.module.clone();
+        module.chunks.insert(0, self.chunk.clone());
+        Ok(module)
+    }
+
+    // Records that the code of top-level statement `index` begins here
+    fn start_statement(&mut self, index: usize) {
+        let start = self.chunk.code.len();
+        self.chunk.statements.push((start, index));
+        if let Some(&line) = self.lines.get(index) {
+            self.chunk.lines.push((start, line));
+        }
     }
 
-    fn generate_statement(&mut self, node: ASTNode) -> Result<(), Box<dyn Error>> {
-        match node {
-            ASTNode::VarDeclaration {
+    fn generate_node(&mut self, ast: &Ast, root: NodeId) -> Result<(), Box<dyn Error>> {
+        let Node::Program(statements) = &ast[root] else {
+            return self.generate_statement(ast, root);
+        };
+        self.deferred.push(Vec::new());
+        let mut deferred_indexes = Vec::new();
+        for (index, &statement) in statements.iter().enumerate() {
+            if matches!(ast[statement], Node::DeferStatement(_)) {
+                deferred_indexes.push(index);
+            }
+            self.start_statement(index);
+            self.generate_statement(ast, statement)?;
+        }
+        // As generate_deferred, remembering which statement each one is
+        let deferred = self.deferred.pop().unwrap_or_default();
+        for (statement, index) in deferred.into_iter().zip(deferred_indexes).rev() {
+            self.start_statement(index);
+            self.generate_statement(ast, statement)?;
+        }
+        self.emit(OpCode::Halt);
+        Ok(())
+    }
+
+    fn generate_statement(&mut self, ast: &Ast, node: NodeId) -> Result<(), Box<dyn Error>> {
+        match &ast[node] {
+            Node::VarDeclaration {
                 var_type: _,
                 name,
                 initializer,
+                doc: _,
             } => {
                 if let Some(init) = initializer {
-                    self.generate_expression(*init)?;
+                    self.generate_expression(ast, *init)?;
                 } else {
                     // Push null as default value
-                    self.emit(OpCode::Constant(Value::Null));
+                    self.emit_constant(Value::Null);
                 }
 
-                self.declare_variable(name)?;
+                self.declare_variable(name.clone())?;
             }
-            ASTNode::Block(statements) => {
+            Node::Block(statements) => {
                 self.begin_scope();
+                self.deferred.push(Vec::new());
 
                 for statement in statements {
-                    self.generate_statement(statement)?;
+                    self.generate_statement(ast, *statement)?;
                 }
 
+                self.generate_deferred(ast)?;
                 self.end_scope();
             }
-            ASTNode::ExpressionStatement(expr) => {
-                self.generate_expression(*expr)?;
+            Node::ExpressionStatement(expr) => {
+                self.generate_expression(ast, *expr)?;
                 self.emit(OpCode::Pop); // Discard the result
             }
-            ASTNode::IfStatement {
+            Node::IfStatement {
                 condition,
                 then_branch,
                 else_branch,
             } => {
-                self.generate_expression(*condition)?;
+                self.generate_expression(ast, *condition)?;
 
                 // Jump to else branch if condition is false
                 let jump_if_false = self.emit_jump(OpCode::JumpIfFalse(0));
 
                 // Compile then branch
-                self.generate_statement(*then_branch)?;
+                self.generate_statement(ast, *then_branch)?;
 
                 // Jump over else branch
                 let jump = self.emit_jump(OpCode::Jump(0));
@@ -163,42 +412,137 @@ impl BytecodeGenerator {
 
                 // Compile else branch if present
                 if let Some(else_stmt) = else_branch {
-                    self.generate_statement(*else_stmt)?;
+                    self.generate_statement(ast, *else_stmt)?;
                 }
 
                 // Patch jump to point to end
                 self.patch_jump(jump);
             }
-            ASTNode::WhileStatement { condition, body } => {
-                let loop_start = self.code.len();
+            Node::WhileStatement { condition, body, label } => {
+                let loop_start = self.chunk.code.len();
+                self.loops.push(LoopContext {
+                    label: label.clone(),
+                    start: loop_start,
+                    breaks: Vec::new(),
+                    deferred: self.deferred.len(),
+                    block_expressions: self.block_expressions,
+                });
 
                 // Compile condition
-                self.generate_expression(*condition)?;
+                self.generate_expression(ast, *condition)?;
 
                 // Jump out of loop if condition is false
                 let exit_jump = self.emit_jump(OpCode::JumpIfFalse(0));
 
                 // Compile loop body
-                self.generate_statement(*body)?;
+                self.generate_statement(ast, *body)?;
 
                 // Jump back to condition
                 self.emit(OpCode::Jump(loop_start));
 
                 // Patch exit jump
                 self.patch_jump(exit_jump);
+
+                // Break statements jump to the end as well
+                if let Some(context) = self.loops.pop() {
+                    for jump in context.breaks {
+                        self.patch_jump(jump);
+                    }
+                }
+            }
+            Node::BreakStatement(label) => {
+                let target = self.leave_loop(ast, "break", label.as_deref())?;
+                let jump = self.emit_jump(OpCode::Jump(0));
+                self.loops[target].breaks.push(jump);
+            }
+            Node::ContinueStatement(label) => {
+                let target = self.leave_loop(ast, "continue", label.as_deref())?;
+                self.emit(OpCode::Jump(self.loops[target].start));
+            }
+            Node::PrintStatement(value) => {
+                self.generate_expression(ast, *value)?;
+                self.emit(OpCode::Print);
+            }
+            // A `return` in a function returns its value, or null, once the
+            // statements the function deferred so far have run. The value
+            // waits in a local meanwhile
+            Node::ReturnStatement(value) if self.in_function => {
+                // The value of the function's body is not on the stack yet
+                if self.block_expressions > 1 {
+                    return Err(Box::new(BytecodeGeneratorError {
+                        message: "'return' cannot leave a block used as a value".to_string(),
+                    }));
+                }
+                match value {
+                    Some(expr) => {
+                        self.generate_expression(ast, *expr)?;
+                    }
+                    None => {
+                        self.emit_constant(Value::Null);
+                    }
+                }
+
+                if self.deferred.iter().any(|deferred| !deferred.is_empty()) {
+                    let slot = self.locals.len();
+                    self.add_local("<return>".to_string());
+                    let scopes = std::mem::take(&mut self.deferred);
+                    for deferred in scopes.iter().rev() {
+                        for statement in deferred.iter().rev() {
+                            self.generate_statement(ast, *statement)?;
+                        }
+                    }
+                    self.deferred = scopes;
+                    self.locals.pop();
+                    self.emit(OpCode::GetLocal(slot));
+                }
+                self.emit(OpCode::Return);
             }
-            ASTNode::ReturnStatement(value) => {
+            // Outside of functions `return` ends the program with its value,
+            // or null, as the result. The statements deferred so far still run
+            Node::ReturnStatement(value) => {
                 if let Some(expr) = value {
-                    self.generate_expression(*expr)?;
+                    self.generate_expression(ast, *expr)?;
                 } else {
-                    self.emit(OpCode::Constant(Value::Null));
+                    self.emit_constant(Value::Null);
                 }
 
-                self.emit(OpCode::Return);
+                if self.deferred.iter().any(|deferred| !deferred.is_empty()) {
+                    self.emit(OpCode::DefineGlobal("<return>".to_string()));
+                    let scopes = std::mem::take(&mut self.deferred);
+                    for deferred in scopes.iter().rev() {
+                        for statement in deferred.iter().rev() {
+                            self.generate_statement(ast, *statement)?;
+                        }
+                    }
+                    self.deferred = scopes;
+                    self.emit(OpCode::GetGlobal("<return>".to_string()));
+                }
+                self.emit(OpCode::Halt);
+            }
+            Node::DeferStatement(statement) => match self.deferred.last_mut() {
+                Some(deferred) => deferred.push(*statement),
+                None => {
+                    return Err(Box::new(BytecodeGeneratorError {
+                        message: "'defer' must be directly inside a block or at the top level".to_string(),
+                    }));
+                }
+            },
+            Node::FunctionDeclaration { name, params, body, .. } => {
+                self.generate_function(ast, name, params, *body)?;
+            }
+            // Tests only run under `compiler test` and POST /test, see `testing`
+            Node::TestBlock { .. } => {}
+            Node::Import(path) => {
+                return Err(Box::new(BytecodeGeneratorError {
+                    message: format!(
+                        "Cannot import \"{}\": imports are only resolved when compiling files from the command line",
+                        path
+                    ),
+                }));
             }
             _ => {
                 return Err(Box::new(BytecodeGeneratorError {
-                    message: format!("Unexpected node type in statement context: {:?}", node),
+                    message: format!("Unexpected node type in statement context: {:?}", ast.to_tree(node)),
                 }));
             }
         }
@@ -206,55 +550,126 @@ impl BytecodeGenerator {
         Ok(())
     }
 
-    fn generate_expression(&mut self, node: ASTNode) -> Result<(), Box<dyn Error>> {
-        match node {
-             ASTNode::BinaryExpression {
-                left,
-                operator,
-                right,
-            } => {
-                self.generate_expression(*left)?;
-                self.generate_expression(*right)?;
+    /// Generates a binary operation and the chain of operations nested in
+    /// its left operand, as `a + b - c` nests `a + b`. The chain is walked
+    /// with a loop rather than by recursion, so a long one doesn't exhaust
+    /// the stack; each right operand is generated as usual.
+    fn generate_chain(&mut self, ast: &Ast, node: NodeId) -> Result<(), Box<dyn Error>> {
+        let mut operations = Vec::new();
+        let mut first = node;
+        while let Node::BinaryExpression { left, operator, right } = &ast[first] {
+            operations.push((operator, *right));
+            first = *left;
+        }
+        self.generate_expression(ast, first)?;
+
+        for (operator, right) in operations.into_iter().rev() {
+            match operator {
+                // `a && b` is `b` when `a` holds and `false` otherwise; `a || b`
+                // is `true` when `a` holds and `b` otherwise. Either way `b` is
+                // only evaluated when needed
+                TokenType::And => {
+                    let false_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+                    self.generate_expression(ast, right)?;
+                    let end_jump = self.emit_jump(OpCode::Jump(0));
+                    self.patch_jump(false_jump);
+                    self.emit_constant(Value::Bool(false));
+                    self.patch_jump(end_jump);
+                }
+                TokenType::Or => {
+                    let right_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+                    self.emit_constant(Value::Bool(true));
+                    let end_jump = self.emit_jump(OpCode::Jump(0));
+                    self.patch_jump(right_jump);
+                    self.generate_expression(ast, right)?;
+                    self.patch_jump(end_jump);
+                }
+                _ => {
+                    self.generate_expression(ast, right)?;
+                    let opcode = match operator {
+                        TokenType::Plus => OpCode::Add,
+                        TokenType::Minus => OpCode::Subtract,
+                        TokenType::Multiply => OpCode::Multiply,
+                        TokenType::Divide => OpCode::Divide,
+                        TokenType::Equal => OpCode::Equal,
+                        TokenType::NotEqual => OpCode::NotEqual,
+                        TokenType::LessThan => OpCode::LessThan,
+                        TokenType::GreaterThan => OpCode::GreaterThan,
+                        TokenType::LessEqual => OpCode::LessEqual,
+                        TokenType::GreaterEqual => OpCode::GreaterEqual,
+                        _ => {
+                            return Err(Box::new(BytecodeGeneratorError {
+                                message: format!("Unsupported binary operator: {:?}", operator),
+                            }));
+                        }
+                    };
+                    self.emit(opcode);
+                }
+            }
+        }
+        Ok(())
+    }
 
-                match operator {
-                    TokenType::Plus => {
-                        _ = self.emit(OpCode::Add);
-                    }
-                    TokenType::Minus => {
-                        _ = self.emit(OpCode::Subtract);
-                    }
-                    TokenType::Multiply => {
-                        _ = self.emit(OpCode::Multiply);
-                    }
-                    TokenType::Divide => {
-                        _ = self.emit(OpCode::Divide);
-                    }
-                    TokenType::Equal => {
-                        _ = self.emit(OpCode::Equal);
-                    }
-                    TokenType::NotEqual => {
-                        _ = self.emit(OpCode::NotEqual);
-                    }
-                    TokenType::LessThan => {
-                        _ = self.emit(OpCode::LessThan);
-                    }
-                    TokenType::GreaterThan => {
-                        _ = self.emit(OpCode::GreaterThan);
-                    }
-                    _ => {
-                        return Err(Box::new(BytecodeGeneratorError {
-                            message: format!("Unsupported binary operator: {:?}", operator),
-                        }));
+    fn generate_expression(&mut self, ast: &Ast, node: NodeId) -> Result<(), Box<dyn Error>> {
+        match &ast[node] {
+            Node::BlockExpression { statements, value } => {
+                self.begin_scope();
+                self.deferred.push(Vec::new());
+                self.block_expressions += 1;
+
+                for statement in statements {
+                    self.generate_statement(ast, *statement)?;
+                }
+                match value {
+                    Some(value) => self.generate_expression(ast, *value)?,
+                    None => {
+                        self.emit_constant(Value::Null);
                     }
                 }
+                self.block_expressions -= 1;
+
+                // The value waits in a temporary while the deferred statements run
+                let deferred = self.deferred.last().is_some_and(|deferred| !deferred.is_empty());
+                if deferred {
+                    let temporary = format!("<block{}>", self.scope_depth);
+                    self.emit(OpCode::DefineGlobal(temporary.clone()));
+                    self.generate_deferred(ast)?;
+                    self.end_scope();
+                    self.emit(OpCode::GetGlobal(temporary));
+                } else {
+                    self.deferred.pop();
+                    self.end_scope();
+                }
+            }
+            Node::IfExpression {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.generate_expression(ast, *condition)?;
+                let jump_if_false = self.emit_jump(OpCode::JumpIfFalse(0));
+                self.generate_expression(ast, *then_branch)?;
+                let jump = self.emit_jump(OpCode::Jump(0));
+                self.patch_jump(jump_if_false);
+                self.generate_expression(ast, *else_branch)?;
+                self.patch_jump(jump);
             }
-            ASTNode::UnaryExpression { operator, operand } => {
-                self.generate_expression(*operand)?;
+            Node::BinaryExpression { .. } => self.generate_chain(ast, node)?,
+            Node::UnaryExpression { operator, operand } => {
+                self.generate_expression(ast, *operand)?;
 
                 match operator {
                     TokenType::Minus => {
                         _ = self.emit(OpCode::Negate);
                     }
+                    TokenType::Not => {
+                        let true_jump = self.emit_jump(OpCode::JumpIfFalse(0));
+                        self.emit_constant(Value::Bool(false));
+                        let end_jump = self.emit_jump(OpCode::Jump(0));
+                        self.patch_jump(true_jump);
+                        self.emit_constant(Value::Bool(true));
+                        self.patch_jump(end_jump);
+                    }
                     _ => {
                         return Err(Box::new(BytecodeGeneratorError {
                             message: format!("Unsupported unary operator: {:?}", operator),
@@ -262,50 +677,137 @@ impl BytecodeGenerator {
                     }
                 }
             }
-            ASTNode::CallExpression { callee, arguments } => {
-                // Generate code for the callee
-                self.generate_expression(*callee)?;
+            Node::CallExpression { callee, arguments } => {
+                // Calls to builtins are resolved statically by name
+                if let Node::Identifier(name) = &ast[*callee] {
+                    if let Some(builtin) = builtins::lookup(name) {
+                        if !builtin.accepts(arguments.len()) {
+                            return Err(Box::new(BytecodeGeneratorError {
+                                message: format!(
+                                    "Function '{}' expects {} argument(s), got {}",
+                                    name, builtin.arity_text(), arguments.len()
+                                ),
+                            }));
+                        }
+                        // The variadic builtins take a format string first; a literal one is
+                        // checked against the values that follow it
+                        if let (true, Some(Node::StringLiteral(format))) = (builtin.variadic, arguments.first().map(|&arg| &ast[arg])) {
+                            let slots = builtins::placeholders(format)
+                                .map_err(|message| BytecodeGeneratorError { message })?
+                                .len();
+                            if slots != arguments.len() - 1 {
+                                return Err(Box::new(BytecodeGeneratorError {
+                                    message: format!(
+                                        "Function '{}' expects {} argument(s) after its format string, got {}",
+                                        name, slots, arguments.len() - 1
+                                    ),
+                                }));
+                            }
+                        }
+
+                        let arg_count = arguments.len();
+                        for arg in arguments {
+                            self.generate_expression(ast, *arg)?;
+                        }
+
+                        self.emit(OpCode::CallNative(name.clone(), arg_count));
+                        return Ok(());
+                    }
+                }
+
+                // Other calls go through the function table, so the callee is resolved here
+                let function = match &ast[*callee] {
+                    Node::Identifier(name) => self.functions.get(name).map(|&(index, params)| (name, index, params)),
+                    _ => None,
+                };
+                let Some((name, function, params)) = function else {
+                    let name = match &ast[*callee] {
+                        Node::Identifier(name) => name.clone(),
+                        _ => "<expression>".to_string(),
+                    };
+                    return Err(Box::new(BytecodeGeneratorError {
+                        message: format!("Undefined function: {}", name),
+                    }));
+                };
+                if arguments.len() != params {
+                    return Err(Box::new(BytecodeGeneratorError {
+                        message: format!("Function '{}' expects {} argument(s), got {}", name, params, arguments.len()),
+                    }));
+                }
 
                 // Generate code for the arguments
-                for arg in &arguments {
-                    self.generate_expression(arg.clone())?;
+                for arg in arguments {
+                    self.generate_expression(ast, *arg)?;
                 }
 
                 // Emit call instruction with arg count
-                self.emit(OpCode::Call(arguments.len()));
+                self.emit(OpCode::Call(function, arguments.len()));
             }
-            ASTNode::AssignmentExpression { name, value } => {
-                self.generate_expression(*value)?;
+            Node::AssignmentExpression { name, value } => {
+                self.generate_expression(ast, *value)?;
+                // An assignment is an expression: its value stays on the stack
+                self.emit(OpCode::Duplicate);
 
                 // Check if it's a local variable
-                if let Some(index) = self.resolve_local(&name) {
+                if let Some(index) = self.resolve_local(name) {
                     self.emit(OpCode::SetLocal(index));
                 } else {
                     // Use global variable
-                    self.emit(OpCode::SetGlobal(name));
+                    self.emit(OpCode::SetGlobal(name.clone()));
+                }
+            }
+            Node::IndexExpression { array, index } => {
+                self.generate_expression(ast, *array)?;
+                self.generate_expression(ast, *index)?;
+                self.emit(OpCode::IndexGet);
+            }
+            Node::IndexAssignment { name, indices, value } => {
+                // The array is updated as a whole, then stored back into the variable
+                let local = self.resolve_local(name);
+                match local {
+                    Some(index) => self.emit(OpCode::GetLocal(index)),
+                    None => self.emit(OpCode::GetGlobal(name.clone())),
+                };
+                for index in indices {
+                    self.generate_expression(ast, *index)?;
+                }
+                self.generate_expression(ast, *value)?;
+                self.emit(OpCode::IndexSet(indices.len()));
+                match local {
+                    Some(index) => self.emit(OpCode::SetLocal(index)),
+                    None => self.emit(OpCode::SetGlobal(name.clone())),
+                };
+            }
+            Node::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.generate_expression(ast, *element)?;
                 }
+                self.emit(OpCode::MakeArray(elements.len()));
             }
-            ASTNode::IntLiteral(value) => {
-                self.emit(OpCode::Constant(Value::Int(value)));
+            Node::IntLiteral(value) => {
+                self.emit_constant(Value::Int(*value));
             }
-            ASTNode::FloatLiteral(value) => {
-                self.emit(OpCode::Constant(Value::Float(value)));
+            Node::FloatLiteral(value) => {
+                self.emit_constant(Value::Float(*value));
             }
-            ASTNode::StringLiteral(value) => {
-                self.emit(OpCode::Constant(Value::String(value)));
+            Node::StringLiteral(value) => {
+                self.emit_constant(Value::String(value.clone()));
             }
-            ASTNode::Identifier(name) => {
+            Node::NullLiteral => {
+                self.emit_constant(Value::Null);
+            }
+            Node::Identifier(name) => {
                 // Check if it's a local variable
-                if let Some(index) = self.resolve_local(&name) {
+                if let Some(index) = self.resolve_local(name) {
                     self.emit(OpCode::GetLocal(index));
                 } else {
                     // Use global variable
-                    self.emit(OpCode::GetGlobal(name));
+                    self.emit(OpCode::GetGlobal(name.clone()));
                 }
             }
             _ => {
                 return Err(Box::new(BytecodeGeneratorError {
-                    message: format!("Unexpected node type in expression context: {:?}", node),
+                    message: format!("Unexpected node type in expression context: {:?}", ast.to_tree(node)),
                 }));
             }
         }
@@ -314,8 +816,13 @@ impl BytecodeGenerator {
     }
 
     fn emit(&mut self, op_code: OpCode) -> usize {
-        self.code.push(op_code);
-        self.code.len() - 1
+        self.chunk.code.push(op_code);
+        self.chunk.code.len() - 1
+    }
+
+    fn emit_constant(&mut self, value: Value) -> usize {
+        let index = self.module.add_constant(value);
+        self.emit(OpCode::Constant(index))
     }
 
     fn emit_jump(&mut self, op_code: OpCode) -> usize {
@@ -323,16 +830,114 @@ impl BytecodeGenerator {
     }
 
     fn patch_jump(&mut self, offset: usize) {
-        let jump_offset = self.code.len();
+        let jump_offset = self.chunk.code.len();
 
         // Update the jump instruction with the correct offset
-        match &mut self.code[offset] {
+        match &mut self.chunk.code[offset] {
             OpCode::JumpIfFalse(to) => *to = jump_offset,
             OpCode::Jump(to) => *to = jump_offset,
             _ => panic!("Tried to patch a non-jump instruction"),
         }
     }
 
+    // Emits the statements deferred in the block or program that ends here,
+    // the last deferred first. They run when execution reaches the end of
+    // the block; exit() and runtime errors skip them
+    fn generate_deferred(&mut self, ast: &Ast) -> Result<(), Box<dyn Error>> {
+        let deferred = self.deferred.pop().unwrap_or_default();
+        for statement in deferred.into_iter().rev() {
+            self.generate_statement(ast, statement)?;
+        }
+        Ok(())
+    }
+
+    // Emits what leaving the loop named `label`, or the innermost loop,
+    // takes before jumping: the deferred statements of the blocks left,
+    // innermost first. Returns the loop's index
+    fn leave_loop(&mut self, ast: &Ast, keyword: &str, label: Option<&str>) -> Result<usize, Box<dyn Error>> {
+        let target = match label {
+            None => self.loops.len().checked_sub(1),
+            Some(label) => self.loops.iter().rposition(|context| context.label.as_deref() == Some(label)),
+        };
+        let Some(target) = target else {
+            let message = match label {
+                None => format!("'{}' outside of a loop", keyword),
+                Some(label) => format!("'{} {}' names no enclosing loop", keyword, label),
+            };
+            return Err(Box::new(BytecodeGeneratorError { message }));
+        };
+        // The value of a block expression being computed would be left on the stack
+        if self.block_expressions > self.loops[target].block_expressions {
+            return Err(Box::new(BytecodeGeneratorError {
+                message: format!("'{}' cannot leave a block used as a value", keyword),
+            }));
+        }
+
+        let scopes = std::mem::take(&mut self.deferred);
+        for deferred in scopes[self.loops[target].deferred..].iter().rev() {
+            for statement in deferred.iter().rev() {
+                self.generate_statement(ast, *statement)?;
+            }
+        }
+        self.deferred = scopes;
+        Ok(target)
+    }
+
+    // Generates a function into a chunk of its own, added to the module:
+    // functions are generated in the order of the function table. The code
+    // of the function around it, if any, is put aside meanwhile
+    fn generate_function(&mut self, ast: &Ast, name: &str, params: &[String], body: NodeId) -> Result<(), Box<dyn Error>> {
+        if self.in_function || self.scope_depth > 0 {
+            return Err(Box::new(BytecodeGeneratorError {
+                message: format!("Function '{}' must be declared at the top level", name),
+            }));
+        }
+        let duplicate = params.iter().enumerate().find(|&(index, param)| params[..index].contains(param));
+        if let Some((_, param)) = duplicate {
+            return Err(Box::new(BytecodeGeneratorError {
+                message: format!("Parameter '{}' of function '{}' is declared twice", param, name),
+            }));
+        }
+
+        let mut function = Chunk::new(name);
+        function.statements = self.chunk.statements.last().map(|&(_, statement)| (0, statement)).into_iter().collect();
+        function.lines = self.chunk.lines.last().map(|&(_, line)| (0, line)).into_iter().collect();
+        let outer_chunk = std::mem::replace(&mut self.chunk, function);
+        let outer_locals = std::mem::take(&mut self.locals);
+        let outer_loops = std::mem::take(&mut self.loops);
+        let outer_deferred = std::mem::take(&mut self.deferred);
+        let outer_block_expressions = std::mem::replace(&mut self.block_expressions, 0);
+        self.in_function = true;
+
+        // The arguments are on the stack, the last on top; each parameter is a
+        // local of the function's outermost scope
+        self.begin_scope();
+        for param in params {
+            self.chunk.locals.push(param.clone());
+            self.locals.push(LocalVariable {
+                name: param.clone(),
+                depth: self.scope_depth,
+            });
+        }
+        for slot in (0..params.len()).rev() {
+            self.emit(OpCode::SetLocal(slot));
+        }
+        // Falling off the end of the body returns its value
+        let generated = self.generate_expression(ast, body);
+        self.emit(OpCode::Return);
+        self.end_scope();
+
+        self.in_function = false;
+        self.block_expressions = outer_block_expressions;
+        self.deferred = outer_deferred;
+        self.loops = outer_loops;
+        self.locals = outer_locals;
+        let function = std::mem::replace(&mut self.chunk, outer_chunk);
+        generated?;
+        self.module.chunks.push(function);
+        Ok(())
+    }
+
     fn begin_scope(&mut self) {
         self.scope_depth += 1;
     }
@@ -340,9 +945,8 @@ impl BytecodeGenerator {
     fn end_scope(&mut self) {
         self.scope_depth -= 1;
 
-        // Pop locals in reverse order
+        // Locals live in slots of the chunk, which the next locals reuse
         while !self.locals.is_empty() && self.locals.last().unwrap().depth > self.scope_depth {
-            self.emit(OpCode::Pop);
             self.locals.pop();
         }
     }
@@ -373,7 +977,13 @@ impl BytecodeGenerator {
         Ok(())
     }
 
+    // Stores the value on the stack into a new local
     fn add_local(&mut self, name: String) {
+        let slot = self.locals.len();
+        if slot == self.chunk.locals.len() {
+            self.chunk.locals.push(name.clone());
+        }
+        self.emit(OpCode::SetLocal(slot));
         self.locals.push(LocalVariable {
             name,
             depth: self.scope_depth,
@@ -0,0 +1,292 @@
+// Static call graph of a program, as JSON or as Graphviz DOT. The callers are
+// the top-level code of the program and its functions: calls naming a
+// builtin or a function of the program are edges to it, and any other call,
+// which picks its callee from a value at runtime, is listed separately as
+// dynamic. The optimizer builds the graph of the bytecode it rewrites, to
+// find the functions that can call themselves again.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::builtins;
+#[cfg(feature = "server")]
+use crate::lexer::Lexer;
+use crate::parser::ASTNode;
+#[cfg(feature = "server")]
+use crate::parser::Parser;
+use crate::vm::Instruction;
+#[cfg(feature = "server")]
+use crate::SourceInput;
+use crate::ErrorDetails;
+
+/// Name of the node standing for the top-level code of the program.
+pub const PROGRAM: &str = "<program>";
+
+/// Calls from one node to another, resolved statically.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CallEdge {
+    pub caller: String,
+    pub callee: String,
+    pub calls: usize, // Call sites, counting each once
+}
+
+/// A call whose callee is only known at runtime.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DynamicCall {
+    pub caller: String,
+    pub callee: Option<String>, // The variable called, or None for another expression
+}
+
+/// Call graph of a program.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct CallGraph {
+    pub nodes: Vec<String>,     // The program, then its functions and the callees in order of name
+    pub edges: Vec<CallEdge>,
+    pub dynamic: Vec<DynamicCall>, // In source order
+}
+
+impl CallGraph {
+    pub fn build(program: &ASTNode) -> Self {
+        let functions: BTreeSet<String> = match program {
+            ASTNode::Program(statements) => statements
+                .iter()
+                .filter_map(|statement| match statement {
+                    ASTNode::FunctionDeclaration { name, .. } => Some(name.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+        let mut visitor = Visitor {
+            functions: &functions,
+            caller: PROGRAM.to_string(),
+            calls: BTreeMap::new(),
+            dynamic: Vec::new(),
+        };
+        visitor.visit(program);
+        Self::from_calls(&functions, visitor.calls, visitor.dynamic)
+    }
+
+    /// Builds the graph of linked bytecode, whose calls name their callee by
+    /// its index in the function table, so none is dynamic.
+    pub fn from_code(code: &[Instruction]) -> Self {
+        let functions: Vec<&String> = code
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Function(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let mut caller = PROGRAM;
+        let mut calls = BTreeMap::new();
+        for instruction in code {
+            let callee = match instruction {
+                Instruction::Function(name) => {
+                    caller = name;
+                    continue;
+                }
+                Instruction::Call(index, _) => functions.get(*index).copied(),
+                Instruction::CallNative(name, _) => Some(name),
+                _ => None,
+            };
+            if let Some(callee) = callee {
+                *calls.entry((caller.to_string(), callee.clone())).or_default() += 1;
+            }
+        }
+        Self::from_calls(&functions.into_iter().cloned().collect(), calls, Vec::new())
+    }
+
+    fn from_calls(functions: &BTreeSet<String>, calls: BTreeMap<(String, String), usize>, dynamic: Vec<DynamicCall>) -> Self {
+        let callees = calls.keys().map(|(_, callee)| callee.clone());
+        let nodes: BTreeSet<String> = functions.iter().cloned().chain(callees).collect();
+        CallGraph {
+            nodes: std::iter::once(PROGRAM.to_string()).chain(nodes).collect(),
+            edges: calls
+                .into_iter()
+                .map(|((caller, callee), calls)| CallEdge { caller, callee, calls })
+                .collect(),
+            dynamic,
+        }
+    }
+
+    /// The nodes that can call themselves again, directly or through others.
+    /// Dynamic calls are not followed.
+    pub fn recursive(&self) -> BTreeSet<&str> {
+        let mut callees: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for edge in &self.edges {
+            callees.entry(&edge.caller).or_default().push(&edge.callee);
+        }
+        let reaches_itself = |node: &str| {
+            let mut seen = BTreeSet::new();
+            let mut pending = callees.get(node).cloned().unwrap_or_default();
+            while let Some(next) = pending.pop() {
+                if next == node {
+                    return true;
+                }
+                if seen.insert(next) {
+                    pending.extend(callees.get(next).into_iter().flatten());
+                }
+            }
+            false
+        };
+        self.nodes.iter().map(String::as_str).filter(|node| reaches_itself(node)).collect()
+    }
+
+    /// Renders the graph in Graphviz DOT. Dynamic calls are dashed edges to
+    /// the variable called, or to a `?` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n");
+        for node in &self.nodes {
+            dot.push_str(&format!("  {:?};\n", node));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!("  {:?} -> {:?} [label=\"{}\"];\n", edge.caller, edge.callee, edge.calls));
+        }
+        for call in &self.dynamic {
+            let callee = call.callee.as_deref().unwrap_or("?");
+            dot.push_str(&format!("  {:?} -> {:?} [style=dashed];\n", call.caller, callee));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Walks a program, recording the calls of the caller it is in
+struct Visitor<'a> {
+    functions: &'a BTreeSet<String>, // Functions the program declares
+    caller: String,
+    calls: BTreeMap<(String, String), usize>, // Call sites by caller and callee
+    dynamic: Vec<DynamicCall>,
+}
+
+impl Visitor<'_> {
+    // Records the calls made by `node` and its subtree
+    fn visit(&mut self, node: &ASTNode) {
+        let children: Vec<&ASTNode> = match node {
+            ASTNode::Program(statements) | ASTNode::Block(statements) => statements.iter().collect(),
+            ASTNode::VarDeclaration { initializer, .. } => initializer.iter().map(|value| value.as_ref()).collect(),
+            ASTNode::ExpressionStatement(expression) | ASTNode::PrintStatement(expression) => vec![expression],
+            ASTNode::DeferStatement(statement) => vec![statement],
+            ASTNode::IfStatement { condition, then_branch, else_branch } => {
+                let mut children = vec![condition.as_ref(), then_branch.as_ref()];
+                children.extend(else_branch.as_deref());
+                children
+            }
+            ASTNode::WhileStatement { condition, body, .. } => vec![condition, body],
+            ASTNode::ReturnStatement(value) => value.iter().map(|value| value.as_ref()).collect(),
+            ASTNode::BinaryExpression { .. } => node.operands(),
+            ASTNode::UnaryExpression { operand, .. } => vec![operand],
+            ASTNode::AssignmentExpression { value, .. } => vec![value],
+            ASTNode::IndexExpression { array, index } => vec![array, index],
+            ASTNode::IndexAssignment { indices, value, .. } => {
+                let mut children: Vec<&ASTNode> = indices.iter().collect();
+                children.push(value);
+                children
+            }
+            ASTNode::ArrayLiteral(elements) => elements.iter().collect(),
+            ASTNode::BlockExpression { statements, value } => {
+                let mut children: Vec<&ASTNode> = statements.iter().collect();
+                children.extend(value.as_deref());
+                children
+            }
+            ASTNode::IfExpression { condition, then_branch, else_branch } => vec![condition, then_branch, else_branch],
+            ASTNode::FunctionDeclaration { name, body, .. } => {
+                let caller = std::mem::replace(&mut self.caller, name.clone());
+                self.visit(body);
+                self.caller = caller;
+                Vec::new()
+            }
+            // Tests are not part of the program's run
+            ASTNode::TestBlock { .. } => Vec::new(),
+            ASTNode::CallExpression { callee, arguments } => {
+                match callee.as_ref() {
+                    ASTNode::Identifier(name) if builtins::lookup(name).is_some() || self.functions.contains(name) => {
+                        *self.calls.entry((self.caller.clone(), name.clone())).or_default() += 1;
+                    }
+                    ASTNode::Identifier(name) => self.dynamic.push(DynamicCall {
+                        caller: self.caller.clone(),
+                        callee: Some(name.clone()),
+                    }),
+                    _ => {
+                        self.dynamic.push(DynamicCall { caller: self.caller.clone(), callee: None });
+                        self.visit(callee);
+                    }
+                }
+                arguments.iter().collect()
+            }
+            ASTNode::Import(_)
+            | ASTNode::BreakStatement(_)
+            | ASTNode::ContinueStatement(_)
+            | ASTNode::IntLiteral(_)
+            | ASTNode::FloatLiteral(_)
+            | ASTNode::StringLiteral(_)
+            | ASTNode::NullLiteral
+            | ASTNode::Identifier(_) => Vec::new(),
+        };
+        for child in children {
+            self.visit(child);
+        }
+    }
+}
+
+// Output format of POST /callgraph
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CallGraphFormat {
+    #[default]
+    Json,
+    Dot,
+}
+
+// Query options accepted by /callgraph
+#[derive(Deserialize, IntoParams)]
+pub struct CallGraphOptions {
+    #[serde(default)]
+    format: CallGraphFormat,
+}
+
+// Response of POST /callgraph?format=json
+#[derive(Serialize, ToSchema)]
+pub struct CallGraphOutput {
+    #[serde(flatten)]
+    graph: CallGraph, // Empty when the source does not parse
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for POST /callgraph
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = SourceInput,
+    params(CallGraphOptions),
+    responses(
+        (status = 200, description = "Call graph of the program, as JSON or as Graphviz DOT (format=dot)", body = CallGraphOutput),
+        (status = 400, description = "The source does not parse", body = CallGraphOutput),
+    )
+)]
+#[post("/callgraph")]
+pub async fn call_graph(input: web::Json<SourceInput>, options: web::Query<CallGraphOptions>) -> impl Responder {
+    let parsed = Lexer::new(&input.source)
+        .tokenize()
+        .and_then(|tokens| Parser::new(tokens).parse());
+
+    match (parsed, options.format) {
+        (Ok(program), CallGraphFormat::Json) => HttpResponse::Ok().json(CallGraphOutput {
+            graph: CallGraph::build(&program),
+            error_details: None,
+        }),
+        (Ok(program), CallGraphFormat::Dot) => HttpResponse::Ok()
+            .content_type("text/vnd.graphviz; charset=utf-8")
+            .body(CallGraph::build(&program).to_dot()),
+        (Err(e), _) => {
+            let details = ErrorDetails::from_error(e.as_ref());
+            HttpResponse::build(details.kind.status()).json(CallGraphOutput {
+                graph: CallGraph::default(),
+                error_details: Some(details),
+            })
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// Control-flow graph of VM instructions: basic blocks, the edges between
+// them, dominators and natural loops. The optimizer finds the loops it
+// optimizes here, and POST /cfg returns the graph of a program as JSON for
+// visualizations.
+//
+// A block is a run of instructions entered only at its first one and left
+// only after its last one. Block A dominates block B when every path from the
+// entry to B goes through A; the immediate dominators form the dominator tree.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use utoipa::ToSchema;
+
+#[cfg(feature = "server")]
+use crate::liveness;
+use crate::liveness::Liveness;
+use crate::optimizer;
+use crate::vm::Instruction;
+use crate::ErrorDetails;
+#[cfg(feature = "server")]
+use crate::ErrorKind;
+
+/// Instructions that always run one after the other.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BasicBlock {
+    pub id: usize,
+    pub start: usize, // Index of the first instruction
+    pub end: usize,   // Index past the last instruction
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+    pub immediate_dominator: Option<usize>, // None for the entry and for blocks the entry never reaches
+}
+
+/// A natural loop: the blocks that can reach a jump back to the header
+/// without going through the header, which dominates them all.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Loop {
+    pub header: usize,
+    pub latches: Vec<usize>, // Blocks that jump back to the header
+    pub blocks: Vec<usize>,  // In order, the header included
+}
+
+/// Blocks of a program, with their edges, dominators and loops.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>, // In instruction order; the entry is block 0
+    pub loops: Vec<Loop>,        // Inner loops before the loops around them
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of instructions with absolute jump targets.
+    pub fn build(code: &[Instruction]) -> Self {
+        // A block starts at the entry, at jump targets and after jumps
+        let mut leaders = vec![false; code.len()];
+        if let Some(entry) = leaders.first_mut() {
+            *entry = true;
+        }
+        for (index, instruction) in code.iter().enumerate() {
+            if let Instruction::Jump(target) | Instruction::JumpIfFalse(target) = instruction {
+                if let Some(leader) = leaders.get_mut(*target) {
+                    *leader = true;
+                }
+            }
+            let ends_block = matches!(
+                instruction,
+                Instruction::Jump(_) | Instruction::JumpIfFalse(_) | Instruction::Call(..) | Instruction::Return | Instruction::Halt
+            ) || optimizer::is_function_entry(instruction);
+            if ends_block {
+                if let Some(leader) = leaders.get_mut(index + 1) {
+                    *leader = true;
+                }
+            }
+            if optimizer::is_function_entry(instruction) {
+                leaders[index] = true;
+            }
+        }
+
+        let mut block_of = Vec::with_capacity(code.len()); // Instruction index -> block id
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        for (index, leader) in leaders.iter().enumerate() {
+            if *leader {
+                blocks.push(BasicBlock {
+                    id: blocks.len(),
+                    start: index,
+                    end: index,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                    immediate_dominator: None,
+                });
+            }
+            if let Some(block) = blocks.last_mut() {
+                block.end = index + 1;
+            }
+            block_of.push(blocks.len() - 1);
+        }
+
+        // Jumps to the end of the program leave the graph
+        for id in 0..blocks.len() {
+            let last = blocks[id].end - 1;
+            let mut successors = match &code[last] {
+                Instruction::Jump(target) => vec![*target],
+                Instruction::JumpIfFalse(target) => vec![last + 1, *target],
+                Instruction::Halt => Vec::new(),
+                _ => vec![last + 1],
+            };
+            successors.retain(|target| *target < code.len());
+            let mut successors: Vec<usize> = successors.into_iter().map(|target| block_of[target]).collect();
+            successors.dedup();
+            for successor in &successors {
+                blocks[*successor].predecessors.push(id);
+            }
+            blocks[id].successors = successors;
+        }
+
+        let mut graph = ControlFlowGraph {
+            blocks,
+            loops: Vec::new(),
+        };
+        graph.compute_dominators();
+        graph.find_loops();
+        graph
+    }
+
+    /// Whether a path from the entry reaches a block.
+    pub fn is_reachable(&self, block: usize) -> bool {
+        block == 0 || self.blocks[block].immediate_dominator.is_some()
+    }
+
+    /// Whether every path from the entry to block `b` goes through block `a`.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        let mut block = b;
+        loop {
+            if block == a {
+                return true;
+            }
+            match self.blocks[block].immediate_dominator {
+                Some(dominator) => block = dominator,
+                None => return false,
+            }
+        }
+    }
+
+    // Iterative algorithm of Cooper, Harvey and Kennedy, over the blocks in
+    // reverse postorder
+    fn compute_dominators(&mut self) {
+        let Some(order) = self.reverse_postorder() else {
+            return;
+        };
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (rank, block) in order.iter().enumerate() {
+            position[*block] = rank;
+        }
+
+        // The entry is its own dominator while computing
+        let mut dominators: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        dominators[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let mut new_dominator = None;
+                for predecessor in &self.blocks[*block].predecessors {
+                    if dominators[*predecessor].is_none() {
+                        continue;
+                    }
+                    new_dominator = Some(match new_dominator {
+                        None => *predecessor,
+                        Some(other) => intersect(&dominators, &position, *predecessor, other),
+                    });
+                }
+                if new_dominator.is_some() && dominators[*block] != new_dominator {
+                    dominators[*block] = new_dominator;
+                    changed = true;
+                }
+            }
+        }
+
+        dominators[0] = None;
+        for (block, dominator) in self.blocks.iter_mut().zip(dominators) {
+            block.immediate_dominator = dominator;
+        }
+    }
+
+    // Blocks the entry reaches, in reverse postorder; None without blocks
+    fn reverse_postorder(&self) -> Option<Vec<usize>> {
+        if self.blocks.is_empty() {
+            return None;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        let mut stack = vec![(0, 0)]; // Block and its next successor to visit
+        visited[0] = true;
+        while let Some((block, next)) = stack.last_mut() {
+            match self.blocks[*block].successors.get(*next) {
+                Some(successor) => {
+                    *next += 1;
+                    if !visited[*successor] {
+                        visited[*successor] = true;
+                        stack.push((*successor, 0));
+                    }
+                }
+                None => {
+                    postorder.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        Some(postorder)
+    }
+
+    // A jump back to a block that dominates the jumping block closes a loop
+    fn find_loops(&mut self) {
+        let mut loops: Vec<Loop> = Vec::new();
+        for block in &self.blocks {
+            for successor in &block.successors {
+                if !self.is_reachable(block.id) || !self.dominates(*successor, block.id) {
+                    continue;
+                }
+                let mut members = BTreeSet::from([*successor]);
+                let mut pending = vec![block.id];
+                while let Some(member) = pending.pop() {
+                    if members.insert(member) {
+                        let predecessors = &self.blocks[member].predecessors;
+                        pending.extend(predecessors.iter().filter(|predecessor| self.is_reachable(**predecessor)));
+                    }
+                }
+                match loops.iter_mut().find(|other| other.header == *successor) {
+                    Some(other) => {
+                        other.latches.push(block.id);
+                        other.blocks = other.blocks.iter().copied().chain(members).collect::<BTreeSet<_>>().into_iter().collect();
+                    }
+                    None => loops.push(Loop {
+                        header: *successor,
+                        latches: vec![block.id],
+                        blocks: members.into_iter().collect(),
+                    }),
+                }
+            }
+        }
+        loops.sort_by_key(|natural_loop| natural_loop.blocks.len());
+        self.loops = loops;
+    }
+}
+
+// Nearest common dominator of two blocks
+fn intersect(dominators: &[Option<usize>], position: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while position[a] > position[b] {
+            a = dominators[a].unwrap_or(0);
+        }
+        while position[b] > position[a] {
+            b = dominators[b].unwrap_or(0);
+        }
+    }
+    a
+}
+
+// Request body of POST /cfg
+#[derive(Deserialize, ToSchema)]
+pub struct CfgInput {
+    source: String,
+    #[serde(default)]
+    opt_level: u8, // The graph is built from the bytecode optimized at this level
+    #[serde(default)]
+    liveness: bool, // Also return the liveness of variables and their interference
+}
+
+// Response of POST /cfg
+#[derive(Serialize, ToSchema)]
+pub struct CfgOutput {
+    bytecode: Vec<String>, // Instructions the blocks refer to by index
+    blocks: Vec<BasicBlock>,
+    loops: Vec<Loop>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    liveness: Option<Liveness>, // When the request sets `liveness`
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for POST /cfg
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = CfgInput,
+    responses(
+        (status = 200, description = "Basic blocks, dominator tree and loops of the compiled program", body = CfgOutput),
+        (status = 400, description = "The source does not compile", body = CfgOutput),
+        (status = 422, description = "Invalid opt_level", body = CfgOutput),
+    )
+)]
+#[post("/cfg")]
+pub async fn control_flow_graph(input: web::Json<CfgInput>) -> impl Responder {
+    let compiled = if input.opt_level > optimizer::MAX_OPT_LEVEL {
+        Err(ErrorDetails {
+            kind: ErrorKind::InvalidInput,
+            code: None,
+            message: format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL),
+            line: None,
+            column: None,
+            rendered: None,
+        })
+    } else {
+        crate::compile_source(&input.source)
+            .map(|code| optimizer::optimize(code, input.opt_level))
+            .map_err(|e| ErrorDetails::from_error(e.as_ref()))
+    };
+
+    match compiled {
+        Ok(code) => {
+            let graph = ControlFlowGraph::build(&code);
+            let liveness = input.liveness.then(|| liveness::analyse(&code, &graph));
+            HttpResponse::Ok().json(CfgOutput {
+                bytecode: code.iter().map(|instruction| format!("{:?}", instruction)).collect(),
+                blocks: graph.blocks,
+                loops: graph.loops,
+                liveness,
+                error_details: None,
+            })
+        }
+        Err(details) => HttpResponse::build(details.kind.status()).json(CfgOutput {
+            bytecode: Vec::new(),
+            blocks: Vec::new(),
+            loops: Vec::new(),
+            liveness: None,
+            error_details: Some(details),
+        }),
+    }
+}
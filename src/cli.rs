@@ -0,0 +1,823 @@
+// Command-line interface, used when the binary is started with arguments:
+//
+//   compiler exec --json   Read a job as JSON from stdin, run it and write the
+//                          result as JSON to stdout (for autograders and CI)
+//   compiler check FILE    Compile a file and report its diagnostics
+//   compiler run FILE      Compile and run a file or built artifact, printing its output
+//   compiler test FILE     Run the test blocks of a file, reporting which pass
+//   compiler build [DIR]   Compile the project in DIR into a bytecode artifact
+//   compiler analyze FILE  Print code metrics of a file
+//   compiler kernel FILE   Run as a Jupyter kernel (feature "jupyter")
+//
+// `check`, `run` and `test` accept `--watch` to repeat the command whenever
+// the file changes.
+
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+#[cfg(feature = "watch")]
+use notify::event::ModifyKind;
+#[cfg(feature = "watch")]
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "watch")]
+use std::sync::mpsc;
+#[cfg(feature = "watch")]
+use std::time::Duration;
+
+use crate::artifact::{Artifact, DebugInfo, Limits};
+use crate::diagnostics::Diagnostic;
+use crate::i18n;
+use crate::incremental::IncrementalCompiler;
+use crate::lexer::Lexer;
+use crate::limits::ExecutionLimits;
+use crate::metrics::{self, Metrics};
+use crate::optimizer::{self, MAX_OPT_LEVEL};
+use crate::pipeline::Mode;
+use crate::project::{self, BuildError, Project};
+use crate::quickfix;
+use crate::registry;
+use crate::sandbox::SandboxPolicy;
+use crate::semantic::{StrictMode, Strictness};
+use crate::testing::{self, TestResult};
+use crate::verifier;
+use crate::vfs::FileSystem;
+use crate::vm::{Instruction, VmConfig};
+use crate::worker::{self, WorkerError, WorkerJob, WorkerLimits};
+use crate::{catch_panics, link, run_artifact, run_job, CodeInput, CodeOutput, ErrorDetails, ErrorKind};
+
+// Exit codes of `exec`, one per outcome so callers don't need to parse the output
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_PROGRAM_ERROR: i32 = 1; // Lexical, syntax, compile or runtime error
+pub const EXIT_INVALID_INPUT: i32 = 2; // Bad command line or malformed job
+pub const EXIT_TIMEOUT: i32 = 3;
+pub const EXIT_INTERNAL_ERROR: i32 = 4;
+
+// Quiet period after a change before re-running, so that an editor saving
+// in several steps triggers a single run
+#[cfg(feature = "watch")]
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+// Default output directory of `build`, inside the project
+const BUILD_DIR: &str = "build";
+
+const USAGE: &str = "Usage:
+  compiler                Start the web server (builds with the \"server\" feature)
+  compiler exec --json    Run the JSON job read from stdin and print the result as JSON
+  compiler check FILE     Compile FILE and report errors without running it
+  compiler run FILE       Compile and run FILE, reading its input from stdin; FILE
+                          may also be an artifact made by `build`
+  compiler test FILE      Run each `test \"name\" { ... }` block of FILE and its
+                          imports after the program, reading input from stdin
+  compiler build [DIR]    Compile the project described by DIR/project.toml
+                          (default: the current directory) into a bytecode artifact
+  compiler explain FILE   Print the bytecode of FILE before optimization, then after
+                          each pass with what the pass did (default: -O2)
+  compiler explain CODE   Explain the error code CODE, e.g. E0203, with an example
+  compiler analyze FILE   Print the size, cyclomatic complexity and nesting depth of
+                          FILE and of each of its top-level statements
+  compiler kernel FILE    Run as a Jupyter kernel with the connection file FILE
+                          (builds with the \"jupyter\" feature)
+
+Options:
+  --message-format=FMT    Diagnostics as `human` text on stderr (default) or
+                          `json` objects on stdout, one per line; for analyze,
+                          the metrics as a table or as one JSON object; for
+                          test, the result of each test as a JSON object
+  --watch                 check, run, test: Run again whenever FILE changes, until interrupted
+                          (builds with the \"watch\" feature)
+  -O0, -O1, -O2           Optimization level: none (default), constant folding and
+                          peephole, or also constant inlining, dead code elimination
+                          and loop-invariant code motion; for build, overrides the
+                          manifest's opt_level
+  --strict                Enable every check of strict mode: no implicit int/float
+                          conversions, bool conditions, initialized declarations
+                          no unused variables and values suiting the placeholders
+                          of format strings; for build, overrides the
+                          manifest's strict
+  --allow-fs              run, test: Let the program read and write files with
+                          read_file(), write_file() and append_file()
+  -o FILE                 build: Write the artifact to FILE instead of DIR/build/NAME.bc
+  --strip                 build: Leave out the debug information that names the
+                          line of runtime errors; overrides the manifest's strip";
+
+// How `check` and `run` report diagnostics
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageFormat {
+    Human, // `file:line:column: error[code]: message` on stderr
+    Json,  // One JSON object per line on stdout
+}
+
+// Arguments of `check`, `run` and `build`
+struct FileCommand {
+    path: String,
+    output: Option<String>, // Artifact path given to `build -o`
+    message_format: MessageFormat,
+    watch: bool,
+    opt_level: Option<u8>,  // Set by -O0, -O1 or -O2
+    strict: bool,           // Set by --strict
+    allow_fs: bool,         // Set by --allow-fs
+    strip: bool,            // Set by `build --strip`
+}
+
+// Job accepted by `exec --json`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExecJob {
+    source: String,
+    #[serde(default)]
+    language: String,                  // "beginner" selects the beginner dialect
+    #[serde(default)]
+    mode: Mode,                        // "expression" when `source` is a single expression
+    #[serde(default)]
+    stdin: String,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    #[serde(default)]
+    globals: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    return_variables: Vec<String>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    inspect_variables: bool,
+    #[serde(default)]
+    opt_level: u8,
+    #[serde(default)]
+    strict: StrictMode,
+    #[serde(default)]
+    verbose: bool,
+    #[serde(default)]
+    capabilities: Option<Vec<String>>, // Defaults to the server's default policy
+    #[serde(default)]
+    limits: Option<ExecLimits>,        // When present, the job runs in a sandboxed worker
+    #[serde(default)]
+    locale: Option<String>,            // Language of error messages, e.g. "es"
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExecLimits {
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    #[serde(default = "default_memory_mb")]
+    memory_mb: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    5_000
+}
+
+fn default_memory_mb() -> u64 {
+    256
+}
+
+/// Runs the command given by `args` (without the program name) and returns
+/// the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["exec", "--json"] => exec_json(),
+        ["check", rest @ ..] => {
+            let compiler = RefCell::new(IncrementalCompiler::new());
+            with_file_command(rest, None, |command| check(command, &mut compiler.borrow_mut()))
+        }
+        ["build", rest @ ..] => with_file_command(rest, Some("."), build),
+        // A file is only taken for a code when there is no file of that name
+        ["explain", code] if registry::is_code(code) && !Path::new(code).exists() => explain_code(code),
+        ["explain", rest @ ..] => with_file_command(rest, None, explain),
+        ["analyze", rest @ ..] => with_file_command(rest, None, analyze),
+        ["run", rest @ ..] => {
+            // Piped input feeds read_line() on every run; a terminal is not waited on
+            let mut stdin = String::new();
+            if !std::io::stdin().is_terminal() {
+                let _ = std::io::stdin().read_to_string(&mut stdin);
+            }
+            let compiler = RefCell::new(IncrementalCompiler::new());
+            with_file_command(rest, None, |command| run_file(command, &mut compiler.borrow_mut(), &stdin))
+        }
+        ["test", rest @ ..] => {
+            let mut stdin = String::new();
+            if !std::io::stdin().is_terminal() {
+                let _ = std::io::stdin().read_to_string(&mut stdin);
+            }
+            with_file_command(rest, None, |command| test(command, &stdin))
+        }
+        #[cfg(feature = "jupyter")]
+        ["kernel", path] => crate::kernel::run(path),
+        #[cfg(not(feature = "jupyter"))]
+        ["kernel", _] => {
+            eprintln!("This build has no Jupyter kernel; build with `--features jupyter`");
+            EXIT_INVALID_INPUT
+        }
+        ["help"] | ["--help"] | ["-h"] => {
+            println!("{}", USAGE);
+            EXIT_SUCCESS
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            EXIT_INVALID_INPUT
+        }
+    }
+}
+
+// `exec --json`: one job in, one result out
+fn exec_json() -> i32 {
+    let output = match read_job() {
+        Ok((input, policy, limits)) => execute(input, policy, limits),
+        Err(message) => CodeOutput::failure(ErrorKind::InvalidInput, message),
+    };
+
+    let code = exit_code(&output);
+    let mut stdout = std::io::stdout();
+    let written = serde_json::to_writer(&mut stdout, &output)
+        .map_err(std::io::Error::from)
+        .and_then(|_| writeln!(stdout))
+        .and_then(|_| stdout.flush());
+    match written {
+        Ok(()) => code,
+        Err(_) => EXIT_INTERNAL_ERROR,
+    }
+}
+
+fn read_job() -> Result<(CodeInput, SandboxPolicy, Option<WorkerLimits>), String> {
+    let mut payload = String::new();
+    std::io::stdin()
+        .read_to_string(&mut payload)
+        .map_err(|e| format!("Failed to read job: {}", e))?;
+    let job: ExecJob = serde_json::from_str(&payload).map_err(|e| format!("Invalid job: {}", e))?;
+
+    let policy = match &job.capabilities {
+        Some(capabilities) => SandboxPolicy::from_list(&capabilities.join(","))?,
+        None => SandboxPolicy::default(),
+    };
+    let limits = job.limits.map(|limits| WorkerLimits {
+        timeout_ms: limits.timeout_ms,
+        memory_mb: limits.memory_mb,
+    });
+    let input = CodeInput {
+        source: job.source,
+        language: job.language,
+        mode: job.mode,
+        inspect_variables: job.inspect_variables,
+        seed: job.seed,
+        stdin: job.stdin,
+        env: job.env,
+        files: job.files,
+        globals: job.globals,
+        return_variables: job.return_variables,
+        document: None,
+        opt_level: job.opt_level,
+        strict: job.strict,
+        verbose: job.verbose,
+        session: None,
+        limits: ExecutionLimits::default(),
+        profile: None,
+        record: false,
+        replay_clock: None,
+        locale: job.locale,
+    };
+
+    Ok((input, policy, limits))
+}
+
+fn execute(input: CodeInput, policy: SandboxPolicy, limits: Option<WorkerLimits>) -> CodeOutput {
+    let locale = i18n::negotiate(input.locale.as_deref(), None);
+    let job = WorkerJob::new(input, policy);
+    let outcome = match limits {
+        None => run_job(&job),
+        Some(limits) => match worker::run_isolated(&job, &limits) {
+            Ok(output) => Ok(output),
+            Err(WorkerError::Timeout(ms)) => Ok(CodeOutput::failure(
+                ErrorKind::Timeout,
+                format!("Execution timed out after {} ms", ms),
+            )),
+            Err(WorkerError::Cancelled) => Ok(CodeOutput::failure(ErrorKind::Runtime, "Execution cancelled".to_string())),
+            Err(WorkerError::Failed(message)) => Err(message),
+        },
+    };
+
+    let mut output = outcome.unwrap_or_else(|message| {
+        CodeOutput::failure(ErrorKind::Internal, format!("Internal compiler error: {}", message))
+    });
+    if let Some(locale) = locale {
+        output.localize(locale);
+    }
+    output
+}
+
+fn exit_code(output: &CodeOutput) -> i32 {
+    match output.error_details.as_ref().map(|details| details.kind) {
+        None => EXIT_SUCCESS,
+        Some(ErrorKind::Lexical | ErrorKind::Syntax | ErrorKind::Compile | ErrorKind::Runtime) => EXIT_PROGRAM_ERROR,
+        Some(ErrorKind::InvalidInput | ErrorKind::Unauthorized | ErrorKind::NotFound | ErrorKind::Conflict | ErrorKind::QuotaExceeded) => EXIT_INVALID_INPUT,
+        Some(ErrorKind::Timeout) => EXIT_TIMEOUT,
+        Some(ErrorKind::Internal) => EXIT_INTERNAL_ERROR,
+    }
+}
+
+// Parses the arguments of a file command and runs it, or prints the usage.
+// `default_path` is used when no path is given; otherwise one is required.
+fn with_file_command(args: &[&str], default_path: Option<&str>, action: impl Fn(&FileCommand) -> i32) -> i32 {
+    match parse_file_command(args, default_path) {
+        Ok(command) if command.watch => watch(&command, &action),
+        Ok(command) => action(&command),
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            EXIT_INVALID_INPUT
+        }
+    }
+}
+
+// Runs the command, then again after every change to the file. Only returns
+// if the file can no longer be watched.
+#[cfg(feature = "watch")]
+fn watch(command: &FileCommand, action: &impl Fn(&FileCommand) -> i32) -> i32 {
+    let path = Path::new(&command.path);
+    // Watch the directory rather than the file, since many editors save by
+    // replacing the file with a new one
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let (sender, receiver) = mpsc::channel();
+    let watching = notify::recommended_watcher(sender)
+        .and_then(|mut watcher| watcher.watch(directory, RecursiveMode::NonRecursive).map(|_| watcher));
+    let _watcher = match watching {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("error: cannot watch {}: {}", command.path, e);
+            return EXIT_INTERNAL_ERROR;
+        }
+    };
+
+    loop {
+        let code = action(command);
+        if command.message_format == MessageFormat::Human {
+            eprintln!("[watch] exit code {}; waiting for changes to {}", code, command.path);
+        }
+
+        // Block until the file itself changes, then let the changes settle
+        loop {
+            match receiver.recv() {
+                Ok(Ok(event)) if is_change_to(&event, path) => break,
+                Ok(_) => continue,
+                Err(_) => return EXIT_INTERNAL_ERROR,
+            }
+        }
+        while receiver.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+
+        if command.message_format == MessageFormat::Human {
+            eprintln!("\n[watch] {} changed, running again", command.path);
+        }
+    }
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_command: &FileCommand, _action: &impl Fn(&FileCommand) -> i32) -> i32 {
+    eprintln!("This build cannot watch files; build with `--features watch`");
+    EXIT_INVALID_INPUT
+}
+
+// Whether an event changes the contents of `path`. Reads of the file, like
+// our own, and metadata updates are ignored.
+#[cfg(feature = "watch")]
+fn is_change_to(event: &notify::Event, path: &Path) -> bool {
+    let relevant_kind = matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+    );
+    relevant_kind && event.paths.iter().any(|changed| changed.file_name() == path.file_name())
+}
+
+fn parse_file_command(args: &[&str], default_path: Option<&str>) -> Result<FileCommand, String> {
+    let mut path = None;
+    let mut output = None;
+    let mut message_format = MessageFormat::Human;
+    let mut watch = false;
+    let mut opt_level = None;
+    let mut strict = false;
+    let mut allow_fs = false;
+    let mut strip = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let format = match arg.strip_prefix("--message-format") {
+            Some("") => Some(*args.next().ok_or("--message-format needs a value")?),
+            Some(value) => Some(value.strip_prefix('=').ok_or_else(|| format!("Unknown option: {}", arg))?),
+            None => None,
+        };
+        match format {
+            Some("human") => message_format = MessageFormat::Human,
+            Some("json") => message_format = MessageFormat::Json,
+            Some(other) => return Err(format!("Unknown message format: {}", other)),
+            None if *arg == "--watch" && default_path.is_none() => watch = true,
+            None if *arg == "--strict" => strict = true,
+            None if *arg == "--allow-fs" => allow_fs = true,
+            None if arg.starts_with("-O") => {
+                let level = arg[2..].parse().ok().filter(|level| *level <= MAX_OPT_LEVEL);
+                opt_level = Some(level.ok_or_else(|| format!("Unknown optimization level: {}", arg))?);
+            }
+            None if *arg == "--strip" && default_path.is_some() => strip = true,
+            None if *arg == "-o" && default_path.is_some() => {
+                output = Some(args.next().ok_or("-o needs a file name")?.to_string())
+            }
+            None if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            None if path.is_none() => path = Some(arg.to_string()),
+            None => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(FileCommand {
+        path: path.or(default_path.map(str::to_string)).ok_or("Missing FILE")?,
+        output,
+        message_format,
+        watch,
+        opt_level,
+        strict,
+        allow_fs,
+        strip,
+    })
+}
+
+// `check FILE`: compile only, then report lint warnings for the file
+fn check(command: &FileCommand, compiler: &mut IncrementalCompiler) -> i32 {
+    match compile(command, compiler) {
+        Ok(_) => {
+            if let Ok(source) = std::fs::read_to_string(&command.path) {
+                for diagnostic in quickfix::lint(&command.path, &source) {
+                    emit(command, &diagnostic);
+                }
+            }
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            report_build_error(command, &e);
+            EXIT_PROGRAM_ERROR
+        }
+    }
+}
+
+// `run FILE`: compile and run a source file, or run a built artifact, with the
+// default sandbox policy, except that the file builtins are refused unless
+// `--allow-fs` maps them onto the real file system
+fn run_file(command: &FileCommand, compiler: &mut IncrementalCompiler, stdin: &str) -> i32 {
+    let bytes = match std::fs::read(&command.path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", command.path, e);
+            return EXIT_INVALID_INPUT;
+        }
+    };
+
+    let artifact = if Artifact::is_artifact(&bytes) {
+        match Artifact::load(&bytes) {
+            Ok(artifact) => artifact,
+            Err(e) => {
+                report(command, &command.path, &ErrorDetails::from_error(e.as_ref()));
+                return EXIT_INVALID_INPUT;
+            }
+        }
+    } else {
+        match compile(command, compiler) {
+            Ok((instructions, debug)) => Artifact {
+                opt_level: command.opt_level.unwrap_or(0),
+                limits: Limits::default(),
+                max_stack: verifier::max_stack_depth(&instructions).unwrap_or(None),
+                instructions,
+                // Errors in the file are reported against its source, which is at hand
+                debug: Some(debug),
+            },
+            Err(e) => {
+                report_build_error(command, &e);
+                return EXIT_PROGRAM_ERROR;
+            }
+        }
+    };
+
+    let output = catch_panics(|| run_artifact(&artifact, &file_input(stdin), vm_config(command))).unwrap_or_else(
+        |message| CodeOutput::failure(ErrorKind::Internal, format!("Internal compiler error: {}", message)),
+    );
+    print!("{}", output.result);
+    let _ = std::io::stdout().flush();
+    if let Some(details) = &output.error_details {
+        report(command, &command.path, details);
+    }
+    // A program that ran exits with the code it gave to exit()
+    match (&output.error_details, output.exit_code) {
+        (None, Some(code)) => code,
+        _ => exit_code(&output),
+    }
+}
+
+// Input of a run of `run` or `test`, reading `stdin`
+fn file_input(stdin: &str) -> CodeInput {
+    CodeInput {
+        source: String::new(),
+        language: String::new(),
+        mode: Mode::Program,
+        inspect_variables: false,
+        seed: None,
+        stdin: stdin.to_string(),
+        env: BTreeMap::new(),
+        files: BTreeMap::new(),
+        globals: BTreeMap::new(),
+        return_variables: Vec::new(),
+        document: None,
+        opt_level: 0, // The artifact is already optimized
+        strict: StrictMode::default(),
+        verbose: false,
+        session: None,
+        limits: ExecutionLimits::default(),
+        profile: None,
+        record: false,
+        replay_clock: None,
+        locale: None,
+    }
+}
+
+// VM of `run` and `test`, see `run_file`
+fn vm_config(command: &FileCommand) -> VmConfig {
+    if command.allow_fs {
+        VmConfig {
+            files: FileSystem::Host,
+            ..VmConfig::default()
+        }
+    } else {
+        VmConfig {
+            policy: SandboxPolicy { allow_fs: false, ..SandboxPolicy::default() },
+            ..VmConfig::default()
+        }
+    }
+}
+
+// `test FILE`: run each test block of a file and its imports, see `testing`
+fn test(command: &FileCommand, stdin: &str) -> i32 {
+    let path = Path::new(&command.path);
+    let strictness = if command.strict { Strictness::ALL } else { Strictness::default() };
+    let compiled = project::load_program(path, &[], strictness).and_then(|program| {
+        testing::compile(program, command.opt_level.unwrap_or(0))
+            .map_err(|error| BuildError { file: path.to_path_buf(), error })
+    });
+    let tests = match compiled {
+        Ok(tests) => tests,
+        Err(e) => {
+            report_build_error(command, &e);
+            return EXIT_PROGRAM_ERROR;
+        }
+    };
+
+    let mut failures = Vec::new();
+    let mut passed = 0;
+    for test in tests {
+        let output = catch_panics(|| run_artifact(&test.artifact, &file_input(stdin), vm_config(command))).unwrap_or_else(
+            |message| CodeOutput::failure(ErrorKind::Internal, format!("Internal compiler error: {}", message)),
+        );
+        let result = TestResult::new(test.name, output);
+        match command.message_format {
+            MessageFormat::Json => println!("{}", serde_json::to_string(&result).unwrap_or_default()),
+            MessageFormat::Human => println!("test {} ... {}", result.name, if result.passed { "ok" } else { "FAILED" }),
+        }
+        if result.passed {
+            passed += 1;
+        } else {
+            failures.push(result);
+        }
+    }
+
+    if command.message_format == MessageFormat::Human {
+        if !failures.is_empty() {
+            println!("\nfailures:");
+        }
+        for failure in &failures {
+            println!("\n---- {} ----", failure.name);
+            print!("{}", failure.output);
+            if !failure.output.is_empty() && !failure.output.ends_with('\n') {
+                println!();
+            }
+            match (&failure.error_details, failure.exit_code) {
+                (Some(details), _) => println!("error: {}", details.message),
+                (None, code) => println!("exited with code {}", code.unwrap_or_default()),
+            }
+        }
+        println!(
+            "\ntest result: {}. {} passed; {} failed",
+            if failures.is_empty() { "ok" } else { "FAILED" },
+            passed,
+            failures.len()
+        );
+    }
+    if failures.is_empty() { EXIT_SUCCESS } else { EXIT_PROGRAM_ERROR }
+}
+
+// `explain CODE`: the explanation of an error code
+fn explain_code(code: &str) -> i32 {
+    match registry::lookup(code) {
+        Some(entry) => {
+            print!("{}", registry::to_text(entry));
+            EXIT_SUCCESS
+        }
+        None => {
+            eprintln!("Unknown error code '{}'", code);
+            EXIT_INVALID_INPUT
+        }
+    }
+}
+
+// `explain FILE`: the bytecode before optimization, then after each pass of
+// the optimization level
+fn explain(command: &FileCommand) -> i32 {
+    let mut code = match compile_unoptimized(command, &mut IncrementalCompiler::new()) {
+        Ok((code, _)) => code,
+        Err(e) => {
+            report_build_error(command, &e);
+            return EXIT_PROGRAM_ERROR;
+        }
+    };
+
+    println!("bytecode ({} instructions):", code.len());
+    print_listing(&code);
+    for pass in optimizer::passes(command.opt_level.unwrap_or(MAX_OPT_LEVEL)) {
+        let before: Vec<String> = code.iter().map(|instruction| format!("{:?}", instruction)).collect();
+        let mut log = Vec::new();
+        code = optimizer::run_pass(*pass, code, &mut log);
+        println!("\n{}:", pass.name());
+        for line in &log {
+            println!("  {}", line);
+        }
+        let after: Vec<String> = code.iter().map(|instruction| format!("{:?}", instruction)).collect();
+        if after == before {
+            println!("  (bytecode unchanged)");
+        } else {
+            println!("bytecode after {} ({} instructions):", pass.name(), code.len());
+            print_listing(&code);
+        }
+    }
+    EXIT_SUCCESS
+}
+
+fn print_listing(code: &[Instruction]) {
+    for (index, instruction) in code.iter().enumerate() {
+        println!("{:>6}  {:?}", index, instruction);
+    }
+}
+
+// Compiles a source file and its imports, then optimizes the bytecode. In
+// watch mode the compiler keeps the code of unchanged top-level items from
+// the previous run.
+fn compile(command: &FileCommand, compiler: &mut IncrementalCompiler) -> Result<(Vec<Instruction>, DebugInfo), BuildError> {
+    let (instructions, mut debug) = compile_unoptimized(command, compiler)?;
+    let opt_level = command.opt_level.unwrap_or(0);
+    // Optimization moves code between statements, so only the locals stay meaningful
+    if opt_level > 0 {
+        debug.lines.clear();
+    }
+    Ok((optimizer::optimize(instructions, opt_level), debug))
+}
+
+// Compiles a source file and its imports, with the debug information that
+// tells where a runtime error happened. Statements of the file itself have no
+// file name: their errors are located by line, those of imports in the message.
+fn compile_unoptimized(command: &FileCommand, compiler: &mut IncrementalCompiler) -> Result<(Vec<Instruction>, DebugInfo), BuildError> {
+    let path = Path::new(&command.path);
+    let strictness = if command.strict { Strictness::ALL } else { Strictness::default() };
+    let (program, lines) = project::load_program_with_lines(path, &[], strictness)?;
+    let (module, report) = compiler
+        .compile(program)
+        .map_err(|error| BuildError { file: path.to_path_buf(), error })?;
+
+    if command.watch && command.message_format == MessageFormat::Human {
+        eprintln!("[watch] {}", report);
+    }
+    let debug = DebugInfo {
+        source_hash: 0,
+        locals: DebugInfo::locals_of(&module),
+        lines: project::line_table(&module, &lines, |file| {
+            if file == path { String::new() } else { file.display().to_string() }
+        }),
+    };
+    Ok((link(&module), debug))
+}
+
+// `analyze FILE`: metrics of the file alone, without its imports
+fn analyze(command: &FileCommand) -> i32 {
+    let source = match std::fs::read_to_string(&command.path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", command.path, e);
+            return EXIT_INVALID_INPUT;
+        }
+    };
+    let metrics = match metrics::analyse(&source) {
+        Ok(metrics) => metrics,
+        Err(error) => {
+            report_build_error(command, &BuildError { file: PathBuf::from(&command.path), error });
+            return EXIT_PROGRAM_ERROR;
+        }
+    };
+
+    match command.message_format {
+        MessageFormat::Json => println!("{}", serde_json::to_string(&metrics).unwrap_or_default()),
+        MessageFormat::Human => print_metrics(&command.path, &metrics),
+    }
+    EXIT_SUCCESS
+}
+
+fn print_metrics(path: &str, metrics: &Metrics) {
+    println!(
+        "{}: {} lines, {} statements, cyclomatic complexity {}, nesting depth {}",
+        path, metrics.lines, metrics.statements, metrics.cyclomatic_complexity, metrics.max_nesting_depth
+    );
+    println!("\n{:<9}  {:<11}  {:<12}  {:>10}  {:>10}  {:>5}", "lines", "kind", "name", "statements", "complexity", "depth");
+    for item in &metrics.items {
+        println!(
+            "{:<9}  {:<11}  {:<12}  {:>10}  {:>10}  {:>5}",
+            format!("{}-{}", item.line, item.end_line),
+            item.kind,
+            item.name.as_deref().unwrap_or("-"),
+            item.statements,
+            item.cyclomatic_complexity,
+            item.nesting_depth
+        );
+    }
+}
+
+// `build [DIR]`: compile the project in DIR into an artifact
+fn build(command: &FileCommand) -> i32 {
+    let root = Path::new(&command.path);
+    let built = Project::load(root).and_then(|mut project| {
+        if let Some(opt_level) = command.opt_level {
+            project.manifest.project.opt_level = opt_level;
+        }
+        if command.strict {
+            project.manifest.project.strict = StrictMode::All(true);
+        }
+        if command.strip {
+            project.manifest.project.strip = true;
+        }
+        project.build().map(|artifact| (project, artifact))
+    });
+    let (project, artifact) = match built {
+        Ok(built) => built,
+        Err(e) => {
+            report_build_error(command, &e);
+            return EXIT_PROGRAM_ERROR;
+        }
+    };
+
+    let output = match &command.output {
+        Some(output) => PathBuf::from(output),
+        None => root.join(BUILD_DIR).join(format!("{}.bc", project.manifest.project.name)),
+    };
+    let written = output
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&output, artifact.encode()));
+    if let Err(e) = written {
+        eprintln!("error: cannot write {}: {}", output.display(), e);
+        return EXIT_INTERNAL_ERROR;
+    }
+
+    if command.message_format == MessageFormat::Human {
+        eprintln!(
+            "Built {} ({} instructions)",
+            output.display(),
+            artifact.instructions.len()
+        );
+    }
+    EXIT_SUCCESS
+}
+
+// Build errors never come from running the program, so errors that are not
+// lexical or syntax errors are reported as compile errors
+fn report_build_error(command: &FileCommand, error: &BuildError) {
+    let mut details = ErrorDetails::from_error(error.error.as_ref());
+    if details.kind == ErrorKind::Runtime {
+        details = ErrorDetails::new(ErrorKind::Compile, details.message);
+    }
+    report(command, &error.file.display().to_string(), &details);
+}
+
+fn report(command: &FileCommand, file: &str, details: &ErrorDetails) {
+    let mut diagnostic = Diagnostic::from_details(file, details);
+    if let Ok(source) = std::fs::read_to_string(file) {
+        if details.kind == ErrorKind::Syntax {
+            let tokens = Lexer::new(&source).tokenize().unwrap_or_default();
+            diagnostic.fixes = quickfix::fixes_for_error(&source, &tokens, details);
+        }
+        diagnostic.render(&source);
+    }
+    emit(command, &diagnostic);
+}
+
+fn emit(command: &FileCommand, diagnostic: &Diagnostic) {
+    match command.message_format {
+        MessageFormat::Human => eprintln!("{}", diagnostic),
+        MessageFormat::Json => println!("{}", diagnostic.to_json()),
+    }
+}
@@ -0,0 +1,313 @@
+// Completion candidates for the web editor. The source before the cursor is
+// usually incomplete, so it is not parsed into a syntax tree: its tokens are
+// scanned for declarations and braces instead, which tolerates unfinished
+// statements. Characters the lexer rejects are skipped and an unterminated
+// string on an earlier line is closed, so one typo doesn't disable completion.
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::builtins;
+use crate::lexer::{Lexer, LexerError, Token, TokenType};
+use crate::{ErrorDetails, ErrorKind};
+
+/// Keywords offered at the start of a statement.
+const KEYWORDS: &[&str] = &["int", "float", "if", "else", "while", "return", "defer", "break", "continue"];
+
+/// Keywords only valid at the top level of a file.
+const TOP_LEVEL_KEYWORDS: &[&str] = &["import", "fn", "test"];
+
+/// Upper bound on lexer errors skipped before giving up.
+const MAX_RECOVERIES: usize = 100;
+
+// Request body of POST /complete
+#[derive(Deserialize, ToSchema)]
+pub struct CompletionInput {
+    source: String,
+    cursor: usize, // Offset of the cursor in characters (not bytes) from the start of the source
+}
+
+// What a completion inserts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionKind {
+    Variable,
+    Function,
+    Keyword,
+}
+
+/// One completion candidate.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String, // Type of a variable, signature of a function, or "keyword"
+}
+
+// Response of POST /complete
+#[derive(Serialize, ToSchema)]
+pub struct CompletionOutput {
+    prefix: String, // Partial identifier before the cursor that the candidates extend
+    items: Vec<Completion>,
+    error_details: Option<ErrorDetails>,
+}
+
+/// Completion candidates at `cursor`, a character offset into `source`, along
+/// with the partial identifier they complete. Candidates are variables and
+/// parameters in scope (innermost first), then the functions of the program,
+/// then builtin functions, then keywords.
+pub fn complete(source: &str, cursor: usize) -> (String, Vec<Completion>) {
+    let before: String = source.chars().take(cursor).collect();
+    let prefix_len = before
+        .chars()
+        .rev()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .count();
+    let prefix: String = before.chars().skip(before.chars().count() - prefix_len).collect();
+
+    // Numbers are not completed
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return (prefix, Vec::new());
+    }
+    let Some(mut tokens) = lex_recovering(&before) else {
+        return (prefix, Vec::new());
+    };
+    if in_line_comment(&before, &tokens) {
+        return (prefix, Vec::new());
+    }
+
+    tokens.pop(); // EOF
+    if !prefix.is_empty() {
+        tokens.pop(); // The partial identifier itself
+    }
+
+    // Declared names, newest last, one list per enclosing block. The
+    // parameters of a function are a scope around its body.
+    let mut scopes: Vec<Vec<(String, String)>> = vec![Vec::new()];
+    let mut parameters: Option<usize> = None; // Depth of the scope of the parameters
+    let mut in_parameters = false;
+    for (index, token) in tokens.iter().enumerate() {
+        match &token.token_type {
+            TokenType::LeftParen if index >= 2 && tokens[index - 2].token_type == TokenType::Fn => {
+                scopes.push(Vec::new());
+                parameters = Some(scopes.len());
+                in_parameters = true;
+            }
+            TokenType::RightParen => in_parameters = false,
+            TokenType::LeftBrace => scopes.push(Vec::new()),
+            TokenType::RightBrace if scopes.len() > 1 => {
+                scopes.pop();
+                if parameters == Some(scopes.len()) {
+                    scopes.pop();
+                    parameters = None;
+                }
+            }
+            TokenType::Identifier(name) if in_parameters => {
+                if let Some(scope) = scopes.last_mut() {
+                    scope.push((name.to_string(), "parameter".to_string()));
+                }
+            }
+            TokenType::Identifier(name) if index > 0 => {
+                let var_type = match tokens[index - 1].token_type {
+                    TokenType::Int => "int",
+                    TokenType::Float => "float",
+                    _ => continue,
+                };
+                if let Some(scope) = scopes.last_mut() {
+                    scope.push((name.to_string(), var_type.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let previous = tokens.last().map(|token| &token.token_type);
+    // A new name is being declared, or an import path is expected
+    if in_parameters || matches!(previous, Some(TokenType::Int | TokenType::Float | TokenType::Fn | TokenType::Import)) {
+        return (prefix, Vec::new());
+    }
+    let statement_start = matches!(
+        previous,
+        None | Some(TokenType::Semicolon | TokenType::LeftBrace | TokenType::RightBrace | TokenType::Else)
+    );
+
+    let mut items: Vec<Completion> = Vec::new();
+    for scope in scopes.iter().rev() {
+        for (name, var_type) in scope.iter().rev() {
+            if name.starts_with(&prefix) && !items.iter().any(|item| &item.label == name) {
+                items.push(Completion {
+                    label: name.clone(),
+                    kind: CompletionKind::Variable,
+                    detail: var_type.clone(),
+                });
+            }
+        }
+    }
+
+    // Functions can be called before their declaration, so the whole source
+    // is scanned for them
+    let functions = match lex_recovering(source) {
+        Some(all) => declared_functions(&all),
+        None => declared_functions(&tokens),
+    };
+    for (name, signature) in functions {
+        if name.starts_with(&prefix) && !items.iter().any(|item| item.label == name) {
+            items.push(Completion {
+                label: name,
+                kind: CompletionKind::Function,
+                detail: signature,
+            });
+        }
+    }
+
+    for builtin in builtins::all() {
+        if builtin.name.starts_with(&prefix) {
+            items.push(Completion {
+                label: builtin.name.to_string(),
+                kind: CompletionKind::Function,
+                detail: builtin.signature.to_string(),
+            });
+        }
+    }
+
+    if statement_start {
+        let top_level = if scopes.len() == 1 { TOP_LEVEL_KEYWORDS } else { &[] };
+        for keyword in KEYWORDS.iter().chain(top_level) {
+            if keyword.starts_with(&prefix) {
+                items.push(Completion {
+                    label: keyword.to_string(),
+                    kind: CompletionKind::Keyword,
+                    detail: "keyword".to_string(),
+                });
+            }
+        }
+    }
+
+    (prefix, items)
+}
+
+// Names and signatures of the functions declared at the top level
+fn declared_functions(tokens: &[Token]) -> Vec<(String, String)> {
+    let mut functions = Vec::new();
+    let mut depth = 0usize;
+    for (index, token) in tokens.iter().enumerate() {
+        match &token.token_type {
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth = depth.saturating_sub(1),
+            TokenType::Fn if depth == 0 => {
+                let Some(TokenType::Identifier(name)) = tokens.get(index + 1).map(|t| &t.token_type) else {
+                    continue;
+                };
+                let params: Vec<&str> = tokens[index + 2..]
+                    .iter()
+                    .take_while(|token| !matches!(token.token_type, TokenType::RightParen | TokenType::LeftBrace))
+                    .filter_map(|token| match &token.token_type {
+                        TokenType::Identifier(param) => Some(param.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                functions.push((name.to_string(), format!("fn {}({})", name, params.join(", "))));
+            }
+            _ => {}
+        }
+    }
+    functions
+}
+
+// Tokenizes text that may contain errors. Returns None when the text ends
+// inside a string literal or block comment, where nothing should be completed.
+fn lex_recovering(text: &str) -> Option<Vec<Token<'static>>> {
+    let mut chars: Vec<char> = text.chars().collect();
+    for _ in 0..MAX_RECOVERIES {
+        let source: String = chars.iter().collect();
+        let error = match Lexer::new(&source).tokenize() {
+            Ok(tokens) => return Some(tokens.into_iter().map(Token::into_owned).collect()),
+            Err(error) => error,
+        };
+        let (line, column) = error.downcast_ref::<LexerError>().map(|e| (e.line(), e.column()))?;
+        let offset = char_offset(&chars, line, column)?;
+        match chars.get(offset) {
+            None => return None,                     // Unterminated at the end of the text
+            Some('\n') => chars.insert(offset, '"'), // String left open on an earlier line
+            Some(_) => chars[offset] = ' ',          // Unexpected character
+        }
+    }
+    None
+}
+
+// Converts a 1-based line and column into an offset into `chars`
+fn char_offset(chars: &[char], line: usize, column: usize) -> Option<usize> {
+    let line_start = if line == 1 {
+        0
+    } else {
+        chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == '\n')
+            .nth(line - 2)
+            .map(|(index, _)| index + 1)?
+    };
+    Some(line_start + column.checked_sub(1)?)
+}
+
+// Whether the end of the text is inside a `//` comment on its last line
+fn in_line_comment(text: &str, tokens: &[Token]) -> bool {
+    let line = text.matches('\n').count() + 1;
+    let current: Vec<char> = text.rsplit('\n').next().unwrap_or("").chars().collect();
+
+    // Columns (0-based, end exclusive) covered by string literals on the line
+    let strings: Vec<(usize, usize)> = tokens
+        .iter()
+        .filter(|token| token.line == line)
+        .filter_map(|token| match &token.token_type {
+            TokenType::StringLiteral(_) => {
+                // The source of the literal, quotes and escapes included
+                let literal = text.get(token.offset..token.offset + token.length)?;
+                Some((token.column - 1, token.column - 1 + literal.chars().count()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    current
+        .windows(2)
+        .enumerate()
+        .any(|(index, pair)| pair == ['/', '/'] && !strings.iter().any(|(start, end)| (*start..*end).contains(&index)))
+}
+
+// Route handler for POST /complete
+#[utoipa::path(
+    tag = "compiler",
+    request_body = CompletionInput,
+    responses(
+        (status = 200, description = "Completion candidates at the cursor", body = CompletionOutput),
+        (status = 422, description = "The cursor is past the end of the source", body = CompletionOutput),
+    )
+)]
+#[post("/complete")]
+pub async fn complete_at(input: web::Json<CompletionInput>) -> impl Responder {
+    let length = input.source.chars().count();
+    if input.cursor > length {
+        let details = ErrorDetails {
+            kind: ErrorKind::InvalidInput,
+            code: None,
+            message: format!("Cursor {} is past the end of the source ({} characters)", input.cursor, length),
+            line: None,
+            column: None,
+            rendered: None,
+        };
+        return HttpResponse::build(details.kind.status()).json(CompletionOutput {
+            prefix: String::new(),
+            items: Vec::new(),
+            error_details: Some(details),
+        });
+    }
+
+    let (prefix, items) = complete(&input.source, input.cursor);
+    HttpResponse::Ok().json(CompletionOutput {
+        prefix,
+        items,
+        error_details: None,
+    })
+}
@@ -0,0 +1,673 @@
+// Step debugger for the web editor and debug adapters. POST /debug compiles a
+// program and pauses it before its first instruction; POST /debug/{id} then
+// runs commands on it, named as in the Debug Adapter Protocol, and DELETE
+// /debug/{id} ends the session. Besides breakpoints on instructions, which
+// may carry a condition, a session can watch variables and pause after every
+// instruction that writes one of them. Locals of blocks and functions are
+// shown, watched and read by conditions under their source names, those of
+// the code running hiding globals of the same name.
+//
+// Going back (`stepBack`, `reverseContinue`) is time travel: the session keeps
+// a snapshot of the VM every SNAPSHOT_INTERVAL instructions and logs where
+// every step ran, so it restores the last snapshot before the target and runs
+// forward to it. Running over steps already taken replays the clock readings
+// recorded the first time, and random() continues from the generator state
+// in the snapshot, so the program goes through the same states again.
+
+use actix_web::{delete, post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::lexer::Dialect;
+use crate::optimizer;
+use crate::pipeline::{Compilation, PassManager};
+use crate::semantic::Strictness;
+use crate::vfs::FileSystem;
+use crate::vm::{ClockRead, Instruction, VariableSnapshot, VirtualMachine, VmConfig};
+use crate::{compile_source, ErrorDetails, ErrorKind, ServerConfig, MAX_SNAPSHOT_VALUE_LEN};
+
+/// Steps between two snapshots of the VM.
+const SNAPSHOT_INTERVAL: usize = 256;
+
+/// Instructions a debugged program may run; its history is kept in memory.
+const MAX_DEBUG_STEPS: u64 = 100_000;
+
+/// Bytes of strings a debugged program may hold, as each snapshot copies them.
+const MAX_DEBUG_HEAP_BYTES: usize = 256 * 1024;
+
+/// Sessions kept at most; the least recently used is ended beyond this.
+const MAX_DEBUG_SESSIONS: usize = 32;
+
+// Source name of the local held by each internal variable of a program
+type Locals = HashMap<String, String>;
+
+/// The debug sessions of all clients, by id.
+#[derive(Default)]
+pub struct DebugStore {
+    next_id: AtomicU64,
+    inner: Mutex<Sessions>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    entries: HashMap<String, Arc<Mutex<DebugSession>>>,
+    order: VecDeque<String>, // Ids from the least to the most recently used
+}
+
+struct DebugSession {
+    code: Vec<Instruction>,
+    locals: Locals,
+    vm: VirtualMachine,
+    breakpoints: BTreeMap<usize, Option<Condition>>, // By instruction index
+    watchpoints: BTreeSet<String>,                  // Names of watched variables
+    trace: Vec<usize>,                              // Instruction run by each step so far
+    snapshots: Vec<(usize, VirtualMachine)>,        // VM before the step at a position, from position 0
+    clock: Vec<ClockRead>,                          // Clock readings of the furthest run
+    replaying: bool,                                // Whether the VM replays `clock`
+    exception: Option<ErrorDetails>,                // Error raised by the next instruction
+    reason: StopReason,
+}
+
+// Condition of a breakpoint, compiled like a program
+struct Condition {
+    source: String,
+    code: Vec<Instruction>,
+}
+
+/// A breakpoint: the index of the instruction to pause before, and an
+/// optional expression that must hold for it to pause.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Breakpoint {
+    At(usize),
+    When { index: usize, condition: String },
+}
+
+/// Why the program is paused where it is.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    Entry,      // Before the first instruction
+    Step,       // After a step forward or back
+    Breakpoint, // At a breakpoint
+    Watchpoint, // After a write to a watched variable
+    End,        // The program has finished
+    Exception,  // The next instruction fails
+}
+
+/// Commands of POST /debug/{id}.
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DebugCommand {
+    Step,               // Run one instruction
+    StepBack,           // Undo the last instruction
+    Continue,           // Run until a breakpoint, a watched write, the end or an error
+    ReverseContinue,    // Go back to the last breakpoint or watched write passed, or to the entry
+    SetBreakpoints,     // Replace the breakpoints
+    SetDataBreakpoints, // Replace the watched variables
+}
+
+// Request body of POST /debug
+#[derive(Deserialize, ToSchema)]
+pub struct DebugStartInput {
+    source: String,
+    #[serde(default)]
+    opt_level: u8,
+    #[serde(default)]
+    stdin: String,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    breakpoints: Vec<Breakpoint>,
+    #[serde(default)]
+    watchpoints: Vec<String>, // Variables to pause after writes to
+}
+
+// Request body of POST /debug/{id}
+#[derive(Deserialize, ToSchema)]
+pub struct DebugCommandInput {
+    command: DebugCommand,
+    #[serde(default)]
+    breakpoints: Vec<Breakpoint>, // For `setBreakpoints`
+    #[serde(default)]
+    watchpoints: Vec<String>, // For `setDataBreakpoints`
+}
+
+/// State of a paused program.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DebugState {
+    pub session: String,
+    pub reason: StopReason,
+    pub position: usize,             // Instructions run so far
+    pub ip: usize,                   // Index of the next instruction
+    pub instruction: Option<String>, // The next instruction; null at the end
+    pub stack: Vec<String>,          // Bottom first
+    pub variables: Vec<VariableSnapshot>,
+    pub output: String,              // Printed so far
+    pub result: Option<String>,      // Output of the finished program, as /compile returns it
+    pub exception: Option<ErrorDetails>,
+    pub written: Option<String>,     // The watched variable just written, when paused at a watchpoint
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<String>,
+}
+
+// Response of the /debug endpoints
+#[derive(Serialize, ToSchema)]
+pub struct DebugOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytecode: Option<Vec<String>>, // Instructions the indexes refer to, when the session starts
+    state: Option<DebugState>,
+    error_details: Option<ErrorDetails>,
+}
+
+impl DebugOutput {
+    fn failure(details: ErrorDetails) -> HttpResponse {
+        HttpResponse::build(details.kind.status()).json(DebugOutput {
+            bytecode: None,
+            state: None,
+            error_details: Some(details),
+        })
+    }
+}
+
+impl DebugStore {
+    fn insert(&self, session: DebugSession) -> String {
+        let id = (self.next_id.fetch_add(1, Ordering::Relaxed) + 1).to_string();
+        let mut sessions = self.lock();
+        if sessions.order.len() >= MAX_DEBUG_SESSIONS {
+            if let Some(oldest) = sessions.order.pop_front() {
+                sessions.entries.remove(&oldest);
+            }
+        }
+        sessions.entries.insert(id.clone(), Arc::new(Mutex::new(session)));
+        sessions.order.push_back(id.clone());
+        id
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Mutex<DebugSession>>> {
+        let mut sessions = self.lock();
+        let session = sessions.entries.get(id)?.clone();
+        sessions.order.retain(|other| other != id);
+        sessions.order.push_back(id.to_string());
+        Some(session)
+    }
+
+    fn remove(&self, id: &str) -> bool {
+        let mut sessions = self.lock();
+        sessions.order.retain(|other| other != id);
+        sessions.entries.remove(id).is_some()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl DebugSession {
+    fn new(
+        code: Vec<Instruction>,
+        locals: Locals,
+        config: VmConfig,
+        breakpoints: BTreeMap<usize, Option<Condition>>,
+        watchpoints: BTreeSet<String>,
+    ) -> Self {
+        let mut vm = VirtualMachine::with_config(config);
+        vm.start(&code);
+        DebugSession {
+            snapshots: vec![(0, vm.clone())],
+            code,
+            locals,
+            vm,
+            breakpoints,
+            watchpoints,
+            trace: Vec::new(),
+            clock: Vec::new(),
+            replaying: false,
+            exception: None,
+            reason: StopReason::Entry,
+        }
+    }
+
+    fn run(&mut self, command: DebugCommand) {
+        self.reason = match command {
+            DebugCommand::Step => match self.step() {
+                Some(true) => StopReason::Step,
+                Some(false) => StopReason::End,
+                None => StopReason::Exception,
+            },
+            DebugCommand::StepBack => {
+                if self.trace.is_empty() {
+                    StopReason::Entry
+                } else {
+                    self.rewind(self.trace.len() - 1);
+                    StopReason::Step
+                }
+            }
+            DebugCommand::Continue => loop {
+                match self.step() {
+                    Some(true) if self.watched_write(self.trace.len()).is_some() => break StopReason::Watchpoint,
+                    Some(true) if self.at_breakpoint() => break StopReason::Breakpoint,
+                    Some(true) => {}
+                    Some(false) => break StopReason::End,
+                    None => break StopReason::Exception,
+                }
+            },
+            DebugCommand::ReverseContinue => {
+                // Conditions are evaluated in the state of the program when
+                // it reached the breakpoint, so go back to each in turn
+                let mut before = self.trace.len();
+                loop {
+                    let stop = (0..before).rev().find_map(|position| {
+                        if self.watched_write(position).is_some() {
+                            Some((position, StopReason::Watchpoint))
+                        } else if self.breakpoints.contains_key(&self.trace[position]) {
+                            Some((position, StopReason::Breakpoint))
+                        } else {
+                            None
+                        }
+                    });
+                    let Some((position, reason)) = stop else {
+                        self.rewind(0);
+                        break StopReason::Entry;
+                    };
+                    self.rewind(position);
+                    if reason == StopReason::Watchpoint || self.at_breakpoint() {
+                        break reason;
+                    }
+                    before = position;
+                }
+            }
+            DebugCommand::SetBreakpoints | DebugCommand::SetDataBreakpoints => self.reason,
+        };
+    }
+
+    // Whether the next instruction has a breakpoint whose condition holds. A
+    // condition that fails, e.g. on a variable not declared yet, doesn't hold.
+    fn at_breakpoint(&self) -> bool {
+        match self.breakpoints.get(&self.vm.ip()) {
+            Some(Some(condition)) => {
+                let locals = self.locals_in_scope();
+                let code: Vec<Instruction> = condition
+                    .code
+                    .iter()
+                    .map(|instruction| match instruction {
+                        Instruction::LoadVariable(name) if locals.contains_key(name.as_str()) => {
+                            Instruction::LoadVariable(locals[name.as_str()].clone())
+                        }
+                        _ => instruction.clone(),
+                    })
+                    .collect();
+                matches!(self.vm.evaluate(&code), Ok(Some(value)) if value.is_truthy())
+            }
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // The watched variable written by the last step before `position`, if any
+    fn watched_write(&self, position: usize) -> Option<&String> {
+        let ip = *self.trace.get(position.checked_sub(1)?)?;
+        match &self.code[ip] {
+            Instruction::StoreVariable(stored) => self.watchpoints.get(self.locals.get(stored).unwrap_or(stored)),
+            _ => None,
+        }
+    }
+
+    // The locals of the code at the instruction pointer that have a value, by
+    // source name, with the internal variable holding each. Slots are taken in
+    // the order locals are declared, so of two locals of the same name the
+    // one in the later slot is the inner one, which hides the other.
+    fn locals_in_scope(&self) -> BTreeMap<&str, &String> {
+        let mut slots: Vec<(&str, &String)> = self
+            .vm
+            .variables_in_scope()
+            .into_iter()
+            .filter_map(|variable| Some((self.locals.get(variable)?.as_str(), variable)))
+            .collect();
+        slots.sort_by_key(|(_, variable)| slot_of(variable));
+        slots.into_iter().collect()
+    }
+
+    // The globals and, under their source names, the locals in scope
+    fn variables(&self) -> Vec<VariableSnapshot> {
+        let locals = self.locals_in_scope();
+        let mut variables: Vec<VariableSnapshot> = self
+            .vm
+            .snapshot_variables(MAX_SNAPSHOT_VALUE_LEN)
+            .into_iter()
+            .filter(|variable| !locals.contains_key(variable.name.as_str()))
+            .collect();
+        variables.extend(locals.iter().filter_map(|(name, variable)| {
+            Some(VariableSnapshot::new(name.to_string(), self.vm.variable(variable)?, MAX_SNAPSHOT_VALUE_LEN))
+        }));
+        variables.sort_by(|a, b| a.name.cmp(&b.name));
+        variables
+    }
+
+    // Runs the next instruction: Some(false) once the program has ended, None
+    // when the instruction fails
+    fn step(&mut self) -> Option<bool> {
+        if self.exception.is_some() {
+            return None;
+        }
+
+        // Replay the clock while going over steps taken before
+        let replay = self.vm.recording().clock_reads.len() < self.clock.len();
+        if replay != self.replaying {
+            self.vm.set_replay_clock(replay.then(|| self.clock.clone()));
+            self.replaying = replay;
+        }
+
+        let ip = self.vm.ip();
+        match self.vm.step(&self.code) {
+            Ok(false) => Some(false),
+            Ok(true) => {
+                self.trace.push(ip);
+                let reads = &self.vm.recording().clock_reads;
+                if reads.len() > self.clock.len() {
+                    self.clock.extend_from_slice(&reads[self.clock.len()..]);
+                }
+                let position = self.trace.len();
+                let taken = self.snapshots.last().is_some_and(|(last, _)| *last >= position);
+                if position.is_multiple_of(SNAPSHOT_INTERVAL) && !taken {
+                    self.snapshots.push((position, self.vm.clone()));
+                }
+                Some(true)
+            }
+            Err(e) => {
+                // The failed instruction may have popped operands; show the
+                // state before it
+                let details = ErrorDetails::from_error(e.as_ref());
+                self.rewind(self.trace.len());
+                self.exception = Some(details);
+                None
+            }
+        }
+    }
+
+    // Goes back to the state after `target` steps: restores the last snapshot
+    // before it and runs forward
+    fn rewind(&mut self, target: usize) {
+        let (position, snapshot) = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(position, _)| *position <= target)
+            .unwrap_or(&self.snapshots[0]);
+        self.trace.truncate(*position);
+        self.vm = snapshot.clone();
+        self.vm.set_replay_clock(None);
+        self.replaying = false;
+        self.exception = None;
+        while self.trace.len() < target && self.step() == Some(true) {}
+    }
+
+    fn state(&self, id: &str) -> DebugState {
+        let ip = self.vm.ip();
+        let result = (ip >= self.code.len() && self.exception.is_none()).then(|| {
+            // Finishing adds the final value to the output; keep the VM as it is
+            let mut finished = self.vm.clone();
+            finished.finish().unwrap_or_default()
+        });
+        DebugState {
+            session: id.to_string(),
+            reason: self.reason,
+            position: self.trace.len(),
+            ip,
+            instruction: self.code.get(ip).map(|instruction| format!("{:?}", instruction)),
+            stack: self.vm.stack().iter().map(|value| value.to_string()).collect(),
+            variables: self.variables(),
+            output: self.vm.printed().to_string(),
+            result,
+            exception: self.exception.clone(),
+            written: match self.reason {
+                StopReason::Watchpoint => self.watched_write(self.trace.len()).cloned(),
+                _ => None,
+            },
+            breakpoints: self
+                .breakpoints
+                .iter()
+                .map(|(index, condition)| match condition {
+                    Some(condition) => Breakpoint::When {
+                        index: *index,
+                        condition: condition.source.clone(),
+                    },
+                    None => Breakpoint::At(*index),
+                })
+                .collect(),
+            watchpoints: self.watchpoints.iter().cloned().collect(),
+        }
+    }
+}
+
+// Breakpoints given in a request, checked against the program, with their
+// conditions compiled
+fn breakpoints(
+    list: &[Breakpoint],
+    code: &[Instruction],
+) -> Result<BTreeMap<usize, Option<Condition>>, ErrorDetails> {
+    let invalid = |message: String| ErrorDetails {
+        kind: ErrorKind::InvalidInput,
+        code: None,
+        message,
+        line: None,
+        column: None,
+        rendered: None,
+    };
+    let mut breakpoints = BTreeMap::new();
+    for breakpoint in list {
+        let (index, condition) = match breakpoint {
+            Breakpoint::At(index) => (*index, None),
+            Breakpoint::When { index, condition } => (*index, Some(condition)),
+        };
+        if index >= code.len() {
+            return Err(invalid(format!(
+                "Breakpoint {} is past the last instruction ({})",
+                index,
+                code.len().saturating_sub(1)
+            )));
+        }
+        let condition = match condition {
+            Some(source) => {
+                let code = compile_source(&format!("{};", source)).map_err(|e| {
+                    invalid(format!("Condition of breakpoint {} does not compile: {}", index, e))
+                })?;
+                Some(Condition { source: source.clone(), code })
+            }
+            None => None,
+        };
+        breakpoints.insert(index, condition);
+    }
+    Ok(breakpoints)
+}
+
+// Slot of an internal variable named by `local_variable`, e.g. 3 for <local1.3>
+fn slot_of(variable: &str) -> usize {
+    let slot = variable.trim_end_matches('>').rsplit(['.', 'l']).next();
+    slot.and_then(|slot| slot.parse().ok()).unwrap_or(0)
+}
+
+// Compiles a program to debug, with the source names of its locals
+fn compile(source: &str) -> Result<(Vec<Instruction>, Locals), Box<dyn Error>> {
+    let mut compilation = Compilation::new(source, Dialect::Standard, Strictness::default(), 0);
+    PassManager::unoptimized().run(&mut compilation)?;
+    Ok((compilation.instructions, compilation.debug.locals.into_iter().collect()))
+}
+
+// Watched variables given in a request, checked against the program, whose
+// locals are written under their internal variables
+fn watchpoints(
+    names: &[String],
+    code: &[Instruction],
+    locals: &Locals,
+) -> Result<BTreeSet<String>, ErrorDetails> {
+    let written = |name: &String| {
+        code.iter().any(|instruction| {
+            matches!(instruction, Instruction::StoreVariable(stored)
+                if stored == name || locals.get(stored) == Some(name))
+        })
+    };
+    match names.iter().find(|name| !written(name)) {
+        Some(name) => Err(ErrorDetails {
+            kind: ErrorKind::InvalidInput,
+            code: None,
+            message: format!("Cannot watch '{}': the program never writes it", name),
+            line: None,
+            column: None,
+            rendered: None,
+        }),
+        None => Ok(names.iter().cloned().collect()),
+    }
+}
+
+// Route handler for POST /debug
+#[utoipa::path(
+    tag = "compiler",
+    request_body = DebugStartInput,
+    responses(
+        (status = 200, description = "Session started, paused before the first instruction", body = DebugOutput),
+        (status = 400, description = "The source does not compile", body = DebugOutput),
+        (status = 422, description = "Invalid opt_level, breakpoint, condition or watchpoint", body = DebugOutput),
+    )
+)]
+#[post("/debug")]
+pub async fn start_debugging(
+    input: web::Json<DebugStartInput>,
+    config: web::Data<ServerConfig>,
+    store: web::Data<DebugStore>,
+) -> impl Responder {
+    if input.opt_level > optimizer::MAX_OPT_LEVEL {
+        return DebugOutput::failure(ErrorDetails {
+            kind: ErrorKind::InvalidInput,
+            code: None,
+            message: format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL),
+            line: None,
+            column: None,
+            rendered: None,
+        });
+    }
+    let (code, locals) = match compile(&input.source) {
+        Ok((code, locals)) => (optimizer::optimize(code, input.opt_level), locals),
+        Err(e) => return DebugOutput::failure(ErrorDetails::from_error(e.as_ref())),
+    };
+    let (breakpoints, watchpoints) = match breakpoints(&input.breakpoints, &code)
+        .and_then(|breakpoints| Ok((breakpoints, watchpoints(&input.watchpoints, &code, &locals)?)))
+    {
+        Ok(points) => points,
+        Err(details) => return DebugOutput::failure(details),
+    };
+
+    let vm_config = VmConfig {
+        seed: input.seed,
+        policy: config.policy.clone(),
+        stdin: input.stdin.clone(),
+        env: input.env.clone(),
+        files: FileSystem::Memory(input.files.clone()),
+        max_instructions: Some(MAX_DEBUG_STEPS),
+        max_heap_bytes: Some(MAX_DEBUG_HEAP_BYTES),
+        ..VmConfig::default()
+    };
+    let bytecode = code.iter().map(|instruction| format!("{:?}", instruction)).collect();
+    let session = DebugSession::new(code, locals, vm_config, breakpoints, watchpoints);
+    let state = session.state("");
+    let id = store.insert(session);
+    HttpResponse::Ok().json(DebugOutput {
+        bytecode: Some(bytecode),
+        state: Some(DebugState { session: id, ..state }),
+        error_details: None,
+    })
+}
+
+// Route handler for POST /debug/{id}
+#[utoipa::path(
+    tag = "compiler",
+    request_body = DebugCommandInput,
+    params(("id" = String, Path, description = "Id of the session, from POST /debug")),
+    responses(
+        (status = 200, description = "Command run; the program is paused again", body = DebugOutput),
+        (status = 404, description = "No session has this id", body = DebugOutput),
+        (status = 422, description = "Invalid breakpoint, condition or watchpoint", body = DebugOutput),
+    )
+)]
+#[post("/debug/{id}")]
+pub async fn debug_command(
+    id: web::Path<String>,
+    input: web::Json<DebugCommandInput>,
+    store: web::Data<DebugStore>,
+) -> impl Responder {
+    let Some(session) = store.get(&id) else {
+        return DebugOutput::failure(unknown_session(&id));
+    };
+    let input = input.into_inner();
+    let state = web::block(move || {
+        let mut session = session.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match input.command {
+            DebugCommand::SetBreakpoints => session.breakpoints = breakpoints(&input.breakpoints, &session.code)?,
+            DebugCommand::SetDataBreakpoints => {
+                session.watchpoints = watchpoints(&input.watchpoints, &session.code, &session.locals)?
+            }
+            _ => {}
+        }
+        session.run(input.command);
+        Ok(session.state(&id))
+    })
+    .await;
+
+    match state {
+        Ok(Ok(state)) => HttpResponse::Ok().json(DebugOutput {
+            bytecode: None,
+            state: Some(state),
+            error_details: None,
+        }),
+        Ok(Err(details)) => DebugOutput::failure(details),
+        Err(e) => DebugOutput::failure(ErrorDetails {
+            kind: ErrorKind::Internal,
+            code: None,
+            message: format!("Internal compiler error: {}", e),
+            line: None,
+            column: None,
+            rendered: None,
+        }),
+    }
+}
+
+// Route handler for DELETE /debug/{id}
+#[utoipa::path(
+    tag = "compiler",
+    params(("id" = String, Path, description = "Id of the session, from POST /debug")),
+    responses(
+        (status = 200, description = "Session ended", body = DebugOutput),
+        (status = 404, description = "No session has this id", body = DebugOutput),
+    )
+)]
+#[delete("/debug/{id}")]
+pub async fn end_debugging(id: web::Path<String>, store: web::Data<DebugStore>) -> impl Responder {
+    if store.remove(&id) {
+        HttpResponse::Ok().json(DebugOutput {
+            bytecode: None,
+            state: None,
+            error_details: None,
+        })
+    } else {
+        DebugOutput::failure(unknown_session(&id))
+    }
+}
+
+fn unknown_session(id: &str) -> ErrorDetails {
+    ErrorDetails {
+        kind: ErrorKind::NotFound,
+        code: None,
+        message: format!("Unknown debug session '{}'", id),
+        line: None,
+        column: None,
+        rendered: None,
+    }
+}
@@ -0,0 +1,187 @@
+// Diagnostics reported by the command-line interface, either as human-readable
+// text or as one JSON object per line for editors and CI problem matchers, and
+// by POST /diagnostics for the documents of editors.
+
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::i18n;
+use crate::quickfix::QuickFix;
+use crate::ErrorDetails;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// Source position of a diagnostic. Errors raised while running a program
+/// have no position.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Span {
+    pub line: Option<usize>,   // 1-based
+    pub column: Option<usize>, // 1-based
+}
+
+/// One problem found in a source file.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Diagnostic {
+    pub file: String,
+    pub span: Span,
+    pub severity: Severity,
+    pub code: String,               // Registered code, e.g. "E0001"; the error kind for errors without one
+    pub message: String,
+    pub suggestion: Option<String>, // Possible fix, when one is known
+    pub fixes: Vec<QuickFix>,       // Edits that fix the problem
+    pub rendered: Option<String>,   // The human-readable form with the source line, when there is a position
+}
+
+impl Diagnostic {
+    /// Diagnostic for an error returned by the compiler pipeline.
+    pub fn from_details(file: &str, details: &ErrorDetails) -> Self {
+        Diagnostic {
+            file: file.to_string(),
+            span: Span {
+                line: details.line,
+                column: details.column,
+            },
+            severity: Severity::Error,
+            code: details.code.clone().unwrap_or_else(|| details.kind.name().to_string()),
+            message: details.message.clone(),
+            suggestion: None,
+            fixes: Vec::new(),
+            rendered: None,
+        }
+    }
+
+    /// Adds the excerpt of `source` at the position of the diagnostic, if it
+    /// has one, to its human-readable form: the whole line is underlined
+    /// when the column is unknown.
+    pub fn render(&mut self, source: &str) {
+        self.rendered = match (self.span.line, self.span.column) {
+            (Some(line), Some(column)) => Some(render(&self.header(), source, line, column)),
+            (Some(line), None) => Some(render_line(&self.header(), source, line)),
+            (None, _) => return,
+        };
+    }
+
+    /// Translates the message into `locale`, when the catalog has it.
+    pub fn localize(&mut self, locale: &str) {
+        if let Some(translated) = i18n::translate(&self.code, &self.message, locale) {
+            self.rendered = self.rendered.take().map(|rendered| rendered.replacen(&self.message, &translated, 1));
+            self.message = translated;
+        }
+    }
+
+    // `file:line:column: severity[code]: message`
+    fn header(&self) -> String {
+        let mut header = self.file.clone();
+        if let Some(line) = self.span.line {
+            header.push_str(&format!(":{}", line));
+            if let Some(column) = self.span.column {
+                header.push_str(&format!(":{}", column));
+            }
+        }
+        format!("{}: {}[{}]: {}", header, self.severity.name(), self.code, self.message)
+    }
+
+    /// Single-line JSON form used by `--message-format=json`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("diagnostics always serialize")
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    // Human-readable form: `file:line:column: severity[code]: message`, then
+    // the source excerpt when there is one
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.rendered {
+            Some(rendered) => write!(f, "{}", rendered)?,
+            None => write!(f, "{}", self.header())?,
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n  help: {}", suggestion)?;
+        }
+        for fix in &self.fixes {
+            write!(f, "\n  help: {}", fix.title)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders `header` above line `line` of `source`, with carets under the token
+/// starting at `column` (both 1-based), in the style of rustc:
+///
+/// ```text
+/// main.src:1:9: error[syntax]: Parser error at 1:9: Expected expression, got Semicolon
+///   |
+/// 1 | int x = ;
+///   |         ^
+/// ```
+///
+/// Positions outside the source leave the header alone.
+pub fn render(header: &str, source: &str, line: usize, column: usize) -> String {
+    let Some(text) = source.lines().nth(line.wrapping_sub(1)) else {
+        return header.to_string();
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let start = column.saturating_sub(1).min(chars.len());
+    let width = token_width(&chars[start..]);
+    excerpt(header, line, &chars, start, width)
+}
+
+/// Renders `header` above line `line` of `source` as `render` does, with
+/// carets under the whole text of the line, for errors located only to it.
+pub fn render_line(header: &str, source: &str, line: usize) -> String {
+    let Some(text) = source.lines().nth(line.wrapping_sub(1)) else {
+        return header.to_string();
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let start = chars.iter().take_while(|c| c.is_whitespace()).count();
+    let width = text.trim().chars().count().max(1);
+    excerpt(header, line, &chars, start, width)
+}
+
+// The header, then line `line`, made of `chars`, with `width` carets
+// under it from character `start`
+fn excerpt(header: &str, line: usize, chars: &[char], start: usize, width: usize) -> String {
+    // Tabs are kept so that the carets line up with the text above them
+    let padding: String = chars[..start].iter().map(|&c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let text: String = chars.iter().collect();
+
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+    format!(
+        "{}\n{} |\n{} | {}\n{} | {}{}",
+        header,
+        gutter,
+        number,
+        text,
+        gutter,
+        padding,
+        "^".repeat(width)
+    )
+}
+
+// Width of the token at the start of `rest`: a name or number, a string
+// literal, or a single character
+fn token_width(rest: &[char]) -> usize {
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_' || *c == '.';
+    match rest.first() {
+        Some(c) if is_word(c) => rest.iter().take_while(|c| is_word(c)).count(),
+        Some('"') => rest[1..].iter().position(|&c| c == '"').map_or(rest.len(), |end| end + 2),
+        _ => 1,
+    }
+}
@@ -0,0 +1,147 @@
+// Documentation generator: lists the top-level declarations of a program with
+// their `///` doc comments, as JSON or as an HTML page.
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::lexer::Lexer;
+use crate::parser::{ASTNode, Parser};
+use crate::{ErrorDetails, SourceInput};
+
+/// One documented declaration.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DocItem {
+    pub kind: String, // Kind of declaration: "variable" or "function"
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String, // Declared type of a variable, "fn" for a function
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Vec<String>>, // Parameters of a function
+    pub doc: Option<String>, // Doc comment text, one line per `///` comment
+}
+
+// Response of POST /docs?format=json
+#[derive(Serialize, ToSchema)]
+pub struct DocsOutput {
+    items: Vec<DocItem>,
+    error_details: Option<ErrorDetails>, // Set when the source does not parse
+}
+
+// Output format of POST /docs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DocsFormat {
+    #[default]
+    Json,
+    Html,
+}
+
+// Query options accepted by /docs
+#[derive(Deserialize, IntoParams)]
+pub struct DocsOptions {
+    #[serde(default)]
+    format: DocsFormat,
+}
+
+/// Lists the top-level declarations of a program, in source order.
+pub fn extract(program: &ASTNode) -> Vec<DocItem> {
+    let ASTNode::Program(statements) = program else {
+        return Vec::new();
+    };
+
+    statements
+        .iter()
+        .filter_map(|statement| match statement {
+            ASTNode::VarDeclaration { var_type, name, doc, .. } => Some(DocItem {
+                kind: "variable".to_string(),
+                name: name.clone(),
+                type_name: var_type.clone(),
+                params: None,
+                doc: doc.clone(),
+            }),
+            ASTNode::FunctionDeclaration { name, params, doc, .. } => Some(DocItem {
+                kind: "function".to_string(),
+                name: name.clone(),
+                type_name: "fn".to_string(),
+                params: Some(params.clone()),
+                doc: doc.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Renders the items as a standalone HTML page.
+pub fn render_html(items: &[DocItem]) -> String {
+    let mut body = String::new();
+    if items.is_empty() {
+        body.push_str("    <p>No declarations.</p>\n");
+    }
+    for item in items {
+        let declaration = match &item.params {
+            Some(params) => format!("fn {}({})", item.name, params.join(", ")),
+            None => format!("{} {}", item.type_name, item.name),
+        };
+        body.push_str(&format!(
+            "    <section id=\"{}\">\n      <h2><code>{}</code></h2>\n",
+            escape_html(&item.name),
+            escape_html(&declaration)
+        ));
+        match &item.doc {
+            Some(doc) => {
+                for paragraph in doc.split("\n\n") {
+                    body.push_str(&format!("      <p>{}</p>\n", escape_html(paragraph).replace('\n', "<br />\n")));
+                }
+            }
+            None => body.push_str("      <p><em>Undocumented.</em></p>\n"),
+        }
+        body.push_str("    </section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"UTF-8\" />\n    <title>Documentation</title>\n  </head>\n  <body>\n    <h1>Documentation</h1>\n{}  </body>\n</html>\n",
+        body
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Route handler for POST /docs
+#[utoipa::path(
+    tag = "compiler",
+    request_body = SourceInput,
+    params(DocsOptions),
+    responses(
+        (status = 200, description = "Declarations with their doc comments, as JSON or as an HTML page (format=html)", body = DocsOutput),
+        (status = 400, description = "The source does not parse", body = DocsOutput),
+    )
+)]
+#[post("/docs")]
+pub async fn generate_docs(input: web::Json<SourceInput>, options: web::Query<DocsOptions>) -> impl Responder {
+    let parsed = Lexer::new(&input.source)
+        .tokenize()
+        .and_then(|tokens| Parser::new(tokens).parse());
+
+    match (parsed, options.format) {
+        (Ok(program), DocsFormat::Json) => HttpResponse::Ok().json(DocsOutput {
+            items: extract(&program),
+            error_details: None,
+        }),
+        (Ok(program), DocsFormat::Html) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(render_html(&extract(&program))),
+        (Err(e), DocsFormat::Json) => HttpResponse::BadRequest().json(DocsOutput {
+            items: Vec::new(),
+            error_details: Some(ErrorDetails::from_error(e.as_ref())),
+        }),
+        (Err(e), DocsFormat::Html) => HttpResponse::BadRequest()
+            .content_type("text/html; charset=utf-8")
+            .body(format!("<p>Error: {}</p>\n", escape_html(&e.to_string()))),
+    }
+}
@@ -0,0 +1,233 @@
+// Documents kept for editor integrations, by id: the incremental parser of
+// each, which holds its source, and the symbol table of its current version
+// when that compiles. Editors send the whole source once, then only their
+// edits, and get diagnostics back after each change (POST /diagnostics).
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::diagnostics::Diagnostic;
+use crate::i18n;
+use crate::quickfix;
+use crate::reparse::{IncrementalParser, ParseReport, SourceEdit};
+use crate::resolver::{self, SymbolTable};
+use crate::{accept_language, ErrorDetails, ErrorKind};
+
+/// Documents kept at most; the least recently changed is dropped beyond this.
+const MAX_DOCUMENTS: usize = 1000;
+
+/// The documents of all editors, by id.
+#[derive(Default)]
+pub struct DocumentStore {
+    inner: Mutex<Documents>,
+}
+
+#[derive(Default)]
+struct Documents {
+    entries: HashMap<String, Arc<Mutex<Document>>>,
+    order: VecDeque<String>, // Ids from the least to the most recently changed
+}
+
+#[derive(Default)]
+struct Document {
+    parser: IncrementalParser,
+    symbols: Option<Arc<SymbolTable>>, // None while the source does not compile
+}
+
+/// New content of a document.
+pub enum Change<'a> {
+    Source(&'a str),         // The whole source
+    Edits(&'a [SourceEdit]), // Edits applied in order to the kept source
+}
+
+/// Result of analysing a document after a change.
+pub struct Analysis {
+    pub symbols: Result<Arc<SymbolTable>, Box<dyn Error>>,
+    pub diagnostics: Vec<Diagnostic>, // With their quick fixes
+    pub report: ParseReport,
+}
+
+impl DocumentStore {
+    /// Applies a change to a document and analyses it. Edits need the document
+    /// to be known; a document whose edits cannot be applied is forgotten, so
+    /// the editor sends its whole source again.
+    pub fn change(&self, id: &str, change: Change) -> Result<Analysis, String> {
+        let document = {
+            let mut documents = self.lock();
+            let document = match (documents.entries.get(id), &change) {
+                (Some(document), _) => document.clone(),
+                (None, Change::Source(_)) => {
+                    if documents.order.len() >= MAX_DOCUMENTS {
+                        if let Some(oldest) = documents.order.pop_front() {
+                            documents.entries.remove(&oldest);
+                        }
+                    }
+                    let document = Arc::new(Mutex::new(Document::default()));
+                    documents.entries.insert(id.to_string(), document.clone());
+                    document
+                }
+                (None, Change::Edits(_)) => {
+                    return Err(format!("Unknown document '{}': send its whole source first", id));
+                }
+            };
+            documents.order.retain(|other| other != id);
+            documents.order.push_back(id.to_string());
+            document
+        };
+
+        let mut document = document.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match change {
+            Change::Source(source) => document.parser.set_source(source),
+            Change::Edits(edits) => {
+                for edit in edits {
+                    if let Err(message) = document.parser.edit(edit) {
+                        drop(document);
+                        self.forget(id);
+                        return Err(message);
+                    }
+                }
+            }
+        }
+
+        let analysis = document.analyse(id);
+        document.symbols = analysis.symbols.as_ref().ok().cloned();
+        Ok(analysis)
+    }
+
+    /// Replaces the source of a document, returning its symbol table.
+    pub fn update(&self, id: &str, source: &str) -> Result<Arc<SymbolTable>, Box<dyn Error>> {
+        self.change(id, Change::Source(source))?.symbols
+    }
+
+    /// The symbol table of a document, when its current source compiles.
+    pub fn get(&self, id: &str) -> Option<Arc<SymbolTable>> {
+        let document = self.lock().entries.get(id).cloned()?;
+        let document = document.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        document.symbols.clone()
+    }
+
+    fn forget(&self, id: &str) {
+        let mut documents = self.lock();
+        documents.entries.remove(id);
+        documents.order.retain(|other| other != id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Documents> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Document {
+    fn analyse(&mut self, id: &str) -> Analysis {
+        let (symbols, report) = match self.parser.parse() {
+            Ok((_, report)) => {
+                let tokens = self.parser.tokens().unwrap_or_default();
+                let symbols = resolver::resolve(self.parser.source(), tokens)
+                    .map(Arc::new)
+                    .map_err(Into::into);
+                (symbols, report)
+            }
+            Err(e) => (Err(e), ParseReport::default()),
+        };
+
+        let source = self.parser.source();
+        let tokens = self.parser.tokens().unwrap_or_default();
+        let found = match &symbols {
+            Ok(table) => quickfix::check(id, source, tokens, table),
+            Err(e) => {
+                let details = ErrorDetails::from_error(e.as_ref());
+                let mut diagnostic = Diagnostic::from_details(id, &details);
+                diagnostic.fixes = quickfix::fixes_for_error(source, tokens, &details);
+                diagnostic.render(source);
+                vec![diagnostic]
+            }
+        };
+        Analysis {
+            symbols,
+            diagnostics: found,
+            report,
+        }
+    }
+}
+
+// Request body of POST /diagnostics
+#[derive(Deserialize, ToSchema)]
+pub struct DiagnosticsInput {
+    document: String,
+    #[serde(default)]
+    source: Option<String>,          // The whole source, to open or replace the document
+    #[serde(default)]
+    edits: Option<Vec<SourceEdit>>,  // Or the edits made to the kept source, in order
+    #[serde(default)]
+    locale: Option<String>,          // Language of the messages, e.g. "es"; overrides Accept-Language
+}
+
+// Response of POST /diagnostics
+#[derive(Serialize, ToSchema)]
+pub struct DiagnosticsOutput {
+    diagnostics: Vec<Diagnostic>,
+    statements: usize,    // Top-level statements in the document
+    reparsed: usize,      // Statements parsed again after this change
+    relexed_bytes: usize, // Bytes lexed again after this change
+    error_details: Option<ErrorDetails>, // Set when the request itself is invalid
+}
+
+// Route handler for POST /diagnostics
+#[utoipa::path(
+    tag = "compiler",
+    request_body = DiagnosticsInput,
+    responses(
+        (status = 200, description = "Problems found in the document after the change, with quick fixes", body = DiagnosticsOutput),
+        (status = 422, description = "Unknown document, invalid edit, or neither or both of source and edits", body = DiagnosticsOutput),
+    )
+)]
+#[post("/diagnostics")]
+pub async fn diagnostics(
+    input: web::Json<DiagnosticsInput>,
+    documents: web::Data<DocumentStore>,
+    request: HttpRequest,
+) -> impl Responder {
+    let change = match (&input.source, &input.edits) {
+        (Some(source), None) => Ok(Change::Source(source)),
+        (None, Some(edits)) => Ok(Change::Edits(edits)),
+        _ => Err("Exactly one of \"source\" and \"edits\" is required".to_string()),
+    };
+
+    match change.and_then(|change| documents.change(&input.document, change)) {
+        Ok(mut analysis) => {
+            if let Some(locale) = i18n::negotiate(input.locale.as_deref(), accept_language(&request)) {
+                for diagnostic in &mut analysis.diagnostics {
+                    diagnostic.localize(locale);
+                }
+            }
+            HttpResponse::Ok().json(DiagnosticsOutput {
+                diagnostics: analysis.diagnostics,
+                statements: analysis.report.items,
+                reparsed: analysis.report.reparsed,
+                relexed_bytes: analysis.report.relexed,
+                error_details: None,
+            })
+        }
+        Err(message) => {
+            let details = ErrorDetails {
+                kind: ErrorKind::InvalidInput,
+                code: None,
+                message,
+                line: None,
+                column: None,
+                rendered: None,
+            };
+            HttpResponse::build(details.kind.status()).json(DiagnosticsOutput {
+                diagnostics: Vec::new(),
+                statements: 0,
+                reparsed: 0,
+                relexed_bytes: 0,
+                error_details: Some(details),
+            })
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// Interface for hosts that embed the compiler in their own process, such as
+// the C library in ffi/ and the Node.js addon in node/: tokenizing, compiling
+// and running programs without the server, and comparing two programs.
+// Programs run in this process with the default sandbox policy, or only the
+// constant-time builtins for expressions handling secrets. A `Pipeline`
+// compiles with phases of the host's own, e.g. to reject programs a course
+// does not allow yet, or without some of the built-in ones, and shows its
+// observers what each phase made and what the program runs.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::lexer::{self, Dialect, Lexer};
+use crate::limits::ExecutionLimits;
+use crate::optimizer;
+use crate::parser::ASTNode;
+use crate::pipeline::{self, Compilation, Mode, PassManager, Phase};
+use crate::sandbox::SandboxPolicy;
+use crate::semantic::{StrictMode, Strictness};
+use crate::similarity;
+use crate::vm::{Instruction, InstructionObservers, VirtualMachine, VmConfig};
+pub use crate::pipeline::PhaseTiming;
+pub use crate::similarity::{Similarity, SimilarityOptions, SubtreeMatch};
+use crate::{catch_panics, run_program_guarded, CodeInput, CodeOutput, ErrorDetails};
+
+/// A token of a program.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: String, // Type of the token, e.g. `Identifier("x")`
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Splits `source` into tokens.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let tokens = Lexer::new(source).tokenize().map_err(|e| ErrorDetails::from_error(e.as_ref()).message)?;
+    Ok(embedded_tokens(&tokens))
+}
+
+fn embedded_tokens(tokens: &[lexer::Token]) -> Vec<Token> {
+    tokens
+        .iter()
+        .map(|token| Token {
+            kind: format!("{:?}", token.token_type),
+            line: token.line,
+            column: token.column,
+        })
+        .collect()
+}
+
+/// Compiles `source`, optimized at `opt_level`, and returns its bytecode, one
+/// instruction per entry.
+pub fn compile(source: &str, opt_level: u8) -> Result<Vec<String>, String> {
+    Pipeline::default().compile(source, opt_level).map(|compiled| compiled.bytecode)
+}
+
+/// The phases a program is compiled with: lex, parse, check, codegen and
+/// optimize, then those the host adds.
+pub struct Pipeline {
+    passes: PassManager,
+    observers: Vec<Arc<dyn pipeline::Observer>>,
+    instruction_interval: u64, // Instructions run per call of on_instruction_executed
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            passes: PassManager::standard(),
+            observers: Vec::new(),
+            instruction_interval: 1000,
+        }
+    }
+}
+
+/// Watches the programs a `Pipeline` compiles and runs. Each method does
+/// nothing unless overridden.
+pub trait Observer: Send + Sync {
+    /// The tokens made by the lex phase.
+    fn on_tokens(&self, _tokens: &[Token]) {}
+
+    /// The syntax tree made by the parse phase, listed as the gRPC service's
+    /// Parse lists it.
+    fn on_ast(&self, _ast: &str) {}
+
+    /// The bytecode made by `phase`, codegen then optimize, one instruction
+    /// per entry.
+    fn on_bytecode(&self, _phase: &str, _bytecode: &[String]) {}
+
+    /// The instruction at `ip`, about to run as the `executed`th instruction
+    /// of the run; only one in every `Pipeline::set_instruction_interval`.
+    fn on_instruction_executed(&self, _ip: usize, _instruction: &str, _executed: u64) {}
+}
+
+// An observer of the host, shown what the compiler made in the form of this
+// interface
+struct HostObserver<O>(O);
+
+impl<O: Observer> pipeline::Observer for HostObserver<O> {
+    fn on_tokens(&self, tokens: &[lexer::Token]) {
+        self.0.on_tokens(&embedded_tokens(tokens));
+    }
+
+    fn on_ast(&self, program: &ASTNode) {
+        self.0.on_ast(&format!("{:#?}", program));
+    }
+
+    fn on_bytecode(&self, phase: &str, instructions: &[Instruction]) {
+        self.0.on_bytecode(phase, &listing(instructions));
+    }
+
+    fn on_instruction_executed(&self, ip: usize, instruction: &Instruction, executed: u64) {
+        self.0.on_instruction_executed(ip, &format!("{:?}", instruction), executed);
+    }
+}
+
+fn listing(instructions: &[Instruction]) -> Vec<String> {
+    instructions.iter().map(|instruction| format!("{:?}", instruction)).collect()
+}
+
+/// A program compiled by a `Pipeline`.
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub bytecode: Vec<String>,      // One instruction per entry
+    pub timings: Vec<PhaseTiming>,  // Time each phase that ran took, in order
+}
+
+/// A program between two phases, as a phase of the host sees it.
+pub struct Program<'a> {
+    compilation: &'a Compilation<'a>,
+}
+
+impl Program<'_> {
+    pub fn source(&self) -> &str {
+        self.compilation.source
+    }
+
+    /// The tokens of the program; empty before the lex phase.
+    pub fn tokens(&self) -> Vec<Token> {
+        embedded_tokens(&self.compilation.tokens)
+    }
+
+    /// The bytecode of the program, one instruction per entry; empty before
+    /// the codegen phase.
+    pub fn bytecode(&self) -> Vec<String> {
+        listing(&self.compilation.instructions)
+    }
+}
+
+// A phase of the host: it inspects the program, and fails the compilation
+// with the message it returns
+struct HostPhase<F> {
+    name: String,
+    run: F,
+}
+
+impl<F: Fn(&Program) -> Result<(), String>> Phase for HostPhase<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>> {
+        (self.run)(&Program { compilation }).map_err(Into::into)
+    }
+}
+
+impl Pipeline {
+    /// Names of the phases, in the order they run.
+    pub fn phases(&self) -> Vec<String> {
+        self.passes.names()
+    }
+
+    /// Enables or disables the phase named `phase`. A phase needing what a
+    /// disabled phase makes fails the compilation.
+    pub fn set_enabled(&mut self, phase: &str, enabled: bool) -> Result<(), String> {
+        self.passes.set_enabled(phase, enabled)
+    }
+
+    /// Adds the phase `name`, which runs `run`, right after the phase `after`.
+    pub fn insert_after<F>(&mut self, after: &str, name: &str, run: F) -> Result<(), String>
+    where
+        F: Fn(&Program) -> Result<(), String> + 'static,
+    {
+        self.passes.insert_after(after, Box::new(HostPhase { name: name.to_string(), run }))
+    }
+
+    /// Adds the phase `name`, which runs `run`, right before the phase `before`.
+    pub fn insert_before<F>(&mut self, before: &str, name: &str, run: F) -> Result<(), String>
+    where
+        F: Fn(&Program) -> Result<(), String> + 'static,
+    {
+        self.passes.insert_before(before, Box::new(HostPhase { name: name.to_string(), run }))
+    }
+
+    /// Shows `observer` what each phase makes, and the instructions the
+    /// programs given to `run` execute.
+    pub fn observe(&mut self, observer: impl Observer + 'static) {
+        let observer: Arc<dyn pipeline::Observer> = Arc::new(HostObserver(observer));
+        self.passes.observe(observer.clone());
+        self.observers.push(observer);
+    }
+
+    /// Shows observers one in every `interval` instructions a run executes,
+    /// 1000 unless set; 1 shows every instruction.
+    pub fn set_instruction_interval(&mut self, interval: u64) {
+        self.instruction_interval = interval.max(1);
+    }
+
+    /// Compiles `source`, optimized at `opt_level`.
+    pub fn compile(&self, source: &str, opt_level: u8) -> Result<Compiled, String> {
+        let (compilation, timings) = self.compilation(source, opt_level)?;
+        Ok(Compiled {
+            bytecode: Program { compilation: &compilation }.bytecode(),
+            timings,
+        })
+    }
+
+    /// Compiles `source`, optimized at `opt_level`, and runs it with `stdin`
+    /// as the input read by read_line(), returning its output as `execute`
+    /// does.
+    pub fn run(&self, source: &str, opt_level: u8, stdin: &str) -> Result<String, String> {
+        let (compilation, _) = self.compilation(source, opt_level)?;
+        let config = VmConfig {
+            stdin: stdin.to_string(),
+            observers: Some(InstructionObservers {
+                observers: self.observers.clone(),
+                interval: self.instruction_interval,
+            }),
+            ..VmConfig::default()
+        };
+        let mut vm = VirtualMachine::with_config(config);
+        catch_panics(|| vm.execute(&compilation.instructions))
+            .map_err(|message| format!("Internal compiler error: {}", message))?
+            .map_err(|e| ErrorDetails::from_error(e.as_ref()).message)
+    }
+
+    fn compilation<'a>(&self, source: &'a str, opt_level: u8) -> Result<(Compilation<'a>, Vec<PhaseTiming>), String> {
+        if opt_level > optimizer::MAX_OPT_LEVEL {
+            return Err(format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL));
+        }
+        let mut compilation = Compilation::new(source, Dialect::Standard, Strictness::default(), opt_level);
+        let timings = self
+            .passes
+            .run(&mut compilation)
+            .map_err(|e| ErrorDetails::from_error(e.as_ref()).message)?;
+        Ok((compilation, timings))
+    }
+}
+
+/// Compiles and runs `source`, with `stdin` as the input read by read_line(),
+/// and returns its output as POST /compile does.
+pub fn execute(source: &str, stdin: &str) -> Result<String, String> {
+    let output = run_embedded(&embedded_input(source, stdin), VmConfig::default())?;
+    Ok(output.result)
+}
+
+/// Evaluates `expression`, e.g. the formula of a spreadsheet cell, with
+/// `globals` as its variables, and returns its value as JSON, as POST
+/// /compile does in expression mode.
+pub fn evaluate(expression: &str, globals: &BTreeMap<String, serde_json::Value>) -> Result<serde_json::Value, String> {
+    let input = CodeInput {
+        mode: Mode::Expression,
+        globals: globals.clone(),
+        ..embedded_input(expression, "")
+    };
+    let output = run_embedded(&input, VmConfig::default())?;
+    Ok(output.value.unwrap_or(serde_json::Value::Null))
+}
+
+/// Evaluates `expression` as `evaluate` does, for expressions handling
+/// secrets, e.g. checking a token against the stored one: it may only call
+/// the builtins whose running time cannot reveal their arguments, such as
+/// secure_equals(). Operators such as `==` are not constant-time.
+pub fn evaluate_constant_time(
+    expression: &str,
+    globals: &BTreeMap<String, serde_json::Value>,
+) -> Result<serde_json::Value, String> {
+    let input = CodeInput {
+        mode: Mode::Expression,
+        globals: globals.clone(),
+        ..embedded_input(expression, "")
+    };
+    let config = VmConfig {
+        policy: SandboxPolicy { constant_time: true, ..SandboxPolicy::default() },
+        ..VmConfig::default()
+    };
+    let output = run_embedded(&input, config)?;
+    Ok(output.value.unwrap_or(serde_json::Value::Null))
+}
+
+// Request to run `source` with `stdin` and the settings of POST /compile
+fn embedded_input(source: &str, stdin: &str) -> CodeInput {
+    CodeInput {
+        source: source.to_string(),
+        language: String::new(),
+        mode: Mode::Program,
+        inspect_variables: false,
+        seed: None,
+        stdin: stdin.to_string(),
+        env: BTreeMap::new(),
+        files: BTreeMap::new(),
+        globals: BTreeMap::new(),
+        return_variables: Vec::new(),
+        document: None,
+        opt_level: 0,
+        strict: StrictMode::default(),
+        verbose: false,
+        session: None,
+        limits: ExecutionLimits::default(),
+        profile: None,
+        record: false,
+        replay_clock: None,
+        locale: None,
+    }
+}
+
+// Runs `input` in this process, failing with the message of its error
+fn run_embedded(input: &CodeInput, config: VmConfig) -> Result<CodeOutput, String> {
+    let output = run_program_guarded(input, config)
+        .map_err(|message| format!("Internal compiler error: {}", message))?;
+    match output.error_details {
+        Some(details) => Err(details.message),
+        None => Ok(output),
+    }
+}
+
+/// Compares the structure of two programs, as POST /similarity does: the
+/// score is the share of both syntax trees covered by identical subtrees.
+pub fn similarity(left: &str, right: &str, options: SimilarityOptions) -> Result<Similarity, String> {
+    similarity::compare(left, right, options).map_err(|e| ErrorDetails::from_error(e.as_ref()).message)
+}
@@ -0,0 +1,147 @@
+// Programs running on the server right now, so that operators can see them
+// and stop one that runs away without restarting the server (GET and DELETE
+// /admin/executions). Every /compile and /execute run is registered while it
+// runs; stopping it cancels the VM, or kills its worker process. Once it
+// finishes, it is recorded in the history of runs, see `history`.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+use utoipa::ToSchema;
+
+use crate::history::History;
+use crate::vm::{CancellationToken, ProgressCounter};
+use crate::worker::WorkerJob;
+use crate::CodeOutput;
+
+/// A running program, as listed by GET /admin/executions.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ExecutionInfo {
+    pub id: u64,
+    pub source_hash: String,           // FNV-1a of the source, or of the artifact for /execute
+    pub instructions: Option<u64>,     // Run so far; null in worker mode, where the count stays in the worker
+    pub elapsed_ms: u64,
+    pub client: Option<String>,        // Address of the client that sent the request
+    pub session: Option<String>,
+    pub cancelled: bool,               // Stopping was requested; the run ends shortly
+}
+
+/// The programs running on the server, by id.
+#[derive(Default)]
+pub struct ExecutionRegistry {
+    next_id: AtomicU64,
+    running: Mutex<BTreeMap<u64, Execution>>,
+    history: History, // Runs that finished
+}
+
+struct Execution {
+    source_hash: String,
+    client: Option<String>,
+    session: Option<String>,
+    started_at: Instant,
+    started_at_wall: SystemTime, // For the history
+    cancel_token: CancellationToken,
+    progress: Option<ProgressCounter>, // None when the count isn't available
+}
+
+/// A registered run; it leaves the registry when dropped.
+pub struct Registration<'a> {
+    registry: &'a ExecutionRegistry,
+    id: u64,
+}
+
+impl Registration<'_> {
+    /// Records the run in the history as ending with `output`.
+    pub fn finish(self, output: &CodeOutput) {
+        let finished = self.registry.lock().get(&self.id).map(|execution| {
+            (execution.source_hash.clone(), execution.client.clone(), execution.session.clone(), execution.started_at_wall)
+        });
+        // The history may write to its file, so the registry isn't kept locked
+        if let Some((source_hash, client, session, started_at)) = finished {
+            self.registry.history.record(&source_hash, client, session, started_at, output);
+        }
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
+
+impl ExecutionRegistry {
+    /// Registry recording finished runs in `history`.
+    pub fn new(history: History) -> Self {
+        ExecutionRegistry {
+            history,
+            ..ExecutionRegistry::default()
+        }
+    }
+
+    /// The runs that finished.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Registers a job about to run. `counts_instructions` tells whether its
+    /// progress counter is updated, which it is only in this process.
+    pub fn register(
+        &self,
+        job: &WorkerJob,
+        client: Option<String>,
+        counts_instructions: bool,
+    ) -> Registration<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let code = job.artifact.as_deref().unwrap_or(&job.input.source);
+        self.lock().insert(id, Execution {
+            source_hash: format!("{:016x}", fnv1a(code.as_bytes())),
+            client,
+            session: job.input.session.clone(),
+            started_at: Instant::now(),
+            started_at_wall: SystemTime::now(),
+            cancel_token: job.cancel_token.clone(),
+            progress: counts_instructions.then(|| job.progress.clone()),
+        });
+        Registration { registry: self, id }
+    }
+
+    /// The running programs, oldest first.
+    pub fn list(&self) -> Vec<ExecutionInfo> {
+        self.lock().iter().map(|(id, execution)| execution.info(*id)).collect()
+    }
+
+    /// Asks a running program to stop, returning it; None if it isn't running.
+    pub fn cancel(&self, id: u64) -> Option<ExecutionInfo> {
+        let running = self.lock();
+        let execution = running.get(&id)?;
+        execution.cancel_token.cancel();
+        Some(execution.info(id))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, Execution>> {
+        self.running.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Execution {
+    fn info(&self, id: u64) -> ExecutionInfo {
+        ExecutionInfo {
+            id,
+            source_hash: self.source_hash.clone(),
+            instructions: self.progress.as_ref().map(ProgressCounter::get),
+            elapsed_ms: self.started_at.elapsed().as_millis().try_into().unwrap_or(u64::MAX),
+            client: self.client.clone(),
+            session: self.session.clone(),
+            cancelled: self.cancel_token.is_cancelled(),
+        }
+    }
+}
+
+// 64-bit FNV-1a: stable across builds, unlike the standard library's hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -0,0 +1,251 @@
+// Grading: POST /grade runs one program on several test cases, each an input
+// and the output expected for it, and reports which cases pass, with a diff
+// of the lines that differ for those that fail. It saves graders the loop
+// they would otherwise script around /compile.
+//
+// Each case is a run of its own, under the request's limits and recorded in
+// the history like a /compile. A case fails when its output differs or the
+// program fails at runtime or runs out of time on it; any other error, such
+// as a program that does not compile, ends the grading and is returned
+// instead of the cases.
+
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use crate::limits::ExecutionLimits;
+use crate::pipeline::Mode;
+use crate::semantic::StrictMode;
+use crate::worker::WorkerJob;
+use crate::{client_of, dispatch_request, CodeInput, ErrorDetails, ErrorKind, ExecutionRegistry, ServerConfig, SessionStore};
+
+/// Cases a request may hold.
+const MAX_CASES: usize = 100;
+
+/// Lines of a diff kept in a case's result; the rest is summarized.
+const MAX_DIFF_LINES: usize = 200;
+
+/// Output lines compared line by line for the shortest diff; longer outputs
+/// are compared line against line at the same position.
+const MAX_LCS_LINES: usize = 2_000;
+
+// Request body of POST /grade
+#[derive(Deserialize, ToSchema)]
+pub struct GradeInput {
+    source: String,
+    #[serde(default)]
+    language: String,        // "beginner" for the beginner dialect, as for /compile
+    cases: Vec<GradeCase>,
+    #[serde(default)]
+    exact: bool,             // Compare outputs byte for byte; by default trailing whitespace is ignored
+    #[serde(default)]
+    opt_level: u8,
+    #[serde(default)]
+    strict: StrictMode,
+    #[serde(default)]
+    seed: Option<u64>,       // Seed of random builtins, the same for every case
+    #[serde(default)]
+    limits: ExecutionLimits, // Limits of each case, as for /compile
+    #[serde(default)]
+    session: Option<String>, // Session charged for every case, as for /compile
+    #[serde(default)]
+    profile: Option<String>, // Profile whose settings apply, as for /compile
+}
+
+/// A test case: an input and the output the program should print for it.
+#[derive(Deserialize, ToSchema)]
+pub struct GradeCase {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    stdin: String,           // Input lines returned by read_line()
+    expected: String,
+}
+
+/// Result of a test case, in the order of the request.
+#[derive(Serialize, ToSchema)]
+pub struct CaseResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    passed: bool,
+    output: String,          // What the program printed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<String>,    // Lines of `expected` missing from the output ("-") and lines it has instead ("+")
+    exit_code: Option<i32>,
+    instructions: u64,       // Instructions executed
+    error_details: Option<ErrorDetails>, // Runtime error or timeout of the case
+}
+
+// Response of POST /grade
+#[derive(Serialize, ToSchema)]
+pub struct GradeOutput {
+    passed: usize,           // Cases that passed
+    total: usize,
+    cases: Vec<CaseResult>,
+    error_details: Option<ErrorDetails>,
+}
+
+impl GradeOutput {
+    fn failure(details: ErrorDetails) -> HttpResponse {
+        HttpResponse::build(details.kind.status()).json(GradeOutput {
+            passed: 0,
+            total: 0,
+            cases: Vec::new(),
+            error_details: Some(details),
+        })
+    }
+}
+
+// Route handler for POST /grade
+#[utoipa::path(
+    tag = "compiler",
+    request_body = GradeInput,
+    responses(
+        (status = 200, description = "Every case ran; failing cases are reported in `cases`", body = GradeOutput),
+        (status = 400, description = "The program does not compile", body = GradeOutput),
+        (status = 422, description = "No cases, too many cases, or a malformed request body", body = GradeOutput),
+        (status = 429, description = "The session has used its instruction budget", body = GradeOutput),
+        (status = 500, description = "Internal compiler error", body = GradeOutput),
+    )
+)]
+#[post("/grade")]
+pub async fn grade(
+    input: web::Json<GradeInput>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+    request: HttpRequest,
+) -> impl Responder {
+    let input = input.into_inner();
+    if input.cases.is_empty() || input.cases.len() > MAX_CASES {
+        let message = format!("A grading request must have between 1 and {} cases", MAX_CASES);
+        return GradeOutput::failure(ErrorDetails::new(ErrorKind::InvalidInput, message));
+    }
+
+    let mut cases = Vec::with_capacity(input.cases.len());
+    for case in input.cases {
+        let code_input = CodeInput {
+            source: input.source.clone(),
+            language: input.language.clone(),
+            mode: Mode::Program,
+            inspect_variables: false,
+            seed: input.seed,
+            stdin: case.stdin,
+            env: BTreeMap::new(),
+            files: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            return_variables: Vec::new(),
+            document: None,
+            opt_level: input.opt_level,
+            strict: input.strict,
+            verbose: false,
+            session: input.session.clone(),
+            limits: input.limits.clone(),
+            profile: input.profile.clone(),
+            record: false,
+            replay_clock: None,
+            locale: None,
+        };
+        let job = WorkerJob::new(code_input, config.policy.clone());
+        let output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+        if let Some(details) = output.error_details.as_ref().filter(|details| !fails_case(details.kind)) {
+            return GradeOutput::failure(details.clone());
+        }
+
+        let passed = output.error_details.is_none() && same_output(&output.result, &case.expected, input.exact);
+        cases.push(CaseResult {
+            name: case.name,
+            passed,
+            diff: (!passed).then(|| diff(&case.expected, &output.result, input.exact)),
+            output: output.result,
+            exit_code: output.exit_code,
+            instructions: output.stats.instructions_executed,
+            error_details: output.error_details,
+        });
+    }
+
+    HttpResponse::Ok().json(GradeOutput {
+        passed: cases.iter().filter(|case| case.passed).count(),
+        total: cases.len(),
+        cases,
+        error_details: None,
+    })
+}
+
+// Whether an error of this kind is the program failing a case, rather than a
+// reason to stop grading
+fn fails_case(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::Runtime | ErrorKind::Timeout)
+}
+
+// Lines of `text` as compared: unless exact, without trailing whitespace and
+// without the empty lines ending it
+fn lines(text: &str, exact: bool) -> Vec<&str> {
+    if exact {
+        return text.split('\n').collect();
+    }
+    let mut lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+fn same_output(output: &str, expected: &str, exact: bool) -> bool {
+    if exact {
+        output == expected
+    } else {
+        lines(output, false) == lines(expected, false)
+    }
+}
+
+// Diff of the expected output and the actual one: every line, prefixed with
+// "-" when only expected, "+" when only printed, and " " when in both
+fn diff(expected: &str, output: &str, exact: bool) -> String {
+    let (old, new) = (lines(expected, exact), lines(output, exact));
+    let mut diff = Vec::new();
+    if old.len() <= MAX_LCS_LINES && new.len() <= MAX_LCS_LINES {
+        // common[i][j]: longest common subsequence of old[i..] and new[j..]
+        let width = new.len() + 1;
+        let mut common = vec![0u16; (old.len() + 1) * width];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i * width + j] = if old[i] == new[j] {
+                    common[(i + 1) * width + j + 1] + 1
+                } else {
+                    common[(i + 1) * width + j].max(common[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                diff.push(format!(" {}", old[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == new.len() || (i < old.len() && common[(i + 1) * width + j] >= common[i * width + j + 1]) {
+                diff.push(format!("-{}", old[i]));
+                i += 1;
+            } else {
+                diff.push(format!("+{}", new[j]));
+                j += 1;
+            }
+        }
+    } else {
+        for index in 0..old.len().max(new.len()) {
+            match (old.get(index), new.get(index)) {
+                (Some(a), Some(b)) if a == b => diff.push(format!(" {}", a)),
+                (a, b) => {
+                    diff.extend(a.map(|a| format!("-{}", a)));
+                    diff.extend(b.map(|b| format!("+{}", b)));
+                }
+            }
+        }
+    }
+    if diff.len() > MAX_DIFF_LINES {
+        let omitted = diff.len() - MAX_DIFF_LINES;
+        diff.truncate(MAX_DIFF_LINES);
+        diff.push(format!("... {} more lines", omitted));
+    }
+    diff.join("\n")
+}
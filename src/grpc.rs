@@ -0,0 +1,271 @@
+// gRPC service (feature "grpc") exposing the same operations as the REST API
+// with the protobuf types from proto/compiler.proto. Programs run as those of
+// POST /compile do: in a worker process if the server uses them, within the
+// limit ceilings and the session they name, listed as running executions and
+// recorded in the history.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use actix_web::web;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::lexer::{Dialect, Lexer};
+use crate::limits::ExecutionLimits;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::pipeline::{Compilation, Mode, PassManager};
+use crate::semantic::StrictMode;
+use crate::vm::ExecutionStats;
+use crate::worker::WorkerJob;
+use crate::{catch_panics, dispatch_request, CodeInput, CodeOutput, ErrorDetails, ErrorKind, ExecutionRegistry, ServerConfig, SessionStore};
+
+pub mod proto {
+    tonic::include_proto!("compiler.v1");
+}
+
+use proto::compiler_server::{Compiler, CompilerServer};
+use proto::execute_event::Event;
+
+// Room for every event of one execution, so the producer never waits
+const EVENT_BUFFER: usize = 4;
+
+/// Implementation of the `compiler.v1.Compiler` service.
+#[derive(Clone)]
+pub struct CompilerService {
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+}
+
+/// Serves the gRPC API on `addr` until the server shuts down, sharing the
+/// settings, sessions and executions of the HTTP server.
+pub async fn serve(
+    addr: SocketAddr,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+) -> Result<(), tonic::transport::Error> {
+    let service = CompilerService { config, sessions, executions };
+    Server::builder()
+        .add_service(CompilerServer::new(service))
+        .serve(addr)
+        .await
+}
+
+#[tonic::async_trait]
+impl Compiler for CompilerService {
+    async fn compile(
+        &self,
+        request: Request<proto::CompileRequest>,
+    ) -> Result<Response<proto::CompileResponse>, Status> {
+        let input = code_input(request.get_ref().clone());
+        let output = self.run(input, client_of(&request)).await;
+        Ok(Response::new(compile_response(output)))
+    }
+
+    async fn tokenize(
+        &self,
+        request: Request<proto::SourceRequest>,
+    ) -> Result<Response<proto::TokenizeResponse>, Status> {
+        let source = request.into_inner().source;
+        let response = match Lexer::new(&source).tokenize() {
+            Ok(tokens) => proto::TokenizeResponse {
+                tokens: tokens
+                    .iter()
+                    .map(|token| proto::Token {
+                        kind: format!("{:?}", token.token_type),
+                        line: token.line as u64,
+                        column: token.column as u64,
+                    })
+                    .collect(),
+                error: None,
+            },
+            Err(e) => proto::TokenizeResponse {
+                tokens: Vec::new(),
+                error: Some(diagnostic(ErrorDetails::from_error(e.as_ref()))),
+            },
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn parse(
+        &self,
+        request: Request<proto::SourceRequest>,
+    ) -> Result<Response<proto::ParseResponse>, Status> {
+        let source = request.into_inner().source;
+        let parsed = Lexer::new(&source)
+            .tokenize()
+            .and_then(|tokens| Parser::new(tokens).parse());
+
+        let response = match parsed {
+            Ok(ast) => proto::ParseResponse {
+                ast: format!("{:#?}", ast),
+                error: None,
+            },
+            Err(e) => proto::ParseResponse {
+                ast: String::new(),
+                error: Some(diagnostic(ErrorDetails::from_error(e.as_ref()))),
+            },
+        };
+
+        Ok(Response::new(response))
+    }
+
+    type ExecuteStream = Pin<Box<dyn Stream<Item = Result<proto::ExecuteEvent, Status>> + Send>>;
+
+    async fn execute(
+        &self,
+        request: Request<proto::CompileRequest>,
+    ) -> Result<Response<Self::ExecuteStream>, Status> {
+        let input = code_input(request.get_ref().clone());
+        let client = client_of(&request);
+        let service = self.clone();
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        tokio::spawn(service.stream_stages(input, client, sender));
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+type EventSender = mpsc::Sender<Result<proto::ExecuteEvent, Status>>;
+
+impl CompilerService {
+    // Runs a request as POST /compile runs it
+    async fn run(&self, input: CodeInput, client: Option<String>) -> CodeOutput {
+        let job = WorkerJob::new(input, self.config.policy.clone());
+        dispatch_request(job, client, &self.config, &self.sessions, &self.executions).await
+    }
+
+    // Sends one event per stage as the stage completes: the bytecode once the
+    // program compiles, then the statistics and the output of its run. A
+    // program that does not compile ends the stream with an error event.
+    async fn stream_stages(self, input: CodeInput, client: Option<String>, sender: EventSender) {
+        let send = |event: Event| sender.send(Ok(proto::ExecuteEvent { event: Some(event) }));
+
+        let compiling = input.clone();
+        let compiled = tokio::task::spawn_blocking(move || catch_panics(|| compile_stage(&compiling)))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result);
+        let listing = match compiled {
+            Ok(Ok(listing)) => listing,
+            Ok(Err(details)) => {
+                let _ = send(Event::Error(diagnostic(details))).await;
+                return;
+            }
+            Err(message) => {
+                let _ = sender.send(Err(Status::internal(format!("Internal compiler error: {}", message)))).await;
+                return;
+            }
+        };
+        if send(Event::Bytecode(proto::BytecodeListing { instructions: listing })).await.is_err() {
+            return; // Client went away
+        }
+
+        let output = self.run(input, client).await;
+        if send(Event::Stats(stats(&output.stats))).await.is_ok() {
+            let _ = send(match output.error_details {
+                Some(details) => Event::Error(diagnostic(details)),
+                None => Event::Output(output.result),
+            })
+            .await;
+        }
+    }
+}
+
+// Address of the client that sent a request
+fn client_of<T>(request: &Request<T>) -> Option<String> {
+    request.remote_addr().map(|address| address.to_string())
+}
+
+// Compiles a program with the settings of its request, listing the
+// instructions it compiles to
+fn compile_stage(input: &CodeInput) -> Result<Vec<String>, ErrorDetails> {
+    if input.opt_level > optimizer::MAX_OPT_LEVEL {
+        return Err(ErrorDetails::new(
+            ErrorKind::InvalidInput,
+            format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL),
+        ));
+    }
+    let mut compilation = Compilation::new(
+        &input.source,
+        Dialect::from_language(&input.language),
+        input.strict.checks(),
+        input.opt_level,
+    );
+    PassManager::standard()
+        .run(&mut compilation)
+        .map_err(|e| ErrorDetails::from_error(e.as_ref()).rendered_in(&input.source))?;
+    Ok(compilation.instructions.iter().map(|instr| format!("{:?}", instr)).collect())
+}
+
+fn code_input(request: proto::CompileRequest) -> CodeInput {
+    CodeInput {
+        source: request.source,
+        language: request.language,
+        mode: Mode::Program,
+        inspect_variables: request.inspect_variables,
+        seed: request.seed,
+        stdin: request.stdin,
+        env: BTreeMap::new(),
+        files: request.files,
+        globals: BTreeMap::new(),
+        return_variables: Vec::new(),
+        document: None,
+        opt_level: u8::try_from(request.opt_level).unwrap_or(u8::MAX),
+        strict: StrictMode::All(request.strict),
+        verbose: false,
+        session: request.session,
+        limits: ExecutionLimits::default(),
+        profile: None,
+        record: false,
+        replay_clock: None,
+        locale: None,
+    }
+}
+
+fn compile_response(output: CodeOutput) -> proto::CompileResponse {
+    proto::CompileResponse {
+        result: output.result,
+        bytecode: output.bytecode,
+        strings: output.strings,
+        error: output.error_details.map(diagnostic),
+        stats: Some(stats(&output.stats)),
+        exit_code: output.exit_code,
+        files: output.files.into_iter().collect(),
+        variables: output
+            .variables
+            .unwrap_or_default()
+            .into_iter()
+            .map(|variable| proto::Variable {
+                name: variable.name,
+                r#type: variable.type_name,
+                value: variable.value,
+                truncated: variable.truncated,
+            })
+            .collect(),
+    }
+}
+
+fn diagnostic(details: ErrorDetails) -> proto::Diagnostic {
+    proto::Diagnostic {
+        kind: details.kind.name().to_string(),
+        message: details.message,
+        line: details.line.map(|line| line as u64),
+        column: details.column.map(|column| column as u64),
+    }
+}
+
+fn stats(stats: &ExecutionStats) -> proto::ExecutionStats {
+    proto::ExecutionStats {
+        instructions_executed: stats.instructions_executed,
+        peak_stack_depth: stats.peak_stack_depth as u64,
+        peak_heap_bytes: stats.peak_heap_bytes as u64,
+        function_calls: stats.function_calls,
+    }
+}
@@ -0,0 +1,297 @@
+// Token listings for editors: the raw tokens of a source (POST /tokenize) and
+// their highlighting classes (POST /highlight). Both can be limited to a byte
+// range of the source and are paginated, so large files can be fetched piece
+// by piece as they scroll into view. Only the requested range is lexed: the
+// lexer resumes at the start of its line, or at the start of a block comment
+// still open there, which a quick scan of the preceding text finds.
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use utoipa::ToSchema;
+
+use crate::lexer::{scan, LexState, Lexer, Token, TokenType};
+use crate::{ErrorDetails, ErrorKind};
+
+/// Most tokens returned by one request.
+const MAX_PAGE_TOKENS: usize = 5000;
+
+// Request body of POST /tokenize and POST /highlight
+#[derive(Deserialize, ToSchema)]
+pub struct TokenRangeInput {
+    source: String,
+    #[serde(default)]
+    start: usize,         // Byte offset where the range starts
+    #[serde(default)]
+    end: Option<usize>,   // Byte offset where the range ends (exclusive); the end of the source by default
+    #[serde(default)]
+    limit: Option<usize>, // Most tokens to return, at most 5000
+}
+
+// A token of POST /tokenize
+#[derive(Serialize, ToSchema)]
+pub struct TokenInfo {
+    kind: String, // Token type as printed by the lexer, e.g. Identifier("x")
+    line: usize,
+    column: usize,
+    offset: usize, // In bytes
+    length: usize, // In bytes
+}
+
+// Response of POST /tokenize
+#[derive(Serialize, ToSchema)]
+pub struct TokenizeOutput {
+    tokens: Vec<TokenInfo>,
+    next: Option<usize>, // Start of the next page, when the range holds more tokens than the limit
+    error_details: Option<ErrorDetails>,
+}
+
+// How a token is highlighted
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightClass {
+    Keyword,
+    Number,
+    String,
+    Comment,
+    Variable,
+    Function,
+    Operator,
+    Punctuation,
+}
+
+// A token of POST /highlight
+#[derive(Serialize, ToSchema)]
+pub struct HighlightToken {
+    class: HighlightClass,
+    line: usize,
+    column: usize,
+    offset: usize, // In bytes
+    length: usize, // In bytes
+}
+
+// Response of POST /highlight
+#[derive(Serialize, ToSchema)]
+pub struct HighlightOutput {
+    tokens: Vec<HighlightToken>,
+    next: Option<usize>, // Start of the next page, when the range holds more tokens than the limit
+    error_details: Option<ErrorDetails>,
+}
+
+/// A token of a range, with the type of the token following it.
+pub struct RangeToken<'a> {
+    pub token: Token<'a>,
+    pub next: Option<TokenType<'a>>, // None after the last token that was lexed
+}
+
+/// Lexes the tokens overlapping `start..end` (byte offsets into `source`),
+/// including those that extend past the range.
+pub fn lex_range(source: &str, start: usize, end: usize, keep_comments: bool) -> Result<Vec<RangeToken<'_>>, Box<dyn Error>> {
+    let bytes = source.as_bytes();
+
+    // Resume at the start of the line, or of a block comment still open there
+    let line_start = bytes[..start].iter().rposition(|byte| *byte == b'\n').map_or(0, |index| index + 1);
+    let (state, line, _) = scan(bytes, 0, line_start, LexState::Code, 1, 1);
+    let (offset, line, column) = match state {
+        LexState::BlockComment { offset, line, column } => (offset, line, column),
+        _ => (line_start, line, 1),
+    };
+
+    // Stop at the end of the last line, which shows what follows the last
+    // token in the range, or after a block comment still open there
+    let line_end = bytes[end..].iter().position(|byte| *byte == b'\n').map_or(bytes.len(), |index| end + index);
+    let stop = match scan(bytes, offset, line_end, LexState::Code, line, column).0 {
+        LexState::BlockComment { .. } => source[line_end..].find("*/").map_or(bytes.len(), |index| line_end + index + 2),
+        _ => line_end,
+    };
+
+    let lexer = Lexer::starting_at(&source[offset..stop], line, column, offset);
+    let mut lexer = if keep_comments { lexer.with_comments() } else { lexer };
+    let mut tokens = lexer.tokenize()?;
+    if stop < bytes.len() {
+        tokens.pop(); // EOF of the lexed part, not of the source
+    }
+
+    let next_types: Vec<Option<TokenType>> = tokens
+        .iter()
+        .skip(1)
+        .map(|token| Some(token.token_type.clone()))
+        .chain(std::iter::once(None))
+        .collect();
+    Ok(tokens
+        .into_iter()
+        .zip(next_types)
+        .filter(|(token, _)| {
+            let overlaps = token.offset + token.length > start && token.offset < end;
+            overlaps || (token.token_type == TokenType::EOF && end == bytes.len())
+        })
+        .map(|(token, next)| RangeToken { token, next })
+        .collect())
+}
+
+// Checks the range of a request, returning its end
+fn checked_range(input: &TokenRangeInput) -> Result<usize, ErrorDetails> {
+    let end = input.end.unwrap_or(input.source.len()).min(input.source.len());
+    if input.start > end {
+        return Err(ErrorDetails {
+            kind: ErrorKind::InvalidInput,
+            code: None,
+            message: format!(
+                "Range start {} is past its end {} (the source has {} bytes)",
+                input.start,
+                end,
+                input.source.len()
+            ),
+            line: None,
+            column: None,
+            rendered: None,
+        });
+    }
+    Ok(end)
+}
+
+// The page of tokens to return and where the next page starts
+fn paginate<T>(mut items: Vec<T>, limit: Option<usize>, offset_of: impl Fn(&T) -> usize) -> (Vec<T>, Option<usize>) {
+    let limit = limit.unwrap_or(MAX_PAGE_TOKENS).clamp(1, MAX_PAGE_TOKENS);
+    let next = items.get(limit).map(offset_of);
+    items.truncate(limit);
+    (items, next)
+}
+
+fn highlight_class(token_type: &TokenType, next: Option<&TokenType>) -> Option<HighlightClass> {
+    Some(match token_type {
+        TokenType::Int
+        | TokenType::Float
+        | TokenType::If
+        | TokenType::Else
+        | TokenType::While
+        | TokenType::Return
+        | TokenType::Null
+        | TokenType::Print
+        | TokenType::Defer
+        | TokenType::Break
+        | TokenType::Continue
+        | TokenType::Fn
+        | TokenType::Import => HighlightClass::Keyword,
+        TokenType::IntLiteral(_) | TokenType::FloatLiteral(_) => HighlightClass::Number,
+        TokenType::StringLiteral(_) => HighlightClass::String,
+        TokenType::Comment | TokenType::DocComment(_) => HighlightClass::Comment,
+        TokenType::Identifier(word) if word == "test" && matches!(next, Some(TokenType::StringLiteral(_))) => {
+            HighlightClass::Keyword
+        }
+        TokenType::Identifier(_) if next == Some(&TokenType::LeftParen) => HighlightClass::Function,
+        TokenType::Identifier(_) => HighlightClass::Variable,
+        TokenType::Plus
+        | TokenType::Minus
+        | TokenType::Multiply
+        | TokenType::Divide
+        | TokenType::Assign
+        | TokenType::Equal
+        | TokenType::NotEqual
+        | TokenType::LessThan
+        | TokenType::GreaterThan
+        | TokenType::LessEqual
+        | TokenType::GreaterEqual
+        | TokenType::And
+        | TokenType::Or
+        | TokenType::Not => HighlightClass::Operator,
+        TokenType::LeftParen
+        | TokenType::RightParen
+        | TokenType::LeftBrace
+        | TokenType::RightBrace
+        | TokenType::LeftBracket
+        | TokenType::RightBracket
+        | TokenType::Semicolon
+        | TokenType::Colon
+        | TokenType::Comma => HighlightClass::Punctuation,
+        TokenType::EOF => return None,
+    })
+}
+
+// Route handler for POST /tokenize
+#[utoipa::path(
+    tag = "compiler",
+    request_body = TokenRangeInput,
+    responses(
+        (status = 200, description = "Tokens overlapping the range", body = TokenizeOutput),
+        (status = 400, description = "The range contains characters the lexer rejects", body = TokenizeOutput),
+        (status = 422, description = "Invalid range", body = TokenizeOutput),
+    )
+)]
+#[post("/tokenize")]
+pub async fn tokenize(input: web::Json<TokenRangeInput>) -> impl Responder {
+    let lexed = checked_range(&input).and_then(|end| {
+        lex_range(&input.source, input.start, end, false).map_err(|e| ErrorDetails::from_error(e.as_ref()))
+    });
+
+    match lexed {
+        Ok(tokens) => {
+            let tokens = tokens
+                .into_iter()
+                .map(|RangeToken { token, .. }| TokenInfo {
+                    kind: format!("{:?}", token.token_type),
+                    line: token.line,
+                    column: token.column,
+                    offset: token.offset,
+                    length: token.length,
+                })
+                .collect();
+            let (tokens, next) = paginate(tokens, input.limit, |token| token.offset);
+            HttpResponse::Ok().json(TokenizeOutput {
+                tokens,
+                next,
+                error_details: None,
+            })
+        }
+        Err(details) => HttpResponse::build(details.kind.status()).json(TokenizeOutput {
+            tokens: Vec::new(),
+            next: None,
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Route handler for POST /highlight
+#[utoipa::path(
+    tag = "compiler",
+    request_body = TokenRangeInput,
+    responses(
+        (status = 200, description = "Highlighting classes of the tokens overlapping the range", body = HighlightOutput),
+        (status = 400, description = "The range contains characters the lexer rejects", body = HighlightOutput),
+        (status = 422, description = "Invalid range", body = HighlightOutput),
+    )
+)]
+#[post("/highlight")]
+pub async fn highlight(input: web::Json<TokenRangeInput>) -> impl Responder {
+    let lexed = checked_range(&input).and_then(|end| {
+        lex_range(&input.source, input.start, end, true).map_err(|e| ErrorDetails::from_error(e.as_ref()))
+    });
+
+    match lexed {
+        Ok(tokens) => {
+            let tokens = tokens
+                .into_iter()
+                .filter_map(|RangeToken { token, next }| {
+                    Some(HighlightToken {
+                        class: highlight_class(&token.token_type, next.as_ref())?,
+                        line: token.line,
+                        column: token.column,
+                        offset: token.offset,
+                        length: token.length,
+                    })
+                })
+                .collect();
+            let (tokens, next) = paginate(tokens, input.limit, |token| token.offset);
+            HttpResponse::Ok().json(HighlightOutput {
+                tokens,
+                next,
+                error_details: None,
+            })
+        }
+        Err(details) => HttpResponse::build(details.kind.status()).json(HighlightOutput {
+            tokens: Vec::new(),
+            next: None,
+            error_details: Some(details),
+        }),
+    }
+}
@@ -0,0 +1,177 @@
+// History of the runs the server finished, for operators auditing its use,
+// e.g. to check a student's "it worked yesterday" against what ran (GET
+// /admin/history). Every /compile, /execute, invocation, webhook and
+// scheduled run is recorded with who sent it, how long it took, how it ended
+// and the start of its output; sources are only recorded by their hash.
+//
+// The last COMPILER_HISTORY_SIZE runs are kept in memory. With
+// COMPILER_HISTORY_FILE set, every run is also appended to that file as a
+// line of JSON, and the file's last runs are loaded when the server starts,
+// so the history outlives restarts. The file is never truncated; rotating
+// it is left to the operator.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::CodeOutput;
+
+/// Characters of a run's output kept in its entry.
+const MAX_HISTORY_OUTPUT: usize = 1024;
+
+/// Entries returned by a query unless it sets `limit`.
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// A finished run, as listed by GET /admin/history.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub started_at_ms: u64,          // Unix time in milliseconds
+    pub source_hash: String,         // FNV-1a of the source, or of the artifact, as for /admin/executions
+    pub client: Option<String>,      // Address of the client that sent the request; null for scheduled runs
+    pub session: Option<String>,
+    pub duration_ms: u64,
+    pub outcome: String,             // "ok", or the kind of the error, e.g. "runtime"
+    pub error: Option<String>,       // Message of the error, if any
+    pub exit_code: Option<i32>,
+    pub instructions: u64,           // Instructions executed
+    pub output: String,              // Output of the run, cut to MAX_HISTORY_OUTPUT characters
+    pub truncated: bool,             // Whether `output` was cut
+}
+
+/// Query of GET /admin/history; every filter that is set must match.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub struct HistoryQuery {
+    session: Option<String>,
+    client: Option<String>,      // Matches the address with or without its port
+    source_hash: Option<String>,
+    outcome: Option<String>,     // "ok" or an error kind
+    since_ms: Option<u64>,       // Runs started at or after this Unix time, in milliseconds
+    limit: Option<usize>,        // Entries returned at most, 100 by default
+}
+
+/// The recorded runs, oldest first.
+#[derive(Default)]
+pub struct History {
+    capacity: usize,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+    file: Option<Mutex<File>>,
+}
+
+impl History {
+    /// History keeping the last `capacity` runs, 0 keeping none in memory,
+    /// and appending them to `file` if set, whose last runs it starts with.
+    pub fn open(capacity: usize, file: Option<&Path>) -> Result<Self, String> {
+        let mut entries = VecDeque::new();
+        let file = match file {
+            Some(path) => {
+                let describe = |e: std::io::Error| format!("Cannot open the history file {}: {}", path.display(), e);
+                if path.exists() {
+                    // A line cut short by a crash is skipped
+                    let reader = BufReader::new(File::open(path).map_err(describe)?);
+                    for line in reader.lines() {
+                        let Ok(entry) = serde_json::from_str::<HistoryEntry>(&line.map_err(describe)?) else {
+                            continue;
+                        };
+                        entries.push_back(entry);
+                        if entries.len() > capacity {
+                            entries.pop_front();
+                        }
+                    }
+                }
+                Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path).map_err(describe)?))
+            }
+            None => None,
+        };
+        let next_id = entries.back().map_or(1, |entry: &HistoryEntry| entry.id + 1);
+        Ok(History {
+            capacity,
+            next_id: AtomicU64::new(next_id),
+            entries: Mutex::new(entries),
+            file,
+        })
+    }
+
+    /// Records a finished run, see `executions::Registration::finish`.
+    pub fn record(
+        &self,
+        source_hash: &str,
+        client: Option<String>,
+        session: Option<String>,
+        started_at: SystemTime,
+        output: &CodeOutput,
+    ) {
+        if self.capacity == 0 && self.file.is_none() {
+            return;
+        }
+        let truncated = output.result.chars().count() > MAX_HISTORY_OUTPUT;
+        let entry = HistoryEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            started_at_ms: millis(started_at.duration_since(UNIX_EPOCH).unwrap_or_default()),
+            source_hash: source_hash.to_string(),
+            client,
+            session,
+            duration_ms: millis(started_at.elapsed().unwrap_or_default()),
+            outcome: output.error_details.as_ref().map_or("ok", |details| details.kind.name()).to_string(),
+            error: output.error_details.as_ref().map(|details| details.message.clone()),
+            exit_code: output.exit_code,
+            instructions: output.stats.instructions_executed,
+            output: if truncated {
+                output.result.chars().take(MAX_HISTORY_OUTPUT).collect::<String>() + "..."
+            } else {
+                output.result.clone()
+            },
+            truncated,
+        };
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let line = serde_json::to_string(&entry).unwrap_or_default();
+            if let Err(e) = writeln!(file, "{}", line) {
+                eprintln!("Cannot write to the history file: {}", e);
+            }
+        }
+        if self.capacity > 0 {
+            let mut entries = self.lock();
+            entries.push_back(entry);
+            if entries.len() > self.capacity {
+                entries.pop_front();
+            }
+        }
+    }
+
+    /// The recorded runs matching `query`, newest first.
+    pub fn query(&self, query: &HistoryQuery) -> Vec<HistoryEntry> {
+        let matches = |filter: &Option<String>, value: &str| filter.as_deref().is_none_or(|filter| filter == value);
+        self.lock()
+            .iter()
+            .rev()
+            .filter(|entry| matches(&query.session, entry.session.as_deref().unwrap_or_default()))
+            .filter(|entry| {
+                let client = entry.client.as_deref().unwrap_or_default();
+                let address = client.rsplit_once(':').map_or(client, |(address, _)| address);
+                matches(&query.client, client) || matches(&query.client, address)
+            })
+            .filter(|entry| matches(&query.source_hash, &entry.source_hash))
+            .filter(|entry| matches(&query.outcome, &entry.outcome))
+            .filter(|entry| query.since_ms.is_none_or(|since| entry.started_at_ms >= since))
+            .take(query.limit.unwrap_or(DEFAULT_QUERY_LIMIT))
+            .cloned()
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<HistoryEntry>> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
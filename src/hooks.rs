@@ -0,0 +1,136 @@
+// Webhooks: a stored program uploaded with `"webhook": true` gets a URL,
+// /hooks/{token}, that other services can POST to, which makes the server a
+// small serverless runtime. The JSON body of the request is the global
+// `body` of the run, and what the program leaves in the global `response` is
+// the JSON of the HTTP response; without one, the response is the program's
+// output as text. The token is the only secret guarding the URL, so it is
+// random and kept when the program is replaced.
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::builtins;
+use crate::limits::ExecutionLimits;
+use crate::pipeline::Mode;
+use crate::programs::ProgramStore;
+use crate::semantic::StrictMode;
+use crate::worker::WorkerJob;
+use crate::{client_of, dispatch_request, CodeInput, CodeOutput, ErrorKind, ExecutionRegistry, ServerConfig, SessionStore};
+
+/// Time limit of a webhook run, unless the server's is lower: the service
+/// calling the hook waits for the response.
+const HOOK_TIMEOUT_MS: u64 = 10_000;
+
+/// Global holding the request body.
+const BODY_GLOBAL: &str = "body";
+
+/// Global the program may set to the response.
+const RESPONSE_GLOBAL: &str = "response";
+
+/// Response header giving the version of the program that ran.
+const VERSION_HEADER: &str = "x-program-version";
+
+/// A new token for a webhook URL, 128 bits no one can guess: RandomState is
+/// keyed from the operating system's random source, and SipHash under a
+/// secret key is unpredictable.
+pub fn new_token() -> String {
+    let random = |salt: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(salt);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", random(0), random(1))
+}
+
+// Route handler for POST /hooks/{token}
+#[utoipa::path(
+    tag = "programs",
+    request_body(content = serde_json::Value, description = "Any JSON value, given to the program as `body`"),
+    params(("token" = String, Path, description = "Token of the webhook, from the `webhook` of its program")),
+    responses(
+        (status = 200, description = "The program's `response` global as JSON, or else its output as text"),
+        (status = 400, description = "Runtime error", body = CodeOutput),
+        (status = 404, description = "No program has this webhook", body = CodeOutput),
+        (status = 408, description = "Program exceeded the time limit", body = CodeOutput),
+        (status = 422, description = "The body is not JSON", body = CodeOutput),
+        (status = 500, description = "Internal compiler error", body = CodeOutput),
+    )
+)]
+#[post("/hooks/{token}")]
+pub async fn call_hook(
+    token: web::Path<String>,
+    body: web::Bytes,
+    request: HttpRequest,
+    store: web::Data<ProgramStore>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+) -> impl Responder {
+    let Some(run) = store.hooked(&token) else {
+        return failure(CodeOutput::failure(ErrorKind::NotFound, "Unknown webhook".to_string()));
+    };
+    let body = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                let message = format!("Invalid request: the body is not JSON ({})", e);
+                return failure(CodeOutput::failure(ErrorKind::InvalidInput, message));
+            }
+        }
+    };
+    // Objects have no matching value in the language yet
+    let body = match builtins::from_json(body.clone()) {
+        Ok(_) => body,
+        Err(_) => serde_json::Value::String(body.to_string()),
+    };
+
+    let input = CodeInput {
+        source: String::new(),
+        language: String::new(),
+        mode: Mode::Program,
+        inspect_variables: false,
+        seed: None,
+        stdin: String::new(),
+        env: BTreeMap::new(),
+        files: BTreeMap::new(),
+        globals: BTreeMap::from([(BODY_GLOBAL.to_string(), body)]),
+        return_variables: vec![RESPONSE_GLOBAL.to_string()],
+        document: None,
+        opt_level: 0,
+        strict: StrictMode::default(),
+        verbose: false,
+        session: None,
+        limits: ExecutionLimits {
+            timeout_ms: Some(HOOK_TIMEOUT_MS),
+            ..ExecutionLimits::default()
+        },
+        profile: None,
+        record: false,
+        replay_clock: None,
+        locale: None,
+    };
+    let job = WorkerJob {
+        artifact: Some(run.artifact),
+        ..WorkerJob::new(input, config.policy.clone())
+    };
+    let mut output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+    let mut response = if output.error_details.is_some() {
+        failure(output)
+    } else {
+        match output.returned.as_mut().and_then(|returned| returned.remove(RESPONSE_GLOBAL)) {
+            Some(value) => HttpResponse::Ok().json(value),
+            None => HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(output.result),
+        }
+    };
+    response.headers_mut().insert(HeaderName::from_static(VERSION_HEADER), HeaderValue::from(run.version));
+    response
+}
+
+fn failure(output: CodeOutput) -> HttpResponse {
+    HttpResponse::build(output.status()).json(output)
+}
@@ -0,0 +1,765 @@
+// Translated error messages, so that a teaching deployment can show errors in
+// the language of its students. Translations are keyed by error code: the
+// English message of an error is matched against the pattern of a translation
+// of its code, and the parts it captures, such as names and positions, are put
+// into the translated text. Codes, kinds and positions stay the same in every
+// language. Messages without a matching translation are kept in English.
+
+/// Locales with translations, besides English.
+pub const LOCALES: &[&str] = &["es", "fr"];
+
+// A translated message. `pattern` is the English message with `{}` for each
+// part that varies; `text` refers to them by position, as `{0}`, `{1}`...
+struct Translation {
+    code: &'static str,
+    locale: &'static str,
+    pattern: &'static str,
+    text: &'static str,
+}
+
+// Translations of a code are tried in order, so a more specific pattern comes
+// before a more general one
+static CATALOG: &[Translation] = &[
+    // Spanish
+    Translation {
+        code: "E0001",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Expected expression, got {}",
+        text: "Error de sintaxis en {0}:{1}: se esperaba una expresión, pero se encontró {2}",
+    },
+    Translation {
+        code: "E0001",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Expected '(' after {}",
+        text: "Error de sintaxis en {0}:{1}: falta '(' después de {2}",
+    },
+    Translation {
+        code: "E0002",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Expected ';' after {}",
+        text: "Error de sintaxis en {0}:{1}: falta ';' al final de la instrucción",
+    },
+    Translation {
+        code: "E0003",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Expected ')' after {}",
+        text: "Error de sintaxis en {0}:{1}: falta ')' para cerrar el paréntesis",
+    },
+    Translation {
+        code: "E0004",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Expected '}' after block",
+        text: "Error de sintaxis en {0}:{1}: falta '}' para cerrar el bloque",
+    },
+    Translation {
+        code: "E0005",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Invalid assignment target",
+        text: "Error de sintaxis en {0}:{1}: solo se puede asignar un valor a una variable",
+    },
+    Translation {
+        code: "E0006",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Expected identifier",
+        text: "Error de sintaxis en {0}:{1}: se esperaba el nombre de la variable",
+    },
+    Translation {
+        code: "E0007",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Program too deeply nested: more than {} levels",
+        text: "Error de sintaxis en {0}:{1}: el programa anida más de {2} niveles",
+    },
+    Translation {
+        code: "E0008",
+        locale: "es",
+        pattern: "Parser error at {}:{}: Chain of {} operators is too long: at most {} can follow one another",
+        text: "Error de sintaxis en {0}:{1}: la cadena de {2} operadores es demasiado larga: se admiten como máximo {3} seguidos",
+    },
+    Translation {
+        code: "E0101",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: Unexpected character: {}",
+        text: "Error léxico en {0}:{1}: carácter inesperado: {2}",
+    },
+    Translation {
+        code: "E0102",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: Unterminated string literal",
+        text: "Error léxico en {0}:{1}: falta cerrar la cadena con '\"'",
+    },
+    Translation {
+        code: "E0103",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: Unterminated block comment",
+        text: "Error léxico en {0}:{1}: falta cerrar el comentario con '*/'",
+    },
+    Translation {
+        code: "E0105",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: Source too long: more than {} tokens",
+        text: "Error léxico en {0}:{1}: el programa tiene más de {2} tokens",
+    },
+    Translation {
+        code: "E0106",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: String literal longer than {} bytes",
+        text: "Error léxico en {0}:{1}: la cadena ocupa más de {2} bytes",
+    },
+    Translation {
+        code: "E0107",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: Unknown escape sequence: {}",
+        text: "Error léxico en {0}:{1}: secuencia de escape desconocida: {2}",
+    },
+    Translation {
+        code: "E0104",
+        locale: "es",
+        pattern: "Lexer error at {}:{}: Invalid {} literal: {}",
+        text: "Error léxico en {0}:{1}: número no válido: {3}",
+    },
+    Translation {
+        code: "E0201",
+        locale: "es",
+        pattern: "{} error at {}:{}: Variable '{}' already declared in this scope",
+        text: "Error de compilación en {1}:{2}: la variable '{3}' ya está declarada en este bloque",
+    },
+    Translation {
+        code: "E0201",
+        locale: "es",
+        pattern: "Bytecode generator error: Variable '{}' already declared in this scope",
+        text: "Error de compilación: la variable '{0}' ya está declarada en este bloque",
+    },
+    Translation {
+        code: "E0202",
+        locale: "es",
+        pattern: "Bytecode generator error: Function '{}' expects at least {} argument(s), got {}",
+        text: "Error de compilación: la función '{0}' espera al menos {1} argumento(s), pero recibió {2}",
+    },
+    Translation {
+        code: "E0202",
+        locale: "es",
+        pattern: "Bytecode generator error: Function '{}' expects {} argument(s) after its format string, got {}",
+        text: "Error de compilación: la función '{0}' espera {1} argumento(s) después de su cadena de formato, pero recibió {2}",
+    },
+    Translation {
+        code: "E0202",
+        locale: "es",
+        pattern: "Bytecode generator error: Function '{}' expects {} argument(s), got {}",
+        text: "Error de compilación: la función '{0}' espera {1} argumento(s), pero recibió {2}",
+    },
+    Translation {
+        code: "E0203",
+        locale: "es",
+        pattern: "Type error in {}: an operand is null",
+        text: "Error de tipo: un operando es null",
+    },
+    Translation {
+        code: "E0203",
+        locale: "es",
+        pattern: "Type error in {}",
+        text: "Error de tipo: la operación no admite esos valores",
+    },
+    Translation {
+        code: "E0204",
+        locale: "es",
+        pattern: "Bytecode generator error: Cannot import \"{}\": {}",
+        text: "Error de compilación: no se puede importar \"{0}\": las importaciones solo se resuelven al compilar archivos desde la línea de comandos",
+    },
+    Translation {
+        code: "E0208",
+        locale: "es",
+        pattern: "Semantic error at {}:{}: Cannot assign {} to {} variable '{}' without a conversion",
+        text: "Error de compilación en {0}:{1}: no se puede guardar un valor {2} en la variable {3} '{4}' sin una conversión",
+    },
+    Translation {
+        code: "E0208",
+        locale: "es",
+        pattern: "Semantic error at {}:{}: Cannot mix {} and {} in '{}' without a conversion",
+        text: "Error de compilación en {0}:{1}: no se pueden mezclar {2} y {3} en '{4}' sin una conversión",
+    },
+    Translation {
+        code: "E0209",
+        locale: "es",
+        pattern: "Semantic error at {}:{}: Condition of {} must be bool, found {}",
+        text: "Error de compilación en {0}:{1}: la condición de {2} debe ser bool, pero es {3}",
+    },
+    Translation {
+        code: "E0210",
+        locale: "es",
+        pattern: "Semantic error at {}:{}: Variable '{}' must be initialized where it is declared",
+        text: "Error de compilación en {0}:{1}: la variable '{2}' debe recibir un valor donde se declara",
+    },
+    Translation {
+        code: "E0211",
+        locale: "es",
+        pattern: "Semantic error at {}:{}: Variable '{}' is declared but never read",
+        text: "Error de compilación en {0}:{1}: la variable '{2}' se declara pero nunca se lee",
+    },
+    Translation {
+        code: "E0212",
+        locale: "es",
+        pattern: "Bytecode generator error: Invalid format string: unclosed '{'",
+        text: "Error de compilación: cadena de formato no válida: falta la '}' de una '{'",
+    },
+    Translation {
+        code: "E0212",
+        locale: "es",
+        pattern: "Bytecode generator error: Invalid format string: unmatched '}'",
+        text: "Error de compilación: cadena de formato no válida: una '}' no tiene su '{'",
+    },
+    Translation {
+        code: "E0212",
+        locale: "es",
+        pattern: "Bytecode generator error: Invalid format string: unknown placeholder '{}'",
+        text: "Error de compilación: cadena de formato no válida: marcador desconocido '{0}'",
+    },
+    Translation {
+        code: "E0213",
+        locale: "es",
+        pattern: "Semantic error at {}:{}: Placeholder {} of {}() needs {}, found {}",
+        text: "Error de compilación en {0}:{1}: el marcador {2} de {3}() necesita {4}, pero recibe {5}",
+    },
+    Translation {
+        code: "E0214",
+        locale: "es",
+        pattern: "Bytecode generator error: Undefined function: {}",
+        text: "Error de compilación: función no definida: {0}",
+    },
+    Translation {
+        code: "R0101",
+        locale: "es",
+        pattern: "Division by zero",
+        text: "División por cero",
+    },
+    Translation {
+        code: "R0102",
+        locale: "es",
+        pattern: "Undefined variable: {}",
+        text: "Variable no definida: {0}",
+    },
+    Translation {
+        code: "R0103",
+        locale: "es",
+        pattern: "Undefined function: {}",
+        text: "Función no definida: {0}",
+    },
+    Translation {
+        code: "R0104",
+        locale: "es",
+        pattern: "Permission denied: {}() requires the '{}' capability",
+        text: "Permiso denegado: {0}() requiere la capacidad '{1}'",
+    },
+    Translation {
+        code: "R0104",
+        locale: "es",
+        pattern: "Permission denied: {}() may reveal its arguments through its running time, and only constant-time builtins are allowed",
+        text: "Permiso denegado: {0}() puede revelar sus argumentos por su tiempo de ejecución, y solo se permiten funciones de tiempo constante",
+    },
+    Translation {
+        code: "R0106",
+        locale: "es",
+        pattern: "Instruction limit exceeded: the program ran {} instructions",
+        text: "Límite de instrucciones superado: el programa ejecutó {0} instrucciones",
+    },
+    Translation {
+        code: "R0107",
+        locale: "es",
+        pattern: "Memory limit exceeded: the program holds more than {} bytes",
+        text: "Límite de memoria superado: el programa ocupa más de {0} bytes",
+    },
+    Translation {
+        code: "R0112",
+        locale: "es",
+        pattern: "Output limit exceeded: the program printed more than {} bytes",
+        text: "Límite de salida superado: el programa imprimió más de {0} bytes",
+    },
+    Translation {
+        code: "R0113",
+        locale: "es",
+        pattern: "Call depth limit exceeded: calls are nested more than {} deep",
+        text: "Límite de profundidad de llamadas superado: las llamadas se anidan a más de {0} niveles",
+    },
+    Translation {
+        code: "R0114",
+        locale: "es",
+        pattern: "Assertion failed: {}",
+        text: "La aserción falló: {0}",
+    },
+    Translation {
+        code: "R0114",
+        locale: "es",
+        pattern: "Assertion failed",
+        text: "La aserción falló",
+    },
+    Translation {
+        code: "R0115",
+        locale: "es",
+        pattern: "Index {} is out of bounds for an array of length {}",
+        text: "El índice {0} está fuera de los límites de un array de longitud {1}",
+    },
+    Translation {
+        code: "R0115",
+        locale: "es",
+        pattern: "Array index must be a whole number, got {}",
+        text: "El índice de un array debe ser un número entero, pero es {0}",
+    },
+    Translation {
+        code: "R0111",
+        locale: "es",
+        pattern: "Execution timed out after {} ms",
+        text: "La ejecución superó el tiempo límite de {0} ms",
+    },
+    Translation {
+        code: "W0001",
+        locale: "es",
+        pattern: "Assignment used as a condition; did you mean '=='?",
+        text: "Asignación usada como condición; ¿quería escribir '=='?",
+    },
+    Translation {
+        code: "W0002",
+        locale: "es",
+        pattern: "Variable '{}' is used without being declared",
+        text: "La variable '{0}' se usa sin haber sido declarada",
+    },
+    Translation {
+        code: "W0003",
+        locale: "es",
+        pattern: "'null' used in arithmetic; this fails at runtime",
+        text: "'null' usado en una operación aritmética; esto falla al ejecutar el programa",
+    },
+    Translation {
+        code: "W0003",
+        locale: "es",
+        pattern: "Variable '{}' may be null here, and arithmetic on null fails at runtime",
+        text: "La variable '{0}' puede ser null aquí, y las operaciones aritméticas con null fallan al ejecutar el programa",
+    },
+    Translation {
+        code: "W0004",
+        locale: "es",
+        pattern: "Dividing two ints truncates the quotient before it is stored in float variable '{}'",
+        text: "La división de dos int trunca el cociente antes de guardarlo en la variable float '{0}'",
+    },
+    Translation {
+        code: "W0004",
+        locale: "es",
+        pattern: "Dividing two ints truncates the quotient before it is used with a float",
+        text: "La división de dos int trunca el cociente antes de usarlo con un float",
+    },
+    Translation {
+        code: "W0004",
+        locale: "es",
+        pattern: "{} / {} divides two ints, so it is {}, not {}",
+        text: "{0} / {1} divide dos int, así que vale {2}, no {3}",
+    },
+    Translation {
+        code: "W0004",
+        locale: "es",
+        pattern: "'{}' is declared int but may hold a float, so whether this division truncates depends on its value",
+        text: "'{0}' está declarada int pero puede contener un float, así que esta división trunca o no según su valor",
+    },
+    // French
+    Translation {
+        code: "E0001",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Expected expression, got {}",
+        text: "Erreur de syntaxe à {0}:{1} : expression attendue, mais {2} trouvé",
+    },
+    Translation {
+        code: "E0001",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Expected '(' after {}",
+        text: "Erreur de syntaxe à {0}:{1} : '(' manquant après {2}",
+    },
+    Translation {
+        code: "E0002",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Expected ';' after {}",
+        text: "Erreur de syntaxe à {0}:{1} : ';' manquant à la fin de l'instruction",
+    },
+    Translation {
+        code: "E0003",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Expected ')' after {}",
+        text: "Erreur de syntaxe à {0}:{1} : ')' manquant pour fermer la parenthèse",
+    },
+    Translation {
+        code: "E0004",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Expected '}' after block",
+        text: "Erreur de syntaxe à {0}:{1} : '}' manquant pour fermer le bloc",
+    },
+    Translation {
+        code: "E0005",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Invalid assignment target",
+        text: "Erreur de syntaxe à {0}:{1} : seule une variable peut recevoir une valeur",
+    },
+    Translation {
+        code: "E0006",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Expected identifier",
+        text: "Erreur de syntaxe à {0}:{1} : nom de variable attendu",
+    },
+    Translation {
+        code: "E0007",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Program too deeply nested: more than {} levels",
+        text: "Erreur de syntaxe à {0}:{1} : le programme imbrique plus de {2} niveaux",
+    },
+    Translation {
+        code: "E0008",
+        locale: "fr",
+        pattern: "Parser error at {}:{}: Chain of {} operators is too long: at most {} can follow one another",
+        text: "Erreur de syntaxe à {0}:{1} : la chaîne de {2} opérateurs est trop longue : {3} au plus peuvent se suivre",
+    },
+    Translation {
+        code: "E0101",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: Unexpected character: {}",
+        text: "Erreur lexicale à {0}:{1} : caractère inattendu : {2}",
+    },
+    Translation {
+        code: "E0102",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: Unterminated string literal",
+        text: "Erreur lexicale à {0}:{1} : chaîne non fermée par '\"'",
+    },
+    Translation {
+        code: "E0103",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: Unterminated block comment",
+        text: "Erreur lexicale à {0}:{1} : commentaire non fermé par '*/'",
+    },
+    Translation {
+        code: "E0105",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: Source too long: more than {} tokens",
+        text: "Erreur lexicale à {0}:{1} : le programme compte plus de {2} lexèmes",
+    },
+    Translation {
+        code: "E0106",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: String literal longer than {} bytes",
+        text: "Erreur lexicale à {0}:{1} : la chaîne dépasse {2} octets",
+    },
+    Translation {
+        code: "E0107",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: Unknown escape sequence: {}",
+        text: "Erreur lexicale à {0}:{1} : séquence d'échappement inconnue : {2}",
+    },
+    Translation {
+        code: "E0104",
+        locale: "fr",
+        pattern: "Lexer error at {}:{}: Invalid {} literal: {}",
+        text: "Erreur lexicale à {0}:{1} : nombre invalide : {3}",
+    },
+    Translation {
+        code: "E0201",
+        locale: "fr",
+        pattern: "{} error at {}:{}: Variable '{}' already declared in this scope",
+        text: "Erreur de compilation à {1}:{2} : la variable '{3}' est déjà déclarée dans ce bloc",
+    },
+    Translation {
+        code: "E0201",
+        locale: "fr",
+        pattern: "Bytecode generator error: Variable '{}' already declared in this scope",
+        text: "Erreur de compilation : la variable '{0}' est déjà déclarée dans ce bloc",
+    },
+    Translation {
+        code: "E0202",
+        locale: "fr",
+        pattern: "Bytecode generator error: Function '{}' expects at least {} argument(s), got {}",
+        text: "Erreur de compilation : la fonction '{0}' attend au moins {1} argument(s), mais en a reçu {2}",
+    },
+    Translation {
+        code: "E0202",
+        locale: "fr",
+        pattern: "Bytecode generator error: Function '{}' expects {} argument(s) after its format string, got {}",
+        text: "Erreur de compilation : la fonction '{0}' attend {1} argument(s) après sa chaîne de format, mais en a reçu {2}",
+    },
+    Translation {
+        code: "E0202",
+        locale: "fr",
+        pattern: "Bytecode generator error: Function '{}' expects {} argument(s), got {}",
+        text: "Erreur de compilation : la fonction '{0}' attend {1} argument(s), mais en a reçu {2}",
+    },
+    Translation {
+        code: "E0203",
+        locale: "fr",
+        pattern: "Type error in {}: an operand is null",
+        text: "Erreur de type : un opérande est null",
+    },
+    Translation {
+        code: "E0203",
+        locale: "fr",
+        pattern: "Type error in {}",
+        text: "Erreur de type : l'opération n'accepte pas ces valeurs",
+    },
+    Translation {
+        code: "E0204",
+        locale: "fr",
+        pattern: "Bytecode generator error: Cannot import \"{}\": {}",
+        text: "Erreur de compilation : impossible d'importer \"{0}\" : les imports ne sont résolus qu'en compilant des fichiers depuis la ligne de commande",
+    },
+    Translation {
+        code: "E0208",
+        locale: "fr",
+        pattern: "Semantic error at {}:{}: Cannot assign {} to {} variable '{}' without a conversion",
+        text: "Erreur de compilation à {0}:{1} : impossible de ranger une valeur {2} dans la variable {3} '{4}' sans conversion",
+    },
+    Translation {
+        code: "E0208",
+        locale: "fr",
+        pattern: "Semantic error at {}:{}: Cannot mix {} and {} in '{}' without a conversion",
+        text: "Erreur de compilation à {0}:{1} : impossible de mélanger {2} et {3} dans '{4}' sans conversion",
+    },
+    Translation {
+        code: "E0209",
+        locale: "fr",
+        pattern: "Semantic error at {}:{}: Condition of {} must be bool, found {}",
+        text: "Erreur de compilation à {0}:{1} : la condition de {2} doit être un bool, mais c'est un {3}",
+    },
+    Translation {
+        code: "E0210",
+        locale: "fr",
+        pattern: "Semantic error at {}:{}: Variable '{}' must be initialized where it is declared",
+        text: "Erreur de compilation à {0}:{1} : la variable '{2}' doit recevoir une valeur là où elle est déclarée",
+    },
+    Translation {
+        code: "E0211",
+        locale: "fr",
+        pattern: "Semantic error at {}:{}: Variable '{}' is declared but never read",
+        text: "Erreur de compilation à {0}:{1} : la variable '{2}' est déclarée mais jamais lue",
+    },
+    Translation {
+        code: "E0212",
+        locale: "fr",
+        pattern: "Bytecode generator error: Invalid format string: unclosed '{'",
+        text: "Erreur de compilation : chaîne de format invalide : une '{' n'est pas fermée",
+    },
+    Translation {
+        code: "E0212",
+        locale: "fr",
+        pattern: "Bytecode generator error: Invalid format string: unmatched '}'",
+        text: "Erreur de compilation : chaîne de format invalide : une '}' n'a pas de '{'",
+    },
+    Translation {
+        code: "E0212",
+        locale: "fr",
+        pattern: "Bytecode generator error: Invalid format string: unknown placeholder '{}'",
+        text: "Erreur de compilation : chaîne de format invalide : marqueur inconnu '{0}'",
+    },
+    Translation {
+        code: "E0213",
+        locale: "fr",
+        pattern: "Semantic error at {}:{}: Placeholder {} of {}() needs {}, found {}",
+        text: "Erreur de compilation à {0}:{1} : le marqueur {2} de {3}() attend {4}, mais reçoit {5}",
+    },
+    Translation {
+        code: "E0214",
+        locale: "fr",
+        pattern: "Bytecode generator error: Undefined function: {}",
+        text: "Erreur de compilation : fonction non définie : {0}",
+    },
+    Translation {
+        code: "R0101",
+        locale: "fr",
+        pattern: "Division by zero",
+        text: "Division par zéro",
+    },
+    Translation {
+        code: "R0102",
+        locale: "fr",
+        pattern: "Undefined variable: {}",
+        text: "Variable non définie : {0}",
+    },
+    Translation {
+        code: "R0103",
+        locale: "fr",
+        pattern: "Undefined function: {}",
+        text: "Fonction non définie : {0}",
+    },
+    Translation {
+        code: "R0104",
+        locale: "fr",
+        pattern: "Permission denied: {}() requires the '{}' capability",
+        text: "Permission refusée : {0}() nécessite la capacité '{1}'",
+    },
+    Translation {
+        code: "R0104",
+        locale: "fr",
+        pattern: "Permission denied: {}() may reveal its arguments through its running time, and only constant-time builtins are allowed",
+        text: "Permission refusée : {0}() peut révéler ses arguments par son temps d'exécution, et seules les fonctions en temps constant sont autorisées",
+    },
+    Translation {
+        code: "R0106",
+        locale: "fr",
+        pattern: "Instruction limit exceeded: the program ran {} instructions",
+        text: "Limite d'instructions dépassée : le programme a exécuté {0} instructions",
+    },
+    Translation {
+        code: "R0107",
+        locale: "fr",
+        pattern: "Memory limit exceeded: the program holds more than {} bytes",
+        text: "Limite de mémoire dépassée : le programme occupe plus de {0} octets",
+    },
+    Translation {
+        code: "R0112",
+        locale: "fr",
+        pattern: "Output limit exceeded: the program printed more than {} bytes",
+        text: "Limite de sortie dépassée : le programme a affiché plus de {0} octets",
+    },
+    Translation {
+        code: "R0113",
+        locale: "fr",
+        pattern: "Call depth limit exceeded: calls are nested more than {} deep",
+        text: "Limite de profondeur d'appels dépassée : les appels sont imbriqués sur plus de {0} niveaux",
+    },
+    Translation {
+        code: "R0114",
+        locale: "fr",
+        pattern: "Assertion failed: {}",
+        text: "L'assertion a échoué : {0}",
+    },
+    Translation {
+        code: "R0114",
+        locale: "fr",
+        pattern: "Assertion failed",
+        text: "L'assertion a échoué",
+    },
+    Translation {
+        code: "R0115",
+        locale: "fr",
+        pattern: "Index {} is out of bounds for an array of length {}",
+        text: "L'indice {0} est hors des limites d'un tableau de longueur {1}",
+    },
+    Translation {
+        code: "R0115",
+        locale: "fr",
+        pattern: "Array index must be a whole number, got {}",
+        text: "L'indice d'un tableau doit être un nombre entier, mais vaut {0}",
+    },
+    Translation {
+        code: "R0111",
+        locale: "fr",
+        pattern: "Execution timed out after {} ms",
+        text: "L'exécution a dépassé le délai de {0} ms",
+    },
+    Translation {
+        code: "W0001",
+        locale: "fr",
+        pattern: "Assignment used as a condition; did you mean '=='?",
+        text: "Affectation utilisée comme condition ; vouliez-vous écrire '==' ?",
+    },
+    Translation {
+        code: "W0002",
+        locale: "fr",
+        pattern: "Variable '{}' is used without being declared",
+        text: "La variable '{0}' est utilisée sans avoir été déclarée",
+    },
+    Translation {
+        code: "W0003",
+        locale: "fr",
+        pattern: "'null' used in arithmetic; this fails at runtime",
+        text: "'null' utilisé dans un calcul ; cela échoue à l'exécution",
+    },
+    Translation {
+        code: "W0003",
+        locale: "fr",
+        pattern: "Variable '{}' may be null here, and arithmetic on null fails at runtime",
+        text: "La variable '{0}' peut valoir null ici, et les calculs avec null échouent à l'exécution",
+    },
+    Translation {
+        code: "W0004",
+        locale: "fr",
+        pattern: "Dividing two ints truncates the quotient before it is stored in float variable '{}'",
+        text: "La division de deux int tronque le quotient avant qu'il soit stocké dans la variable float '{0}'",
+    },
+    Translation {
+        code: "W0004",
+        locale: "fr",
+        pattern: "Dividing two ints truncates the quotient before it is used with a float",
+        text: "La division de deux int tronque le quotient avant qu'il soit utilisé avec un float",
+    },
+    Translation {
+        code: "W0004",
+        locale: "fr",
+        pattern: "{} / {} divides two ints, so it is {}, not {}",
+        text: "{0} / {1} divise deux int, donc vaut {2} et non {3}",
+    },
+    Translation {
+        code: "W0004",
+        locale: "fr",
+        pattern: "'{}' is declared int but may hold a float, so whether this division truncates depends on its value",
+        text: "'{0}' est déclarée int mais peut contenir un float, donc cette division tronque ou non selon sa valeur",
+    },
+];
+
+/// The locale to answer in: `requested`, the `locale` field of a request, if
+/// given, otherwise the preferred language of an `Accept-Language` header
+/// that has translations. None stands for English.
+pub fn negotiate(requested: Option<&str>, accept_language: Option<&str>) -> Option<&'static str> {
+    if let Some(requested) = requested {
+        return supported(requested);
+    }
+    // `fr-CH, fr;q=0.9, en;q=0.8`: languages in order of decreasing quality
+    let mut ranges: Vec<(&str, f32)> = accept_language?
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+            Some((tag, quality))
+        })
+        .filter(|&(_, quality)| quality > 0.0)
+        .collect();
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges
+        .into_iter()
+        .map(|(tag, _)| primary_language(tag))
+        .find(|language| *language == "en" || LOCALES.contains(&language.as_str()))
+        .and_then(|language| supported(&language))
+}
+
+// The locale of `tag`, e.g. "es" for "es-MX", if it has translations
+fn supported(tag: &str) -> Option<&'static str> {
+    let language = primary_language(tag);
+    LOCALES.iter().copied().find(|locale| *locale == language)
+}
+
+fn primary_language(tag: &str) -> String {
+    tag.split(['-', '_']).next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+/// `message`, an error filed under `code`, translated into `locale`, if the
+/// catalog has a translation for it.
+pub fn translate(code: &str, message: &str, locale: &str) -> Option<String> {
+    CATALOG
+        .iter()
+        .filter(|translation| translation.code == code && translation.locale == locale)
+        .find_map(|translation| {
+            let parts = captures(translation.pattern, message)?;
+            let mut text = translation.text.to_string();
+            for (index, part) in parts.iter().enumerate() {
+                text = text.replace(&format!("{{{}}}", index), part);
+            }
+            Some(text)
+        })
+}
+
+// The parts of `message` matching the `{}` of `pattern`, if it matches. Each
+// part ends at the first occurrence of the text that follows it in `pattern`.
+fn captures<'a>(pattern: &str, message: &'a str) -> Option<Vec<&'a str>> {
+    let mut literals = pattern.split("{}");
+    let mut rest = message.strip_prefix(literals.next()?)?;
+    let mut parts = Vec::new();
+    for literal in literals {
+        let end = if literal.is_empty() { rest.len() } else { rest.find(literal)? };
+        parts.push(&rest[..end]);
+        rest = &rest[end + literal.len()..];
+    }
+    rest.is_empty().then_some(parts)
+}
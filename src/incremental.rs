@@ -0,0 +1,170 @@
+// Incremental compilation for the edit-and-rerun loop of `--watch`. Each
+// top-level item is compiled on its own and cached under a hash of its syntax
+// tree, so after an edit only the items that changed are compiled again.
+//
+// Top-level items share no generator state besides the function table
+// (globals are looked up by name), which makes compiling them separately
+// with it and appending their code, with jump targets moved by the item's
+// offset, constants moved into one table and functions placed in the order
+// they are declared, equal to compiling the whole program. The function
+// table is part of every item's cache key.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::bytecode::{self, BytecodeGenerator, Module};
+use crate::parser::ASTNode;
+
+/// Compiler that remembers the code of top-level items between compilations.
+#[derive(Default)]
+pub struct IncrementalCompiler {
+    cache: HashMap<u64, Module>, // Item hash -> code of the item alone
+}
+
+/// What an incremental compilation had to do.
+#[derive(Debug, Clone)]
+pub struct CompileReport {
+    pub items: usize,         // Top-level items in the program
+    pub rebuilt: Vec<String>, // Short descriptions of the items compiled again
+}
+
+impl fmt::Display for CompileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.rebuilt.len() == self.items {
+            return write!(f, "compiled all {} top-level items", self.items);
+        }
+        write!(
+            f,
+            "recompiled {} of {} top-level items ({} unchanged)",
+            self.rebuilt.len(),
+            self.items,
+            self.items - self.rebuilt.len()
+        )?;
+        if !self.rebuilt.is_empty() {
+            write!(f, ": {}", self.rebuilt.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl IncrementalCompiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles a program, reusing the code of items that did not change
+    /// since the previous call. Items that disappeared are dropped from the
+    /// cache. The chunks of the module know which item their code is from, as
+    /// those of the whole program compiled at once do.
+    pub fn compile(&mut self, program: ASTNode) -> Result<(Module, CompileReport), Box<dyn Error>> {
+        let items = match program {
+            ASTNode::Program(items) => items,
+            item => vec![item],
+        };
+
+        let functions = bytecode::function_table(&items)?;
+        let mut function_order: Vec<_> = functions.iter().collect();
+        function_order.sort_by_key(|(_, (index, _))| *index);
+        let functions_hash = item_hash(&function_order);
+        let generator = |deferred: &[ASTNode]| {
+            BytecodeGenerator::new()
+                .with_functions(functions.clone())
+                .with_deferred(deferred.to_vec())
+        };
+
+        let mut cache = HashMap::with_capacity(items.len());
+        let mut module = Module::default();
+        let mut report = CompileReport {
+            items: items.len(),
+            rebuilt: Vec::new(),
+        };
+
+        // Deferred items are placed after all others, the last deferred first.
+        // A `return` runs those deferred before it, so they are part of the key
+        let mut deferred = Vec::new();
+        let mut deferred_statements = Vec::new();
+        for (index, item) in items.into_iter().enumerate() {
+            let mut key = item_hash(&item) ^ functions_hash;
+            if !deferred_statements.is_empty() {
+                key ^= item_hash(&deferred_statements);
+            }
+            let item_code = match self.cache.remove(&key).or_else(|| cache.get(&key).cloned()) {
+                Some(item_code) => item_code,
+                None => {
+                    report.rebuilt.push(describe(&item));
+                    match &item {
+                        ASTNode::DeferStatement(statement) => generator(&deferred_statements).generate((**statement).clone())?,
+                        item => generator(&deferred_statements).generate(item.clone())?,
+                    }
+                }
+            };
+
+            if let ASTNode::DeferStatement(statement) = item {
+                deferred_statements.push(*statement);
+                deferred.push((index, item_code.clone()));
+            } else {
+                append_item(&mut module, index, &item_code);
+            }
+            cache.insert(key, item_code);
+        }
+        for (index, item_code) in deferred.iter().rev() {
+            append_item(&mut module, *index, item_code);
+        }
+
+        // Ends as a whole program does: as an empty one
+        module.append(&BytecodeGenerator::new().generate(ASTNode::Program(Vec::new()))?);
+        self.cache = cache;
+        Ok((module, report))
+    }
+}
+
+// Appends the code of the item numbered `index` to the module, recording
+// where the code of the item and of the functions it declares begins
+fn append_item(module: &mut Module, index: usize, item_code: &Module) {
+    let start = module.chunks.first().map_or(0, |program| program.code.len());
+    let functions = module.chunks.len().max(1);
+    module.append(item_code);
+    if let Some(program) = module.chunks.first_mut() {
+        program.statements.push((start, index));
+    }
+    for function in module.chunks.iter_mut().skip(functions) {
+        function.statements = vec![(0, index)];
+    }
+}
+
+// Identifies an item by its whole syntax tree, or the functions by their
+// function table
+fn item_hash(item: &impl fmt::Debug) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", item).hash(&mut hasher);
+    hasher.finish()
+}
+
+// Short description of an item for the report, e.g. "int total"
+fn describe(item: &ASTNode) -> String {
+    match item {
+        ASTNode::VarDeclaration { var_type, name, .. } => format!("{} {}", var_type, name),
+        ASTNode::ExpressionStatement(expr) => match expr.as_ref() {
+            ASTNode::AssignmentExpression { name, .. } | ASTNode::IndexAssignment { name, .. } => {
+                format!("assignment to {}", name)
+            }
+            ASTNode::CallExpression { callee, .. } => match callee.as_ref() {
+                ASTNode::Identifier(name) => format!("call to {}", name),
+                _ => "call".to_string(),
+            },
+            _ => "expression".to_string(),
+        },
+        ASTNode::IfStatement { .. } => "if statement".to_string(),
+        ASTNode::WhileStatement { .. } => "while loop".to_string(),
+        ASTNode::Block(_) => "block".to_string(),
+        ASTNode::ReturnStatement(_) => "return".to_string(),
+        ASTNode::PrintStatement(_) => "print".to_string(),
+        ASTNode::DeferStatement(statement) => format!("defer {}", describe(statement)),
+        ASTNode::FunctionDeclaration { name, .. } => format!("fn {}", name),
+        ASTNode::TestBlock { name, .. } => format!("test {:?}", name),
+        _ => "statement".to_string(),
+    }
+}
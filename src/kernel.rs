@@ -0,0 +1,427 @@
+// Jupyter kernel (feature "jupyter"), started by Jupyter as
+// `compiler kernel <connection file>`, so that programs can be written in
+// Jupyter and VS Code notebooks. It speaks the Jupyter messaging protocol over
+// the ZeroMQ sockets named in the connection file: requests arrive on the
+// shell and control sockets, output is published on iopub, and the heartbeat
+// socket echoes what it receives.
+//
+// The cells of a notebook run one after another on the same VM, so the
+// variables declared by a cell are visible to the next ones.
+
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+use sha2::Sha256;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeromq::{PubSocket, RepSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+use crate::optimizer;
+use crate::sandbox::SandboxPolicy;
+use crate::vm::{CancellationToken, VirtualMachine, VmConfig};
+use crate::{catch_panics, compile_source, ErrorDetails, ErrorKind};
+
+/// Version of the messaging protocol implemented.
+const PROTOCOL_VERSION: &str = "5.3";
+
+// Frame separating the routing identities of a message from its content
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+// Connection file written by Jupyter for the kernel
+#[derive(Deserialize)]
+struct ConnectionInfo {
+    transport: String,
+    ip: String,
+    shell_port: u16,
+    iopub_port: u16,
+    stdin_port: u16,
+    control_port: u16,
+    hb_port: u16,
+    key: String,              // Empty when messages are not signed
+    signature_scheme: String, // Only "hmac-sha256" is supported
+}
+
+impl ConnectionInfo {
+    fn endpoint(&self, port: u16) -> String {
+        format!("{}://{}:{}", self.transport, self.ip, port)
+    }
+}
+
+// A message received on the shell or control socket
+struct Message {
+    identities: Vec<Bytes>, // Routing prefix, sent back with the replies
+    header: Json,
+    content: Json,
+}
+
+impl Message {
+    fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or_default()
+    }
+}
+
+// Signs, checks and frames messages for one kernel session. Clones share the
+// counter of message ids.
+#[derive(Clone)]
+struct Codec {
+    key: Option<Hmac<Sha256>>, // None when messages are not signed
+    session: String,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Codec {
+    fn decode(&self, message: ZmqMessage) -> Result<Message, String> {
+        let frames = message.into_vec();
+        let delimiter = frames
+            .iter()
+            .position(|frame| frame.as_ref() == DELIMITER)
+            .ok_or("Message without delimiter")?;
+        let [signature, header, parent_header, metadata, content] = frames
+            .get(delimiter + 1..delimiter + 6)
+            .and_then(|parts| <&[Bytes; 5]>::try_from(parts).ok())
+            .ok_or("Message with missing parts")?;
+        if let Some(key) = &self.key {
+            let mut mac = key.clone();
+            for part in [header, parent_header, metadata, content] {
+                mac.update(part);
+            }
+            let signature = decode_hex(signature).ok_or("Malformed signature")?;
+            mac.verify_slice(&signature).map_err(|_| "Wrong signature")?;
+        }
+        let parse = |part: &Bytes| serde_json::from_slice(part).map_err(|e| format!("Malformed message: {}", e));
+        Ok(Message {
+            identities: frames[..delimiter].to_vec(),
+            header: parse(header)?,
+            content: parse(content)?,
+        })
+    }
+
+    // A message answering or caused by `parent`, to send to `identities`
+    fn encode(&self, identities: &[Bytes], msg_type: &str, parent: &Message, content: Json) -> ZmqMessage {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = json!({
+            "msg_id": format!("{}-{}", self.session, id),
+            "session": self.session,
+            "username": "kernel",
+            "date": timestamp(SystemTime::now()),
+            "msg_type": msg_type,
+            "version": PROTOCOL_VERSION,
+        });
+        let parts = [header, parent.header.clone(), json!({}), content].map(|part| Bytes::from(part.to_string()));
+        let signature = match &self.key {
+            Some(key) => {
+                let mut mac = key.clone();
+                for part in &parts {
+                    mac.update(part);
+                }
+                encode_hex(&mac.finalize().into_bytes())
+            }
+            None => String::new(),
+        };
+
+        let mut frames = identities.to_vec();
+        frames.push(Bytes::from_static(DELIMITER));
+        frames.push(Bytes::from(signature));
+        frames.extend(parts);
+        ZmqMessage::try_from(frames).expect("a message has frames")
+    }
+}
+
+// The shell side of the kernel: runs cells and publishes their output
+struct Kernel {
+    codec: Codec,
+    iopub: PubSocket,
+    vm: Option<VirtualMachine>, // Taken while a cell runs
+    execution_count: u64,
+    cancel_token: CancellationToken, // Also held by the control loop, to interrupt a cell
+}
+
+impl Kernel {
+    async fn publish(&mut self, msg_type: &str, parent: &Message, content: Json) {
+        let topic = Bytes::from(msg_type.to_string());
+        let message = self.codec.encode(&[topic], msg_type, parent, content);
+        if let Err(e) = self.iopub.send(message).await {
+            eprintln!("Failed to publish {}: {}", msg_type, e);
+        }
+    }
+
+    // Handles a shell request, returning the type and content of the reply
+    async fn handle(&mut self, request: &Message) -> Option<(String, Json)> {
+        self.publish("status", request, json!({ "execution_state": "busy" })).await;
+        let reply = match request.msg_type() {
+            "kernel_info_request" => Some(("kernel_info_reply".to_string(), kernel_info())),
+            "execute_request" => Some(("execute_reply".to_string(), self.execute(request).await)),
+            "is_complete_request" => Some(("is_complete_reply".to_string(), json!({ "status": "unknown" }))),
+            "comm_info_request" => Some(("comm_info_reply".to_string(), json!({ "status": "ok", "comms": {} }))),
+            "history_request" => Some(("history_reply".to_string(), json!({ "status": "ok", "history": [] }))),
+            "shutdown_request" => shutdown(request),
+            other => {
+                eprintln!("Ignoring unsupported request {}", other);
+                None
+            }
+        };
+        self.publish("status", request, json!({ "execution_state": "idle" })).await;
+        reply
+    }
+
+    async fn execute(&mut self, request: &Message) -> Json {
+        let source = request.content["code"].as_str().unwrap_or_default().to_string();
+        let silent = request.content["silent"].as_bool().unwrap_or(false);
+        if !silent {
+            self.execution_count += 1;
+            let input = json!({ "code": source, "execution_count": self.execution_count });
+            self.publish("execute_input", request, input).await;
+        }
+
+        // Run on a blocking thread, so the heartbeat and the control socket
+        // are answered while a cell runs. A panic fails only the cell: the
+        // next one starts from a clean stack, with the variables kept.
+        let Some(mut vm) = self.vm.take() else {
+            return json!({ "status": "abort", "execution_count": self.execution_count });
+        };
+        self.cancel_token.reset();
+        let (vm, printed, outcome) = actix_web::rt::task::spawn_blocking(move || {
+            let outcome = catch_panics(|| {
+                compile_source(&source)
+                    .map(|code| optimizer::optimize(code, 0))
+                    .and_then(|code| vm.execute_cell(&code))
+                    .map_err(|e| ErrorDetails::from_error(e.as_ref()))
+            })
+            .unwrap_or_else(|message| {
+                Err(ErrorDetails::new(ErrorKind::Internal, format!("Internal compiler error: {}", message)))
+            });
+            let printed = vm.printed().to_string();
+            (Some(vm), printed, outcome)
+        })
+        .await
+        .unwrap_or_else(|e| {
+            let message = format!("Internal compiler error: {}", e);
+            (None, String::new(), Err(ErrorDetails::new(ErrorKind::Internal, message)))
+        });
+        self.vm = vm;
+
+        if !silent && !printed.is_empty() {
+            self.publish("stream", request, json!({ "name": "stdout", "text": printed })).await;
+        }
+        match outcome {
+            Ok(value) => {
+                if let (Some(value), false) = (value, silent) {
+                    let result = json!({
+                        "execution_count": self.execution_count,
+                        "data": { "text/plain": value.to_string() },
+                        "metadata": {},
+                    });
+                    self.publish("execute_result", request, result).await;
+                }
+                json!({
+                    "status": "ok",
+                    "execution_count": self.execution_count,
+                    "user_expressions": {},
+                    "payload": [],
+                })
+            }
+            Err(details) => {
+                let error = json!({
+                    "ename": details.kind.name(),
+                    "evalue": details.message,
+                    "traceback": [format!("{}: {}", details.kind.name(), details.message)],
+                });
+                if !silent {
+                    self.publish("error", request, error.clone()).await;
+                }
+                let mut reply = error;
+                reply["status"] = json!("error");
+                reply["execution_count"] = json!(self.execution_count);
+                reply
+            }
+        }
+    }
+}
+
+/// Runs the kernel described by the connection file at `path` until it is
+/// asked to shut down, with the default sandbox policy. Returns the process
+/// exit code.
+pub fn run(path: &str) -> i32 {
+    let connection = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))
+        .and_then(|text| serde_json::from_str::<ConnectionInfo>(&text).map_err(|e| format!("Invalid {}: {}", path, e)));
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(message) => {
+            eprintln!("{}", message);
+            return crate::cli::EXIT_INVALID_INPUT;
+        }
+    };
+    if !connection.key.is_empty() && connection.signature_scheme != "hmac-sha256" {
+        eprintln!("Unsupported signature scheme {}", connection.signature_scheme);
+        return crate::cli::EXIT_INVALID_INPUT;
+    }
+
+    match actix_web::rt::System::new().block_on(serve(connection, SandboxPolicy::default())) {
+        Ok(()) => crate::cli::EXIT_SUCCESS,
+        Err(e) => {
+            eprintln!("Kernel stopped: {}", e);
+            crate::cli::EXIT_INTERNAL_ERROR
+        }
+    }
+}
+
+async fn serve(connection: ConnectionInfo, policy: SandboxPolicy) -> Result<(), zeromq::ZmqError> {
+    let codec = Codec {
+        key: (!connection.key.is_empty())
+            .then(|| Hmac::<Sha256>::new_from_slice(connection.key.as_bytes()).expect("HMAC takes keys of any length")),
+        session: format!("{:x}", SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()),
+        next_id: Arc::new(AtomicU64::new(0)),
+    };
+
+    let mut shell = RouterSocket::new();
+    shell.bind(&connection.endpoint(connection.shell_port)).await?;
+    let mut control = RouterSocket::new();
+    control.bind(&connection.endpoint(connection.control_port)).await?;
+    let mut iopub = PubSocket::new();
+    iopub.bind(&connection.endpoint(connection.iopub_port)).await?;
+    let mut heartbeat = RepSocket::new();
+    heartbeat.bind(&connection.endpoint(connection.hb_port)).await?;
+    // Bound so that clients can connect, but programs read no input from it
+    let mut stdin = RouterSocket::new();
+    stdin.bind(&connection.endpoint(connection.stdin_port)).await?;
+
+    actix_web::rt::spawn(async move {
+        while let Ok(ping) = heartbeat.recv().await {
+            if heartbeat.send(ping).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let cancel_token = CancellationToken::new();
+    let control_codec = codec.clone();
+    let interrupt = cancel_token.clone();
+    actix_web::rt::spawn(async move {
+        while let Ok(message) = control.recv().await {
+            let request = match control_codec.decode(message) {
+                Ok(request) => request,
+                Err(e) => {
+                    eprintln!("Dropping control message: {}", e);
+                    continue;
+                }
+            };
+            let reply = match request.msg_type() {
+                "kernel_info_request" => Some(("kernel_info_reply".to_string(), kernel_info())),
+                "interrupt_request" => {
+                    interrupt.cancel();
+                    Some(("interrupt_reply".to_string(), json!({ "status": "ok" })))
+                }
+                "shutdown_request" => shutdown(&request),
+                other => {
+                    eprintln!("Ignoring unsupported control request {}", other);
+                    None
+                }
+            };
+            if let Some((msg_type, content)) = reply {
+                let reply = control_codec.encode(&request.identities, &msg_type, &request, content);
+                let _ = control.send(reply).await;
+                if msg_type == "shutdown_reply" {
+                    std::process::exit(crate::cli::EXIT_SUCCESS);
+                }
+            }
+        }
+    });
+
+    let mut vm = VirtualMachine::with_config(VmConfig {
+        policy,
+        cancel_token: cancel_token.clone(),
+        ..VmConfig::default()
+    });
+    vm.start(&[]);
+    let mut kernel = Kernel {
+        codec,
+        iopub,
+        vm: Some(vm),
+        execution_count: 0,
+        cancel_token,
+    };
+    loop {
+        let message = shell.recv().await?;
+        let request = match kernel.codec.decode(message) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Dropping shell message: {}", e);
+                continue;
+            }
+        };
+        if let Some((msg_type, content)) = kernel.handle(&request).await {
+            let reply = kernel.codec.encode(&request.identities, &msg_type, &request, content);
+            shell.send(reply).await?;
+            if msg_type == "shutdown_reply" {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn kernel_info() -> Json {
+    json!({
+        "status": "ok",
+        "protocol_version": PROTOCOL_VERSION,
+        "implementation": "compiler",
+        "implementation_version": env!("CARGO_PKG_VERSION"),
+        "language_info": {
+            "name": "custom",
+            "version": env!("CARGO_PKG_VERSION"),
+            "mimetype": "text/plain",
+            "file_extension": ".src",
+        },
+        "banner": "Custom Language Compiler",
+        "help_links": [],
+    })
+}
+
+fn shutdown(request: &Message) -> Option<(String, Json)> {
+    let restart = request.content["restart"].as_bool().unwrap_or(false);
+    Some(("shutdown_reply".to_string(), json!({ "status": "ok", "restart": restart })))
+}
+
+// ISO 8601 time in UTC, as message headers carry it
+fn timestamp(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = elapsed.as_secs();
+    let (days, rest) = ((seconds / 86_400) as i64, seconds % 86_400);
+
+    // Civil date of a day count (Howard Hinnant's days_from_civil, inverted)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
@@ -1,39 +1,91 @@
+use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
+use std::sync::OnceLock;
 
-/// Enum representing different types of tokens.
+/// Enum representing different types of tokens. Text is borrowed from the
+/// source, except for string literals whose escapes had to be translated.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
-pub enum TokenType {
+pub enum TokenType<'a> {
     // Keywords
-    Int, Float, If, Else, While, Return, 
+    Int, Float, If, Else, While, Return, Import, Null, Defer, Break, Continue, Fn,
     
     // Literals
     IntLiteral(i64),
     FloatLiteral(f64),
-    StringLiteral(String),
+    StringLiteral(Cow<'a, str>),
     
     // Identifiers
-    Identifier(String),
+    Identifier(Cow<'a, str>),
     
     // Operators
     Plus, Minus, Multiply, Divide, Assign,
-    Equal, NotEqual, LessThan, GreaterThan,
+    Equal, NotEqual, LessThan, GreaterThan, LessEqual, GreaterEqual,
+    And, Or, Not,
+
+    // Statement of the beginner dialect: `print x;`
+    Print,
     
     // Punctuation
     LeftParen, RightParen, 
     LeftBrace, RightBrace,
+    LeftBracket, RightBracket, // Around array literals and indices
     Semicolon, Comma,
+    Colon, // After the label of a loop: `outer: while (...)`
     
+    // Documentation comment (/// text), attached to the next declaration
+    DocComment(Cow<'a, str>),
+
+    // Any other comment, only produced by a lexer created with `with_comments`
+    Comment,
+
     // Special
     EOF,
 }
 
-/// Struct representing a token, along with its line and column in the source.
+impl TokenType<'_> {
+    /// The same token type owning its text, to be kept after the source.
+    pub fn into_owned(self) -> TokenType<'static> {
+        use TokenType::*;
+        match self {
+            StringLiteral(text) => StringLiteral(Cow::Owned(text.into_owned())),
+            Identifier(name) => Identifier(Cow::Owned(name.into_owned())),
+            DocComment(text) => DocComment(Cow::Owned(text.into_owned())),
+            IntLiteral(value) => IntLiteral(value),
+            FloatLiteral(value) => FloatLiteral(value),
+            Int => Int, Float => Float, If => If, Else => Else, While => While, Return => Return,
+            Import => Import, Null => Null, Defer => Defer, Break => Break, Continue => Continue, Fn => Fn,
+            Plus => Plus, Minus => Minus, Multiply => Multiply, Divide => Divide, Assign => Assign,
+            Equal => Equal, NotEqual => NotEqual, LessThan => LessThan, GreaterThan => GreaterThan,
+            LessEqual => LessEqual, GreaterEqual => GreaterEqual,
+            And => And, Or => Or, Not => Not,
+            Print => Print,
+            LeftParen => LeftParen, RightParen => RightParen,
+            LeftBrace => LeftBrace, RightBrace => RightBrace,
+            LeftBracket => LeftBracket, RightBracket => RightBracket,
+            Semicolon => Semicolon, Comma => Comma, Colon => Colon,
+            Comment => Comment,
+            EOF => EOF,
+        }
+    }
+}
+
+/// Struct representing a token, along with its position in the source.
 #[derive(Debug, Clone)]
-pub struct Token {
-    pub token_type: TokenType,
+pub struct Token<'a> {
+    pub token_type: TokenType<'a>,
     pub line: usize,
     pub column: usize,
+    pub offset: usize, // Byte offset of the first character
+    pub length: usize, // In bytes
+}
+
+impl Token<'_> {
+    /// The same token owning its text, see `TokenType::into_owned`.
+    pub fn into_owned(self) -> Token<'static> {
+        Token { token_type: self.token_type.into_owned(), ..self }
+    }
 }
 
 /// Custom error for the lexer.
@@ -52,31 +104,151 @@ impl fmt::Display for LexerError {
 
 impl Error for LexerError {}
 
-/// Lexer struct that holds state while tokenizing input.
-pub struct Lexer {
-    input: Vec<char>,
-    position: usize,
+impl LexerError {
+    /// Line (1-based) where the error was detected.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column (1-based) where the error was detected.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+/// Lexer struct that holds state while tokenizing input. It reads the
+/// source in place, by byte offset, and copies only the text of the tokens
+/// that keep it: tokens outlive the source in the editor's caches.
+pub struct Lexer<'a> {
+    input: &'a str,
+    position: usize,     // Byte offset of the current character in `input`
     line: usize,
     column: usize,
+    offset: usize,       // Byte offset of the current character in the whole source
+    keep_comments: bool, // Emit Comment tokens instead of skipping comments
+    dialect: Dialect,
+    limits: SourceLimits,
+}
+
+/// Caps on the size of a source, checked by the lexer and the parser so that
+/// a pathological input, such as thousands of nested parentheses, is rejected
+/// with a diagnostic instead of exhausting memory or the parser's stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SourceLimits {
+    pub max_tokens: usize,       // Tokens of the whole source
+    pub max_string_bytes: usize, // Bytes between the quotes of a string literal
+    pub max_nesting: usize,      // Expressions, statements and blocks nested in one another, chains of calls and indexes included
+    pub max_chain: usize,        // Binary operators applied one after another, as the two of `a + b * c` are
+}
+
+impl SourceLimits {
+    /// The caps used when the environment sets none.
+    pub const BUILT_IN: SourceLimits = SourceLimits {
+        max_tokens: 1_000_000,
+        max_string_bytes: 1024 * 1024,
+        max_nesting: 100,
+        max_chain: 1000,
+    };
+
+    /// Reads the caps from COMPILER_MAX_TOKENS, COMPILER_MAX_STRING_BYTES,
+    /// COMPILER_MAX_NESTING and COMPILER_MAX_CHAIN, keeping the built-in cap
+    /// of those unset.
+    pub fn from_env() -> Result<Self, String> {
+        let read = |name: &str, default: usize| match std::env::var(name) {
+            Ok(value) => value.parse().map_err(|_| format!("{} must be a number, got '{}'", name, value)),
+            Err(_) => Ok(default),
+        };
+        Ok(SourceLimits {
+            max_tokens: read("COMPILER_MAX_TOKENS", Self::BUILT_IN.max_tokens)?,
+            max_string_bytes: read("COMPILER_MAX_STRING_BYTES", Self::BUILT_IN.max_string_bytes)?,
+            max_nesting: read("COMPILER_MAX_NESTING", Self::BUILT_IN.max_nesting)?,
+            max_chain: read("COMPILER_MAX_CHAIN", Self::BUILT_IN.max_chain)?,
+        })
+    }
+
+    /// The environment variables `from_env` reads these caps from.
+    pub fn to_env(self) -> [(&'static str, String); 4] {
+        [
+            ("COMPILER_MAX_TOKENS", self.max_tokens.to_string()),
+            ("COMPILER_MAX_STRING_BYTES", self.max_string_bytes.to_string()),
+            ("COMPILER_MAX_NESTING", self.max_nesting.to_string()),
+            ("COMPILER_MAX_CHAIN", self.max_chain.to_string()),
+        ]
+    }
+}
+
+impl Default for SourceLimits {
+    /// The caps of the environment, read once per process; worker processes
+    /// are given those of the server. Invalid values fall back to the
+    /// built-in caps, but the server refuses to start with them.
+    fn default() -> Self {
+        static CONFIGURED: OnceLock<SourceLimits> = OnceLock::new();
+        *CONFIGURED.get_or_init(|| SourceLimits::from_env().unwrap_or(SourceLimits::BUILT_IN))
+    }
+}
+
+/// Variant of the language a source is written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Dialect {
+    #[default]
+    Standard,
+    Beginner, // `and`, `or` and `not` for `&&`, `||` and `!`, and the `print x;` statement
 }
 
-impl Lexer {
+impl Dialect {
+    /// The dialect named by the `language` of a request: "beginner", in any
+    /// case, or the standard language for anything else.
+    pub fn from_language(language: &str) -> Self {
+        if language.eq_ignore_ascii_case("beginner") {
+            Dialect::Beginner
+        } else {
+            Dialect::Standard
+        }
+    }
+}
+
+impl<'a> Lexer<'a> {
     /// Creates a new Lexer instance from an input string.
-    pub fn new(input: &str) -> Self {
+    pub fn new(input: &'a str) -> Self {
+        Self::starting_at(input, 1, 1, 0)
+    }
+
+    /// Creates a Lexer for the part of a larger source that starts at the
+    /// given line, column and byte offset, so tokens carry their position in
+    /// the whole source. The part must not start inside a token or comment.
+    pub fn starting_at(input: &'a str, line: usize, column: usize, offset: usize) -> Self {
         Lexer {
-            input: input.chars().collect(),
+            input,
             position: 0,
-            line: 1,
-            column: 1,
+            line,
+            column,
+            offset,
+            keep_comments: false,
+            dialect: Dialect::Standard,
+            limits: SourceLimits::default(),
         }
     }
+
+    /// Makes the lexer emit comments as Comment tokens, for syntax highlighting.
+    pub fn with_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
+    /// Reads the keywords of `dialect` as well as those of the standard
+    /// language.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
     
     /// Tokenizes the input into a vector of tokens.
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, Box<dyn Error>> {
+    pub fn tokenize(&mut self) -> Result<Vec<Token<'a>>, Box<dyn Error>> {
         let mut tokens = Vec::new();
         
         while self.position < self.input.len() {
             let c = self.current_char();
+            let start = (tokens.len(), self.offset);
             
             match c {
                 // Whitespace characters
@@ -84,9 +256,9 @@ impl Lexer {
 
                 // Newline: increment line count
                 '\n' => {
+                    self.advance();
                     self.line += 1;
                     self.column = 1;
-                    self.advance();
                 }
 
                 // Numeric literal
@@ -114,13 +286,30 @@ impl Lexer {
                 '/' => {
                     // Handle comments
                     if self.peek() == '/' {
+                        let comment = self.create_token(TokenType::Comment);
                         self.advance();
                         self.advance();
-                        self.skip_line_comment();
+                        // Exactly three slashes start a doc comment; more are a plain comment
+                        if self.position < self.input.len() && self.current_char() == '/' && self.peek() != '/' {
+                            self.advance();
+                            tokens.push(Token {
+                                token_type: TokenType::DocComment(Cow::Borrowed(self.line_comment_text())),
+                                ..comment
+                            });
+                        } else {
+                            self.skip_line_comment();
+                            if self.keep_comments {
+                                tokens.push(comment);
+                            }
+                        }
                     } else if self.peek() == '*' {
+                        let comment = self.create_token(TokenType::Comment);
                         self.advance();
                         self.advance();
                         self.skip_block_comment()?;
+                        if self.keep_comments {
+                            tokens.push(comment);
+                        }
                     } else {
                         tokens.push(self.create_token(TokenType::Divide));
                         self.advance();
@@ -128,9 +317,9 @@ impl Lexer {
                 },
                 '=' => {
                     if self.peek() == '=' {
+                        tokens.push(self.create_token(TokenType::Equal));
                         self.advance();
                         self.advance();
-                        tokens.push(self.create_token(TokenType::Equal));
                     } else {
                         tokens.push(self.create_token(TokenType::Assign));
                         self.advance();
@@ -138,25 +327,42 @@ impl Lexer {
                 },
                 '!' => {
                     if self.peek() == '=' {
+                        tokens.push(self.create_token(TokenType::NotEqual));
                         self.advance();
                         self.advance();
-                        tokens.push(self.create_token(TokenType::NotEqual));
                     } else {
+                        tokens.push(self.create_token(TokenType::Not));
+                        self.advance();
+                    }
+                },
+                '&' | '|' => {
+                    if self.peek() != c {
                         return Err(Box::new(LexerError {
-                            message: format!("Unexpected character: !"),
+                            message: format!("Unexpected character: {}", c),
                             line: self.line,
                             column: self.column,
                         }));
                     }
-                },
-                '<' => {
-                    tokens.push(self.create_token(TokenType::LessThan));
+                    let operator = if c == '&' { TokenType::And } else { TokenType::Or };
+                    tokens.push(self.create_token(operator));
                     self.advance();
-                },
-                '>' => {
-                    tokens.push(self.create_token(TokenType::GreaterThan));
                     self.advance();
                 },
+                '<' | '>' => {
+                    let (strict, or_equal) = if c == '<' {
+                        (TokenType::LessThan, TokenType::LessEqual)
+                    } else {
+                        (TokenType::GreaterThan, TokenType::GreaterEqual)
+                    };
+                    if self.peek() == '=' {
+                        tokens.push(self.create_token(or_equal));
+                        self.advance();
+                        self.advance();
+                    } else {
+                        tokens.push(self.create_token(strict));
+                        self.advance();
+                    }
+                },
 
                 // Punctuation
                 '(' => {
@@ -175,6 +381,14 @@ impl Lexer {
                     tokens.push(self.create_token(TokenType::RightBrace));
                     self.advance();
                 },
+                '[' => {
+                    tokens.push(self.create_token(TokenType::LeftBracket));
+                    self.advance();
+                },
+                ']' => {
+                    tokens.push(self.create_token(TokenType::RightBracket));
+                    self.advance();
+                },
                 ';' => {
                     tokens.push(self.create_token(TokenType::Semicolon));
                     self.advance();
@@ -183,6 +397,10 @@ impl Lexer {
                     tokens.push(self.create_token(TokenType::Comma));
                     self.advance();
                 },
+                ':' => {
+                    tokens.push(self.create_token(TokenType::Colon));
+                    self.advance();
+                },
 
                 // Any other character is unexpected
                 _ => {
@@ -193,56 +411,75 @@ impl Lexer {
                     }));
                 }
             }
+
+            // A token pushed in this step spans everything read since its start
+            let (count, start_offset) = start;
+            if tokens.len() > count {
+                if let Some(token) = tokens.last_mut() {
+                    token.length = self.offset - start_offset;
+                }
+                if tokens.len() > self.limits.max_tokens {
+                    let token = &tokens[count];
+                    return Err(Box::new(LexerError {
+                        message: format!("Source too long: more than {} tokens", self.limits.max_tokens),
+                        line: token.line,
+                        column: token.column,
+                    }));
+                }
+            }
         }
         
         // Add EOF token at the end
-        tokens.push(Token {
-            token_type: TokenType::EOF,
-            line: self.line,
-            column: self.column,
-        });
+        tokens.push(self.create_token(TokenType::EOF));
         
         Ok(tokens)
     }
     
-    /// Returns the current character.
+    /// Returns the current character, or '\0' at the end of the input.
     fn current_char(&self) -> char {
-        self.input[self.position]
+        self.input[self.position..].chars().next().unwrap_or('\0')
     }
     
     /// Peeks ahead to the next character without advancing.
     fn peek(&self) -> char {
-        if self.position + 1 >= self.input.len() {
-            '\0'
-        } else {
-            self.input[self.position + 1]
-        }
+        self.next_char().unwrap_or('\0')
+    }
+
+    /// The character after the current one, if any.
+    fn next_char(&self) -> Option<char> {
+        self.input[self.position..].chars().nth(1)
     }
     
     /// Advances the lexer by one character.
     fn advance(&mut self) {
-        self.position += 1;
+        let width = self.input[self.position..].chars().next().map_or(1, char::len_utf8);
+        self.position += width;
+        self.offset += width;
         self.column += 1;
     }
     
-    /// Helper to create a token at the current position.
-    fn create_token(&self, token_type: TokenType) -> Token {
+    /// Helper to create a token at the current position. Its length is set
+    /// once the token has been read.
+    fn create_token(&self, token_type: TokenType<'a>) -> Token<'a> {
         Token {
             token_type,
             line: self.line,
             column: self.column,
+            offset: self.offset,
+            length: 0,
         }
     }
     
     /// Parses a number (integer or float).
-    fn number(&mut self) -> Result<Token, Box<dyn Error>> {
+    fn number(&mut self) -> Result<Token<'a>, Box<dyn Error>> {
         let start_pos = self.position;
+        let start_offset = self.offset;
         let mut is_float = false;
         
         while self.position < self.input.len() {
             let c = self.current_char();
             
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 self.advance();
             } else if c == '.' && !is_float {
                 is_float = true;
@@ -252,7 +489,7 @@ impl Lexer {
             }
         }
         
-        let number_str: String = self.input[start_pos..self.position].iter().collect();
+        let number_str = &self.input[start_pos..self.position];
         
         let token_type = if is_float {
             match number_str.parse::<f64>() {
@@ -278,12 +515,15 @@ impl Lexer {
             token_type,
             line: self.line,
             column: self.column - number_str.len(),
+            offset: start_offset,
+            length: 0,
         })
     }
     
     /// Parses an identifier or keyword.
-    fn identifier(&mut self) -> Result<Token, Box<dyn Error>> {
+    fn identifier(&mut self) -> Result<Token<'a>, Box<dyn Error>> {
         let start_pos = self.position;
+        let start_offset = self.offset;
         
         while self.position < self.input.len() {
             let c = self.current_char();
@@ -295,31 +535,47 @@ impl Lexer {
             }
         }
         
-        let ident: String = self.input[start_pos..self.position].iter().collect();
-        let column = self.column - ident.len();
+        let ident: &'a str = &self.input[start_pos..self.position];
+        let column = self.column - ident.chars().count();
         
         // Check if it's a keyword
-        let token_type = match ident.as_str() {
+        let token_type = match ident {
             "int" => TokenType::Int,
             "float" => TokenType::Float,
             "if" => TokenType::If,
             "else" => TokenType::Else,
             "while" => TokenType::While,
             "return" => TokenType::Return,
-            _ => TokenType::Identifier(ident),
+            "import" => TokenType::Import,
+            "null" => TokenType::Null,
+            "defer" => TokenType::Defer,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
+            "fn" => TokenType::Fn,
+            "and" if self.dialect == Dialect::Beginner => TokenType::And,
+            "or" if self.dialect == Dialect::Beginner => TokenType::Or,
+            "not" if self.dialect == Dialect::Beginner => TokenType::Not,
+            "print" if self.dialect == Dialect::Beginner => TokenType::Print,
+            _ => TokenType::Identifier(Cow::Borrowed(ident)),
         };
         
         Ok(Token {
             token_type,
             line: self.line,
             column,
+            offset: start_offset,
+            length: 0,
         })
     }
     
-    /// Parses a string literal.
-    fn string_literal(&mut self) -> Result<Token, Box<dyn Error>> {
+    /// Parses a string literal, translating its escape sequences: `\n`,
+    /// `\t`, `\\`, `\"` and `\0`. Only a literal with escapes is copied.
+    fn string_literal(&mut self) -> Result<Token<'a>, Box<dyn Error>> {
+        let start_offset = self.offset;
+        let column = self.column;
         self.advance(); // Skip opening quote
         let start_pos = self.position;
+        let mut translated: Option<String> = None; // Set at the first escape
         
         while self.position < self.input.len() && self.current_char() != '"' {
             if self.current_char() == '\n' {
@@ -330,9 +586,27 @@ impl Lexer {
                 }));
             }
 
-            // Handle escaped characters like \" or \n
-            if self.current_char() == '\\' && self.position + 1 < self.input.len() {
+            // A backslash before a line break escapes nothing: the line break still ends the string
+            if self.current_char() == '\\' && self.next_char().is_some_and(|next| next != '\n') {
+                let escape_column = self.column;
+                let text = translated.get_or_insert_with(|| self.input[start_pos..self.position].to_string());
                 self.advance(); // Skip backslash
+                text.push(match self.current_char() {
+                    'n' => '\n',
+                    't' => '\t',
+                    '\\' => '\\',
+                    '"' => '"',
+                    '0' => '\0',
+                    other => {
+                        return Err(Box::new(LexerError {
+                            message: format!("Unknown escape sequence: \\{}", other),
+                            line: self.line,
+                            column: escape_column,
+                        }));
+                    }
+                });
+            } else if let Some(text) = &mut translated {
+                text.push(self.current_char());
             }
             
             self.advance();
@@ -346,15 +620,26 @@ impl Lexer {
             }));
         }
         
-        let string_content: String = self.input[start_pos..self.position].iter().collect();
-        let column = self.column - string_content.len() - 1; // account for opening quote
+        if self.position - start_pos > self.limits.max_string_bytes {
+            return Err(Box::new(LexerError {
+                message: format!("String literal longer than {} bytes", self.limits.max_string_bytes),
+                line: self.line,
+                column,
+            }));
+        }
         
+        let text = match translated {
+            Some(text) => Cow::Owned(text),
+            None => Cow::Borrowed(&self.input[start_pos..self.position]),
+        };
         self.advance(); // Skip closing quote
         
         Ok(Token {
-            token_type: TokenType::StringLiteral(string_content),
+            token_type: TokenType::StringLiteral(text),
             line: self.line,
             column,
+            offset: start_offset,
+            length: 0,
         })
     }
     
@@ -365,21 +650,29 @@ impl Lexer {
         }
     }
     
+    /// Reads the rest of a line comment, without one leading space.
+    fn line_comment_text(&mut self) -> &'a str {
+        let start_pos = self.position;
+        self.skip_line_comment();
+        let text = self.input[start_pos..self.position].trim_end_matches('\r');
+        text.strip_prefix(' ').unwrap_or(text)
+    }
+
     /// Skips a block comment (/* ... */).
     fn skip_block_comment(&mut self) -> Result<(), Box<dyn Error>> {
-        while self.position + 1 < self.input.len() {
+        while self.next_char().is_some() {
             if self.current_char() == '*' && self.peek() == '/' {
                 self.advance(); // Skip '*'
                 self.advance(); // Skip '/'
                 return Ok(());
             }
             
-            if self.current_char() == '\n' {
+            let newline = self.current_char() == '\n';
+            self.advance();
+            if newline {
                 self.line += 1;
                 self.column = 1;
             }
-            
-            self.advance();
         }
         
         Err(Box::new(LexerError {
@@ -389,3 +682,96 @@ impl Lexer {
         }))
     }
 }
+
+/// What the lexer is reading at some point of the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexState {
+    Code,
+    String,
+    LineComment,
+    BlockComment { offset: usize, line: usize, column: usize }, // Where the comment started
+}
+
+/// Follows the lexer's rules for strings and comments over `bytes[from..to]`
+/// without producing tokens, which is much cheaper than lexing. Returns the
+/// state, line and column reached at `to`.
+pub fn scan(bytes: &[u8], from: usize, to: usize, mut state: LexState, mut line: usize, mut column: usize) -> (LexState, usize, usize) {
+    let mut index = from;
+    while index < to {
+        let byte = bytes[index];
+        let next = bytes.get(index + 1).copied();
+        let mut width = 1;
+        state = match state {
+            LexState::Code if byte == b'"' => LexState::String,
+            LexState::Code if byte == b'/' && next == Some(b'/') => LexState::LineComment,
+            LexState::Code if byte == b'/' && next == Some(b'*') => {
+                width = 2;
+                LexState::BlockComment { offset: index, line, column }
+            }
+            LexState::String if byte == b'\\' && next.is_some_and(|next| next != b'\n') => {
+                width = 2;
+                LexState::String
+            }
+            LexState::String if byte == b'"' || byte == b'\n' => LexState::Code,
+            LexState::LineComment if byte == b'\n' => LexState::Code,
+            LexState::BlockComment { .. } if byte == b'*' && next == Some(b'/') => {
+                width = 2;
+                LexState::Code
+            }
+            state => state,
+        };
+
+        for &byte in &bytes[index..(index + width).min(bytes.len())] {
+            if byte == b'\n' {
+                line += 1;
+                column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                column += 1; // First byte of a character
+            }
+        }
+        index += width;
+    }
+    (state, line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_text_with_escapes_is_copied() {
+        let tokens = Lexer::new("/// doc\nint name = \"plain\" + \"a\\tb\";").tokenize().unwrap();
+        let texts: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| match &token.token_type {
+                TokenType::DocComment(text) | TokenType::Identifier(text) | TokenType::StringLiteral(text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(texts[..], [Cow::Borrowed("doc"), Cow::Borrowed("name"), Cow::Borrowed("plain"), Cow::Owned(_)]));
+        assert_eq!(texts[3], "a\tb");
+    }
+
+    fn string(source: &str) -> Result<String, String> {
+        let tokens = Lexer::new(source).tokenize().map_err(|e| e.to_string())?;
+        match &tokens[0].token_type {
+            TokenType::StringLiteral(text) => Ok(text.to_string()),
+            other => panic!("{:?} is not a string literal", other),
+        }
+    }
+
+    #[test]
+    fn escape_sequences_are_translated() {
+        assert_eq!(string(r#""a\nb\tc""#).unwrap(), "a\nb\tc");
+        assert_eq!(string(r#""\\ \" \0""#).unwrap(), "\\ \" \0");
+        assert_eq!(string(r#""\\n""#).unwrap(), "\\n");
+        assert_eq!(string(r#""é\t""#).unwrap(), "é\t");
+    }
+
+    #[test]
+    fn unknown_escapes_and_escaped_line_breaks_are_errors() {
+        assert_eq!(string(r#""a\qb""#).unwrap_err(), "Lexer error at 1:3: Unknown escape sequence: \\q");
+        let error = string("\"a\\\nb\"").unwrap_err();
+        assert!(error.ends_with("Unterminated string literal"), "{}", error);
+    }
+}
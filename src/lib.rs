@@ -0,0 +1,1283 @@
+// Without the web server, much of the shared code below only serves the CLI
+#![cfg_attr(not(feature = "server"), allow(dead_code))]
+
+// External crates
+#[cfg(feature = "server")]
+use actix_cors::Cors;
+#[cfg(feature = "server")]
+use actix_web::{error::InternalError, http::{header::ACCEPT_LANGUAGE, StatusCode}, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+#[cfg(feature = "server")]
+use actix_files as fs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "server")]
+use utoipa::IntoParams;
+use utoipa::ToSchema;
+
+// Local module declarations
+mod lexer;
+mod parser;
+mod ast;
+mod bytecode;
+mod vm;
+mod builtins;
+mod sandbox;
+mod vfs;
+mod worker;
+#[cfg(feature = "server")]
+mod openapi;
+mod cli;
+mod diagnostics;
+mod project;
+mod artifact;
+mod strings;
+mod verifier;
+mod incremental;
+#[cfg(feature = "server")]
+mod docs;
+#[cfg(feature = "server")]
+mod completion;
+mod resolver;
+mod semantic;
+mod rename;
+#[cfg(feature = "server")]
+mod navigation;
+#[cfg(feature = "server")]
+mod highlight;
+mod reparse;
+#[cfg(feature = "server")]
+mod documents;
+mod quickfix;
+mod optimizer;
+mod pipeline;
+mod cfg;
+mod liveness;
+mod sessions;
+mod vmpool;
+mod limits;
+#[cfg(feature = "server")]
+mod profiles;
+#[cfg(feature = "server")]
+mod executions;
+#[cfg(feature = "server")]
+mod history;
+mod replay;
+#[cfg(feature = "server")]
+mod debugger;
+#[cfg(feature = "server")]
+mod admin;
+#[cfg(feature = "server")]
+mod programs;
+#[cfg(feature = "server")]
+mod scheduler;
+#[cfg(feature = "server")]
+mod hooks;
+#[cfg(feature = "server")]
+mod grading;
+mod testing;
+mod similarity;
+mod metrics;
+mod callgraph;
+mod registry;
+mod i18n;
+pub mod embed;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "jupyter")]
+mod kernel;
+
+// Use statements for convenience
+use lexer::{Dialect, LexerError};
+use parser::ParserError;
+use bytecode::{BytecodeGenerator, BytecodeGeneratorError};
+use vm::VirtualMachine;
+use vm::VmConfig;
+use vm::Instruction;
+use vm::ExecutionStats;
+use vm::VariableSnapshot;
+use vm::ClockRead;
+use limits::{tightest, ExecutionLimits};
+use pipeline::{Compilation, Mode, PassManager, PhaseTiming};
+#[cfg(feature = "server")]
+use sandbox::SandboxPolicy;
+use artifact::{Artifact, ArtifactError, StatementError};
+use verifier::VerifyError;
+use resolver::ResolveError;
+use semantic::{SemanticError, StrictMode, Strictness};
+use rename::RenameError;
+#[cfg(feature = "server")]
+use documents::DocumentStore;
+#[cfg(feature = "server")]
+use sessions::{SessionLimits, SessionStore};
+#[cfg(feature = "server")]
+use executions::ExecutionRegistry;
+#[cfg(feature = "server")]
+use history::History;
+#[cfg(feature = "server")]
+use profiles::Profiles;
+use replay::ReplayBundle;
+#[cfg(feature = "server")]
+use debugger::DebugStore;
+#[cfg(feature = "server")]
+use programs::ProgramStore;
+use worker::WorkerJob;
+#[cfg(feature = "server")]
+use worker::{WorkerError, WorkerLimits};
+
+// Longest rendered value included in a variables snapshot
+const MAX_SNAPSHOT_VALUE_LEN: usize = 200;
+
+// Struct to deserialize incoming JSON from frontend
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+struct CodeInput {
+    source: String,       // The actual code to compile
+    language: String,     // "beginner" for the beginner dialect, see `lexer::Dialect`; otherwise unused
+    #[serde(default, skip_serializing_if = "Mode::is_program")]
+    mode: Mode,           // "expression" when `source` is a single expression, see `pipeline::Mode`
+    #[serde(default)]
+    inspect_variables: bool, // Return the final value of every global variable
+    #[serde(default)]
+    seed: Option<u64>,       // Seed for random builtins, for reproducible runs
+    #[serde(default)]
+    stdin: String,           // Input lines returned by read_line()
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>, // Variables returned by getenv()
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    files: BTreeMap<String, String>, // Contents by path of the files read_file() can read
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    globals: BTreeMap<String, serde_json::Value>, // Globals defined before the program runs, see `preloaded_globals`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    return_variables: Vec<String>, // Globals whose final values are returned as JSON, see `returned_globals`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    document: Option<String>, // Editor document id; its symbol table is kept for /definition and /references
+    #[serde(default)]
+    opt_level: u8,            // 0 (default) to 2, see `optimizer`
+    #[serde(default)]
+    strict: StrictMode,       // Checks of strict mode, see `semantic`
+    #[serde(default)]
+    verbose: bool,            // Return the optimization log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session: Option<String>,  // Session whose limits apply to the run, see `sessions`
+    #[serde(default, skip_serializing_if = "ExecutionLimits::is_unset")]
+    limits: ExecutionLimits,  // Limits of this run, clamped by the server's, see `limits`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,  // Server-configured profile whose settings apply, see `profiles`
+    #[serde(default)]
+    record: bool,             // Return a replay bundle of the run, see `replay`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replay_clock: Option<Vec<ClockRead>>, // Clock readings to replay; set by POST /replay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,   // Language of error messages, e.g. "es"; overrides Accept-Language
+}
+
+// Request body of endpoints that only analyse source code
+#[cfg(feature = "server")]
+#[derive(Deserialize, ToSchema)]
+struct SourceInput {
+    source: String,
+}
+
+// Where submitted programs are executed
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExecutionMode {
+    InProcess, // Directly inside the web server process
+    Worker,    // In a separate, resource-limited worker process per request
+}
+
+// Settings shared by every request handled by the server
+#[cfg(feature = "server")]
+struct ServerConfig {
+    policy: SandboxPolicy,         // Capabilities granted to submitted programs
+    execution_mode: ExecutionMode,
+    worker_limits: WorkerLimits,   // Only used in worker mode
+    session_limits: SessionLimits, // Applied to runs that name a session
+    limit_ceilings: ExecutionLimits, // Highest limits a request may set; absent ones are unbounded
+    profiles: Profiles,            // Selected by name in requests
+    admin_token: Option<String>,   // Token required by /admin endpoints; they are disabled without one
+    history_size: usize,           // Finished runs kept for GET /admin/history
+    history_file: Option<std::path::PathBuf>, // File the history is appended to and loaded from, see `history`
+    #[cfg(feature = "grpc")]
+    grpc_addr: std::net::SocketAddr,
+}
+
+#[cfg(feature = "server")]
+impl ServerConfig {
+    // Reads the configuration from environment variables:
+    // COMPILER_CAPABILITIES     - comma-separated capabilities to grant, e.g. "time,random,stdin,env"
+    // COMPILER_EXECUTION_MODE   - "in-process" (default) or "worker"
+    // COMPILER_WORKER_TIMEOUT_MS - wall-clock limit per worker run (default 5000)
+    // COMPILER_WORKER_MEMORY_MB  - address-space limit per worker (default 256)
+    // COMPILER_SESSION_MEMORY_MB - memory ceiling of each run in a session (default 16)
+    // COMPILER_SESSION_INSTRUCTIONS - instruction budget of a session (default 100000000)
+    // COMPILER_SESSION_IDLE_SECS - idle time after which a session is dropped (default 1800)
+    // COMPILER_MAX_INSTRUCTIONS, COMPILER_MAX_OUTPUT_BYTES, COMPILER_MAX_CALL_DEPTH,
+    // COMPILER_MAX_TIMEOUT_MS   - highest execution limits of a run (default: unbounded)
+    // COMPILER_MAX_TOKENS, COMPILER_MAX_STRING_BYTES, COMPILER_MAX_NESTING
+    //                           - caps on the sources compiled, see `lexer::SourceLimits`
+    // COMPILER_PROFILES         - TOML file of the profiles requests can select (default: none)
+    // COMPILER_ADMIN_TOKEN      - bearer token for the /admin endpoints (unset: disabled)
+    // COMPILER_HISTORY_SIZE     - finished runs kept in the history (default 1000, 0 for none)
+    // COMPILER_HISTORY_FILE     - JSON Lines file the history is kept in across restarts (default: none)
+    // COMPILER_GRPC_ADDR        - listen address of the gRPC service (feature "grpc", default 0.0.0.0:50051)
+    fn from_env() -> Result<Self, String> {
+        let policy = match std::env::var("COMPILER_CAPABILITIES") {
+            Ok(list) => SandboxPolicy::from_list(&list)?,
+            Err(_) => SandboxPolicy::default(),
+        };
+        let execution_mode = match std::env::var("COMPILER_EXECUTION_MODE").as_deref() {
+            Err(_) | Ok("in-process") => ExecutionMode::InProcess,
+            Ok("worker") => ExecutionMode::Worker,
+            Ok(other) => return Err(format!("Unknown execution mode: {}", other)),
+        };
+        let worker_limits = WorkerLimits {
+            timeout_ms: env_number("COMPILER_WORKER_TIMEOUT_MS", 5_000)?,
+            memory_mb: env_number("COMPILER_WORKER_MEMORY_MB", 256)?,
+        };
+        let session_limits = SessionLimits {
+            max_heap_bytes: usize::try_from(env_number("COMPILER_SESSION_MEMORY_MB", 16)?)
+                .ok()
+                .and_then(|mb| mb.checked_mul(1024 * 1024))
+                .ok_or("COMPILER_SESSION_MEMORY_MB is too large")?,
+            instruction_budget: env_number("COMPILER_SESSION_INSTRUCTIONS", 100_000_000)?,
+            idle_timeout: Duration::from_secs(env_number("COMPILER_SESSION_IDLE_SECS", 1_800)?),
+        };
+        let limit_ceilings = ExecutionLimits {
+            max_instructions: env_optional_number("COMPILER_MAX_INSTRUCTIONS")?,
+            max_output_bytes: env_optional_number("COMPILER_MAX_OUTPUT_BYTES")?
+                .map(|bytes| usize::try_from(bytes).map_err(|_| "COMPILER_MAX_OUTPUT_BYTES is too large"))
+                .transpose()?,
+            max_call_depth: env_optional_number("COMPILER_MAX_CALL_DEPTH")?
+                .map(|depth| usize::try_from(depth).map_err(|_| "COMPILER_MAX_CALL_DEPTH is too large"))
+                .transpose()?,
+            timeout_ms: env_optional_number("COMPILER_MAX_TIMEOUT_MS")?,
+        };
+        // The lexer and parser read the caps themselves; a bad value stops the server here
+        lexer::SourceLimits::from_env()?;
+        let profiles = match std::env::var("COMPILER_PROFILES") {
+            Ok(path) => Profiles::load(path.as_ref())?,
+            Err(_) => Profiles::default(),
+        };
+        let admin_token = std::env::var("COMPILER_ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+        let history_size = usize::try_from(env_number("COMPILER_HISTORY_SIZE", 1_000)?)
+            .map_err(|_| "COMPILER_HISTORY_SIZE is too large")?;
+        let history_file = std::env::var_os("COMPILER_HISTORY_FILE").filter(|path| !path.is_empty()).map(Into::into);
+        #[cfg(feature = "grpc")]
+        let grpc_addr = std::env::var("COMPILER_GRPC_ADDR")
+            .unwrap_or_else(|_| "0.0.0.0:50051".to_string())
+            .parse()
+            .map_err(|e| format!("Invalid COMPILER_GRPC_ADDR: {}", e))?;
+
+        Ok(ServerConfig {
+            policy,
+            execution_mode,
+            worker_limits,
+            session_limits,
+            limit_ceilings,
+            profiles,
+            admin_token,
+            history_size,
+            history_file,
+            #[cfg(feature = "grpc")]
+            grpc_addr,
+        })
+    }
+}
+
+// Reads a numeric environment variable, falling back to a default when unset
+#[cfg(feature = "server")]
+fn env_number(name: &str, default: u64) -> Result<u64, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().map_err(|_| format!("{} must be a number, got '{}'", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+// Reads a numeric environment variable that may be unset
+#[cfg(feature = "server")]
+fn env_optional_number(name: &str) -> Result<Option<u64>, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().map(Some).map_err(|_| format!("{} must be a number, got '{}'", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+// Struct to serialize the output back to frontend
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CodeOutput {
+    result: String,            // Result of code execution
+    bytecode: Vec<String>,     // Human-readable version of bytecode instructions, strings as `#N`
+    #[serde(default)]
+    strings: Vec<String>,      // Strings of the bytecode, `#N` being the Nth
+    error: Option<String>,     // Error message if something goes wrong
+    stats: ExecutionStats,     // Instruction count and memory usage of the run
+    #[serde(default)]
+    exit_code: Option<i32>,    // Code given to exit(), 0 when the program ran to its end; null when it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    variables: Option<Vec<VariableSnapshot>>, // Final globals, when `inspect_variables` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    returned: Option<BTreeMap<String, serde_json::Value>>, // Final values of the `return_variables`, on success
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<serde_json::Value>, // Value of the expression in expression mode, on success
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    files: BTreeMap<String, String>, // Files the program wrote, with their final contents
+    #[serde(default)]
+    error_details: Option<ErrorDetails>, // Kind and position of the error, if any
+    #[serde(default)]
+    passes: Vec<String>,       // Optimization passes applied to the bytecode, in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    optimization_log: Option<Vec<String>>, // What each pass did, when `verbose` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timings: Option<Vec<PhaseTiming>>, // Time each compiler phase took, when `verbose` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replay: Option<ReplayBundle>, // Bundle for POST /replay, when `record` is set
+}
+
+impl CodeOutput {
+    // Output for a request that failed before or outside the program run
+    fn failure(kind: ErrorKind, message: String) -> Self {
+        CodeOutput {
+            result: String::new(),
+            bytecode: Vec::new(),
+            strings: Vec::new(),
+            error: Some(format!("Error: {}", message)),
+            stats: ExecutionStats::default(),
+            exit_code: None,
+            variables: None,
+            returned: None,
+            value: None,
+            files: BTreeMap::new(),
+            error_details: Some(ErrorDetails::new(kind, message)),
+            passes: Vec::new(),
+            optimization_log: None,
+            timings: None,
+            replay: None,
+        }
+    }
+
+    // Translates the error, if any, into `locale`
+    fn localize(&mut self, locale: &str) {
+        if let Some(details) = &mut self.error_details {
+            details.localize(locale);
+            self.error = Some(format!("Error: {}", details.message));
+        }
+    }
+
+    // HTTP status matching the outcome of the request
+    #[cfg(feature = "server")]
+    fn status(&self) -> StatusCode {
+        self.error_details.as_ref().map_or(StatusCode::OK, |details| details.kind.status())
+    }
+}
+
+// Category of a failed request, which determines its HTTP status
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ErrorKind {
+    Lexical,       // Invalid characters or literals (400)
+    Syntax,        // Source does not parse (400)
+    Compile,       // Bytecode generation rejected the program (400)
+    Runtime,       // The program failed while running, e.g. division by zero (400)
+    InvalidInput,  // The request body is malformed (422)
+    Timeout,       // The program ran longer than allowed (408)
+    Unauthorized,  // An admin or stored program request without the right token or key (401)
+    NotFound,      // An execution, debug session or stored program that does not exist (404)
+    Conflict,      // A stored program replaced since the client last saw it (409)
+    QuotaExceeded, // The session has used its instruction budget (429)
+    Internal,      // A bug in the compiler or its worker process (500)
+}
+
+impl ErrorKind {
+    // Name used in responses, e.g. "invalid_input"
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Lexical => "lexical",
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Compile => "compile",
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::InvalidInput => "invalid_input",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Unauthorized => "unauthorized",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::QuotaExceeded => "quota_exceeded",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    #[cfg(feature = "server")]
+    fn status(self) -> StatusCode {
+        match self {
+            ErrorKind::Lexical | ErrorKind::Syntax | ErrorKind::Compile | ErrorKind::Runtime => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorKind::InvalidInput => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::Timeout => StatusCode::REQUEST_TIMEOUT,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Structured description of an error, returned alongside the `error` string
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+struct ErrorDetails {
+    kind: ErrorKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<String>, // Stable code of the error, e.g. "E0001", explained by GET /explain/{code}
+    message: String,      // Message without the "Error: " prefix
+    line: Option<usize>,  // Source position, when the error has one
+    column: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rendered: Option<String>, // The message above the source line, with a caret at the position
+}
+
+impl ErrorDetails {
+    // Classifies an error raised while compiling or running a program
+    fn from_error(e: &(dyn Error + 'static)) -> Self {
+        let (kind, position) = if let Some(lexer_error) = e.downcast_ref::<LexerError>() {
+            (ErrorKind::Lexical, Some((lexer_error.line(), Some(lexer_error.column()))))
+        } else if let Some(parser_error) = e.downcast_ref::<ParserError>() {
+            (ErrorKind::Syntax, Some((parser_error.line(), Some(parser_error.column()))))
+        } else if let Some(resolve_error) = e.downcast_ref::<ResolveError>() {
+            (ErrorKind::Compile, Some((resolve_error.line(), Some(resolve_error.column()))))
+        } else if let Some(semantic_error) = e.downcast_ref::<SemanticError>() {
+            (ErrorKind::Compile, Some((semantic_error.line(), Some(semantic_error.column()))))
+        } else if let Some(statement_error) = e.downcast_ref::<StatementError>() {
+            // Runtime errors are known to the statement, not the column
+            (ErrorKind::Runtime, Some((statement_error.line, None)))
+        } else if e.downcast_ref::<BytecodeGeneratorError>().is_some() {
+            (ErrorKind::Compile, None)
+        } else if e.downcast_ref::<ArtifactError>().is_some()
+            || e.downcast_ref::<VerifyError>().is_some()
+            || e.downcast_ref::<RenameError>().is_some()
+        {
+            (ErrorKind::InvalidInput, None)
+        } else {
+            (ErrorKind::Runtime, None)
+        };
+
+        ErrorDetails {
+            line: position.map(|(line, _)| line),
+            column: position.and_then(|(_, column)| column),
+            ..ErrorDetails::new(kind, e.to_string())
+        }
+    }
+
+    // Error without a source position, with the code its message is filed under
+    fn new(kind: ErrorKind, message: String) -> Self {
+        ErrorDetails {
+            kind,
+            code: registry::classify(kind, &message).map(|entry| entry.code.to_string()),
+            message,
+            line: None,
+            column: None,
+            rendered: None,
+        }
+    }
+
+    // Translates the message into `locale`, when the catalog has it
+    fn localize(&mut self, locale: &str) {
+        let translated = self.code.as_deref().and_then(|code| i18n::translate(code, &self.message, locale));
+        if let Some(translated) = translated {
+            self.rendered = self.rendered.take().map(|rendered| rendered.replacen(&self.message, &translated, 1));
+            self.message = translated;
+        }
+    }
+
+    // Adds the rendering of the error in `source`, when it has a position:
+    // carets under the token at its column, or under its whole line
+    fn rendered_in(mut self, source: &str) -> Self {
+        if let Some(line) = self.line {
+            let header = format!("error[{}]: {}", self.code.as_deref().unwrap_or(self.kind.name()), self.message);
+            self.rendered = Some(match self.column {
+                Some(column) => diagnostics::render(&header, source, line, column),
+                None => diagnostics::render_line(&header, source, line),
+            });
+        }
+        self
+    }
+}
+
+// Query options accepted by /compile
+#[cfg(feature = "server")]
+#[derive(Deserialize, IntoParams)]
+struct StatusOptions {
+    #[serde(default)]
+    legacy_status: bool, // Answer 200 for every error, as older frontends expect
+}
+
+// Query string check usable before the body has been parsed
+#[cfg(feature = "server")]
+fn wants_legacy_status(query: &str) -> bool {
+    web::Query::<StatusOptions>::from_query(query).is_ok_and(|options| options.legacy_status)
+}
+
+// Route handler for POST /compile
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = CodeInput,
+    params(StatusOptions),
+    responses(
+        (status = 200, description = "Program compiled and ran", body = CodeOutput),
+        (status = 400, description = "Lexical, syntax, compile or runtime error", body = CodeOutput),
+        (status = 408, description = "Program exceeded the time limit", body = CodeOutput),
+        (status = 422, description = "Malformed request body", body = CodeOutput),
+        (status = 429, description = "The session has used its instruction budget", body = CodeOutput),
+        (status = 500, description = "Internal compiler error", body = CodeOutput),
+    )
+)]
+#[post("/compile")]
+async fn compile(
+    code_input: web::Json<CodeInput>,
+    options: web::Query<StatusOptions>,
+    config: web::Data<ServerConfig>,
+    documents: web::Data<DocumentStore>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+    request: HttpRequest,
+) -> impl Responder {
+    if let Some(document) = &code_input.document {
+        // Errors are reported by the compilation itself
+        let _ = documents.update(document, &code_input.source);
+    }
+    let locale = i18n::negotiate(code_input.locale.as_deref(), accept_language(&request));
+    let job = WorkerJob::new(code_input.into_inner(), config.policy.clone());
+    let mut output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+    if let Some(locale) = locale {
+        output.localize(locale);
+    }
+    respond(output, &options)
+}
+
+// Request body of POST /execute
+#[cfg(feature = "server")]
+#[derive(Deserialize, ToSchema)]
+struct ExecuteInput {
+    artifact: String,        // Base64 of an artifact written by `compiler build`
+    #[serde(default)]
+    stdin: String,           // Input lines returned by read_line()
+    #[serde(default)]
+    env: BTreeMap<String, String>, // Variables returned by getenv()
+    #[serde(default)]
+    files: BTreeMap<String, String>, // Files read_file() can read, as for /compile
+    #[serde(default)]
+    globals: BTreeMap<String, serde_json::Value>, // Globals defined before the program runs, as for /compile
+    #[serde(default)]
+    return_variables: Vec<String>, // Globals whose final values are returned, as for /compile
+    #[serde(default)]
+    seed: Option<u64>,       // Seed for random builtins, for reproducible runs
+    #[serde(default)]
+    inspect_variables: bool, // Return the final value of every global variable
+    #[serde(default)]
+    session: Option<String>, // Session whose limits apply to the run, as for /compile
+    #[serde(default)]
+    limits: ExecutionLimits, // Limits of the run, as for /compile; the artifact's also apply
+    #[serde(default)]
+    profile: Option<String>, // Profile whose settings apply, as for /compile
+    #[serde(default)]
+    record: bool,            // Return a replay bundle of the run
+}
+
+// Route handler for POST /execute
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = ExecuteInput,
+    params(StatusOptions),
+    responses(
+        (status = 200, description = "Artifact ran", body = CodeOutput),
+        (status = 400, description = "Runtime error", body = CodeOutput),
+        (status = 408, description = "Program exceeded the time limit", body = CodeOutput),
+        (status = 422, description = "Malformed request body, or an artifact that is invalid or fails verification", body = CodeOutput),
+        (status = 429, description = "The session has used its instruction budget", body = CodeOutput),
+        (status = 500, description = "Internal compiler error", body = CodeOutput),
+    )
+)]
+#[post("/execute")]
+async fn execute(
+    execute_input: web::Json<ExecuteInput>,
+    options: web::Query<StatusOptions>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+    request: HttpRequest,
+) -> impl Responder {
+    let execute_input = execute_input.into_inner();
+    let input = CodeInput {
+        source: String::new(),
+        language: String::new(),
+        mode: Mode::Program,
+        inspect_variables: execute_input.inspect_variables,
+        seed: execute_input.seed,
+        stdin: execute_input.stdin,
+        env: execute_input.env,
+        files: execute_input.files,
+        globals: execute_input.globals,
+        return_variables: execute_input.return_variables,
+        document: None,
+        opt_level: 0,
+        strict: StrictMode::default(),
+        verbose: false,
+        session: execute_input.session,
+        limits: execute_input.limits,
+        profile: execute_input.profile,
+        record: execute_input.record,
+        replay_clock: None,
+        locale: None,
+    };
+    let job = WorkerJob {
+        artifact: Some(execute_input.artifact),
+        ..WorkerJob::new(input, config.policy.clone())
+    };
+    let output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+    respond(output, &options)
+}
+
+// Address of the client that sent a request, as seen by the server
+#[cfg(feature = "server")]
+fn client_of(request: &HttpRequest) -> Option<String> {
+    request.peer_addr().map(|address| address.to_string())
+}
+
+// Languages the client accepts, from its Accept-Language header
+#[cfg(feature = "server")]
+fn accept_language(request: &HttpRequest) -> Option<&str> {
+    request.headers().get(ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok())
+}
+
+// Response with the status matching the outcome, unless the client asked for 200
+#[cfg(feature = "server")]
+fn respond(output: CodeOutput, options: &StatusOptions) -> HttpResponse {
+    let status = if options.legacy_status { StatusCode::OK } else { output.status() };
+    HttpResponse::build(status).json(output)
+}
+
+// Runs the job of a request from `client` with the settings of the profile it
+// selects, if any, within its own limits clamped to the server's, and within the limits of the session it names, if any, which
+// is charged for it. A session that has used its budget is refused before the
+// job runs. The job is listed as a running execution until it finishes, and
+// then recorded in the history.
+#[cfg(feature = "server")]
+async fn dispatch_request(
+    mut job: WorkerJob,
+    client: Option<String>,
+    config: &ServerConfig,
+    sessions: &SessionStore,
+    executions: &ExecutionRegistry,
+) -> CodeOutput {
+    if let Some(name) = &job.input.profile {
+        match config.profiles.get(name) {
+            Some(profile) => profile.apply(&mut job),
+            None => return CodeOutput::failure(ErrorKind::InvalidInput, format!("Unknown profile: {}", name)),
+        }
+    }
+    job.input.limits = job.input.limits.clamped(&config.limit_ceilings);
+    let run = match job.input.session.as_deref().map(|id| sessions.start(id)).transpose() {
+        Ok(run) => run,
+        Err(message) => return CodeOutput::failure(ErrorKind::QuotaExceeded, message),
+    };
+    if let Some(run) = &run {
+        job.quota = run.quota.clone();
+    }
+    let execution = executions.register(&job, client, config.execution_mode == ExecutionMode::InProcess);
+    let output = dispatch(job, config).await;
+    if let Some(run) = run {
+        run.finish(&output.stats);
+    }
+    execution.finish(&output);
+    output
+}
+
+// Runs a job as configured: on a blocking thread so long computations don't
+// stall the server, or in a separate worker process
+#[cfg(feature = "server")]
+async fn dispatch(job: WorkerJob, config: &ServerConfig) -> CodeOutput {
+    let outcome = match config.execution_mode {
+        ExecutionMode::InProcess => web::block(move || run_job(&job)).await,
+        ExecutionMode::Worker => {
+            let limits = config.worker_limits.clone();
+            web::block(move || match worker::run_isolated(&job, &limits) {
+                Ok(output) => Ok(output),
+                Err(WorkerError::Timeout(ms)) => Ok(CodeOutput::failure(
+                    ErrorKind::Timeout,
+                    format!("Execution timed out after {} ms", ms),
+                )),
+                Err(WorkerError::Cancelled) => Ok(CodeOutput::failure(ErrorKind::Runtime, "Execution cancelled".to_string())),
+                Err(WorkerError::Failed(message)) => Err(message),
+            }).await
+        }
+    };
+
+    match outcome {
+        Ok(Ok(output)) => output,
+        Ok(Err(message)) => {
+            CodeOutput::failure(ErrorKind::Internal, format!("Internal compiler error: {}", message))
+        }
+        Err(e) => CodeOutput::failure(ErrorKind::Internal, format!("Internal compiler error: {}", e)),
+    }
+}
+
+// Runs a job in this process: its artifact if it has one, otherwise its source.
+// Panics are returned as errors.
+fn run_job(job: &WorkerJob) -> Result<CodeOutput, String> {
+    let mut output = match &job.artifact {
+        None => run_program_guarded(&job.input, job.vm_config()),
+        Some(encoded) => match Artifact::load_base64(encoded) {
+            Ok(mut artifact) => {
+                if job.timed_by_parent {
+                    artifact.limits.timeout_ms = None;
+                }
+                catch_panics(|| run_artifact(&artifact, &job.input, job.vm_config()))
+            }
+            Err(e) => {
+                let details = ErrorDetails::from_error(e.as_ref());
+                Ok(CodeOutput::failure(details.kind, details.message))
+            }
+        },
+    }?;
+    // The bundle of an artifact run replays the artifact
+    if let Some(replay) = &mut output.replay {
+        replay.artifact = job.artifact.clone();
+    }
+    Ok(output)
+}
+
+// Runs a program, turning any panic in the lexer, parser, code generator or VM
+// into an error message instead of unwinding into the server
+fn run_program_guarded(code_input: &CodeInput, config: VmConfig) -> Result<CodeOutput, String> {
+    catch_panics(|| run_program(code_input, config))
+}
+
+// Runs `run`, returning the panic message if it panics
+fn catch_panics<T>(run: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(run)).map_err(|payload| {
+        if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        }
+    })
+}
+
+// Compiles and runs one program, capturing the result or error. `config`
+// holds the settings of the server; those of the request are added to it,
+// and its timeout cancels the run once it passes.
+fn run_program(code_input: &CodeInput, config: VmConfig) -> CodeOutput {
+    if code_input.opt_level > optimizer::MAX_OPT_LEVEL {
+        return CodeOutput::failure(
+            ErrorKind::InvalidInput,
+            format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL),
+        );
+    }
+    let globals = match preloaded_globals(&code_input.globals) {
+        Ok(globals) => globals,
+        Err(message) => return CodeOutput::failure(ErrorKind::InvalidInput, message),
+    };
+    let config = VmConfig {
+        globals,
+        seed: code_input.seed,
+        stdin: code_input.stdin.clone(),
+        env: code_input.env.clone(),
+        files: config.files.with_files(&code_input.files),
+        replay_clock: code_input.replay_clock.clone(),
+        ..config
+    };
+    with_timeout(code_input.limits.timeout_ms, config, |config| {
+        let mut compilation = Compilation::new(
+            &code_input.source,
+            Dialect::from_language(&code_input.language),
+            code_input.strict.checks(),
+            code_input.opt_level,
+        );
+        compilation.mode = code_input.mode;
+        let mut timings = Vec::new();
+        let mut output = run_on_vm(config, code_input, |vm| process_code(&mut compilation, &mut timings, vm));
+        output.passes = optimizer::pass_names(code_input.opt_level);
+        output.optimization_log = code_input.verbose.then_some(compilation.log);
+        output.timings = code_input.verbose.then_some(timings);
+        output
+    })
+}
+
+// Runs a program compiled earlier, e.g. loaded from a bytecode artifact. Its
+// debug information, unless stripped, tells where a runtime error happened
+fn run_compiled(artifact: &Artifact, config: VmConfig, code_input: &CodeInput) -> CodeOutput {
+    run_on_vm(config, code_input, |vm| {
+        execute_instructions(&artifact.instructions, artifact.max_stack, vm).map_err(|error| match &artifact.debug {
+            Some(debug) => debug.describe_error(&error.to_string(), vm.ip()),
+            None => error,
+        })
+    })
+}
+
+// Runs a verified artifact. Its limits can only tighten those of `config`: the
+// sleep budget is capped, and the sooner of its timeout and the request's
+// cancels the run once it passes.
+fn run_artifact(artifact: &Artifact, code_input: &CodeInput, config: VmConfig) -> CodeOutput {
+    let globals = match preloaded_globals(&code_input.globals) {
+        Ok(globals) => globals,
+        Err(message) => return CodeOutput::failure(ErrorKind::InvalidInput, message),
+    };
+    let config = VmConfig {
+        globals,
+        max_sleep_ms: artifact.limits.max_sleep_ms.map_or(config.max_sleep_ms, |ms| ms.min(config.max_sleep_ms)),
+        seed: code_input.seed,
+        stdin: code_input.stdin.clone(),
+        env: code_input.env.clone(),
+        files: config.files.with_files(&code_input.files),
+        replay_clock: code_input.replay_clock.clone(),
+        ..config
+    };
+    let timeout_ms = tightest(artifact.limits.timeout_ms, code_input.limits.timeout_ms);
+    with_timeout(timeout_ms, config, |config| run_compiled(artifact, config, code_input))
+}
+
+// Globals a request defines before its program runs, from their JSON
+// values. A name must be an identifier, and a value null, a boolean, a
+// number, a string or an array of those.
+fn preloaded_globals(globals: &BTreeMap<String, serde_json::Value>) -> Result<BTreeMap<String, vm::Value>, String> {
+    globals
+        .iter()
+        .map(|(name, value)| {
+            let tokens = lexer::Lexer::new(name).tokenize().unwrap_or_default();
+            let identifier = matches!(tokens.first().map(|token| &token.token_type), Some(lexer::TokenType::Identifier(token)) if token == name);
+            if !identifier || tokens.len() != 2 {
+                return Err(format!("Global '{}' is not a valid variable name", name));
+            }
+            let value = builtins::from_json(value.clone()).map_err(|e| format!("Global '{}': {}", name, e))?;
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+// Final values of the globals `names` as JSON, unless none are asked for.
+// Globals the program left undefined are left out, and numbers JSON cannot
+// encode, NaN and the infinities, are null.
+fn returned_globals(vm: &VirtualMachine, names: &[String]) -> Option<BTreeMap<String, serde_json::Value>> {
+    if names.is_empty() {
+        return None;
+    }
+    let returned = names
+        .iter()
+        .filter_map(|name| {
+            let value = builtins::to_json(vm.global(name)?).unwrap_or(serde_json::Value::Null);
+            Some((name.clone(), value))
+        })
+        .collect();
+    Some(returned)
+}
+
+// Value of the expression of an expression-mode run as JSON, null when JSON
+// cannot encode it
+fn expression_value(vm: &VirtualMachine, mode: Mode) -> Option<serde_json::Value> {
+    if mode.is_program() {
+        return None;
+    }
+    let value = vm.last_value().map_or(Ok(serde_json::Value::Null), builtins::to_json);
+    Some(value.unwrap_or(serde_json::Value::Null))
+}
+
+// Runs `run` with `config`, cancelling it once `timeout_ms` passes, if set
+fn with_timeout(timeout_ms: Option<u64>, config: VmConfig, run: impl FnOnce(VmConfig) -> CodeOutput) -> CodeOutput {
+    let Some(timeout_ms) = timeout_ms else {
+        return run(config);
+    };
+
+    // The watchdog cancels the run unless it is told first that the run
+    // finished, and reports whether it did: the run may also be cancelled by
+    // an operator
+    let (finished, finished_signal) = mpsc::channel::<()>();
+    let watchdog_token = config.cancel_token.clone();
+    // Platforms without threads, like WASI, cannot enforce the timeout, so
+    // the program is refused there rather than run without it
+    let watchdog = thread::Builder::new().spawn(move || {
+        let timed_out = finished_signal.recv_timeout(Duration::from_millis(timeout_ms)) == Err(RecvTimeoutError::Timeout);
+        if timed_out {
+            watchdog_token.cancel();
+        }
+        timed_out
+    });
+    let watchdog = match watchdog {
+        Ok(watchdog) => watchdog,
+        Err(e) => {
+            return CodeOutput::failure(
+                ErrorKind::Internal,
+                format!("Cannot enforce the timeout of {} ms: {}", timeout_ms, e),
+            );
+        }
+    };
+    let output = run(config);
+    drop(finished);
+
+    if watchdog.join().unwrap_or(false) {
+        CodeOutput::failure(ErrorKind::Timeout, format!("Execution timed out after {} ms", timeout_ms))
+    } else {
+        output
+    }
+}
+
+// Runs `run` on a fresh VM and packs its result, statistics and variables,
+// and a replay bundle when `code_input` asks to record the run
+fn run_on_vm(
+    config: VmConfig,
+    code_input: &CodeInput,
+    run: impl FnOnce(&mut VirtualMachine) -> Result<(String, Listing), Box<dyn Error>>,
+) -> CodeOutput {
+    // Process the input code and handle result or error
+    let mut vm = vmpool::POOL.checkout(config);
+    let result = run(&mut vm);
+    let stats = vm.stats().clone();
+    let files = vm.written_files();
+    let variables = if code_input.inspect_variables {
+        Some(vm.snapshot_variables(MAX_SNAPSHOT_VALUE_LEN))
+    } else {
+        None
+    };
+
+    let mut output = match result {
+        Ok((output, (bytecode, strings))) => {
+            // On success, return execution result and bytecode
+            CodeOutput {
+                result: output,
+                bytecode,
+                strings,
+                error: None,
+                stats,
+                exit_code: Some(vm.exit_code().unwrap_or(0)),
+                variables,
+                returned: returned_globals(&vm, &code_input.return_variables),
+                value: expression_value(&vm, code_input.mode),
+                files,
+                error_details: None,
+                passes: Vec::new(),
+                optimization_log: None,
+                timings: None,
+                replay: None,
+            }
+        },
+        Err(e) => {
+            // On error, return the error message
+            CodeOutput {
+                result: String::new(),
+                bytecode: Vec::new(),
+                strings: Vec::new(),
+                error: Some(format!("Error: {}", e)),
+                stats,
+                exit_code: None,
+                variables,
+                returned: None,
+                value: None,
+                files,
+                error_details: Some(ErrorDetails::from_error(e.as_ref()).rendered_in(&code_input.source)),
+                passes: Vec::new(),
+                optimization_log: None,
+                timings: None,
+                replay: None,
+            }
+        }
+    };
+    if code_input.record {
+        output.replay = Some(ReplayBundle::new(code_input, vm.recording(), &output));
+    }
+    output
+}
+
+// Function to process and compile the source code
+fn process_code(
+    compilation: &mut Compilation,
+    timings: &mut Vec<PhaseTiming>,
+    vm: &mut VirtualMachine,
+) -> Result<(String, Listing), Box<dyn Error>> {
+    // Steps 1-4: Compile the source into VM instructions, then optimize them
+    *timings = PassManager::standard().run(compilation)?;
+    let instructions = &compilation.instructions;
+    // Generated code never pops an empty stack; without a bound the stack grows as needed
+    let max_stack = verifier::max_stack_depth(instructions).unwrap_or(None);
+    
+    // Step 5: Execute instructions on the virtual machine, telling which
+    // statement a runtime error happened in
+    execute_instructions(instructions, max_stack, vm)
+        .map_err(|error| compilation.debug.describe_error(&error.to_string(), vm.ip()))
+}
+
+// Bytecode listing, one instruction per line, and the strings its lines refer to
+type Listing = (Vec<String>, Vec<String>);
+
+// Runs compiled instructions and returns the output with the bytecode listing
+fn execute_instructions(
+    instructions: &[Instruction],
+    max_stack: Option<usize>,
+    vm: &mut VirtualMachine,
+) -> Result<(String, Listing), Box<dyn Error>> {
+    if let Some(slots) = max_stack {
+        vm.reserve_stack(slots);
+    }
+    let output = vm.execute(instructions)?;
+    
+    // Convert each instruction into a string for debugging/display
+    Ok((output, strings::listing(instructions)))
+}
+
+// Compiles source code into VM instructions without running it
+fn compile_source(source: &str) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    compile_dialect(source, Dialect::Standard, Strictness::default())
+}
+
+// Compiles source code written in `dialect` into VM instructions, rejecting
+// programs that fail the checks of `strictness`
+fn compile_dialect(source: &str, dialect: Dialect, strictness: Strictness) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    // Steps 1-4, see `pipeline`: semantic checks are all off unless strict mode enables them
+    let mut compilation = Compilation::new(source, dialect, strictness, 0);
+    PassManager::unoptimized().run(&mut compilation)?;
+    Ok(compilation.instructions)
+}
+
+// Compiles a parsed program into VM instructions
+fn compile_ast(ast: parser::ASTNode) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    compile_module(ast).map(|(instructions, _)| instructions)
+}
+
+// Compiles a parsed program into VM instructions, also returning the module
+// they were linked from, which artifacts take their debug information from
+fn compile_module(ast: parser::ASTNode) -> Result<(Vec<Instruction>, bytecode::Module), Box<dyn Error>> {
+    // Step 3: Bytecode generation - turn AST into bytecode
+    let mut bytecode_gen = BytecodeGenerator::new();
+    let module = bytecode_gen.generate(ast)?;
+    
+    // Step 4: Convert bytecode to VM instructions
+    Ok((link(&module), module))
+}
+
+// VM variable holding slot `slot` of the locals of the chunk numbered
+// `chunk`, hidden from snapshots by its '<'
+fn local_variable(chunk: usize, slot: usize) -> String {
+    match chunk {
+        0 => format!("<local{}>", slot),
+        _ => format!("<local{}.{}>", chunk, slot),
+    }
+}
+
+// Moves the entries of a table kept for each chunk of a module, like its
+// line table, to the instructions `link` lays the code they refer to out at
+fn link_table<T: Clone>(module: &bytecode::Module, table: impl Fn(&bytecode::Chunk) -> &[(usize, T)]) -> Vec<(usize, T)> {
+    let mut linked = Vec::new();
+    let mut offset = 0;
+    for (index, chunk) in module.chunks.iter().enumerate() {
+        // Each function begins with its entry in the function table
+        if index > 0 {
+            offset += 1;
+        }
+        linked.extend(table(chunk).iter().map(|(start, entry)| (start + offset, entry.clone())));
+        offset += chunk.code.len();
+    }
+    linked
+}
+
+// Lays the chunks of a module out one after another as VM instructions,
+// moving the jumps of each chunk by the offset it is placed at
+fn link(module: &bytecode::Module) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    for (index, chunk) in module.chunks.iter().enumerate() {
+        // Each function begins with its entry in the function table
+        if index > 0 {
+            instructions.push(Instruction::Function(chunk.name.clone()));
+        }
+        let offset = instructions.len();
+        instructions.extend(chunk.code.iter().map(|op| convert_to_instruction(op, module, index, offset)));
+    }
+    instructions
+}
+
+// Convert a bytecode OpCode of the chunk numbered `index`, placed at
+// `offset`, to a VM Instruction
+fn convert_to_instruction(op: &bytecode::OpCode, module: &bytecode::Module, index: usize, offset: usize) -> Instruction {
+    use bytecode::OpCode;
+    use bytecode::Value as BytecodeValue;
+    use vm::Value as VMValue;
+    
+    let local = |slot: &usize| local_variable(index, *slot);
+    
+    match op {
+        OpCode::Constant(constant) => {
+            // Map bytecode constants to VM runtime values
+            let vm_value = match module.constant(*constant) {
+                BytecodeValue::Int(i) => VMValue::Int(*i),
+                BytecodeValue::Float(f) => VMValue::Number(*f),
+                BytecodeValue::String(s) => VMValue::String(s.clone()),
+                BytecodeValue::Bool(b) => VMValue::Boolean(*b),
+                BytecodeValue::Null => VMValue::Null,
+            };
+            Instruction::Push(vm_value)
+        },
+        // Arithmetic operations
+        OpCode::Add => Instruction::Add,
+        OpCode::Subtract => Instruction::Subtract,
+        OpCode::Multiply => Instruction::Multiply,
+        OpCode::Divide => Instruction::Divide,
+        OpCode::Negate => Instruction::Negate,
+        
+        // Comparison operations
+        OpCode::Equal => Instruction::Equal,
+        OpCode::NotEqual => Instruction::NotEqual,
+        OpCode::LessThan => Instruction::LessThan,
+        OpCode::GreaterThan => Instruction::GreaterThan,
+        OpCode::LessEqual => Instruction::LessEqual,
+        OpCode::GreaterEqual => Instruction::GreaterEqual,
+
+        // Array operations
+        OpCode::MakeArray(count) => Instruction::MakeArray(*count),
+        OpCode::IndexGet => Instruction::IndexGet,
+        OpCode::IndexSet(count) => Instruction::IndexSet(*count),
+        
+        // Control flow
+        OpCode::Jump(target) => Instruction::Jump(target + offset),
+        OpCode::JumpIfFalse(target) => Instruction::JumpIfFalse(target + offset),
+        OpCode::Halt => Instruction::Halt,
+        
+        // Function call
+        OpCode::Call(function, arg_count) => Instruction::Call(*function, *arg_count),
+        OpCode::CallNative(name, arg_count) => Instruction::CallNative(name.clone(), *arg_count),
+        OpCode::Return => Instruction::Return,
+        
+        // Output and cleanup
+        OpCode::Print => Instruction::Print,
+        OpCode::Pop => Instruction::Pop,
+        OpCode::Duplicate => Instruction::Duplicate,
+        
+        // Variable operations
+        OpCode::DefineGlobal(name) => Instruction::StoreVariable(name.clone()),
+        OpCode::GetGlobal(name) => Instruction::LoadVariable(name.clone()),
+        OpCode::SetGlobal(name) => Instruction::StoreVariable(name.clone()),
+        
+        // Local variables
+        OpCode::GetLocal(slot) => Instruction::LoadVariable(local(slot)),
+        OpCode::SetLocal(slot) => Instruction::StoreVariable(local(slot)),
+    }
+}
+
+/// Entry point of the `compiler` binary: a sandboxed worker running one job,
+/// a CLI command, or the web server. Builds without the "server" feature
+/// print the usage instead of starting it.
+pub fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some(worker::WORKER_FLAG) => worker::worker_main(),
+        #[cfg(feature = "server")]
+        None => run_server(),
+        _ => std::process::exit(cli::run(&args)),
+    }
+}
+
+// Starts the Actix Web server
+#[cfg(feature = "server")]
+#[actix_web::main]
+async fn run_server() -> std::io::Result<()> {
+    let config = ServerConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let config = web::Data::new(config);
+    let documents = web::Data::new(DocumentStore::default());
+    let sessions = web::Data::new(SessionStore::new(config.session_limits.clone()));
+    let history = History::open(config.history_size, config.history_file.as_deref())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let executions = web::Data::new(ExecutionRegistry::new(history));
+    let debug_sessions = web::Data::new(DebugStore::default());
+    let programs = web::Data::new(ProgramStore::default());
+
+    println!("Starting server at http://127.0.0.1:8080");
+    println!("Sandbox capabilities granted to programs: {}", config.policy);
+    println!("Execution mode: {:?}", config.execution_mode);
+    if config.profiles.names().next().is_some() {
+        println!("Profiles: {}", config.profiles.names().collect::<Vec<_>>().join(", "));
+    }
+
+    // The gRPC service runs on the same runtime, on its own port
+    #[cfg(feature = "grpc")]
+    {
+        println!("Starting gRPC service at {}", config.grpc_addr);
+        let serve = grpc::serve(config.grpc_addr, config.clone(), sessions.clone(), executions.clone());
+        actix_web::rt::spawn(async move {
+            if let Err(e) = serve.await {
+                eprintln!("gRPC service stopped: {}", e);
+            }
+        });
+    }
+    actix_web::rt::spawn(scheduler::run(programs.clone(), config.clone(), sessions.clone(), executions.clone()));
+    println!("Visit http://127.0.0.1:8080 in your browser to access the compiler interface");
+    
+    // Create HTTP server
+    HttpServer::new(move || {
+        // Enable CORS for local frontend development
+        let cors = Cors::default()
+            .allow_any_origin()
+            .allow_any_method()
+            .allow_any_header();
+            
+        // Malformed request bodies get the same error envelope as other failures
+        let json_config = web::JsonConfig::default().error_handler(|err, req| {
+            let output = CodeOutput::failure(ErrorKind::InvalidInput, format!("Invalid request: {}", err));
+            let status = if wants_legacy_status(req.query_string()) { StatusCode::OK } else { output.status() };
+            InternalError::from_response(err, HttpResponse::build(status).json(output)).into()
+        });
+
+        App::new()
+            .wrap(cors)
+            .app_data(config.clone())
+            .app_data(documents.clone())
+            .app_data(sessions.clone())
+            .app_data(executions.clone())
+            .app_data(debug_sessions.clone())
+            .app_data(programs.clone())
+            .app_data(json_config)
+            .service(compile) // Register the /compile endpoint
+            .service(execute)
+            .service(docs::generate_docs)
+            .service(completion::complete_at)
+            .service(rename::rename_symbol)
+            .service(navigation::definition)
+            .service(navigation::references)
+            .service(highlight::tokenize)
+            .service(highlight::highlight)
+            .service(documents::diagnostics)
+            .service(cfg::control_flow_graph)
+            .service(replay::replay)
+            .service(grading::grade)
+            .service(testing::run_tests)
+            .service(debugger::start_debugging)
+            .service(debugger::debug_command)
+            .service(debugger::end_debugging)
+            .service(similarity::similarity)
+            .service(metrics::analyze)
+            .service(callgraph::call_graph)
+            .service(registry::explain)
+            .service(programs::upload)
+            .service(programs::list_programs)
+            .service(programs::get_program)
+            .service(programs::delete_program)
+            .service(programs::invoke)
+            .service(programs::list_runs)
+            .service(hooks::call_hook)
+            .service(admin::list_sessions)
+            .service(admin::list_executions)
+            .service(admin::cancel_execution)
+            .service(admin::list_history)
+            .service(vmpool::metrics)
+            .service(openapi::openapi_json)
+            .service(openapi::swagger_ui)
+            .service(fs::Files::new("/", "./").index_file("index.html")) // Serve frontend files
+    })
+    .bind("0.0.0.0:8080")? // Bind server to all network interfaces
+    .run()
+    .await
+}
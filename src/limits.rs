@@ -0,0 +1,47 @@
+// Execution limits a request sets for its own run, so that an embedder or a
+// grader can give each exercise a tighter budget than the server's. The
+// server clamps every limit to the maximum it is configured with: a request
+// can lower a limit but never raise it, and a limit it leaves out is the
+// server's maximum.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Limits of one run; a limit that is absent does not bound it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExecutionLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_instructions: Option<u64>, // Instructions the program may execute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<usize>, // Bytes the program may print, its final value included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_call_depth: Option<usize>, // Calls that may be nested at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,       // Wall-clock time the run may take
+}
+
+impl ExecutionLimits {
+    /// Whether no limit is set.
+    pub fn is_unset(&self) -> bool {
+        *self == ExecutionLimits::default()
+    }
+
+    /// These limits, each lowered to its maximum in `ceilings`.
+    pub fn clamped(&self, ceilings: &ExecutionLimits) -> ExecutionLimits {
+        ExecutionLimits {
+            max_instructions: tightest(self.max_instructions, ceilings.max_instructions),
+            max_output_bytes: tightest(self.max_output_bytes, ceilings.max_output_bytes),
+            max_call_depth: tightest(self.max_call_depth, ceilings.max_call_depth),
+            timeout_ms: tightest(self.timeout_ms, ceilings.timeout_ms),
+        }
+    }
+}
+
+/// The lower of two limits, either of which may be absent.
+pub fn tightest<T: Ord>(limit: Option<T>, other: Option<T>) -> Option<T> {
+    match (limit, other) {
+        (Some(limit), Some(other)) => Some(limit.min(other)),
+        (limit, other) => limit.or(other),
+    }
+}
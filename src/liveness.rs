@@ -0,0 +1,131 @@
+// Liveness of variables: a variable is live at a point when a path from there
+// reads it before storing it again. Computed backwards over the control-flow
+// graph until no block changes. Two variables interfere when one is stored
+// while the other is live, so they could not share a storage slot.
+//
+// The end of the program reads nothing: a variable whose value is only seen
+// in the final `variables` snapshot is not live there.
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+use utoipa::ToSchema;
+
+use crate::cfg::ControlFlowGraph;
+use crate::vm::Instruction;
+
+/// Variables live on entry to and exit from a block.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlockLiveness {
+    pub block: usize,
+    pub live_in: Vec<String>,
+    pub live_out: Vec<String>,
+}
+
+/// Variables that are live at the same time as a variable.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Interference {
+    pub variable: String,
+    pub interferes_with: Vec<String>,
+}
+
+/// Liveness of every block, and the interference graph.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Liveness {
+    pub blocks: Vec<BlockLiveness>,        // By block id
+    pub interference: Vec<Interference>, // By variable name
+}
+
+/// Computes the liveness of the variables of `code`, whose graph is `graph`.
+pub fn analyse(code: &[Instruction], graph: &ControlFlowGraph) -> Liveness {
+    // What each block reads before storing it, and what it stores
+    let mut uses = Vec::with_capacity(graph.blocks.len());
+    let mut stores = Vec::with_capacity(graph.blocks.len());
+    for block in &graph.blocks {
+        let mut used = BTreeSet::new();
+        let mut stored = BTreeSet::new();
+        for instruction in &code[block.start..block.end] {
+            match instruction {
+                Instruction::LoadVariable(name) if !stored.contains(name) => {
+                    used.insert(name.clone());
+                }
+                Instruction::StoreVariable(name) => {
+                    stored.insert(name.clone());
+                }
+                _ => {}
+            }
+        }
+        uses.push(used);
+        stores.push(stored);
+    }
+
+    let mut live_in: Vec<BTreeSet<String>> = vec![BTreeSet::new(); graph.blocks.len()];
+    let mut live_out: Vec<BTreeSet<String>> = vec![BTreeSet::new(); graph.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in graph.blocks.iter().rev() {
+            let out: BTreeSet<String> = block
+                .successors
+                .iter()
+                .flat_map(|successor| live_in[*successor].iter().cloned())
+                .collect();
+            let mut live: BTreeSet<String> = out.difference(&stores[block.id]).cloned().collect();
+            live.extend(uses[block.id].iter().cloned());
+            if live != live_in[block.id] || out != live_out[block.id] {
+                live_in[block.id] = live;
+                live_out[block.id] = out;
+                changed = true;
+            }
+        }
+    }
+
+    // Walk each block backwards from its live-out set: a store interferes
+    // with everything live just after it
+    let mut edges: BTreeSet<(String, String)> = BTreeSet::new();
+    let mut variables: BTreeSet<String> = BTreeSet::new();
+    for block in &graph.blocks {
+        let mut live = live_out[block.id].clone();
+        for instruction in code[block.start..block.end].iter().rev() {
+            match instruction {
+                Instruction::StoreVariable(name) => {
+                    variables.insert(name.clone());
+                    for other in &live {
+                        if other != name {
+                            edges.insert((name.clone(), other.clone()));
+                            edges.insert((other.clone(), name.clone()));
+                        }
+                    }
+                    live.remove(name);
+                }
+                Instruction::LoadVariable(name) => {
+                    variables.insert(name.clone());
+                    live.insert(name.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Liveness {
+        blocks: graph
+            .blocks
+            .iter()
+            .map(|block| BlockLiveness {
+                block: block.id,
+                live_in: live_in[block.id].iter().cloned().collect(),
+                live_out: live_out[block.id].iter().cloned().collect(),
+            })
+            .collect(),
+        interference: variables
+            .into_iter()
+            .map(|variable| Interference {
+                interferes_with: edges
+                    .iter()
+                    .filter(|(from, _)| *from == variable)
+                    .map(|(_, to)| to.clone())
+                    .collect(),
+                variable,
+            })
+            .collect(),
+    }
+}
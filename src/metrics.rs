@@ -0,0 +1,244 @@
+// Code metrics, for feedback on code quality in the teaching UI: the size of
+// a program in lines and statements, its cyclomatic complexity and how deeply
+// its control structures nest, in total and for each top-level statement,
+// which includes each function.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::error::Error;
+use utoipa::ToSchema;
+
+use crate::lexer::{Lexer, TokenType};
+use crate::parser::{ASTNode, Parser};
+use crate::reparse::split_statements;
+use crate::ErrorDetails;
+#[cfg(feature = "server")]
+use crate::SourceInput;
+
+/// Metrics of a whole program.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct Metrics {
+    pub lines: usize,                 // Lines holding code, not counting blank and comment lines
+    pub statements: usize,            // Statements at any depth; blocks are not counted
+    pub cyclomatic_complexity: usize, // Independent paths: 1 plus one per `if` and `while`
+    pub max_nesting_depth: usize,     // Deepest `if` or `while` inside others; 0 without any
+    pub items: Vec<ItemMetrics>,      // One per top-level statement, in source order
+}
+
+/// Metrics of one top-level statement.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ItemMetrics {
+    pub kind: String,         // "declaration", "function", "expression", "if", "while", "block", "return", "defer" or "import"
+    pub name: Option<String>, // The declared variable or function, for declarations
+    pub line: usize,          // First line, after any doc comment
+    pub end_line: usize,
+    pub statements: usize,
+    pub cyclomatic_complexity: usize,
+    pub nesting_depth: usize,
+}
+
+/// Computes the metrics of `source`, which must parse.
+pub fn analyse(source: &str) -> Result<Metrics, Box<dyn Error>> {
+    let tokens = Lexer::new(source).tokenize()?;
+    let mut metrics = Metrics {
+        lines: tokens
+            .iter()
+            .filter(|token| !matches!(token.token_type, TokenType::EOF | TokenType::DocComment(_)))
+            .map(|token| token.line)
+            .collect::<BTreeSet<_>>()
+            .len(),
+        cyclomatic_complexity: 1,
+        ..Metrics::default()
+    };
+
+    for statement in split_statements(&tokens) {
+        let mut code = statement.iter().filter(|token| !matches!(token.token_type, TokenType::DocComment(_)));
+        let (Some(first), Some(last)) = (code.next(), statement.last()) else {
+            continue;
+        };
+        let items = match Parser::new(statement.to_vec()).parse()? {
+            ASTNode::Program(items) => items,
+            item => vec![item],
+        };
+        for item in &items {
+            let mut counts = Counts::default();
+            counts.visit(item, 0);
+            metrics.statements += counts.statements;
+            metrics.cyclomatic_complexity += counts.decisions;
+            metrics.max_nesting_depth = metrics.max_nesting_depth.max(counts.depth);
+            metrics.items.push(ItemMetrics {
+                kind: kind(item).to_string(),
+                name: match item {
+                    ASTNode::VarDeclaration { name, .. }
+                    | ASTNode::FunctionDeclaration { name, .. }
+                    | ASTNode::TestBlock { name, .. } => Some(name.clone()),
+                    _ => None,
+                },
+                line: first.line,
+                end_line: last.line,
+                statements: counts.statements,
+                cyclomatic_complexity: counts.decisions + 1,
+                nesting_depth: counts.depth,
+            });
+        }
+    }
+    Ok(metrics)
+}
+
+// Totals gathered while walking a statement
+#[derive(Default)]
+struct Counts {
+    statements: usize,
+    decisions: usize, // `if` and `while` statements, and if expressions
+    depth: usize,     // Deepest nesting of decisions
+}
+
+impl Counts {
+    // `depth` is the number of decisions enclosing `node`
+    fn visit(&mut self, node: &ASTNode, depth: usize) {
+        match node {
+            ASTNode::Program(statements) | ASTNode::Block(statements) => {
+                for statement in statements {
+                    self.visit(statement, depth);
+                }
+            }
+            ASTNode::IfStatement { then_branch, else_branch, .. } => {
+                self.decide(depth);
+                self.visit(then_branch, depth + 1);
+                if let Some(else_branch) = else_branch {
+                    self.visit(else_branch, depth + 1);
+                }
+            }
+            ASTNode::WhileStatement { body, .. } => {
+                self.decide(depth);
+                self.visit(body, depth + 1);
+            }
+            ASTNode::DeferStatement(statement) | ASTNode::TestBlock { body: statement, .. } => self.visit(statement, depth),
+            ASTNode::FunctionDeclaration { body, .. } => self.expression(body, depth),
+            ASTNode::VarDeclaration { initializer: Some(expression), .. }
+            | ASTNode::ExpressionStatement(expression)
+            | ASTNode::PrintStatement(expression)
+            | ASTNode::ReturnStatement(Some(expression)) => {
+                self.statements += 1;
+                self.expression(expression, depth);
+            }
+            ASTNode::VarDeclaration { .. }
+            | ASTNode::ReturnStatement(None)
+            | ASTNode::BreakStatement(_)
+            | ASTNode::ContinueStatement(_)
+            | ASTNode::Import(_) => self.statements += 1,
+            _ => {}
+        }
+    }
+
+    // Visits the statements of block expressions in `node`; if expressions
+    // are decisions like if statements, though not statements themselves
+    fn expression(&mut self, node: &ASTNode, depth: usize) {
+        match node {
+            ASTNode::BlockExpression { statements, value } => {
+                for statement in statements {
+                    self.visit(statement, depth);
+                }
+                if let Some(value) = value {
+                    self.expression(value, depth);
+                }
+            }
+            ASTNode::IfExpression { condition, then_branch, else_branch } => {
+                self.decisions += 1;
+                self.depth = self.depth.max(depth + 1);
+                self.expression(condition, depth);
+                self.expression(then_branch, depth + 1);
+                self.expression(else_branch, depth + 1);
+            }
+            ASTNode::BinaryExpression { .. } => {
+                for operand in node.operands() {
+                    self.expression(operand, depth);
+                }
+            }
+            ASTNode::UnaryExpression { operand: value, .. } | ASTNode::AssignmentExpression { value, .. } => {
+                self.expression(value, depth)
+            }
+            ASTNode::CallExpression { callee, arguments } => {
+                self.expression(callee, depth);
+                for argument in arguments {
+                    self.expression(argument, depth);
+                }
+            }
+            ASTNode::IndexExpression { array, index } => {
+                self.expression(array, depth);
+                self.expression(index, depth);
+            }
+            ASTNode::IndexAssignment { indices: elements, value, .. } => {
+                for element in elements {
+                    self.expression(element, depth);
+                }
+                self.expression(value, depth);
+            }
+            ASTNode::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.expression(element, depth);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn decide(&mut self, depth: usize) {
+        self.statements += 1;
+        self.decisions += 1;
+        self.depth = self.depth.max(depth + 1);
+    }
+}
+
+fn kind(item: &ASTNode) -> &'static str {
+    match item {
+        ASTNode::VarDeclaration { .. } => "declaration",
+        ASTNode::FunctionDeclaration { .. } => "function",
+        ASTNode::TestBlock { .. } => "test",
+        ASTNode::IfStatement { .. } => "if",
+        ASTNode::WhileStatement { .. } => "while",
+        ASTNode::Block(_) | ASTNode::Program(_) => "block",
+        ASTNode::ReturnStatement(_) => "return",
+        ASTNode::Import(_) => "import",
+        ASTNode::PrintStatement(_) => "print",
+        ASTNode::DeferStatement(_) => "defer",
+        _ => "expression",
+    }
+}
+
+// Response of POST /analyze
+#[derive(Serialize, ToSchema)]
+pub struct AnalyzeOutput {
+    #[serde(flatten)]
+    metrics: Metrics, // Empty when the source does not parse
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for POST /analyze
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = SourceInput,
+    responses(
+        (status = 200, description = "Metrics of the program and of each top-level statement", body = AnalyzeOutput),
+        (status = 400, description = "The source does not parse", body = AnalyzeOutput),
+    )
+)]
+#[post("/analyze")]
+pub async fn analyze(input: web::Json<SourceInput>) -> impl Responder {
+    match analyse(&input.source) {
+        Ok(metrics) => HttpResponse::Ok().json(AnalyzeOutput {
+            metrics,
+            error_details: None,
+        }),
+        Err(e) => {
+            let details = ErrorDetails::from_error(e.as_ref());
+            HttpResponse::build(details.kind.status()).json(AnalyzeOutput {
+                metrics: Metrics::default(),
+                error_details: Some(details),
+            })
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// Symbol navigation for the LSP and the web editor: go to definition and find
+// references. Both work on the symbol table of a document, which is either
+// resolved from the source sent with the request or taken from the documents
+// kept for /compile and /diagnostics requests with a "document" id.
+
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::documents::DocumentStore;
+use crate::resolver::{SymbolKind, SymbolTable, TextSpan};
+use crate::{ErrorDetails, ErrorKind};
+
+// Request body of POST /definition and POST /references
+#[derive(Deserialize, ToSchema)]
+pub struct NavigationInput {
+    #[serde(default)]
+    document: Option<String>, // Id given to /compile or /diagnostics; with `source`, the document is updated
+    #[serde(default)]
+    source: Option<String>,   // Required unless the document is kept and compiles
+    cursor: usize,            // Character offset of any part of a name
+}
+
+// The symbol under the cursor
+#[derive(Serialize, ToSchema)]
+pub struct SymbolInfo {
+    name: String,
+    kind: SymbolKind,
+    detail: String, // Declared type of a variable, signature of a builtin
+}
+
+// One use of a symbol
+#[derive(Serialize, ToSchema)]
+pub struct Reference {
+    span: TextSpan,
+    is_declaration: bool,
+}
+
+// Response of POST /definition
+#[derive(Serialize, ToSchema)]
+pub struct DefinitionOutput {
+    symbol: Option<SymbolInfo>,  // null when the cursor is not on a name
+    definitions: Vec<TextSpan>,  // Declarations of the symbol; empty for builtins
+    error_details: Option<ErrorDetails>,
+}
+
+// Response of POST /references
+#[derive(Serialize, ToSchema)]
+pub struct ReferencesOutput {
+    symbol: Option<SymbolInfo>, // null when the cursor is not on a name
+    references: Vec<Reference>, // In source order, declarations included
+    error_details: Option<ErrorDetails>,
+}
+
+// Finds the symbol table the request refers to
+fn symbol_table(input: &NavigationInput, documents: &DocumentStore) -> Result<Arc<SymbolTable>, ErrorDetails> {
+    let table = match (&input.document, &input.source) {
+        (Some(document), Some(source)) => documents.update(document, source),
+        (None, Some(source)) => SymbolTable::build(source).map(Arc::new),
+        (Some(document), None) => documents.get(document).ok_or_else(|| {
+            format!(
+                "Unknown document '{}', or its current source does not compile: send its source",
+                document
+            )
+            .into()
+        }),
+        (None, None) => Err("Either \"document\" or \"source\" is required".into()),
+    };
+    table.map_err(|e| {
+        let mut details = ErrorDetails::from_error(e.as_ref());
+        if details.kind == ErrorKind::Runtime {
+            details.kind = ErrorKind::InvalidInput;
+        }
+        details
+    })
+}
+
+fn symbol_info(table: &SymbolTable, symbol: usize) -> SymbolInfo {
+    let symbol = &table.symbols[symbol];
+    SymbolInfo {
+        name: symbol.name.clone(),
+        kind: symbol.kind,
+        detail: symbol.detail.clone(),
+    }
+}
+
+// Route handler for POST /definition
+#[utoipa::path(
+    tag = "compiler",
+    request_body = NavigationInput,
+    responses(
+        (status = 200, description = "Declarations of the symbol under the cursor", body = DefinitionOutput),
+        (status = 400, description = "The source does not compile", body = DefinitionOutput),
+        (status = 422, description = "Unknown document, or neither a document nor a source", body = DefinitionOutput),
+    )
+)]
+#[post("/definition")]
+pub async fn definition(input: web::Json<NavigationInput>, documents: web::Data<DocumentStore>) -> impl Responder {
+    let table = match symbol_table(&input, &documents) {
+        Ok(table) => table,
+        Err(details) => {
+            return HttpResponse::build(details.kind.status()).json(DefinitionOutput {
+                symbol: None,
+                definitions: Vec::new(),
+                error_details: Some(details),
+            })
+        }
+    };
+
+    let occurrence = table.occurrence_at(input.cursor);
+    HttpResponse::Ok().json(DefinitionOutput {
+        symbol: occurrence.map(|occurrence| symbol_info(&table, occurrence.symbol)),
+        definitions: occurrence.map_or_else(Vec::new, |occurrence| table.symbols[occurrence.symbol].declarations.clone()),
+        error_details: None,
+    })
+}
+
+// Route handler for POST /references
+#[utoipa::path(
+    tag = "compiler",
+    request_body = NavigationInput,
+    responses(
+        (status = 200, description = "Every use of the symbol under the cursor", body = ReferencesOutput),
+        (status = 400, description = "The source does not compile", body = ReferencesOutput),
+        (status = 422, description = "Unknown document, or neither a document nor a source", body = ReferencesOutput),
+    )
+)]
+#[post("/references")]
+pub async fn references(input: web::Json<NavigationInput>, documents: web::Data<DocumentStore>) -> impl Responder {
+    let table = match symbol_table(&input, &documents) {
+        Ok(table) => table,
+        Err(details) => {
+            return HttpResponse::build(details.kind.status()).json(ReferencesOutput {
+                symbol: None,
+                references: Vec::new(),
+                error_details: Some(details),
+            })
+        }
+    };
+
+    let Some(occurrence) = table.occurrence_at(input.cursor) else {
+        return HttpResponse::Ok().json(ReferencesOutput {
+            symbol: None,
+            references: Vec::new(),
+            error_details: None,
+        });
+    };
+    let declarations = &table.symbols[occurrence.symbol].declarations;
+    HttpResponse::Ok().json(ReferencesOutput {
+        symbol: Some(symbol_info(&table, occurrence.symbol)),
+        references: table
+            .occurrences_of(occurrence.symbol)
+            .map(|reference| Reference {
+                span: reference.span,
+                is_declaration: declarations.contains(&reference.span),
+            })
+            .collect(),
+        error_details: None,
+    })
+}
@@ -0,0 +1,87 @@
+// OpenAPI description of the HTTP API, generated from the request/response
+// types, plus a Swagger UI page for exploring it in the browser.
+
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Custom Language Compiler",
+        description = "Compile and run programs written in the custom language."
+    ),
+    paths(
+        crate::compile,
+        crate::execute,
+        crate::docs::generate_docs,
+        crate::completion::complete_at,
+        crate::rename::rename_symbol,
+        crate::navigation::definition,
+        crate::navigation::references,
+        crate::highlight::tokenize,
+        crate::highlight::highlight,
+        crate::documents::diagnostics,
+        crate::cfg::control_flow_graph,
+        crate::replay::replay,
+        crate::grading::grade,
+        crate::testing::run_tests,
+        crate::debugger::start_debugging,
+        crate::debugger::debug_command,
+        crate::debugger::end_debugging,
+        crate::similarity::similarity,
+        crate::metrics::analyze,
+        crate::callgraph::call_graph,
+        crate::registry::explain,
+        crate::programs::upload,
+        crate::programs::list_programs,
+        crate::programs::get_program,
+        crate::programs::delete_program,
+        crate::programs::invoke,
+        crate::programs::list_runs,
+        crate::hooks::call_hook,
+        crate::admin::list_sessions,
+        crate::admin::list_executions,
+        crate::admin::cancel_execution,
+        crate::admin::list_history,
+        crate::vmpool::metrics
+    ),
+    tags(
+        (name = "compiler", description = "Compiling and running programs"),
+        (name = "programs", description = "Storing programs and invoking them by name"),
+        (name = "admin", description = "Operating the server; needs the admin token"),
+        (name = "metrics", description = "Monitoring the server")
+    )
+)]
+pub struct ApiDoc;
+
+// Swagger UI assets are loaded from a CDN, like the editor's CodeMirror assets
+const SWAGGER_UI_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="UTF-8" />
+    <title>Custom Language Compiler - API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+// Route handler for GET /openapi.json
+#[get("/openapi.json")]
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+// Route handler for GET /swagger-ui
+#[get("/swagger-ui")]
+pub async fn swagger_ui() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI_PAGE)
+}
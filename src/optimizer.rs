@@ -0,0 +1,916 @@
+// Bytecode optimizer: passes over VM instructions, chosen by optimization level.
+//
+//   0: none
+//   1: constant folding and peephole
+//   2: also function inlining, constant inlining, dead code elimination and
+//      loop-invariant code motion
+//
+// Passes never change what a program observes: its result (the value on the
+// stack at the end, or else the last value popped), its output, its errors
+// and the final value of its variables. Only the instruction count and the
+// other statistics of a run change. Each pass logs what it did, and the
+// inliners why they inlined a function or a variable or not.
+
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
+use crate::callgraph::CallGraph;
+use crate::cfg::ControlFlowGraph;
+use crate::vm::{self, Instruction, Value};
+
+/// Highest optimization level.
+pub const MAX_OPT_LEVEL: u8 = 2;
+
+// Most instructions of a function body copied to its calls
+const INLINE_LIMIT: usize = 16;
+
+/// One transformation of the bytecode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    InlineFunctions,     // Calls of small non-recursive functions become a copy of their body
+    InlineConstants,     // Loads of variables only ever set to a constant become that constant
+    ConstantFolding,     // Operations on constants are computed at compile time
+    DeadCodeElimination, // Branches on constant conditions and unreachable code are removed
+    LoopInvariantCodeMotion, // Computations a loop repeats with the same operands run once before it
+    Peephole,            // Redundant jumps and stack traffic are removed
+}
+
+impl Pass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::InlineFunctions => "inline_functions",
+            Pass::InlineConstants => "inline_constants",
+            Pass::ConstantFolding => "constant_folding",
+            Pass::DeadCodeElimination => "dead_code_elimination",
+            Pass::LoopInvariantCodeMotion => "loop_invariant_code_motion",
+            Pass::Peephole => "peephole",
+        }
+    }
+}
+
+/// Passes run at an optimization level, in order.
+pub fn passes(opt_level: u8) -> &'static [Pass] {
+    match opt_level {
+        0 => &[],
+        1 => &[Pass::ConstantFolding, Pass::Peephole],
+        _ => &[
+            Pass::InlineFunctions,
+            Pass::InlineConstants,
+            Pass::ConstantFolding,
+            Pass::DeadCodeElimination,
+            Pass::LoopInvariantCodeMotion,
+            Pass::Peephole,
+        ],
+    }
+}
+
+/// Names of the passes run at an optimization level, for responses.
+pub fn pass_names(opt_level: u8) -> Vec<String> {
+    passes(opt_level).iter().map(|pass| pass.name().to_string()).collect()
+}
+
+/// Optimizes instructions with the passes of a level.
+pub fn optimize(code: Vec<Instruction>, opt_level: u8) -> Vec<Instruction> {
+    optimize_logged(code, opt_level).0
+}
+
+/// Optimizes instructions with the passes of a level, returning the log of
+/// what each pass did.
+pub fn optimize_logged(mut code: Vec<Instruction>, opt_level: u8) -> (Vec<Instruction>, Vec<String>) {
+    let mut log = Vec::new();
+    for pass in passes(opt_level) {
+        let before = code.len();
+        let mut details = Vec::new();
+        code = run_pass(*pass, code, &mut details);
+        log.push(format!("{}: {} -> {} instructions", pass.name(), before, code.len()));
+        log.extend(details.into_iter().map(|detail| format!("{}: {}", pass.name(), detail)));
+    }
+    (code, log)
+}
+
+/// Runs one pass, adding what it did to `log`.
+pub fn run_pass(pass: Pass, code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    match pass {
+        Pass::InlineFunctions => inline_functions(code, log),
+        Pass::InlineConstants => inline_constants(code, log),
+        Pass::ConstantFolding => fold_constants(code, log),
+        Pass::DeadCodeElimination => eliminate_dead_code(code, log),
+        Pass::LoopInvariantCodeMotion => hoist_loop_invariants(code, log),
+        Pass::Peephole => peephole(code, log),
+    }
+}
+
+// Replaces the calls of small functions by a copy of their body, which takes
+// the arguments from the stack and leaves the return value there as the call
+// would. A function is inlined when it runs straight to its first return, in
+// at most INLINE_LIMIT instructions, and cannot call itself again, as the
+// call graph tells. Its locals then belong to the caller, which saves them
+// around its own calls. Each round inlines the functions whose body calls no
+// other function to inline; as those cannot call one another in a cycle,
+// every round inlines some until none is left. Functions whose calls were
+// all inlined are then removed.
+fn inline_functions(mut code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    let graph = CallGraph::from_code(&code);
+    let recursive: HashSet<String> = graph.recursive().into_iter().map(str::to_string).collect();
+    let mut inlined = BTreeMap::new(); // Function index -> calls inlined
+    for _ in 0..graph.nodes.len() {
+        let candidates = inline_candidates(&code, &recursive);
+        let inlinable = |callee: usize| candidates.get(callee).is_some_and(|(_, body)| body.is_ok());
+        let ready: Vec<Option<Range<usize>>> = candidates
+            .iter()
+            .map(|(_, body)| {
+                let body = body.clone().ok()?;
+                let calls_inlinable = code[body.clone()]
+                    .iter()
+                    .any(|instruction| matches!(instruction, Instruction::Call(callee, _) if inlinable(*callee)));
+                (!calls_inlinable).then_some(body)
+            })
+            .collect();
+        let is_ready = |callee: usize| ready.get(callee).is_some_and(Option::is_some);
+        if !code.iter().any(|instruction| matches!(instruction, Instruction::Call(callee, _) if is_ready(*callee))) {
+            break;
+        }
+
+        let mut new_index = Vec::with_capacity(code.len() + 1);
+        let mut rewritten = Vec::with_capacity(code.len());
+        for instruction in &code {
+            new_index.push(rewritten.len());
+            match instruction_callee(instruction).and_then(|callee| Some((callee, ready.get(callee)?.clone()?))) {
+                Some((callee, body)) => {
+                    rewritten.extend(code[body].iter().cloned());
+                    *inlined.entry(callee).or_insert(0) += 1;
+                }
+                None => rewritten.push(instruction.clone()),
+            }
+        }
+        new_index.push(rewritten.len());
+        for instruction in &mut rewritten {
+            if let Instruction::Jump(target) | Instruction::JumpIfFalse(target) = instruction {
+                *target = new_index[*target];
+            }
+        }
+        code = rewritten;
+    }
+
+    let candidates = inline_candidates(&code, &recursive);
+    for (callee, calls) in &inlined {
+        log.push(format!("inlined '{}' at {} call(s)", candidates[*callee].0, calls));
+    }
+    let called: HashSet<usize> = code.iter().filter_map(instruction_callee).collect();
+    for (callee, (name, body)) in candidates.iter().enumerate() {
+        if let (true, Err(reason)) = (called.contains(&callee), body) {
+            log.push(format!("kept '{}': {}", name, reason));
+        }
+    }
+
+    // Functions whose calls were all inlined are removed, and the calls of
+    // the others renumbered in the function table
+    let entries: Vec<usize> = functions(&code).into_iter().map(|(_, entry)| entry).collect();
+    let ends = entries.iter().skip(1).copied().chain([code.len()]);
+    let mut removed = vec![false; code.len()];
+    let mut table = Vec::with_capacity(entries.len()); // Index in the function table once the others are removed
+    let mut removed_functions = 0;
+    for (callee, (entry, end)) in entries.iter().zip(ends).enumerate() {
+        table.push(callee - removed_functions);
+        if inlined.contains_key(&callee) && !called.contains(&callee) {
+            removed[*entry..end].fill(true);
+            removed_functions += 1;
+            log.push(format!("removed '{}': all its calls are inlined", candidates[callee].0));
+        }
+    }
+    for instruction in &mut code {
+        if let Instruction::Call(callee, _) = instruction {
+            *callee = table[*callee];
+        }
+    }
+    compact(code, &removed)
+}
+
+// The functions of the code, by index in the function table, with the body
+// copied to their calls when they can be inlined, or why they cannot. The
+// body runs from the entry to the first return, without jumps.
+fn inline_candidates(code: &[Instruction], recursive: &HashSet<String>) -> Vec<(String, Result<Range<usize>, String>)> {
+    functions(code)
+        .into_iter()
+        .map(|(name, entry)| {
+            let start = entry + 1;
+            let end = code[start..]
+                .iter()
+                .position(|instruction| !falls_through_only(instruction) && !matches!(instruction, Instruction::Call(..)))
+                .map_or(code.len(), |end| start + end);
+            let body = if recursive.contains(&name) {
+                Err("it calls itself, directly or through other functions".to_string())
+            } else if !matches!(code.get(end), Some(Instruction::Return)) {
+                Err("it branches before returning".to_string())
+            } else if end - start > INLINE_LIMIT {
+                Err(format!("its body has {} instructions, more than {}", end - start, INLINE_LIMIT))
+            } else {
+                Ok(start..end)
+            };
+            (name, body)
+        })
+        .collect()
+}
+
+// Names and entries of the functions of the code, by index in the function table
+fn functions(code: &[Instruction]) -> Vec<(String, usize)> {
+    code.iter()
+        .enumerate()
+        .filter_map(|(entry, instruction)| match instruction {
+            Instruction::Function(name) => Some((name.clone(), entry)),
+            _ => None,
+        })
+        .collect()
+}
+
+// The index in the function table of the function an instruction calls
+fn instruction_callee(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Call(callee, _) => Some(*callee),
+        _ => None,
+    }
+}
+
+// Replaces loads of a variable stored exactly once, with a constant, by that
+// constant. The store must run before any jump and not be a jump target, so
+// it runs once and before every load that follows it.
+fn inline_constants(mut code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    let targets = jump_targets(&code);
+    let straight_line = code
+        .iter()
+        .enumerate()
+        .position(|(index, instruction)| targets[index] || !falls_through_only(instruction))
+        .unwrap_or(code.len());
+
+    let mut names: Vec<&String> = Vec::new();
+    for instruction in &code {
+        if let Instruction::StoreVariable(name) = instruction {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    let mut constants = Vec::new();
+    for name in names {
+        let stores: Vec<usize> = code
+            .iter()
+            .enumerate()
+            .filter(|(_, instruction)| matches!(instruction, Instruction::StoreVariable(other) if other == name))
+            .map(|(index, _)| index)
+            .collect();
+        let store = stores[0];
+        let decision = match (stores.len(), store.checked_sub(1).map(|previous| &code[previous])) {
+            (1, Some(Instruction::Push(value))) if store < straight_line => {
+                let loads = code[store + 1..]
+                    .iter()
+                    .filter(|instruction| matches!(instruction, Instruction::LoadVariable(load) if load == name))
+                    .count();
+                log.push(format!("inlined '{}' = {} at {} load(s)", name, value, loads));
+                constants.push((store, name.clone(), value.clone()));
+                continue;
+            }
+            (1, Some(Instruction::Push(_))) => "its value is set after a branch or inside a loop",
+            (1, _) => "it is not set to a constant",
+            _ => "it is set more than once",
+        };
+        log.push(format!("kept '{}': {}", name, decision));
+    }
+
+    for (store, name, value) in constants {
+        for instruction in &mut code[store + 1..] {
+            if matches!(instruction, Instruction::LoadVariable(load) if *load == name) {
+                *instruction = Instruction::Push(value.clone());
+            }
+        }
+    }
+    code
+}
+
+// Computes operations whose operands are constants pushed just before them.
+// No jump may land between the operands and the operation.
+fn fold_constants(mut code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    let mut entry = jump_targets(&code);
+    let mut removed = vec![false; code.len()];
+    let mut live: Vec<usize> = Vec::new(); // Indexes of the instructions kept so far
+    let mut folded_operations = 0;
+
+    for index in 0..code.len() {
+        let operands = match &code[index] {
+            Instruction::Negate => 1,
+            Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::IntDivide
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::LessThan
+            | Instruction::GreaterThan
+            | Instruction::LessEqual
+            | Instruction::GreaterEqual => 2,
+            _ => 0,
+        };
+        let first = live.len().checked_sub(operands);
+        let folded = match (operands, first) {
+            (1, Some(first)) if !entry[index] => match &code[live[first]] {
+                Instruction::Push(value) => vm::negate(value.clone()).ok(),
+                _ => None,
+            },
+            (2, Some(first)) if !entry[index] && !entry[live[first + 1]] => {
+                match (&code[live[first]], &code[live[first + 1]]) {
+                    (Instruction::Push(a), Instruction::Push(b)) => fold(&code[index], a, b),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        if let (Some(value), Some(first)) = (folded, first) {
+            // Jumps to the first operand now land on the result
+            entry[index] = entry[live[first]];
+            for operand in live.drain(first..) {
+                removed[operand] = true;
+            }
+            code[index] = Instruction::Push(value);
+            folded_operations += 1;
+        }
+        live.push(index);
+    }
+    log.push(format!("folded {} operation(s)", folded_operations));
+    compact(code, &removed)
+}
+
+// Result of a binary operation on two constants, as the VM computes it,
+// when it does not fail
+fn fold(operation: &Instruction, a: &Value, b: &Value) -> Option<Value> {
+    vm::binary(operation, a.clone(), b.clone()).ok()
+}
+
+// Turns branches on constant conditions into jumps, or removes them, then
+// removes the instructions no path reaches
+fn eliminate_dead_code(mut code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    let targets = jump_targets(&code);
+    let mut removed = vec![false; code.len()];
+    let mut constant_branches = 0;
+    for index in 1..code.len() {
+        if targets[index] {
+            continue;
+        }
+        if let (Instruction::Push(condition), Instruction::JumpIfFalse(target)) = (&code[index - 1], &code[index]) {
+            // Only `false` makes the VM take the branch
+            let taken = !condition.is_truthy();
+            removed[index - 1] = true;
+            constant_branches += 1;
+            if taken {
+                code[index] = Instruction::Jump(*target);
+            } else {
+                removed[index] = true;
+            }
+        }
+    }
+    let code = compact(code, &removed);
+
+    let mut reachable = vec![false; code.len()];
+    let mut pending = vec![0];
+    pending.extend(code.iter().enumerate().filter(|(_, instruction)| is_function_entry(instruction)).map(|(index, _)| index));
+    while let Some(index) = pending.pop() {
+        if index >= code.len() || reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        match &code[index] {
+            Instruction::Jump(target) => pending.push(*target),
+            Instruction::JumpIfFalse(target) => pending.extend([*target, index + 1]),
+            Instruction::Halt => {}
+            _ => pending.push(index + 1),
+        }
+    }
+    let unreachable: Vec<bool> = reachable.iter().map(|reachable| !reachable).collect();
+    log.push(format!(
+        "resolved {} constant branch(es), removed {} unreachable instruction(s)",
+        constant_branches,
+        unreachable.iter().filter(|unreachable| **unreachable).count()
+    ));
+    compact(code, &unreachable)
+}
+
+// Moves computations whose operands do not change in a loop to just before
+// it, into a temporary the loop then reads. A computation is moved only when
+// it cannot fail, since the loop body may never run: its variables surely hold
+// numbers when the loop starts, and it only divides by non-zero constants.
+fn hoist_loop_invariants(mut code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    // Functions may set any variable while a loop runs
+    if code.iter().any(|instruction| matches!(instruction, Instruction::Call(..)) || is_function_entry(instruction)) {
+        log.push("skipped: the program calls functions".to_string());
+        return code;
+    }
+    let mut temporaries = code
+        .iter()
+        .filter(|instruction| matches!(instruction, Instruction::StoreVariable(name) if name.starts_with("<licm")))
+        .count();
+
+    let mut hoisted = 0;
+    // Each round moves computations out of one loop; a computation leaves at
+    // most one loop per round, so the rounds are bounded by the code size
+    for _ in 0..code.len() {
+        let Some(states) = number_states(&code) else {
+            log.push("skipped: the stack depth differs between paths".to_string());
+            break;
+        };
+        let found = loops(&code).into_iter().find_map(|(header, end)| {
+            let fragments = invariant_fragments(&code, &states, header, end);
+            (!fragments.is_empty()).then_some((header, end, fragments))
+        });
+        let Some((header, end, fragments)) = found else {
+            break;
+        };
+
+        let mut removed = vec![false; code.len()];
+        let mut preheader = Vec::new();
+        for (start, stop) in &fragments {
+            let name = format!("<licm{}>", temporaries);
+            temporaries += 1;
+            let listing: Vec<String> = code[*start..*stop].iter().map(|instruction| format!("{:?}", instruction)).collect();
+            log.push(format!("hoisted [{}] out of the loop at {} into {}", listing.join(", "), header, name));
+
+            preheader.extend(code[*start..*stop].iter().cloned());
+            preheader.push(Instruction::StoreVariable(name.clone()));
+            code[*start] = Instruction::LoadVariable(name);
+            removed[*start + 1..*stop].fill(true);
+        }
+        hoisted += fragments.len();
+        let end = end - removed.iter().filter(|removed| **removed).count();
+        code = compact(code, &removed);
+
+        // The preheader goes before the header: jumps into the loop from
+        // outside now enter the preheader, jumps from inside still go to the header
+        let size = preheader.len();
+        let moved = |from: usize, target: usize| match target {
+            target if target == header && !(header..=end).contains(&from) => header,
+            target if target >= header => target + size,
+            target => target,
+        };
+        let body = code.split_off(header);
+        code.extend(preheader);
+        code.extend(body);
+        for (index, instruction) in code.iter_mut().enumerate() {
+            let from = if index >= header + size { index - size } else { index };
+            match instruction {
+                Instruction::Jump(target) | Instruction::JumpIfFalse(target) => *target = moved(from, *target),
+                _ => {}
+            }
+        }
+    }
+    log.push(format!("hoisted {} computation(s)", hoisted));
+    code
+}
+
+// Loops as (header, end): the instruction ranges of the natural loops that
+// are contiguous, so the loop is entered only at the start of the range
+fn loops(code: &[Instruction]) -> Vec<(usize, usize)> {
+    let graph = ControlFlowGraph::build(code);
+    graph
+        .loops
+        .iter()
+        .filter_map(|natural_loop| {
+            let blocks = &natural_loop.blocks;
+            let (first, last) = (*blocks.first()?, *blocks.last()?);
+            let contiguous = first == natural_loop.header && last - first + 1 == blocks.len();
+            contiguous.then(|| (graph.blocks[first].start, graph.blocks[last].end - 1))
+        })
+        .collect()
+}
+
+// Computations in a loop that can be moved before it, as instruction ranges.
+// Each range pushes one value from constants and variables the loop never
+// sets, and contains at least one operation.
+fn invariant_fragments(
+    code: &[Instruction],
+    states: &[Option<NumberState>],
+    header: usize,
+    end: usize,
+) -> Vec<(usize, usize)> {
+    let set_in_loop: HashSet<&String> = code[header..=end]
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::StoreVariable(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let targets = jump_targets(code);
+
+    // For the top stack slots, the range of invariant code that pushed each
+    // value and whether the value is a number; None for any other value
+    let mut slots: Vec<Option<(usize, usize, bool)>> = Vec::new();
+    let mut fragments = Vec::new();
+
+    for index in header..=end {
+        let Some(state) = &states[index] else {
+            slots.drain(..).for_each(|slot| finish(slot, &mut fragments));
+            continue;
+        };
+        if targets[index] {
+            slots.drain(..).for_each(|slot| finish(slot, &mut fragments));
+        }
+        match &code[index] {
+            Instruction::Push(Value::Int(_) | Value::Number(_)) => slots.push(Some((index, index + 1, true))),
+            Instruction::LoadVariable(name)
+                if !set_in_loop.contains(name) && state.variables.contains(name) =>
+            {
+                slots.push(Some((index, index + 1, true)))
+            }
+            Instruction::Push(_) | Instruction::LoadVariable(_) => slots.push(None),
+            Instruction::Negate => match slots.pop().flatten() {
+                Some((start, stop, true)) if stop == index => slots.push(Some((start, index + 1, true))),
+                operand => {
+                    finish(operand, &mut fragments);
+                    slots.push(None);
+                }
+            },
+            operation @ (Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::IntDivide
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::LessThan
+            | Instruction::GreaterThan
+            | Instruction::LessEqual
+            | Instruction::GreaterEqual) => {
+                let (b, a) = (slots.pop().flatten(), slots.pop().flatten());
+                let divisor_safe = |(start, stop, _): (usize, usize, bool)| {
+                    !matches!(operation, Instruction::Divide | Instruction::IntDivide)
+                        || (stop - start == 1 && matches!(&code[start], Instruction::Push(n) if n.as_number().is_some_and(|n| n != 0.0)))
+                };
+                match (a, b) {
+                    (Some((start, middle, true)), Some(right @ (_, stop, true)))
+                        if right.0 == middle && stop == index && divisor_safe(right) =>
+                    {
+                        let number = matches!(
+                            operation,
+                            Instruction::Add
+                                | Instruction::Subtract
+                                | Instruction::Multiply
+                                | Instruction::Divide
+                                | Instruction::IntDivide
+                        );
+                        slots.push(Some((start, index + 1, number)));
+                    }
+                    (a, b) => {
+                        finish(a, &mut fragments);
+                        finish(b, &mut fragments);
+                        slots.push(None);
+                    }
+                }
+            }
+            _ => slots.drain(..).for_each(|slot| finish(slot, &mut fragments)),
+        }
+    }
+    slots.drain(..).for_each(|slot| finish(slot, &mut fragments));
+    fragments.sort_unstable();
+    fragments
+}
+
+// Keeps the range that pushed a value used by code that is not invariant,
+// when the range contains an operation
+fn finish(slot: Option<(usize, usize, bool)>, fragments: &mut Vec<(usize, usize)>) {
+    if let Some((start, stop, _)) = slot {
+        if stop - start > 1 {
+            fragments.push((start, stop));
+        }
+    }
+}
+
+// What is sure before an instruction: which stack slots, from the bottom, and
+// which variables hold numbers
+#[derive(Clone, PartialEq)]
+struct NumberState {
+    stack: Vec<bool>,
+    variables: HashSet<String>,
+}
+
+// The state before each instruction, None where no path reaches; None overall
+// when paths meet with different stack depths
+fn number_states(code: &[Instruction]) -> Option<Vec<Option<NumberState>>> {
+    let mut states: Vec<Option<NumberState>> = vec![None; code.len()];
+    let mut pending = vec![(
+        0,
+        NumberState {
+            stack: Vec::new(),
+            variables: HashSet::new(),
+        },
+    )];
+    while let Some((index, incoming)) = pending.pop() {
+        if index >= code.len() {
+            continue;
+        }
+        let state = match &states[index] {
+            None => incoming,
+            Some(state) => {
+                if state.stack.len() != incoming.stack.len() {
+                    return None;
+                }
+                let met = NumberState {
+                    stack: state.stack.iter().zip(&incoming.stack).map(|(a, b)| *a && *b).collect(),
+                    variables: state.variables.intersection(&incoming.variables).cloned().collect(),
+                };
+                if met == *state {
+                    continue;
+                }
+                met
+            }
+        };
+        states[index] = Some(state.clone());
+
+        let mut next = state;
+        let stack = &mut next.stack;
+        match &code[index] {
+            Instruction::Push(value) => stack.push(value.as_number().is_some()),
+            Instruction::Pop | Instruction::Print => {
+                stack.pop()?;
+            }
+            Instruction::Duplicate => stack.push(*stack.last()?),
+            // Addition continues only on two numbers or two strings
+            Instruction::Add => {
+                let (b, a) = (stack.pop()?, stack.pop()?);
+                stack.push(a || b);
+            }
+            Instruction::Subtract | Instruction::Multiply | Instruction::Divide | Instruction::IntDivide => {
+                stack.pop()?;
+                stack.pop()?;
+                stack.push(true);
+            }
+            Instruction::Negate => {
+                stack.pop()?;
+                stack.push(true);
+            }
+            Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::LessThan
+            | Instruction::GreaterThan
+            | Instruction::LessEqual
+            | Instruction::GreaterEqual => {
+                stack.pop()?;
+                stack.pop()?;
+                stack.push(false);
+            }
+            Instruction::StoreVariable(name) => {
+                if stack.pop()? {
+                    next.variables.insert(name.clone());
+                } else {
+                    next.variables.remove(name);
+                }
+            }
+            Instruction::LoadVariable(name) => {
+                let number = next.variables.contains(name);
+                next.stack.push(number);
+            }
+            Instruction::Jump(target) => {
+                pending.push((*target, next));
+                continue;
+            }
+            Instruction::JumpIfFalse(target) => {
+                stack.pop()?;
+                pending.push((*target, next.clone()));
+            }
+            Instruction::CallNative(_, arguments) => {
+                stack.truncate(stack.len().checked_sub(*arguments)?);
+                stack.push(false);
+            }
+            Instruction::MakeArray(elements) => {
+                stack.truncate(stack.len().checked_sub(*elements)?);
+                stack.push(false);
+            }
+            Instruction::IndexGet => {
+                stack.pop()?;
+                stack.pop()?;
+                stack.push(false);
+            }
+            // The value assigned stays below the updated array
+            Instruction::IndexSet(indices) => {
+                let value = stack.pop()?;
+                stack.truncate(stack.len().checked_sub(indices + 1)?);
+                stack.extend([value, false]);
+            }
+            Instruction::Halt => continue,
+            // Without functions a return continues with the next instruction
+            Instruction::Call(..) | Instruction::Function(_) => return None,
+            Instruction::Return => {}
+        }
+        pending.push((index + 1, next));
+    }
+    Some(states)
+}
+
+// Threads jumps to jumps, removes jumps to the next instruction, and removes
+// values pushed only to be popped when a later pop in the same straight-line
+// run replaces the program's last popped value anyway
+fn peephole(mut code: Vec<Instruction>, log: &mut Vec<String>) -> Vec<Instruction> {
+    let mut threaded_jumps = 0;
+    for index in 0..code.len() {
+        let threaded = match &code[index] {
+            Instruction::Jump(target) => Instruction::Jump(final_target(&code, *target)),
+            Instruction::JumpIfFalse(target) => Instruction::JumpIfFalse(final_target(&code, *target)),
+            _ => continue,
+        };
+        if !same_jump(&threaded, &code[index]) {
+            threaded_jumps += 1;
+        }
+        code[index] = threaded;
+    }
+
+    let targets = jump_targets(&code);
+    let mut removed = vec![false; code.len()];
+    let mut index = 0;
+    while index < code.len() {
+        match &code[index..] {
+            [Instruction::Jump(target), ..] if *target == index + 1 => removed[index] = true,
+            [Instruction::Push(_), Instruction::Pop, ..] if !targets[index + 1] && popped_later(&code, index + 2) => {
+                removed[index] = true;
+                removed[index + 1] = true;
+                index += 1;
+            }
+            [Instruction::Duplicate, Instruction::StoreVariable(_), Instruction::Pop, ..]
+                if !targets[index + 1] && !targets[index + 2] && popped_later(&code, index + 3) =>
+            {
+                removed[index] = true;
+                removed[index + 2] = true;
+                index += 2;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    log.push(format!(
+        "threaded {} jump(s), removed {} instruction(s)",
+        threaded_jumps,
+        removed.iter().filter(|removed| **removed).count()
+    ));
+    compact(code, &removed)
+}
+
+fn same_jump(a: &Instruction, b: &Instruction) -> bool {
+    match (a, b) {
+        (Instruction::Jump(a), Instruction::Jump(b)) | (Instruction::JumpIfFalse(a), Instruction::JumpIfFalse(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Where a jump to `target` ends up after following the jumps it lands on
+fn final_target(code: &[Instruction], mut target: usize) -> usize {
+    for _ in 0..code.len() {
+        match code.get(target) {
+            Some(Instruction::Jump(next)) if *next != target => target = *next,
+            _ => break,
+        }
+    }
+    target
+}
+
+// Whether execution from `index` surely pops a value before any jump or the end
+fn popped_later(code: &[Instruction], index: usize) -> bool {
+    for instruction in &code[index.min(code.len())..] {
+        match instruction {
+            Instruction::Pop => return true,
+            instruction if !falls_through_only(instruction) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+// Whether an instruction always continues with the next one
+fn falls_through_only(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Jump(_) | Instruction::JumpIfFalse(_) | Instruction::Call(..) | Instruction::Return | Instruction::Halt
+    ) && !is_function_entry(instruction)
+}
+
+/// Whether the VM enters a function at an instruction: at its entry in the
+/// function table.
+pub fn is_function_entry(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::Function(_))
+}
+
+// Which instructions jumps land on; the entry past the end is the end of the program
+fn jump_targets(code: &[Instruction]) -> Vec<bool> {
+    let mut targets = vec![false; code.len() + 1];
+    for (index, instruction) in code.iter().enumerate() {
+        match instruction {
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => {
+                if let Some(target) = targets.get_mut(*target) {
+                    *target = true;
+                }
+            }
+            instruction if is_function_entry(instruction) => targets[index] = true,
+            _ => {}
+        }
+    }
+    targets
+}
+
+// Drops the removed instructions, moving each jump to the first instruction
+// kept at or after its target
+fn compact(code: Vec<Instruction>, removed: &[bool]) -> Vec<Instruction> {
+    let mut new_index = Vec::with_capacity(code.len() + 1);
+    let mut kept = 0;
+    for removed in removed {
+        new_index.push(kept);
+        if !removed {
+            kept += 1;
+        }
+    }
+    new_index.push(kept);
+
+    let moved = |target: usize| new_index.get(target).copied().unwrap_or(kept);
+    code.into_iter()
+        .zip(removed)
+        .filter(|(_, removed)| !**removed)
+        .map(|(instruction, _)| match instruction {
+            Instruction::Jump(target) => Instruction::Jump(moved(target)),
+            Instruction::JumpIfFalse(target) => Instruction::JumpIfFalse(moved(target)),
+            other => other,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{VirtualMachine, VmConfig};
+
+    // What a run observes: its result or error, and what it printed
+    fn observed(code: &[Instruction]) -> (Result<String, String>, String) {
+        let mut vm = VirtualMachine::with_config(VmConfig::default());
+        let result = vm.execute(code).map_err(|e| e.to_string());
+        (result, vm.printed().to_string())
+    }
+
+    #[test]
+    fn every_level_runs_programs_as_level_0() {
+        let programs = [
+            "1 + 2 * 3 - 4 / 2;",
+            "7 / 2 + 7.0 / 2 + -(3 - 5);",
+            "9223372036854775807 + 1;",
+            "int limit = 10; int total = 0; int i = 0; while (i < limit) { total = total + limit * 2; i = i + 1; } total;",
+            "fn fib(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } } fib(12);",
+            "int x = 3; if (1 < 2) { x = x + 1; } else { x = 0; } if (2 < 1) { x = 100; } x;",
+            "\"a\" + \"b\" + 1;",
+            "int a = [1, [2, 3]]; a[1][0] = 5; len(a) + a[1][0];",
+            "int n = 0; outer: while (n < 5) { defer n = n + 1; while (1) { break outer; } } n;",
+            "int k = 2; printf(\"{} and {}\", k, k / 4); k == 2 && !(k > 3) || null;",
+            "int zero = 0; 1 / zero;",
+            "1 / 0;",
+            "int u = 1; u + missing;",
+            "fn sq(a) { return a * a; } fn norm(a, b) { sq(a) + sq(b) } int i = 0; while (i < 3) { i = i + 1; } norm(i, sq(2));",
+            "fn half(a) { a / 2 } fn fib(n) { if (n < 2) { n } else { fib(n - 1) + fib(half(n * 2) - 2) } } half(fib(10)) + half(1 / 0);",
+        ];
+        for source in programs {
+            let code = crate::compile_source(source).unwrap();
+            let expected = observed(&optimize(code.clone(), 0));
+            for level in 1..=MAX_OPT_LEVEL {
+                assert_eq!(observed(&optimize(code.clone(), level)), expected, "{} at -O{}", source, level);
+            }
+        }
+    }
+
+    #[test]
+    fn level_2_folds_constants_and_drops_dead_branches() {
+        let code = crate::compile_source("int x = 2 * 3; if (1 > 2) { x = 100; } x + 1;").unwrap();
+        let optimized = optimize(code.clone(), MAX_OPT_LEVEL);
+        assert!(optimized.len() < code.len());
+        assert_eq!(observed(&optimized).0.unwrap(), "7");
+    }
+
+    #[test]
+    fn level_2_inlines_small_functions_unless_they_recurse_or_branch() {
+        let code = crate::compile_source(
+            "fn sq(a) { return a * a; } fn norm(a, b) { sq(a) + sq(b) }\n\
+             fn even(n) { if (n < 1) { 1 } else { odd(n - 1) } } fn odd(n) { if (n < 1) { 0 } else { even(n - 1) } }\n\
+             fn sign(n) { if (n < 0) { -1 } else { 1 } }\n\
+             norm(3, 4) + even(sq(2)) + sign(-2);",
+        )
+        .unwrap();
+        let mut log = Vec::new();
+        let optimized = run_pass(Pass::InlineFunctions, code.clone(), &mut log);
+        assert_eq!(
+            log,
+            [
+                "inlined 'sq' at 3 call(s)",
+                "inlined 'norm' at 1 call(s)",
+                "kept 'even': it calls itself, directly or through other functions",
+                "kept 'odd': it calls itself, directly or through other functions",
+                "kept 'sign': it branches before returning",
+                "removed 'sq': all its calls are inlined",
+                "removed 'norm': all its calls are inlined",
+            ]
+        );
+        let functions: Vec<&Instruction> = optimized.iter().filter(|instruction| is_function_entry(instruction)).collect();
+        assert_eq!(format!("{:?}", functions), r#"[Function("even"), Function("odd"), Function("sign")]"#);
+        assert_eq!(observed(&optimized), observed(&code));
+        assert_eq!(observed(&optimized).0.unwrap(), "25");
+    }
+}
@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt;
-use crate::lexer::{Token, TokenType};
+use crate::ast::{Ast, Node, NodeId};
+use crate::lexer::{SourceLimits, Token, TokenType};
 
 /// Abstract Syntax Tree (AST) node definitions
 #[derive(Debug, Clone)]
@@ -9,10 +10,10 @@ pub enum ASTNode {
 
     // Variable declaration: type, name, optional initializer
     VarDeclaration {
-        #[allow(dead_code)]
         var_type: String,
         name: String,
         initializer: Option<Box<ASTNode>>,
+        doc: Option<String>, // Text of the /// comments just before the declaration
     },
 
     // Different types of statements
@@ -26,17 +27,33 @@ pub enum ASTNode {
     WhileStatement {
         condition: Box<ASTNode>,
         body: Box<ASTNode>,
+        label: Option<String>, // `outer` in `outer: while (...)`, named by break and continue
     },
+    BreakStatement(Option<String>),    // break; or break label;
+    ContinueStatement(Option<String>), // continue; or continue label;
     ReturnStatement(Option<Box<ASTNode>>), // Optional return value
+    PrintStatement(Box<ASTNode>), // print value; (beginner dialect)
+    DeferStatement(Box<ASTNode>), // defer statement; runs when the enclosing block ends
+    Import(String), // import "file"; resolved by the project loader
+    FunctionDeclaration {
+        name: String,
+        params: Vec<String>,
+        body: Box<ASTNode>, // Block expression, whose value is returned when the body ends
+        doc: Option<String>, // Text of the /// comments just before the declaration
+    },
+    TestBlock {
+        name: String,
+        body: Box<ASTNode>, // Block statement, run after the program by `compiler test`, see `testing`
+    },
 
     // Expressions
     BinaryExpression {
         left: Box<ASTNode>,
-        operator: TokenType,
+        operator: TokenType<'static>,
         right: Box<ASTNode>,
     },
     UnaryExpression {
-        operator: TokenType,
+        operator: TokenType<'static>,
         operand: Box<ASTNode>,
     },
     CallExpression {
@@ -47,14 +64,135 @@ pub enum ASTNode {
         name: String,
         value: Box<ASTNode>,
     },
+    IndexExpression {
+        array: Box<ASTNode>,
+        index: Box<ASTNode>,
+    },
+    // a[i][j] = value: the element at indices i then j of variable a
+    IndexAssignment {
+        name: String,
+        indices: Vec<ASTNode>,
+        value: Box<ASTNode>,
+    },
+    BlockExpression {
+        statements: Vec<ASTNode>,
+        value: Option<Box<ASTNode>>, // Expression ending the block without a semicolon; null without one
+    },
+    IfExpression {
+        condition: Box<ASTNode>,
+        then_branch: Box<ASTNode>,
+        else_branch: Box<ASTNode>,
+    },
 
     // Literals
     IntLiteral(i64),
     FloatLiteral(f64),
     StringLiteral(String),
+    NullLiteral,
+    ArrayLiteral(Vec<ASTNode>), // [1, 2, 3]
     Identifier(String),
 }
 
+impl ASTNode {
+    /// The operands of the chain of binary operators at this node, in source
+    /// order: `a`, `b` and `c` for `a + b - c`, which nests `a + b` as its
+    /// left operand. The chain is walked with a loop, so a pass that only
+    /// visits the operands need not recurse down a long one.
+    pub fn operands(&self) -> Vec<&ASTNode> {
+        let mut operands = Vec::new();
+        let mut node = self;
+        while let ASTNode::BinaryExpression { left, right, .. } = node {
+            operands.push(right.as_ref());
+            node = left;
+        }
+        operands.push(node);
+        operands.reverse();
+        operands
+    }
+}
+
+// Whether a statement can stand for the expression ending a block, and if
+// so whether it has a value: blocks can, and if statements with an else
+// whose branches can
+fn branch_value(ast: &Ast, node: NodeId) -> Option<bool> {
+    match &ast[node] {
+        Node::Block(_) => Some(false),
+        Node::ExpressionStatement(expr) => matches!(ast[*expr], Node::BlockExpression { .. }).then_some(true),
+        Node::IfStatement { then_branch, else_branch: Some(else_branch), .. } => {
+            Some(branch_value(ast, *then_branch)? | branch_value(ast, *else_branch)?)
+        }
+        _ => None,
+    }
+}
+
+// Turns a statement `branch_value` accepts into the matching expression, in
+// place where the node stays
+fn into_expression(ast: &mut Ast, node: NodeId) -> NodeId {
+    match std::mem::replace(&mut ast[node], Node::NullLiteral) {
+        Node::Block(statements) => ast[node] = Node::BlockExpression { statements, value: None },
+        Node::ExpressionStatement(expr) => return expr,
+        Node::IfStatement { condition, then_branch, else_branch } => {
+            let then_branch = into_expression(ast, then_branch);
+            let else_branch = match else_branch {
+                Some(else_branch) => into_expression(ast, else_branch),
+                None => ast.push(Node::BlockExpression { statements: Vec::new(), value: None }),
+            };
+            ast[node] = Node::IfExpression { condition, then_branch, else_branch };
+        }
+        other => ast[node] = other,
+    }
+    node
+}
+
+// The variable and indices an element assignment stores into, when `node`
+// indexes a variable, as `a[i][j]` does
+fn index_target(ast: &Ast, node: NodeId) -> Option<(String, Vec<NodeId>)> {
+    match &ast[node] {
+        Node::IndexExpression { array, index } => {
+            let (name, mut indices) = match &ast[*array] {
+                Node::Identifier(name) => (name.clone(), Vec::new()),
+                _ => index_target(ast, *array)?,
+            };
+            indices.push(*index);
+            Some((name, indices))
+        }
+        _ => None,
+    }
+}
+
+// Binary operators on the longest path from `node` down to a leaf, so those
+// applied one after another: two in `a + b * c` as in `(a + b) + c`
+fn chain_length(ast: &Ast, node: NodeId) -> usize {
+    let mut length = 0;
+    let mut pending = vec![(node, 0)];
+    while let Some((node, operators)) = pending.pop() {
+        let operators = operators + matches!(ast[node], Node::BinaryExpression { .. }) as usize;
+        length = length.max(operators);
+        pending.extend(ast.children(node).into_iter().map(|child| (child, operators)));
+    }
+    length
+}
+
+// Nodes on the longest path from `node` down to a leaf, counted with a stack
+// of its own rather than by recursion. The operations of a chain of binary
+// operators count as one node, as the passes walk a chain with a loop
+fn tree_height(ast: &Ast, node: NodeId) -> usize {
+    let mut height = 0;
+    let mut pending = vec![(node, 1)];
+    while let Some((node, depth)) = pending.pop() {
+        height = height.max(depth);
+        let chained = match ast[node] {
+            Node::BinaryExpression { left, .. } => matches!(ast[left], Node::BinaryExpression { .. }).then_some(left),
+            _ => None,
+        };
+        pending.extend(ast.children(node).into_iter().map(|child| match chained {
+            Some(left) if child == left => (child, depth),
+            _ => (child, depth + 1),
+        }));
+    }
+    height
+}
+
 /// Error type used for reporting parsing errors
 #[derive(Debug)]
 pub struct ParserError {
@@ -71,239 +209,596 @@ impl fmt::Display for ParserError {
 
 impl Error for ParserError {}
 
+impl ParserError {
+    /// Line (1-based) where the error was detected.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column (1-based) where the error was detected.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
 /// Parser that takes a vector of tokens and produces an AST
-pub struct Parser {
-    tokens: Vec<Token>, // All tokens from the lexer
+pub struct Parser<'a> {
+    tokens: Vec<Token<'a>>, // All tokens from the lexer
     current: usize,     // Current token index
+    statement_lines: Vec<usize>, // Line where each top-level statement parsed so far begins
+    limits: SourceLimits, // Only max_nesting and max_chain concern the parser
+    depth: usize,       // Expressions, statements and blocks being parsed, one inside the other
+    ast: Ast,           // Nodes parsed so far
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+impl<'a> Parser<'a> {
+    /// Creates a parser over `tokens`. An EOF token is appended when the
+    /// stream does not end with one, so the parser never reads past the end.
+    pub fn new(mut tokens: Vec<Token<'a>>) -> Self {
+        if !matches!(tokens.last(), Some(Token { token_type: TokenType::EOF, .. })) {
+            let (line, column, offset) = tokens
+                .last()
+                .map_or((1, 1, 0), |token| (token.line, token.column, token.offset + token.length));
+            tokens.push(Token {
+                token_type: TokenType::EOF,
+                line,
+                column,
+                offset,
+                length: 0,
+            });
+        }
+
+        // Doc comments are only kept in front of declarations; anywhere else
+        // they are plain comments, which the parser skips
+        let mut next_is_declaration = false;
+        let mut kept = Vec::with_capacity(tokens.len());
+        for token in tokens.into_iter().rev() {
+            match token.token_type {
+                TokenType::Comment => continue,
+                TokenType::DocComment(_) if !next_is_declaration => continue,
+                TokenType::DocComment(_) => {}
+                TokenType::Int | TokenType::Float | TokenType::Fn => next_is_declaration = true,
+                _ => next_is_declaration = false,
+            }
+            kept.push(token);
+        }
+        kept.reverse();
+
+        Parser {
+            tokens: kept,
+            current: 0,
+            statement_lines: Vec::new(),
+            limits: SourceLimits::default(),
+            depth: 0,
+            ast: Ast::default(),
+        }
     }
 
     /// Parse a complete program
     pub fn parse(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+        let (ast, root) = self.parse_ast()?;
+        Ok(ast.to_tree(root))
+    }
+
+    /// Parse a complete program into an arena, returning the arena and the
+    /// id of its `Program` node
+    pub fn parse_ast(&mut self) -> Result<(Ast, NodeId), Box<dyn Error>> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            self.statement_lines.push(self.current_token().line);
+            if self.match_token(&[TokenType::Import]) {
+                statements.push(self.import_declaration()?);
+            } else if self.at_test_block() {
+                statements.push(self.test_block()?);
+            } else {
+                statements.push(self.declaration()?);
+            }
         }
-        Ok(ASTNode::Program(statements))
+        let root = self.ast.push(Node::Program(statements));
+        Ok((std::mem::take(&mut self.ast), root))
+    }
+
+    /// Parse a single expression, with an optional semicolon after it, as a
+    /// program of one expression statement, into an arena
+    pub fn parse_expression_ast(&mut self) -> Result<(Ast, NodeId), Box<dyn Error>> {
+        self.statement_lines.push(self.current_token().line);
+        let expr = self.expression()?;
+        self.match_token(&[TokenType::Semicolon]);
+        if !self.is_at_end() {
+            return Err(self.error("Expected end of expression"));
+        }
+        let statement = self.ast.push(Node::ExpressionStatement(expr));
+        let root = self.ast.push(Node::Program(vec![statement]));
+        Ok((std::mem::take(&mut self.ast), root))
+    }
+
+    /// Line where each top-level statement of the parsed program begins.
+    pub fn statement_lines(&self) -> &[usize] {
+        &self.statement_lines
+    }
+
+    /// Parses an import (import "file";), only allowed at the top level
+    fn import_declaration(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let path = match &self.current_token().token_type {
+            TokenType::StringLiteral(path) => path.to_string(),
+            _ => return Err(self.error("Expected file name in quotes after 'import'")),
+        };
+        self.advance();
+        self.consume(TokenType::Semicolon, "Expected ';' after import")?;
+        Ok(self.ast.push(Node::Import(path)))
+    }
+
+    /// Whether a test block starts here. `test` is only a keyword in front
+    /// of the name of a test, so it still names variables and functions
+    fn at_test_block(&self) -> bool {
+        let next = self.tokens.get(self.current + 1).map(|token| &token.token_type);
+        matches!(&self.current_token().token_type, TokenType::Identifier(word) if word == "test")
+            && matches!(next, Some(TokenType::StringLiteral(_)))
+    }
+
+    /// Parses a test block (test "name" { body }), only allowed at the top level
+    fn test_block(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.advance();
+        let TokenType::StringLiteral(name) = &self.current_token().token_type else {
+            return Err(self.error("Expected the name of the test in quotes after 'test'"));
+        };
+        let name = name.to_string();
+        self.advance();
+        self.consume(TokenType::LeftBrace, "Expected '{' after the name of the test")?;
+        let body = self.block()?;
+        Ok(self.ast.push(Node::TestBlock { name, body }))
     }
 
     /// Parses top-level declarations (e.g., variable declarations)
-    fn declaration(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn declaration(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let doc = self.doc_comments();
         if self.match_token(&[TokenType::Int, TokenType::Float]) {
-            return self.var_declaration();
+            return self.var_declaration(doc);
+        }
+        if self.match_token(&[TokenType::Defer]) {
+            return self.defer_statement();
+        }
+        if self.match_token(&[TokenType::Fn]) {
+            return self.function_declaration(doc);
         }
         self.statement()
     }
 
+    /// Collects consecutive doc comments into one text, one line per comment
+    fn doc_comments(&mut self) -> Option<String> {
+        let mut lines = Vec::new();
+        while let TokenType::DocComment(text) = &self.current_token().token_type {
+            lines.push(text.to_string());
+            self.advance();
+        }
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+
     /// Parses a variable declaration (type name = initializer;)
-    fn var_declaration(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn var_declaration(&mut self, doc: Option<String>) -> Result<NodeId, Box<dyn Error>> {
         let var_type = match &self.previous().token_type {
             TokenType::Int => "int".to_string(),
             TokenType::Float => "float".to_string(),
-            _ => unreachable!(),
+            other => return Err(self.error(&format!("Expected type name, got {:?}", other))),
         };
 
         // Expect identifier
         if let TokenType::Identifier(name) = &self.current_token().token_type {
-            let name = name.clone();
+            let name = name.to_string();
             self.advance();
 
             // Optional initializer
             let initializer = if self.match_token(&[TokenType::Assign]) {
-                Some(Box::new(self.expression()?))
+                Some(self.expression()?)
             } else {
                 None
             };
 
             self.consume(TokenType::Semicolon, "Expected ';' after variable declaration")?;
-            Ok(ASTNode::VarDeclaration {
+            Ok(self.ast.push(Node::VarDeclaration {
                 var_type,
                 name,
                 initializer,
-            })
+                doc,
+            }))
         } else {
             Err(self.error("Expected identifier"))
         }
     }
 
-    /// Parses a statement
-    fn statement(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    /// Parses a function declaration (fn name(params) { body })
+    fn function_declaration(&mut self, doc: Option<String>) -> Result<NodeId, Box<dyn Error>> {
+        let TokenType::Identifier(name) = &self.current_token().token_type else {
+            return Err(self.error("Expected function name after 'fn'"));
+        };
+        let name = name.to_string();
+        self.advance();
+
+        self.consume(TokenType::LeftParen, "Expected '(' after function name")?;
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                let TokenType::Identifier(param) = &self.current_token().token_type else {
+                    return Err(self.error("Expected parameter name"));
+                };
+                params.push(param.to_string());
+                self.advance();
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expected ')' after parameters")?;
+        self.consume(TokenType::LeftBrace, "Expected '{' before function body")?;
+        let body = self.nested(Self::block_expression)?;
+        Ok(self.ast.push(Node::FunctionDeclaration { name, params, body, doc }))
+    }
+
+    /// Parses a statement, one level deeper than the statement or block it
+    /// is in
+    fn statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.nested(Self::unnested_statement)
+    }
+
+    fn unnested_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
         if self.match_token(&[TokenType::If]) {
             self.if_statement()
         } else if self.match_token(&[TokenType::While]) {
-            self.while_statement()
+            self.while_statement(None)
+        } else if self.starts_label() {
+            self.labeled_statement()
+        } else if self.match_token(&[TokenType::Break]) {
+            let label = self.jump_label("break")?;
+            Ok(self.ast.push(Node::BreakStatement(label)))
+        } else if self.match_token(&[TokenType::Continue]) {
+            let label = self.jump_label("continue")?;
+            Ok(self.ast.push(Node::ContinueStatement(label)))
         } else if self.match_token(&[TokenType::Return]) {
             self.return_statement()
+        } else if self.match_token(&[TokenType::Print]) {
+            self.print_statement()
         } else if self.match_token(&[TokenType::LeftBrace]) {
             self.block()
+        } else if self.check(&TokenType::Defer) {
+            Err(self.error("'defer' must be directly inside a block or at the top level"))
         } else {
             self.expression_statement()
         }
     }
 
     /// Parses an if statement
-    fn if_statement(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn if_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
         self.consume(TokenType::LeftParen, "Expected '(' after 'if'")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expected ')' after if condition")?;
         let then_branch = self.statement()?;
         let else_branch = if self.match_token(&[TokenType::Else]) {
-            Some(Box::new(self.statement()?))
+            Some(self.statement()?)
         } else {
             None
         };
-        Ok(ASTNode::IfStatement {
-            condition: Box::new(condition),
-            then_branch: Box::new(then_branch),
+        Ok(self.ast.push(Node::IfStatement {
+            condition,
+            then_branch,
             else_branch,
-        })
+        }))
     }
 
     /// Parses a while loop
-    fn while_statement(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn while_statement(&mut self, label: Option<String>) -> Result<NodeId, Box<dyn Error>> {
         self.consume(TokenType::LeftParen, "Expected '(' after 'while'")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expected ')' after while condition")?;
         let body = self.statement()?;
-        Ok(ASTNode::WhileStatement {
-            condition: Box::new(condition),
-            body: Box::new(body),
-        })
+        Ok(self.ast.push(Node::WhileStatement { condition, body, label }))
+    }
+
+    /// Whether the current token is the label of a loop: a name followed
+    /// by a colon
+    fn starts_label(&self) -> bool {
+        matches!(self.peek().token_type, TokenType::Identifier(_))
+            && matches!(self.tokens.get(self.current + 1), Some(Token { token_type: TokenType::Colon, .. }))
+    }
+
+    /// Parses a labeled loop: `label: while (...) ...`
+    fn labeled_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let label = match &self.peek().token_type {
+            TokenType::Identifier(label) => label.to_string(),
+            other => return Err(self.error(&format!("Expected label, got {:?}", other))),
+        };
+        self.advance();
+        self.advance(); // The colon
+        self.consume(TokenType::While, "Expected a loop after a label")?;
+        self.while_statement(Some(label))
+    }
+
+    /// Parses the rest of a break or continue statement: the label of the
+    /// loop it targets, if any, and the semicolon
+    fn jump_label(&mut self, keyword: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let label = match &self.peek().token_type {
+            TokenType::Identifier(label) => Some(label.to_string()),
+            _ => None,
+        };
+        if label.is_some() {
+            self.advance();
+        }
+        self.consume(TokenType::Semicolon, &format!("Expected ';' after '{}'", keyword))?;
+        Ok(label)
     }
 
     /// Parses a return statement
-    fn return_statement(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn return_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
         let value = if !self.check(&TokenType::Semicolon) {
-            Some(Box::new(self.expression()?))
+            Some(self.expression()?)
         } else {
             None
         };
         self.consume(TokenType::Semicolon, "Expected ';' after return value")?;
-        Ok(ASTNode::ReturnStatement(value))
+        Ok(self.ast.push(Node::ReturnStatement(value)))
+    }
+
+    /// Parses a print statement: `print value;`
+    fn print_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expected ';' after print value")?;
+        Ok(self.ast.push(Node::PrintStatement(value)))
     }
 
-    /// Parses a block statement: `{ statement* }`
-    fn block(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    /// Parses a defer statement: `defer statement;`. Deferring ties the
+    /// statement to the block around it, so the defer cannot be the body of
+    /// an if or while, nor defer another defer
+    fn defer_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let statement = self.statement()?;
+        Ok(self.ast.push(Node::DeferStatement(statement)))
+    }
+
+    /// Parses a block statement: `{ statement* }`. A block ending in an
+    /// expression without a semicolon is an expression statement instead,
+    /// whose value is that of the block
+    fn block(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let block = self.nested(Self::block_expression)?;
+        if let Node::BlockExpression { statements, value: None } = &mut self.ast[block] {
+            let statements = std::mem::take(statements);
+            self.ast[block] = Node::Block(statements);
+            return Ok(block);
+        }
+        Ok(self.ast.push(Node::ExpressionStatement(block)))
+    }
+
+    /// Parses a block expression after its `{`: the statements of the block,
+    /// then the expression ending it without a semicolon, if any
+    fn block_expression(&mut self) -> Result<NodeId, Box<dyn Error>> {
         let mut statements = Vec::new();
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if self.starts_statement() {
+                let statement = self.declaration()?;
+                // An if statement or block ending the block gives it its value
+                if self.check(&TokenType::RightBrace) && branch_value(&self.ast, statement) == Some(true) {
+                    self.advance();
+                    let value = Some(into_expression(&mut self.ast, statement));
+                    return Ok(self.ast.push(Node::BlockExpression { statements, value }));
+                }
+                statements.push(statement);
+                continue;
+            }
+            let expr = self.expression()?;
+            if self.match_token(&[TokenType::Semicolon]) {
+                statements.push(self.ast.push(Node::ExpressionStatement(expr)));
+            } else if self.check(&TokenType::RightBrace) {
+                self.advance();
+                return Ok(self.ast.push(Node::BlockExpression { statements, value: Some(expr) }));
+            } else {
+                return Err(self.error("Expected ';' after expression"));
+            }
         }
         self.consume(TokenType::RightBrace, "Expected '}' after block")?;
-        Ok(ASTNode::Block(statements))
+        Ok(self.ast.push(Node::BlockExpression { statements, value: None }))
+    }
+
+    /// Whether the current token starts a declaration or a statement other
+    /// than an expression statement
+    fn starts_statement(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::Int
+                | TokenType::Float
+                | TokenType::Defer
+                | TokenType::Fn
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Return
+                | TokenType::Print
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::LeftBrace
+                | TokenType::DocComment(_)
+        ) || self.starts_label()
+    }
+
+    /// Parses an if expression: `if (condition) a else b`, whose value is
+    /// that of the branch taken. A branch is any expression, so it can be a
+    /// block, and `else if` adds further branches
+    fn if_expression(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.consume(TokenType::LeftParen, "Expected '(' after 'if'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after if condition")?;
+        let then_branch = self.expression()?;
+        self.consume(TokenType::Else, "Expected 'else' after the first branch of an if expression")?;
+        let else_branch = self.expression()?;
+        Ok(self.ast.push(Node::IfExpression {
+            condition,
+            then_branch,
+            else_branch,
+        }))
     }
 
     /// Parses an expression statement
-    fn expression_statement(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn expression_statement(&mut self) -> Result<NodeId, Box<dyn Error>> {
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after expression")?;
-        Ok(ASTNode::ExpressionStatement(Box::new(expr)))
+        Ok(self.ast.push(Node::ExpressionStatement(expr)))
+    }
+
+    /// Entry point for expression parsing, one level deeper than the
+    /// expression or statement it is in
+    fn expression(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.nested(Self::assignment)
+    }
+
+    /// Runs `parse` one level of nesting deeper, failing once the program
+    /// nests deeper than its limits allow: each level takes some of the
+    /// stack of the parser and of the passes that walk the tree it builds.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<NodeId, Box<dyn Error>>) -> Result<NodeId, Box<dyn Error>> {
+        if self.depth >= self.limits.max_nesting {
+            return Err(self.too_deep());
+        }
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
     }
 
-    /// Entry point for expression parsing
-    fn expression(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        self.assignment()
+    /// Checks the node a call or an index is about to make of `left` and
+    /// `operands` against the nesting limit. A chain of them, as in
+    /// `a[1][2]`, nests each in the next without nesting the calls of the
+    /// parser, so its levels are measured instead. `height` is that of
+    /// `left` once known, and becomes that of the new node.
+    fn chained(&self, height: &mut Option<usize>, left: NodeId, operands: &[NodeId]) -> Result<(), Box<dyn Error>> {
+        let left_height = height.unwrap_or_else(|| tree_height(&self.ast, left));
+        let new_height = 1 + operands.iter().map(|&operand| tree_height(&self.ast, operand)).fold(left_height, usize::max);
+        if self.depth + new_height > self.limits.max_nesting {
+            return Err(self.too_deep());
+        }
+        *height = Some(new_height);
+        Ok(())
+    }
+
+    fn too_deep(&self) -> Box<dyn Error> {
+        self.error(&format!("Program too deeply nested: more than {} levels", self.limits.max_nesting))
     }
 
     /// Parses assignment expressions
-    fn assignment(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        let expr = self.equality()?;
+    fn assignment(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let expr = self.logical_or()?;
         if self.match_token(&[TokenType::Assign]) {
-            if let ASTNode::Identifier(name) = expr {
-                let value = self.assignment()?;
-                return Ok(ASTNode::AssignmentExpression {
-                    name,
-                    value: Box::new(value),
-                });
+            if let Node::Identifier(name) = &self.ast[expr] {
+                let name = name.clone();
+                let value = self.nested(Self::assignment)?;
+                return Ok(self.ast.push(Node::AssignmentExpression { name, value }));
+            }
+            if let Some((name, indices)) = index_target(&self.ast, expr) {
+                let value = self.nested(Self::assignment)?;
+                return Ok(self.ast.push(Node::IndexAssignment { name, indices, value }));
             }
             return Err(self.error("Invalid assignment target"));
         }
         Ok(expr)
     }
 
+    /// Parses `||` expressions, which only evaluate their right side when
+    /// the left one is false
+    fn logical_or(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.binary(&[TokenType::Or], Self::logical_and)
+    }
+
+    /// Parses `&&` expressions, which only evaluate their right side when
+    /// the left one is not false
+    fn logical_and(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.binary(&[TokenType::And], Self::equality)
+    }
+
     /// Parses equality expressions (==, !=)
-    fn equality(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        let mut expr = self.comparison()?;
-        while self.match_token(&[TokenType::Equal, TokenType::NotEqual]) {
-            let operator = self.previous().token_type.clone();
-            let right = self.comparison()?;
-            expr = ASTNode::BinaryExpression {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+    fn equality(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.binary(&[TokenType::Equal, TokenType::NotEqual], Self::comparison)
     }
 
-    /// Parses comparison expressions (<, >)
-    fn comparison(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        let mut expr = self.term()?;
-        while self.match_token(&[TokenType::LessThan, TokenType::GreaterThan]) {
-            let operator = self.previous().token_type.clone();
-            let right = self.term()?;
-            expr = ASTNode::BinaryExpression {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+    /// Parses comparison expressions (<, >, <=, >=)
+    fn comparison(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.binary(
+            &[TokenType::LessThan, TokenType::GreaterThan, TokenType::LessEqual, TokenType::GreaterEqual],
+            Self::term,
+        )
     }
 
     /// Parses addition and subtraction
-    fn term(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        let mut expr = self.factor()?;
-        while self.match_token(&[TokenType::Plus, TokenType::Minus]) {
-            let operator = self.previous().token_type.clone();
-            let right = self.factor()?;
-            expr = ASTNode::BinaryExpression {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+    fn term(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.binary(&[TokenType::Plus, TokenType::Minus], Self::factor)
     }
 
     /// Parses multiplication and division
-    fn factor(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        let mut expr = self.unary()?;
-        while self.match_token(&[TokenType::Multiply, TokenType::Divide]) {
-            let operator = self.previous().token_type.clone();
-            let right = self.unary()?;
-            expr = ASTNode::BinaryExpression {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
-        }
-        
-        Ok(expr)
+    fn factor(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        self.binary(&[TokenType::Multiply, TokenType::Divide], Self::unary)
     }
-    
-    fn unary(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        if self.match_token(&[TokenType::Minus]) {
-            let operator = self.previous().token_type.clone();
-            let operand = self.unary()?;
-            return Ok(ASTNode::UnaryExpression {
+
+    /// Parses a left-associative chain of the binary `operators`, whose
+    /// operands `operand` parses. The passes walk a chain with a loop, so it
+    /// doesn't count towards the nesting limit. The operators applied one
+    /// after another, those of its operands included, have a cap of their
+    /// own instead, reported at the first operator past it once the whole
+    /// chain is known.
+    fn binary(
+        &mut self,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Result<NodeId, Box<dyn Error>>,
+    ) -> Result<NodeId, Box<dyn Error>> {
+        let mut expr = operand(self)?;
+        let mut length = chain_length(&self.ast, expr);
+        let mut past_cap = None;
+        while self.match_token(operators) {
+            let position = (self.previous().line, self.previous().column);
+            let operator = self.previous().token_type.clone().into_owned();
+            let right = operand(self)?;
+            length = length.max(chain_length(&self.ast, right)) + 1;
+            if length > self.limits.max_chain && past_cap.is_none() {
+                past_cap = Some(position);
+            }
+            expr = self.ast.push(Node::BinaryExpression {
+                left: expr,
                 operator,
-                operand: Box::new(operand),
+                right,
             });
         }
+        match past_cap {
+            Some((line, column)) => Err(Box::new(ParserError {
+                message: format!(
+                    "Chain of {} operators is too long: at most {} can follow one another",
+                    length, self.limits.max_chain
+                ),
+                line,
+                column,
+            })),
+            None => Ok(expr),
+        }
+    }
+
+    fn unary(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        if self.match_token(&[TokenType::Minus, TokenType::Not]) {
+            let operator = self.previous().token_type.clone().into_owned();
+            let operand = self.nested(Self::unary)?;
+            return Ok(self.ast.push(Node::UnaryExpression { operator, operand }));
+        }
         
         self.call()
     }
     
-    fn call(&mut self) -> Result<ASTNode, Box<dyn Error>> {
+    fn call(&mut self) -> Result<NodeId, Box<dyn Error>> {
         let mut expr = self.primary()?;
+        let mut height = None;
         
         loop {
             if self.match_token(&[TokenType::LeftParen]) {
-                expr = self.finish_call(expr)?;
+                let arguments = self.arguments()?;
+                self.chained(&mut height, expr, &arguments)?;
+                expr = self.ast.push(Node::CallExpression { callee: expr, arguments });
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "Expected ']' after index")?;
+                self.chained(&mut height, expr, &[index])?;
+                expr = self.ast.push(Node::IndexExpression { array: expr, index });
             } else {
                 break;
             }
@@ -312,7 +807,8 @@ impl Parser {
         Ok(expr)
     }
     
-    fn finish_call(&mut self, callee: ASTNode) -> Result<ASTNode, Box<dyn Error>> {
+    /// Parses the arguments of a call, after its `(`
+    fn arguments(&mut self) -> Result<Vec<NodeId>, Box<dyn Error>> {
         let mut arguments = Vec::new();
         
         if !self.check(&TokenType::RightParen) {
@@ -326,52 +822,57 @@ impl Parser {
         }
         
         self.consume(TokenType::RightParen, "Expected ')' after arguments")?;
-        
-        Ok(ASTNode::CallExpression {
-            callee: Box::new(callee),
-            arguments,
-        })
+        Ok(arguments)
     }
     
-    fn primary(&mut self) -> Result<ASTNode, Box<dyn Error>> {
-        if self.match_token(&[TokenType::IntLiteral(0)]) {
-            if let TokenType::IntLiteral(value) = &self.previous().token_type {
-                return Ok(ASTNode::IntLiteral(*value));
+    fn primary(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let node = match &self.peek().token_type {
+            TokenType::IntLiteral(value) => Node::IntLiteral(*value),
+            TokenType::FloatLiteral(value) => Node::FloatLiteral(*value),
+            TokenType::StringLiteral(value) => Node::StringLiteral(value.to_string()),
+            TokenType::Null => Node::NullLiteral,
+            TokenType::Identifier(name) => Node::Identifier(name.to_string()),
+            TokenType::LeftParen => {
+                self.advance();
+                let expr = self.expression()?;
+                self.consume(TokenType::RightParen, "Expected ')' after expression")?;
+                return Ok(expr);
             }
-            unreachable!(); // Should never reach here
-        }
-        
-        if self.match_token(&[TokenType::FloatLiteral(0.0)]) {
-            if let TokenType::FloatLiteral(value) = &self.previous().token_type {
-                return Ok(ASTNode::FloatLiteral(*value));
+            TokenType::LeftBrace => {
+                self.advance();
+                return self.nested(Self::block_expression);
             }
-            unreachable!(); // Should never reach here
-        }
-        
-        if self.match_token(&[TokenType::StringLiteral(String::new())]) {
-            if let TokenType::StringLiteral(value) = &self.previous().token_type {
-                return Ok(ASTNode::StringLiteral(value.clone()));
+            TokenType::If => {
+                self.advance();
+                return self.if_expression();
             }
-            unreachable!(); // Should never reach here
-        }
-        
-        if self.match_token(&[TokenType::Identifier(String::new())]) {
-            if let TokenType::Identifier(name) = &self.previous().token_type {
-                return Ok(ASTNode::Identifier(name.clone()));
+            TokenType::LeftBracket => {
+                self.advance();
+                return self.array_literal();
             }
-            unreachable!(); // Should never reach here
-        }
-        
-        if self.match_token(&[TokenType::LeftParen]) {
-            let expr = self.expression()?;
-            self.consume(TokenType::RightParen, "Expected ')' after expression")?;
-            return Ok(expr);
-        }
-        
-        Err(self.error(&format!("Expected expression, got {:?}", self.peek().token_type)))
+            other => return Err(self.error(&format!("Expected expression, got {:?}", other))),
+        };
+
+        self.advance();
+        Ok(self.ast.push(node))
     }
     
     // Helper methods
+    /// Parses the elements of an array literal, after its `[`
+    fn array_literal(&mut self) -> Result<NodeId, Box<dyn Error>> {
+        let mut elements = Vec::new();
+        if !self.check(&TokenType::RightBracket) {
+            loop {
+                elements.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expected ']' after array elements")?;
+        Ok(self.ast.push(Node::ArrayLiteral(elements)))
+    }
+
     fn match_token(&mut self, types: &[TokenType]) -> bool {
         for token_type in types {
             if self.check(token_type) {
@@ -397,7 +898,7 @@ impl Parser {
         }
     }
     
-    fn advance(&mut self) -> &Token {
+    fn advance(&mut self) -> &Token<'a> {
         if !self.is_at_end() {
             self.current += 1;
         }
@@ -408,19 +909,21 @@ impl Parser {
         matches!(self.peek().token_type, TokenType::EOF)
     }
     
-    fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+    // `current` never moves past the trailing EOF token added in `new`,
+    // so these accessors clamp instead of indexing out of bounds.
+    fn peek(&self) -> &Token<'a> {
+        &self.tokens[self.current.min(self.tokens.len() - 1)]
     }
     
-    fn previous(&self) -> &Token {
-        &self.tokens[self.current - 1]
+    fn previous(&self) -> &Token<'a> {
+        &self.tokens[self.current.saturating_sub(1).min(self.tokens.len() - 1)]
     }
     
-    fn current_token(&self) -> &Token {
-        &self.tokens[self.current]
+    fn current_token(&self) -> &Token<'a> {
+        self.peek()
     }
     
-    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, Box<dyn Error>> {
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token<'a>, Box<dyn Error>> {
         if self.check(&token_type) {
             Ok(self.advance())
         } else {
@@ -437,3 +940,40 @@ impl Parser {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::BytecodeGenerator;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn the_arena_generates_the_code_of_the_tree() {
+        let source = "fn f(n) { if (n < 2) { n } else { f(n - 1) + f(n - 2) } }\n\
+            int a = [1, [2, 3]]; a[1][0] = { int b = 4; if (b > 3) { b } else { 0 } };\n\
+            outer: while (f(3) < 2) { defer a = -a[0]; break outer; }\n\
+            f(10) + a[1][0];";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let (ast, root) = Parser::new(tokens.clone()).parse_ast().unwrap();
+        let tree = Parser::new(tokens).parse().unwrap();
+        assert_eq!(format!("{:?}", ast.to_tree(root)), format!("{:?}", tree));
+
+        let from_arena = crate::link(&BytecodeGenerator::new().generate_ast(ast, root).unwrap());
+        let from_tree = crate::link(&BytecodeGenerator::new().generate(tree).unwrap());
+        assert_eq!(format!("{:?}", from_arena), format!("{:?}", from_tree));
+    }
+
+    #[test]
+    fn a_chain_of_operators_has_a_cap_of_its_own() {
+        let terms = vec!["1"; 1000].join(" + ");
+        let source = format!("int a = ({}) * 2;", terms);
+        let tokens = Lexer::new(&source).tokenize().unwrap();
+        let (ast, root) = Parser::new(tokens.clone()).parse_ast().unwrap();
+        assert!(BytecodeGenerator::new().generate_ast(ast, root).is_ok());
+
+        let mut parser = Parser::new(tokens);
+        parser.limits.max_chain = 400;
+        let error = parser.parse_ast().unwrap_err().to_string();
+        assert!(error.contains("Chain of 999 operators is too long: at most 400"), "{}", error);
+    }
+}
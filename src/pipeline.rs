@@ -0,0 +1,329 @@
+// The compiler driver: the phases that turn source code into VM instructions,
+// run in order by a `PassManager`. Each phase reads what the earlier ones
+// left in a `Compilation` and adds its own result. The manager times every
+// phase, skips the disabled ones, and lets embedders insert their own phases
+// between the built-in ones, e.g. a check of the tokens before parsing.
+// Observers are shown what each phase made, and, given to the VM, every so
+// many of the instructions the program runs.
+//
+// The built-in phases, in order:
+//
+//   lex       Source to tokens
+//   parse     Tokens to a syntax tree in an arena; in expression mode, the
+//             tokens must be a single expression
+//   check     Semantic checks of strict mode, which include the type checks;
+//             names are resolved by the code generator
+//   codegen   Syntax tree to VM instructions, consuming the tree, with the
+//             debug information that locates runtime errors
+//   optimize  Passes of the optimizer at the requested level
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::artifact::{self, DebugInfo, LineEntry};
+use crate::ast::{Ast, NodeId};
+use crate::bytecode::BytecodeGenerator;
+use crate::lexer::{Dialect, Lexer, Token};
+use crate::optimizer;
+use crate::parser::{ASTNode, Parser};
+use crate::semantic::{self, Strictness};
+use crate::vm::Instruction;
+
+/// What the source of a compilation is: a whole program, or in expression
+/// mode a single expression, e.g. the formula of a spreadsheet cell, with no
+/// statements or semicolons around it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Program,
+    Expression,
+}
+
+impl Mode {
+    /// Whether this is the default, whole-program mode.
+    pub fn is_program(&self) -> bool {
+        *self == Mode::Program
+    }
+}
+
+/// A program being compiled, and the settings of its compilation. Its
+/// tokens borrow their text from the source.
+#[derive(Debug, Clone, Default)]
+pub struct Compilation<'a> {
+    pub source: &'a str,
+    pub dialect: Dialect,
+    pub strictness: Strictness,
+    pub opt_level: u8,
+    pub mode: Mode,
+    pub tokens: Vec<Token<'a>>,          // Filled in by lex
+    pub ast: Option<(Ast, NodeId)>,      // Arena and root filled in by parse, taken by codegen
+    pub lines: Vec<usize>,               // Line of each top-level statement, filled in by parse
+    pub instructions: Vec<Instruction>,  // Filled in by codegen
+    pub debug: DebugInfo,                // Filled in by codegen; optimize drops its line table
+    pub log: Vec<String>,                // What each optimization pass did
+}
+
+impl<'a> Compilation<'a> {
+    pub fn new(source: &'a str, dialect: Dialect, strictness: Strictness, opt_level: u8) -> Self {
+        Compilation {
+            source,
+            dialect,
+            strictness,
+            opt_level,
+            ..Compilation::default()
+        }
+    }
+}
+
+/// A step of the compilation.
+pub trait Phase {
+    fn name(&self) -> &str;
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>>;
+
+    /// Shows what the phase made to `observer`, once it has run.
+    fn report(&self, _compilation: &Compilation, _observer: &dyn Observer) {}
+}
+
+/// Watches a compilation and, once given to the VM in `VmConfig`, the run of
+/// its program. Each method does nothing unless overridden.
+pub trait Observer: Send + Sync {
+    /// The tokens made by the lex phase.
+    fn on_tokens(&self, _tokens: &[Token]) {}
+
+    /// The syntax tree made by the parse phase.
+    fn on_ast(&self, _program: &ASTNode) {}
+
+    /// The instructions made by `phase`: codegen, then optimize.
+    fn on_bytecode(&self, _phase: &str, _instructions: &[Instruction]) {}
+
+    /// The instruction at `ip`, about to run as the `executed`th instruction
+    /// of the run; only every `interval`th one, see `vm::InstructionObservers`.
+    fn on_instruction_executed(&self, _ip: usize, _instruction: &Instruction, _executed: u64) {}
+}
+
+/// Time one phase took.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub micros: u64,
+}
+
+/// The phases of a compilation, in the order they run.
+pub struct PassManager {
+    phases: Vec<(Box<dyn Phase>, bool)>, // Each phase, and whether it is enabled
+    observers: Vec<Arc<dyn Observer>>,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        PassManager::standard()
+    }
+}
+
+impl PassManager {
+    /// The built-in phases, all enabled.
+    pub fn standard() -> Self {
+        let phases: Vec<Box<dyn Phase>> = vec![Box::new(Lex), Box::new(Parse), Box::new(Check), Box::new(Codegen), Box::new(Optimize)];
+        PassManager {
+            phases: phases.into_iter().map(|phase| (phase, true)).collect(),
+            observers: Vec::new(),
+        }
+    }
+
+    /// The built-in phases up to code generation: compilation without
+    /// optimization.
+    pub fn unoptimized() -> Self {
+        let mut passes = PassManager::standard();
+        passes.phases.retain(|(phase, _)| phase.name() != "optimize");
+        passes
+    }
+
+    /// Names of the phases, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.phases.iter().map(|(phase, _)| phase.name().to_string()).collect()
+    }
+
+    /// Enables or disables the phase named `name`.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), String> {
+        let index = self.position(name)?;
+        self.phases[index].1 = enabled;
+        Ok(())
+    }
+
+    /// Inserts `phase` right after the phase named `after`.
+    pub fn insert_after(&mut self, after: &str, phase: Box<dyn Phase>) -> Result<(), String> {
+        let index = self.position(after)?;
+        self.insert_at(index + 1, phase)
+    }
+
+    /// Inserts `phase` right before the phase named `before`.
+    pub fn insert_before(&mut self, before: &str, phase: Box<dyn Phase>) -> Result<(), String> {
+        let index = self.position(before)?;
+        self.insert_at(index, phase)
+    }
+
+    fn insert_at(&mut self, index: usize, phase: Box<dyn Phase>) -> Result<(), String> {
+        if self.position(phase.name()).is_ok() {
+            return Err(format!("A phase named {} already exists", phase.name()));
+        }
+        self.phases.insert(index, (phase, true));
+        Ok(())
+    }
+
+    /// Shows the result of every phase to `observer`.
+    pub fn observe(&mut self, observer: Arc<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    fn position(&self, name: &str) -> Result<usize, String> {
+        self.phases
+            .iter()
+            .position(|(phase, _)| phase.name() == name)
+            .ok_or_else(|| format!("Unknown phase: {}", name))
+    }
+
+    /// Runs the enabled phases on `compilation`, stopping at the first error,
+    /// and returns the time each of them took, observers left out.
+    pub fn run(&self, compilation: &mut Compilation) -> Result<Vec<PhaseTiming>, Box<dyn Error>> {
+        let mut timings = Vec::new();
+        for (phase, _) in self.phases.iter().filter(|(_, enabled)| *enabled) {
+            let start = Instant::now();
+            phase.run(compilation)?;
+            timings.push(PhaseTiming {
+                name: phase.name().to_string(),
+                micros: u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX),
+            });
+            for observer in &self.observers {
+                phase.report(compilation, observer.as_ref());
+            }
+        }
+        Ok(timings)
+    }
+}
+
+// Error of a phase whose input an earlier, disabled phase should have made
+fn missing(phase: &str, input: &str) -> Box<dyn Error> {
+    format!("The {} phase needs the {} of an earlier phase", phase, input).into()
+}
+
+struct Lex;
+
+impl Phase for Lex {
+    fn name(&self) -> &str {
+        "lex"
+    }
+
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>> {
+        compilation.tokens = Lexer::new(compilation.source).with_dialect(compilation.dialect).tokenize()?;
+        Ok(())
+    }
+
+    fn report(&self, compilation: &Compilation, observer: &dyn Observer) {
+        observer.on_tokens(&compilation.tokens);
+    }
+}
+
+struct Parse;
+
+impl Phase for Parse {
+    fn name(&self) -> &str {
+        "parse"
+    }
+
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>> {
+        // The lexer always ends the tokens with Eof
+        if compilation.tokens.is_empty() {
+            return Err(missing(self.name(), "tokens"));
+        }
+        let mut parser = Parser::new(compilation.tokens.clone());
+        compilation.ast = Some(match compilation.mode {
+            Mode::Program => parser.parse_ast()?,
+            Mode::Expression => parser.parse_expression_ast()?,
+        });
+        compilation.lines = parser.statement_lines().to_vec();
+        Ok(())
+    }
+
+    fn report(&self, compilation: &Compilation, observer: &dyn Observer) {
+        if let Some((ast, root)) = &compilation.ast {
+            observer.on_ast(&ast.to_tree(*root));
+        }
+    }
+}
+
+struct Check;
+
+impl Phase for Check {
+    fn name(&self) -> &str {
+        "check"
+    }
+
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>> {
+        let (ast, root) = compilation.ast.as_ref().ok_or_else(|| missing(self.name(), "syntax tree"))?;
+        // The checks walk a tree, only built when one of them is enabled
+        if !compilation.strictness.any() {
+            return Ok(());
+        }
+        let program = ast.to_tree(*root);
+        let unit = semantic::Unit { tokens: &compilation.tokens, program: &program };
+        semantic::check(&[unit], compilation.strictness).map_err(|(_, error)| error)?;
+        Ok(())
+    }
+}
+
+struct Codegen;
+
+impl Phase for Codegen {
+    fn name(&self) -> &str {
+        "codegen"
+    }
+
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>> {
+        let (ast, root) = compilation.ast.take().ok_or_else(|| missing(self.name(), "syntax tree"))?;
+        let module = BytecodeGenerator::new()
+            .with_lines(compilation.lines.clone())
+            .generate_ast(ast, root)?;
+        compilation.instructions = crate::link(&module);
+        compilation.debug = DebugInfo {
+            source_hash: artifact::source_hash([compilation.source]),
+            locals: DebugInfo::locals_of(&module),
+            lines: crate::link_table(&module, |chunk| &chunk.lines)
+                .into_iter()
+                .map(|(instruction, line)| LineEntry { instruction, file: String::new(), line })
+                .collect(),
+        };
+        Ok(())
+    }
+
+    fn report(&self, compilation: &Compilation, observer: &dyn Observer) {
+        observer.on_bytecode(self.name(), &compilation.instructions);
+    }
+}
+
+struct Optimize;
+
+impl Phase for Optimize {
+    fn name(&self) -> &str {
+        "optimize"
+    }
+
+    fn run(&self, compilation: &mut Compilation) -> Result<(), Box<dyn Error>> {
+        let code = std::mem::take(&mut compilation.instructions);
+        let (instructions, log) = optimizer::optimize_logged(code, compilation.opt_level);
+        compilation.instructions = instructions;
+        compilation.log = log;
+        // Optimization moves code between statements, so only the locals stay meaningful
+        if compilation.opt_level > 0 {
+            compilation.debug.lines.clear();
+        }
+        Ok(())
+    }
+
+    fn report(&self, compilation: &Compilation, observer: &dyn Observer) {
+        observer.on_bytecode(self.name(), &compilation.instructions);
+    }
+}
@@ -0,0 +1,116 @@
+// Profiles: named bundles of execution settings configured on the server,
+// e.g. "exam", "playground" and "trusted", so that a request selects the
+// settings of a whole classroom with `"profile": "exam"` instead of each
+// client spelling them out. They are read from the TOML file named by
+// COMPILER_PROFILES:
+//
+//   [exam]
+//   capabilities = ["random"]  # Replace COMPILER_CAPABILITIES; absent: unchanged
+//   opt_level = 0              # Replaces the request's; absent: the request's
+//   echo = false               # Don't print the final value of the program
+//
+//   [exam.limits]              # Highest limits of a run, see `limits`
+//   max_instructions = 1000000
+//   timeout_ms = 2000
+//
+// The limits of a profile lower those of the request as the server's maxima
+// do, which still apply on top of them.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::limits::ExecutionLimits;
+use crate::optimizer::MAX_OPT_LEVEL;
+use crate::sandbox::SandboxPolicy;
+use crate::worker::WorkerJob;
+
+/// Settings a profile applies to the runs that select it.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub policy: Option<SandboxPolicy>, // Capabilities of the run, instead of the server's
+    pub limits: ExecutionLimits,
+    pub opt_level: Option<u8>,
+    pub echo: bool,                    // Whether the final value of the program is printed
+}
+
+// A profile as written in the file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileEntry {
+    #[serde(default)]
+    capabilities: Option<Vec<String>>,
+    #[serde(default)]
+    limits: ExecutionLimits,
+    #[serde(default)]
+    opt_level: Option<u8>,
+    #[serde(default = "default_echo")]
+    echo: bool,
+}
+
+fn default_echo() -> bool {
+    true
+}
+
+/// The profiles of the server, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Reads the profiles from the file at `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read profiles from {}: {}", path.display(), e))?;
+        Profiles::parse(&text).map_err(|e| format!("Invalid profiles in {}: {}", path.display(), e))
+    }
+
+    /// Parses profiles in the format of the COMPILER_PROFILES file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let entries: BTreeMap<String, ProfileEntry> = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut profiles = BTreeMap::new();
+        for (name, entry) in entries {
+            let policy = entry
+                .capabilities
+                .map(|capabilities| SandboxPolicy::from_list(&capabilities.join(",")))
+                .transpose()
+                .map_err(|e| format!("profile {}: {}", name, e))?;
+            if entry.opt_level.is_some_and(|level| level > MAX_OPT_LEVEL) {
+                return Err(format!("profile {}: opt_level must be between 0 and {}", name, MAX_OPT_LEVEL));
+            }
+            let profile = Profile {
+                policy,
+                limits: entry.limits,
+                opt_level: entry.opt_level,
+                echo: entry.echo,
+            };
+            profiles.insert(name, profile);
+        }
+        Ok(Profiles { profiles })
+    }
+
+    /// The profile named `name`.
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    /// Names of the profiles, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+}
+
+impl Profile {
+    /// Applies the profile to `job`, before the server's maxima are.
+    pub fn apply(&self, job: &mut WorkerJob) {
+        if let Some(policy) = &self.policy {
+            job.policy = policy.clone();
+        }
+        if let Some(opt_level) = self.opt_level {
+            job.input.opt_level = opt_level;
+        }
+        job.input.limits = job.input.limits.clamped(&self.limits);
+        job.echo = self.echo;
+    }
+}
@@ -0,0 +1,817 @@
+// Stored programs, for using the server as a scripting service: a program is
+// uploaded once under a name with PUT /programs/{name}, compiled then, and
+// invoked any number of times with POST /programs/{name}/invoke, which runs
+// the compiled code with the invocation's arguments as globals. Uploading
+// again under the same name adds a version and switches invocations to it at
+// once, while runs already started finish on the version they started with.
+// The last MAX_VERSIONS versions are kept, so a caller can pin one.
+//
+// A program uploaded with a `schedule` also runs on its own, see `scheduler`,
+// and one uploaded with `"webhook": true` runs when its URL is called, see
+// `hooks`.
+//
+// Programs live in namespaces, one per tenant, named by the X-Namespace
+// header ("default" without it). Only an operator can create a namespace:
+// the upload that creates it must send the admin token, see `admin`, and
+// gets the key of the namespace, a random token like those of webhooks.
+// Every later request to the namespace must send the key as
+// `Authorization: Bearer <key>`, so tenants are kept out of each other's
+// programs. Webhook URLs are only shown to the
+// upload, as anyone reading them could run the program. Programs are kept
+// in memory and lost on restart.
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::admin;
+use crate::artifact::{Artifact, Limits};
+use crate::hooks;
+use crate::lexer::Dialect;
+use crate::limits::ExecutionLimits;
+use crate::pipeline::{Compilation, Mode, PassManager};
+use crate::scheduler::{RunRecord, Schedule};
+use crate::semantic::StrictMode;
+use crate::worker::WorkerJob;
+use crate::{
+    builtins, catch_panics, client_of, dispatch_request, optimizer, respond, verifier, CodeInput, CodeOutput, ErrorDetails,
+    ErrorKind, ExecutionRegistry, ServerConfig, SessionStore, StatusOptions,
+};
+
+/// Programs kept at most in each namespace; uploading more is refused.
+const MAX_PROGRAMS: usize = 100;
+
+/// Namespaces kept at most. A namespace keeps its key once its programs are
+/// removed, so that no one else can take it over.
+const MAX_NAMESPACES: usize = 1_000;
+
+/// Versions kept of each program; the oldest is dropped beyond this.
+const MAX_VERSIONS: usize = 10;
+
+/// Scheduled runs whose records are kept of each program.
+const MAX_RUNS: usize = 50;
+
+/// Longest name of a program or namespace.
+const MAX_NAME_LEN: usize = 64;
+
+/// Header naming the namespace of a request.
+const NAMESPACE_HEADER: &str = "x-namespace";
+
+/// Response header giving the version an invocation ran.
+const VERSION_HEADER: &str = "x-program-version";
+
+/// The stored programs of all namespaces, by namespace and name.
+#[derive(Default)]
+pub struct ProgramStore {
+    programs: Mutex<HashMap<(String, String), Program>>,
+    keys: Mutex<HashMap<String, String>>, // Key of each namespace; locked after `programs` when both are
+}
+
+struct Program {
+    latest: u64,                     // Number of the newest version
+    versions: VecDeque<Arc<Version>>, // Oldest first
+    schedule: Option<Schedule>,      // Set by the newest upload
+    runs: VecDeque<RunRecord>,       // Scheduled runs, oldest first
+    running: bool,                   // A scheduled run is in progress
+    hook: Option<String>,            // Token of the webhook URL
+}
+
+struct Version {
+    number: u64,
+    artifact: String, // Base64 of the compiled program, run as by /execute
+    instructions: usize,
+    uploaded_at: Instant,
+}
+
+/// A version of a program, as listed in responses.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VersionInfo {
+    version: u64,
+    instructions: usize, // Length of the compiled code
+    age_ms: u64,         // Since it was uploaded
+}
+
+/// A stored program and its versions.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProgramInfo {
+    namespace: String,
+    name: String,
+    version: u64,                // The version invocations run unless they pin another
+    versions: Vec<VersionInfo>,  // Kept versions, oldest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,    // Cron expression the program runs on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_run: Option<u64>,       // Unix time of its next scheduled run
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    has_webhook: bool,           // Whether it has a webhook
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webhook: Option<String>,     // Path of its webhook, "/hooks/{token}", only in the response of an upload
+}
+
+/// A run of the newest version of a program that is about to start, on its
+/// schedule or through its webhook.
+pub struct ProgramRun {
+    pub namespace: String,
+    pub name: String,
+    pub version: u64,
+    pub artifact: String,
+}
+
+impl ProgramStore {
+    /// Adds `version` as the newest version of a program, creating the
+    /// program if needed. With `expected`, the program must be at that
+    /// version, 0 meaning that it must not exist yet, so that two clients
+    /// replacing it at once cannot overwrite each other's upload unseen.
+    /// The program runs on `schedule` from now on, or no longer runs on its
+    /// own without one, and has a webhook if `webhook` is set, keeping the
+    /// URL it had. `credential` must be the key of the namespace, unless it
+    /// is new: it is then created if the request is from an `admin`, and its
+    /// key returned along the program.
+    #[allow(clippy::too_many_arguments)]
+    fn put(
+        &self,
+        namespace: &str,
+        credential: Option<&str>,
+        admin: bool,
+        name: &str,
+        expected: Option<u64>,
+        compiled: Version,
+        schedule: Option<Schedule>,
+        webhook: bool,
+    ) -> Result<(ProgramInfo, Option<String>), ErrorDetails> {
+        let mut programs = self.lock();
+        let mut keys = self.lock_keys();
+        let created = match keys.get(namespace) {
+            Some(key) => {
+                check_key(namespace, key, credential)?;
+                None
+            }
+            None if !admin => {
+                return Err(ErrorDetails::new(
+                    ErrorKind::Unauthorized,
+                    format!(
+                        "Namespace '{}' does not exist: creating it needs the admin token, sent as 'Authorization: Bearer <token>'",
+                        namespace
+                    ),
+                ));
+            }
+            None if keys.len() >= MAX_NAMESPACES => {
+                return Err(ErrorDetails::new(
+                    ErrorKind::QuotaExceeded,
+                    format!("The server already holds {} namespaces, the most it keeps", MAX_NAMESPACES),
+                ));
+            }
+            None => Some(hooks::new_token()),
+        };
+        let key = (namespace.to_string(), name.to_string());
+        let current = programs.get(&key).map_or(0, |program| program.latest);
+        if let Some(expected) = expected.filter(|&expected| expected != current) {
+            return Err(ErrorDetails::new(
+                ErrorKind::Conflict,
+                format!("Program '{}' is at version {}, not {}", name, current, expected),
+            ));
+        }
+        if current == 0 && programs.keys().filter(|(owner, _)| owner == namespace).count() >= MAX_PROGRAMS {
+            return Err(ErrorDetails::new(
+                ErrorKind::QuotaExceeded,
+                format!("Namespace '{}' already holds {} programs, the most it keeps", namespace, MAX_PROGRAMS),
+            ));
+        }
+
+        let program = programs.entry(key).or_insert_with(|| Program {
+            latest: 0,
+            versions: VecDeque::new(),
+            schedule: None,
+            runs: VecDeque::new(),
+            running: false,
+            hook: None,
+        });
+        program.latest += 1;
+        program.schedule = schedule;
+        program.hook = webhook.then(|| program.hook.take().unwrap_or_else(hooks::new_token));
+        program.versions.push_back(Arc::new(Version { number: program.latest, ..compiled }));
+        if program.versions.len() > MAX_VERSIONS {
+            program.versions.pop_front();
+        }
+        if let Some(created) = &created {
+            keys.insert(namespace.to_string(), created.clone());
+        }
+        let mut uploaded = info(namespace, name, program);
+        uploaded.webhook = program.hook.as_ref().map(|token| format!("/hooks/{}", token));
+        Ok((uploaded, created))
+    }
+
+    /// Checks that `credential` is the key of `namespace`. A namespace no
+    /// upload has created yet needs none, as it holds nothing.
+    fn authorize(&self, namespace: &str, credential: Option<&str>) -> Result<(), ErrorDetails> {
+        match self.lock_keys().get(namespace) {
+            Some(key) => check_key(namespace, key, credential),
+            None => Ok(()),
+        }
+    }
+
+    /// The version `number` of a program, or its newest one.
+    fn version(&self, namespace: &str, name: &str, number: Option<u64>) -> Result<Arc<Version>, ErrorDetails> {
+        let programs = self.lock();
+        let program = programs
+            .get(&(namespace.to_string(), name.to_string()))
+            .ok_or_else(|| unknown_program(name))?;
+        let version = match number {
+            Some(number) => program.versions.iter().find(|version| version.number == number),
+            None => program.versions.back(),
+        };
+        version.cloned().ok_or_else(|| {
+            ErrorDetails::new(
+                ErrorKind::NotFound,
+                format!("Program '{}' has no version {}", name, number.unwrap_or_default()),
+            )
+        })
+    }
+
+    /// A program and its versions.
+    fn get(&self, namespace: &str, name: &str) -> Result<ProgramInfo, ErrorDetails> {
+        let programs = self.lock();
+        let program = programs
+            .get(&(namespace.to_string(), name.to_string()))
+            .ok_or_else(|| unknown_program(name))?;
+        Ok(info(namespace, name, program))
+    }
+
+    /// The programs of a namespace, by name.
+    fn list(&self, namespace: &str) -> Vec<ProgramInfo> {
+        let programs = self.lock();
+        let mut listed: Vec<ProgramInfo> = programs
+            .iter()
+            .filter(|((owner, _), _)| owner == namespace)
+            .map(|((_, name), program)| info(namespace, name, program))
+            .collect();
+        listed.sort_by(|a, b| a.name.cmp(&b.name));
+        listed
+    }
+
+    /// The records of a program's scheduled runs, oldest first.
+    fn runs(&self, namespace: &str, name: &str) -> Result<Vec<RunRecord>, ErrorDetails> {
+        let programs = self.lock();
+        let program = programs
+            .get(&(namespace.to_string(), name.to_string()))
+            .ok_or_else(|| unknown_program(name))?;
+        Ok(program.runs.iter().cloned().collect())
+    }
+
+    /// The programs scheduled to run at `minute`, in Unix time, which are
+    /// marked as running. Those still running since an earlier minute are
+    /// left out.
+    pub fn due(&self, minute: u64) -> Vec<ProgramRun> {
+        let mut programs = self.lock();
+        programs
+            .iter_mut()
+            .filter(|(_, program)| !program.running)
+            .filter(|(_, program)| program.schedule.as_ref().is_some_and(|schedule| schedule.matches(minute)))
+            .filter_map(|((namespace, name), program)| {
+                let version = program.versions.back()?;
+                program.running = true;
+                Some(ProgramRun {
+                    namespace: namespace.clone(),
+                    name: name.clone(),
+                    version: version.number,
+                    artifact: version.artifact.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The newest version of the program whose webhook has `token`.
+    pub fn hooked(&self, token: &str) -> Option<ProgramRun> {
+        let programs = self.lock();
+        programs.iter().find_map(|((namespace, name), program)| {
+            let version = program.versions.back().filter(|_| program.hook.as_deref() == Some(token))?;
+            Some(ProgramRun {
+                namespace: namespace.clone(),
+                name: name.clone(),
+                version: version.number,
+                artifact: version.artifact.clone(),
+            })
+        })
+    }
+
+    /// Keeps the record of a finished scheduled run, unless the program has
+    /// been removed meanwhile.
+    pub fn record(&self, namespace: &str, name: &str, record: RunRecord) {
+        let mut programs = self.lock();
+        if let Some(program) = programs.get_mut(&(namespace.to_string(), name.to_string())) {
+            program.running = false;
+            program.runs.push_back(record);
+            if program.runs.len() > MAX_RUNS {
+                program.runs.pop_front();
+            }
+        }
+    }
+
+    /// Removes a program with all its versions, returning what it was.
+    fn remove(&self, namespace: &str, name: &str) -> Result<ProgramInfo, ErrorDetails> {
+        let mut programs = self.lock();
+        let program = programs
+            .remove(&(namespace.to_string(), name.to_string()))
+            .ok_or_else(|| unknown_program(name))?;
+        Ok(info(namespace, name, &program))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(String, String), Program>> {
+        self.programs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_keys(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Checks the credential sent for a namespace against its key, in constant
+// time, so the response time doesn't reveal how much of a guess was right
+fn check_key(namespace: &str, key: &str, credential: Option<&str>) -> Result<(), ErrorDetails> {
+    match credential {
+        Some(credential) if builtins::constant_time_eq(credential.as_bytes(), key.as_bytes()) => Ok(()),
+        Some(_) => Err(ErrorDetails::new(
+            ErrorKind::Unauthorized,
+            format!("Wrong key for namespace '{}'", namespace),
+        )),
+        None => Err(ErrorDetails::new(
+            ErrorKind::Unauthorized,
+            format!("Missing key of namespace '{}': send 'Authorization: Bearer <key>'", namespace),
+        )),
+    }
+}
+
+fn info(namespace: &str, name: &str, program: &Program) -> ProgramInfo {
+    let now = Instant::now();
+    let unix_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    ProgramInfo {
+        namespace: namespace.to_string(),
+        name: name.to_string(),
+        version: program.latest,
+        versions: program
+            .versions
+            .iter()
+            .map(|version| VersionInfo {
+                version: version.number,
+                instructions: version.instructions,
+                age_ms: millis(now - version.uploaded_at),
+            })
+            .collect(),
+        schedule: program.schedule.as_ref().map(|schedule| schedule.expression().to_string()),
+        next_run: program.schedule.as_ref().and_then(|schedule| schedule.next_after(unix_now)),
+        has_webhook: program.hook.is_some(),
+        webhook: None,
+    }
+}
+
+fn unknown_program(name: &str) -> ErrorDetails {
+    ErrorDetails::new(ErrorKind::NotFound, format!("Unknown program '{}'", name))
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+// Names of programs and namespaces appear in paths and headers, so they are
+// kept to letters, digits, '_', '-' and '.'
+fn check_name(what: &str, name: &str) -> Result<(), ErrorDetails> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(ErrorDetails::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid {} '{}': use 1 to {} letters, digits, '_', '-' or '.'",
+                what, name, MAX_NAME_LEN
+            ),
+        ))
+    }
+}
+
+// Namespace of a request, from its X-Namespace header
+fn namespace_of(request: &HttpRequest) -> Result<String, ErrorDetails> {
+    let namespace = match request.headers().get(NAMESPACE_HEADER) {
+        Some(value) => value.to_str().unwrap_or_default().to_string(),
+        None => "default".to_string(),
+    };
+    check_name("namespace", &namespace)?;
+    Ok(namespace)
+}
+
+// Key of a namespace sent with a request, as a bearer token
+fn credential_of(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// Namespace of a request, once its key is checked
+fn authorized_namespace(request: &HttpRequest, store: &ProgramStore) -> Result<String, ErrorDetails> {
+    let namespace = namespace_of(request)?;
+    store.authorize(&namespace, credential_of(request))?;
+    Ok(namespace)
+}
+
+// Request body of PUT /programs/{name}
+#[derive(Deserialize, ToSchema)]
+pub struct UploadInput {
+    source: String,
+    #[serde(default)]
+    language: String,     // "beginner" for the beginner dialect, as for /compile
+    #[serde(default)]
+    opt_level: u8,        // 0 (default) to 2, as for /compile
+    #[serde(default)]
+    strict: StrictMode,   // Checks of strict mode, as for /compile
+    #[serde(default)]
+    expected_version: Option<u64>, // Version the program must be at, 0 for a new program
+    #[serde(default)]
+    schedule: Option<String>, // Cron expression to run the program on, see `scheduler::Schedule`
+    #[serde(default)]
+    webhook: bool,            // Give the program a webhook URL, see `hooks`
+}
+
+// Request body of POST /programs/{name}/invoke
+#[derive(Deserialize, ToSchema)]
+pub struct InvokeInput {
+    #[serde(default)]
+    args: BTreeMap<String, serde_json::Value>, // Globals defined before the program runs, as `globals` for /compile
+    #[serde(default)]
+    version: Option<u64>,    // Kept version to run instead of the newest
+    #[serde(default)]
+    stdin: String,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+    #[serde(default)]
+    return_variables: Vec<String>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    inspect_variables: bool,
+    #[serde(default)]
+    session: Option<String>,
+    #[serde(default)]
+    limits: ExecutionLimits,
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default)]
+    record: bool,
+}
+
+// Response of PUT, GET and DELETE /programs/{name}
+#[derive(Serialize, ToSchema)]
+pub struct ProgramOutput {
+    program: Option<ProgramInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace_key: Option<String>, // Key of the namespace, only from the upload that created it
+    error_details: Option<ErrorDetails>,
+}
+
+// Response of GET /programs
+#[derive(Serialize, ToSchema)]
+pub struct ProgramsOutput {
+    programs: Vec<ProgramInfo>, // Programs of the namespace, by name
+    error_details: Option<ErrorDetails>,
+}
+
+impl ProgramOutput {
+    fn respond(result: Result<ProgramInfo, ErrorDetails>) -> HttpResponse {
+        match result {
+            Ok(program) => HttpResponse::Ok().json(ProgramOutput {
+                program: Some(program),
+                namespace_key: None,
+                error_details: None,
+            }),
+            Err(details) => HttpResponse::build(details.kind.status()).json(ProgramOutput {
+                program: None,
+                namespace_key: None,
+                error_details: Some(details),
+            }),
+        }
+    }
+}
+
+// Response of GET /programs/{name}/runs
+#[derive(Serialize, ToSchema)]
+pub struct RunsOutput {
+    runs: Vec<RunRecord>, // The last scheduled runs, oldest first
+    error_details: Option<ErrorDetails>,
+}
+
+// Compiles an uploaded program into the artifact its invocations run
+fn compile(input: &UploadInput) -> Result<Version, ErrorDetails> {
+    if input.opt_level > optimizer::MAX_OPT_LEVEL {
+        return Err(ErrorDetails::new(
+            ErrorKind::InvalidInput,
+            format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL),
+        ));
+    }
+    let mut compilation = Compilation::new(
+        &input.source,
+        Dialect::from_language(&input.language),
+        input.strict.checks(),
+        input.opt_level,
+    );
+    PassManager::standard()
+        .run(&mut compilation)
+        .map_err(|e| ErrorDetails::from_error(e.as_ref()).rendered_in(&input.source))?;
+    let instructions = compilation.instructions;
+    let artifact = Artifact {
+        opt_level: input.opt_level,
+        limits: Limits::default(),
+        max_stack: verifier::max_stack_depth(&instructions).unwrap_or(None),
+        debug: None,
+        instructions,
+    };
+    Ok(Version {
+        number: 0,
+        artifact: base64::engine::general_purpose::STANDARD.encode(artifact.encode()),
+        instructions: artifact.instructions.len(),
+        uploaded_at: Instant::now(),
+    })
+}
+
+// Route handler for PUT /programs/{name}
+#[utoipa::path(
+    tag = "programs",
+    request_body = UploadInput,
+    params(
+        ("name" = String, Path, description = "Name of the program"),
+        ("X-Namespace" = Option<String>, Header, description = "Namespace of the program, \"default\" when absent"),
+    ),
+    responses(
+        (status = 200, description = "Program compiled and stored as its newest version; an upload creating the namespace also gets its key", body = ProgramOutput),
+        (status = 400, description = "The program does not compile; the stored versions are unchanged", body = ProgramOutput),
+        (status = 401, description = "Missing or wrong key of the namespace, or a new namespace without the admin token", body = ProgramOutput),
+        (status = 409, description = "The program is not at `expected_version`", body = ProgramOutput),
+        (status = 422, description = "Invalid name, namespace, schedule or request body", body = ProgramOutput),
+        (status = 429, description = "The namespace holds the most programs, or the server the most namespaces, it keeps", body = ProgramOutput),
+    )
+)]
+#[put("/programs/{name}")]
+pub async fn upload(
+    name: web::Path<String>,
+    input: web::Json<UploadInput>,
+    request: HttpRequest,
+    store: web::Data<ProgramStore>,
+    config: web::Data<ServerConfig>,
+) -> impl Responder {
+    let namespace = match authorized_namespace(&request, &store).and_then(|namespace| check_name("program name", &name).map(|()| namespace)) {
+        Ok(namespace) => namespace,
+        Err(details) => return ProgramOutput::respond(Err(details)),
+    };
+    let input = input.into_inner();
+    let expected = input.expected_version;
+    let webhook = input.webhook;
+    let schedule = match input.schedule.as_deref().map(Schedule::parse).transpose() {
+        Ok(schedule) => schedule,
+        Err(message) => return ProgramOutput::respond(Err(ErrorDetails::new(ErrorKind::InvalidInput, message))),
+    };
+    // Compiling is done before taking the store's lock, so invocations of
+    // the current version go on meanwhile
+    let compiled = web::block(move || catch_panics(|| compile(&input))).await;
+    let version = match compiled {
+        Ok(Ok(Ok(version))) => version,
+        Ok(Ok(Err(details))) => return ProgramOutput::respond(Err(details)),
+        Ok(Err(message)) => {
+            let details = ErrorDetails::new(ErrorKind::Internal, format!("Internal compiler error: {}", message));
+            return ProgramOutput::respond(Err(details));
+        }
+        Err(e) => {
+            let details = ErrorDetails::new(ErrorKind::Internal, format!("Internal compiler error: {}", e));
+            return ProgramOutput::respond(Err(details));
+        }
+    };
+    let admin = admin::authorize(&request, &config).is_ok();
+    match store.put(&namespace, credential_of(&request), admin, &name, expected, version, schedule, webhook) {
+        Ok((program, namespace_key)) => HttpResponse::Ok().json(ProgramOutput {
+            program: Some(program),
+            namespace_key,
+            error_details: None,
+        }),
+        Err(details) => ProgramOutput::respond(Err(details)),
+    }
+}
+
+// Route handler for GET /programs
+#[utoipa::path(
+    tag = "programs",
+    params(("X-Namespace" = Option<String>, Header, description = "Namespace to list, \"default\" when absent")),
+    responses(
+        (status = 200, description = "Programs of the namespace", body = ProgramsOutput),
+        (status = 401, description = "Missing or wrong key of the namespace", body = ProgramsOutput),
+        (status = 422, description = "Invalid namespace", body = ProgramsOutput),
+    )
+)]
+#[get("/programs")]
+pub async fn list_programs(request: HttpRequest, store: web::Data<ProgramStore>) -> impl Responder {
+    match authorized_namespace(&request, &store) {
+        Ok(namespace) => HttpResponse::Ok().json(ProgramsOutput {
+            programs: store.list(&namespace),
+            error_details: None,
+        }),
+        Err(details) => HttpResponse::build(details.kind.status()).json(ProgramsOutput {
+            programs: Vec::new(),
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Route handler for GET /programs/{name}
+#[utoipa::path(
+    tag = "programs",
+    params(
+        ("name" = String, Path, description = "Name of the program"),
+        ("X-Namespace" = Option<String>, Header, description = "Namespace of the program, \"default\" when absent"),
+    ),
+    responses(
+        (status = 200, description = "The program and its kept versions", body = ProgramOutput),
+        (status = 401, description = "Missing or wrong key of the namespace", body = ProgramOutput),
+        (status = 404, description = "No program has this name in the namespace", body = ProgramOutput),
+    )
+)]
+#[get("/programs/{name}")]
+pub async fn get_program(name: web::Path<String>, request: HttpRequest, store: web::Data<ProgramStore>) -> impl Responder {
+    ProgramOutput::respond(authorized_namespace(&request, &store).and_then(|namespace| store.get(&namespace, &name)))
+}
+
+// Route handler for DELETE /programs/{name}
+#[utoipa::path(
+    tag = "programs",
+    params(
+        ("name" = String, Path, description = "Name of the program"),
+        ("X-Namespace" = Option<String>, Header, description = "Namespace of the program, \"default\" when absent"),
+    ),
+    responses(
+        (status = 200, description = "The program was removed with all its versions; runs in progress finish", body = ProgramOutput),
+        (status = 401, description = "Missing or wrong key of the namespace", body = ProgramOutput),
+        (status = 404, description = "No program has this name in the namespace", body = ProgramOutput),
+    )
+)]
+#[delete("/programs/{name}")]
+pub async fn delete_program(name: web::Path<String>, request: HttpRequest, store: web::Data<ProgramStore>) -> impl Responder {
+    ProgramOutput::respond(authorized_namespace(&request, &store).and_then(|namespace| store.remove(&namespace, &name)))
+}
+
+// Route handler for GET /programs/{name}/runs
+#[utoipa::path(
+    tag = "programs",
+    params(
+        ("name" = String, Path, description = "Name of the program"),
+        ("X-Namespace" = Option<String>, Header, description = "Namespace of the program, \"default\" when absent"),
+    ),
+    responses(
+        (status = 200, description = "The last scheduled runs of the program", body = RunsOutput),
+        (status = 401, description = "Missing or wrong key of the namespace", body = RunsOutput),
+        (status = 404, description = "No program has this name in the namespace", body = RunsOutput),
+    )
+)]
+#[get("/programs/{name}/runs")]
+pub async fn list_runs(name: web::Path<String>, request: HttpRequest, store: web::Data<ProgramStore>) -> impl Responder {
+    match authorized_namespace(&request, &store).and_then(|namespace| store.runs(&namespace, &name)) {
+        Ok(runs) => HttpResponse::Ok().json(RunsOutput { runs, error_details: None }),
+        Err(details) => HttpResponse::build(details.kind.status()).json(RunsOutput {
+            runs: Vec::new(),
+            error_details: Some(details),
+        }),
+    }
+}
+
+// Route handler for POST /programs/{name}/invoke
+#[utoipa::path(
+    tag = "programs",
+    request_body = InvokeInput,
+    params(
+        ("name" = String, Path, description = "Name of the program"),
+        ("X-Namespace" = Option<String>, Header, description = "Namespace of the program, \"default\" when absent"),
+        StatusOptions,
+    ),
+    responses(
+        (status = 200, description = "Program ran; the X-Program-Version header gives the version", body = CodeOutput),
+        (status = 400, description = "Runtime error", body = CodeOutput),
+        (status = 401, description = "Missing or wrong key of the namespace", body = CodeOutput),
+        (status = 404, description = "No program has this name, or no kept version this number", body = CodeOutput),
+        (status = 408, description = "Program exceeded the time limit", body = CodeOutput),
+        (status = 422, description = "Malformed request body or invalid argument", body = CodeOutput),
+        (status = 429, description = "The session has used its instruction budget", body = CodeOutput),
+        (status = 500, description = "Internal compiler error", body = CodeOutput),
+    )
+)]
+#[post("/programs/{name}/invoke")]
+#[allow(clippy::too_many_arguments)]
+pub async fn invoke(
+    name: web::Path<String>,
+    input: web::Json<InvokeInput>,
+    options: web::Query<StatusOptions>,
+    request: HttpRequest,
+    store: web::Data<ProgramStore>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+) -> impl Responder {
+    let input = input.into_inner();
+    let version = match authorized_namespace(&request, &store).and_then(|namespace| store.version(&namespace, &name, input.version)) {
+        Ok(version) => version,
+        Err(details) => return respond(CodeOutput::failure(details.kind, details.message), &options),
+    };
+    let code_input = CodeInput {
+        source: String::new(),
+        language: String::new(),
+        mode: Mode::Program,
+        inspect_variables: input.inspect_variables,
+        seed: input.seed,
+        stdin: input.stdin,
+        env: input.env,
+        files: input.files,
+        globals: input.args,
+        return_variables: input.return_variables,
+        document: None,
+        opt_level: 0,
+        strict: StrictMode::default(),
+        verbose: false,
+        session: input.session,
+        limits: input.limits,
+        profile: input.profile,
+        record: input.record,
+        replay_clock: None,
+        locale: None,
+    };
+    let job = WorkerJob {
+        artifact: Some(version.artifact.clone()),
+        ..WorkerJob::new(code_input, config.policy.clone())
+    };
+    let output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+    let mut response = respond(output, &options);
+    response.headers_mut().insert(HeaderName::from_static(VERSION_HEADER), HeaderValue::from(version.number));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version() -> Version {
+        Version {
+            number: 0,
+            artifact: String::new(),
+            instructions: 0,
+            uploaded_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn a_namespace_is_kept_to_the_holder_of_its_key() {
+        let store = ProgramStore::default();
+        // Only an operator creates namespaces
+        let refused = store.put("team", None, false, "hook", None, version(), None, true);
+        assert_eq!(refused.unwrap_err().kind, ErrorKind::Unauthorized);
+        let (uploaded, key) = store.put("team", None, true, "hook", None, version(), None, true).unwrap();
+        let key = key.unwrap();
+        assert!(uploaded.webhook.is_some());
+
+        for credential in [None, Some("guess")] {
+            assert_eq!(store.authorize("team", credential).unwrap_err().kind, ErrorKind::Unauthorized);
+            let refused = store.put("team", credential, false, "other", None, version(), None, false);
+            assert_eq!(refused.unwrap_err().kind, ErrorKind::Unauthorized);
+        }
+        let (_, created) = store.put("team", Some(&key), false, "other", None, version(), None, false).unwrap();
+        assert_eq!(created, None);
+
+        // Reads show that the program has a webhook, not its URL
+        let read = store.get("team", "hook").unwrap();
+        assert!(read.has_webhook && read.webhook.is_none());
+        assert!(store.list("team").iter().all(|program| program.webhook.is_none()));
+
+        // The namespace keeps its key once empty
+        store.remove("team", "hook").unwrap();
+        store.remove("team", "other").unwrap();
+        assert!(store.authorize("team", None).is_err());
+        assert!(store.authorize("elsewhere", None).is_ok());
+    }
+
+    #[test]
+    fn each_namespace_has_its_own_program_cap() {
+        let store = ProgramStore::default();
+        let (_, key) = store.put("full", None, true, "p0", None, version(), None, false).unwrap();
+        let key = key.unwrap();
+        for n in 1..MAX_PROGRAMS {
+            store.put("full", Some(&key), false, &format!("p{}", n), None, version(), None, false).unwrap();
+        }
+        let refused = store.put("full", Some(&key), false, "one-more", None, version(), None, false);
+        assert_eq!(refused.unwrap_err().kind, ErrorKind::QuotaExceeded);
+        // New versions of kept programs are still accepted, and other namespaces are unaffected
+        store.put("full", Some(&key), false, "p0", None, version(), None, false).unwrap();
+        store.put("other", None, true, "p0", None, version(), None, false).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+// Multi-file programs: a project.toml manifest describing the project, and a
+// loader that follows `import "file";` statements from the entry file. The
+// files are lexed and parsed in parallel, one wave of imports at a time, then
+// included in the order of their first import, so the program, and the first
+// error reported, do not depend on which file was parsed first.
+//
+//   [project]
+//   name = "hello"
+//   entry = "src/main.src"
+//   source_dirs = ["lib"]   # Searched for imports after the importing file's directory
+//   opt_level = 0           # 0-2, see the optimizer
+//   strict = true           # Or a table of checks, e.g. { conditions = true }
+//   strip = false           # Leave the debug information out of the artifact
+//
+//   [limits]
+//   timeout_ms = 2000
+//   max_sleep_ms = 500
+
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::artifact::{self, Artifact, DebugInfo, LineEntry, Limits};
+use crate::bytecode::Module;
+use crate::compile_module;
+use crate::lexer::{Lexer, LexerError, Token};
+use crate::optimizer::{self, MAX_OPT_LEVEL};
+use crate::parser::{ASTNode, Parser, ParserError};
+use crate::semantic::{self, StrictMode, Strictness, Unit};
+use crate::verifier;
+
+/// Name of the manifest file at the root of a project.
+pub const MANIFEST_FILE: &str = "project.toml";
+
+/// Contents of project.toml.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub project: ProjectSection,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSection {
+    pub name: String,
+    pub entry: PathBuf,             // Relative to the project root
+    #[serde(default)]
+    pub source_dirs: Vec<PathBuf>,  // Relative to the project root
+    #[serde(default)]
+    pub opt_level: u8,              // 0-2, applied to the bytecode and recorded in the artifact
+    #[serde(default)]
+    pub strict: StrictMode,         // Checks of strict mode, applied to every file
+    #[serde(default)]
+    pub strip: bool,                // Build without debug information
+}
+
+/// A project loaded from its root directory.
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+/// Error while loading or compiling a project, with the file it concerns.
+#[derive(Debug)]
+pub struct BuildError {
+    pub file: PathBuf,
+    pub error: Box<dyn Error>, // Lexer, parser or generator error, or a message
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.error)
+    }
+}
+
+impl Error for BuildError {}
+
+impl BuildError {
+    fn new(file: &Path, error: impl Into<Box<dyn Error>>) -> Self {
+        BuildError {
+            file: file.to_path_buf(),
+            error: error.into(),
+        }
+    }
+}
+
+impl Project {
+    /// Reads and validates the manifest in `root`.
+    pub fn load(root: &Path) -> Result<Self, BuildError> {
+        let manifest_path = root.join(MANIFEST_FILE);
+        let text = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| BuildError::new(&manifest_path, format!("Cannot read manifest: {}", e)))?;
+        let manifest: Manifest = toml::from_str(&text)
+            .map_err(|e| BuildError::new(&manifest_path, format!("Invalid manifest: {}", e.message())))?;
+
+        if manifest.project.opt_level > MAX_OPT_LEVEL {
+            return Err(BuildError::new(
+                &manifest_path,
+                format!("opt_level must be between 0 and {}", MAX_OPT_LEVEL),
+            ));
+        }
+
+        Ok(Project {
+            root: root.to_path_buf(),
+            manifest,
+        })
+    }
+
+    /// Compiles the project, starting from its entry file, into an artifact.
+    pub fn build(&self) -> Result<Artifact, BuildError> {
+        let entry = self.root.join(&self.manifest.project.entry);
+        let source_dirs: Vec<PathBuf> = self
+            .manifest
+            .project
+            .source_dirs
+            .iter()
+            .map(|dir| self.root.join(dir))
+            .collect();
+
+        let opt_level = self.manifest.project.opt_level;
+        let strictness = self.manifest.project.strict.checks();
+        let loader = load(&entry, &source_dirs, strictness)?;
+        let lines = loader.lines;
+        let source_hash = artifact::source_hash(loader.sources.iter().map(String::as_str));
+        let (instructions, module) = compile_module(ASTNode::Program(loader.statements))
+            .map_err(|error| BuildError { file: entry.clone(), error })?;
+
+        // Optimization moves code between statements, so only the locals stay meaningful
+        let debug = (!self.manifest.project.strip).then(|| DebugInfo {
+            source_hash,
+            locals: DebugInfo::locals_of(&module),
+            lines: match opt_level {
+                0 => line_table(&module, &lines, |file| file.strip_prefix(&self.root).unwrap_or(file).display().to_string()),
+                _ => Vec::new(),
+            },
+        });
+        let instructions = optimizer::optimize(instructions, opt_level);
+        Ok(Artifact {
+            opt_level,
+            limits: self.manifest.limits.clone(),
+            max_stack: verifier::max_stack_depth(&instructions).unwrap_or(None),
+            instructions,
+            debug,
+        })
+    }
+}
+
+/// Parses a source file and everything it imports into a single program,
+/// checked as a whole by the semantic checks of `strictness`.
+pub fn load_program(entry: &Path, source_dirs: &[PathBuf], strictness: Strictness) -> Result<ASTNode, BuildError> {
+    load(entry, source_dirs, strictness).map(|loader| ASTNode::Program(loader.statements))
+}
+
+/// `load_program`, also giving the file and line of each top-level
+/// statement of the program.
+pub fn load_program_with_lines(
+    entry: &Path,
+    source_dirs: &[PathBuf],
+    strictness: Strictness,
+) -> Result<(ASTNode, Vec<(PathBuf, usize)>), BuildError> {
+    load(entry, source_dirs, strictness).map(|loader| (ASTNode::Program(loader.statements), loader.lines))
+}
+
+/// Line table of a program loaded with its `lines`, compiled unoptimized
+/// into `module`: where the code of each statement of each chunk begins, in
+/// the linked instructions, with its file, named by `file_name`, and line.
+pub fn line_table(module: &Module, lines: &[(PathBuf, usize)], file_name: impl Fn(&Path) -> String) -> Vec<LineEntry> {
+    crate::link_table(module, |chunk| &chunk.statements)
+        .into_iter()
+        .filter_map(|(instruction, statement)| {
+            let (file, line) = lines.get(statement)?;
+            Some(LineEntry { instruction, file: file_name(file), line: *line })
+        })
+        .collect()
+}
+
+fn load<'a>(entry: &Path, source_dirs: &'a [PathBuf], strictness: Strictness) -> Result<Loader<'a>, BuildError> {
+    let mut loader = Loader {
+        source_dirs,
+        parsed: parse_files(entry, source_dirs),
+        loaded: HashSet::new(),
+        loading: Vec::new(),
+        statements: Vec::new(),
+        files: strictness.any().then(Vec::new),
+        sources: Vec::new(),
+        lines: Vec::new(),
+    };
+    loader.load(entry)?;
+
+    if let Some(files) = &loader.files {
+        let units: Vec<Unit> = files
+            .iter()
+            .map(|(_, tokens, program)| Unit { tokens, program })
+            .collect();
+        semantic::check(&units, strictness)
+            .map_err(|(index, error)| BuildError::new(&files[index].0, error))?;
+    }
+    Ok(loader)
+}
+
+// A file read, lexed and parsed, with the line of each top-level statement
+struct ParsedFile {
+    source: String,
+    tokens: Vec<Token<'static>>, // Owning their text, as they are moved along with `source`
+    program: ASTNode,
+    lines: Vec<usize>,
+}
+
+// Error of a file parsed on another thread
+type ParseError = Box<dyn Error + Send + Sync>;
+
+// Reads and parses the file at `path`
+fn parse_file(path: &Path) -> Result<ParsedFile, ParseError> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("Cannot read file: {}", e))?;
+    let parsed = Lexer::new(&source).tokenize().and_then(|tokens| {
+        let mut parser = Parser::new(tokens.clone());
+        let program = parser.parse()?;
+        let tokens = tokens.into_iter().map(Token::into_owned).collect::<Vec<_>>();
+        Ok((tokens, program, parser.statement_lines().to_vec()))
+    });
+    // The lexer and parser errors are sendable; they are boxed as plain errors
+    let (tokens, program, lines) = parsed.map_err(|error| -> ParseError {
+        match error.downcast::<LexerError>() {
+            Ok(error) => error,
+            Err(error) => match error.downcast::<ParserError>() {
+                Ok(error) => error,
+                Err(error) => error.to_string().into(),
+            },
+        }
+    })?;
+    Ok(ParsedFile { source, tokens, program, lines })
+}
+
+// Parses the entry file and every file it imports, directly or not, by
+// canonical path. Each wave parses in parallel the files first imported by
+// the previous one. Imports that cannot be resolved are left to the loader,
+// which reports them in order.
+fn parse_files(entry: &Path, source_dirs: &[PathBuf]) -> HashMap<PathBuf, Result<ParsedFile, ParseError>> {
+    let mut parsed = HashMap::new();
+    let mut wave: Vec<PathBuf> = entry.canonicalize().into_iter().collect();
+    while !wave.is_empty() {
+        let files: Vec<_> = wave.par_iter().map(|path| parse_file(path)).collect();
+        let mut next = Vec::new();
+        for (path, file) in wave.into_iter().zip(files) {
+            if let Ok(ParsedFile { program: ASTNode::Program(statements), .. }) = &file {
+                for statement in statements {
+                    let ASTNode::Import(target) = statement else {
+                        continue;
+                    };
+                    let Some(Ok(imported)) = resolve(&path, target, source_dirs).map(|file| file.canonicalize()) else {
+                        continue;
+                    };
+                    if imported != path && !parsed.contains_key(&imported) && !next.contains(&imported) {
+                        next.push(imported);
+                    }
+                }
+            }
+            parsed.insert(path, file);
+        }
+        // Files of this wave imported by a later file of it were parsed with it
+        next.retain(|path| !parsed.contains_key(path));
+        wave = next;
+    }
+    parsed
+}
+
+// Looks for an imported file next to the importing file, then in the source directories
+fn resolve(importer: &Path, target: &str, source_dirs: &[PathBuf]) -> Option<PathBuf> {
+    importer
+        .parent()
+        .into_iter()
+        .chain(source_dirs.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(target))
+        .find(|candidate| candidate.is_file())
+}
+
+// Collects the statements of a file and its imports into one program. Each
+// file is included once, at its first import, before the importing code.
+struct Loader<'a> {
+    source_dirs: &'a [PathBuf],
+    parsed: HashMap<PathBuf, Result<ParsedFile, ParseError>>, // Files parsed ahead, by canonical path
+    loaded: HashSet<PathBuf>, // Canonical paths of files already included
+    loading: Vec<PathBuf>,    // Files whose imports are being resolved, to detect cycles
+    statements: Vec<ASTNode>,
+    files: Option<Vec<(PathBuf, Vec<Token<'static>>, ASTNode)>>, // Each file as parsed, kept for the semantic checks
+    sources: Vec<String>,           // Contents of the files, in the order they were read
+    lines: Vec<(PathBuf, usize)>,   // File and line of each statement
+}
+
+impl Loader<'_> {
+    fn load(&mut self, path: &Path) -> Result<(), BuildError> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| BuildError::new(path, format!("Cannot read file: {}", e)))?;
+        if self.loading.contains(&canonical) {
+            return Err(BuildError::new(path, "Import cycle: the file imports itself through its imports"));
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        // A file missing from the parsed ones appeared during the build
+        let ParsedFile { source, tokens, program, lines } = self
+            .parsed
+            .remove(&canonical)
+            .unwrap_or_else(|| parse_file(&canonical))
+            .map_err(|error| BuildError { file: path.to_path_buf(), error })?;
+        self.sources.push(source);
+        if let Some(files) = &mut self.files {
+            files.push((path.to_path_buf(), tokens, program.clone()));
+        }
+        let ASTNode::Program(statements) = program else {
+            return Err(BuildError::new(path, "Parser did not return a program"));
+        };
+
+        self.loading.push(canonical.clone());
+        for (statement, line) in statements.into_iter().zip(lines) {
+            match statement {
+                ASTNode::Import(target) => {
+                    let resolved = resolve(&canonical, &target, self.source_dirs).ok_or_else(|| {
+                        BuildError::new(path, format!("Cannot find imported file \"{}\"", target))
+                    })?;
+                    self.load(&resolved)?;
+                }
+                statement => {
+                    self.statements.push(statement);
+                    self.lines.push((path.to_path_buf(), line));
+                }
+            }
+        }
+        self.loading.pop();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+// Quick fixes: edits that correct common mistakes, attached to the diagnostics
+// that report them so editors can apply them in one step. Fixes for syntax
+// errors are found from the tokens around the error. The checks here report
+// mistakes the compiler lets through, each with its fix.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::diagnostics::{Diagnostic, Severity, Span};
+use crate::lexer::{Lexer, Token, TokenType};
+use crate::parser::Parser;
+use crate::registry;
+use crate::rename::TextEdit;
+use crate::reparse::split_statements;
+use crate::resolver::{self, SymbolKind, SymbolTable, TextSpan};
+use crate::semantic::{self, Unit};
+use crate::{ErrorDetails, ErrorKind};
+
+/// Edits that fix the problem reported by a diagnostic.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuickFix {
+    pub title: String,
+    pub edits: Vec<TextEdit>, // In source order
+}
+
+/// Fixes for an error raised while compiling `source`, given its tokens.
+pub fn fixes_for_error(source: &str, tokens: &[Token], details: &ErrorDetails) -> Vec<QuickFix> {
+    if details.kind != ErrorKind::Syntax {
+        return Vec::new();
+    }
+    let (Some(line), Some(column)) = (details.line, details.column) else {
+        return Vec::new();
+    };
+    // The parser reports the token where it expected something else
+    let Some(index) = tokens.iter().position(|token| token.line == line && token.column == column) else {
+        return Vec::new();
+    };
+
+    if details.message.contains("Expected ';'") && index > 0 {
+        let title = if details.message.contains("after return value") {
+            "Add missing ';' after return value"
+        } else {
+            "Insert missing ';'"
+        };
+        return vec![QuickFix {
+            title: title.to_string(),
+            edits: vec![insert(end_of(source, &tokens[index - 1]), ";")],
+        }];
+    }
+    Vec::new()
+}
+
+/// Mistakes in a source file that compiles, as found by `check`.
+pub fn lint(file: &str, source: &str) -> Vec<Diagnostic> {
+    let Ok(tokens) = Lexer::new(source).tokenize() else {
+        return Vec::new();
+    };
+    match resolver::resolve(source, &tokens) {
+        Ok(table) => check(file, source, &tokens, &table),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Mistakes in a program that compiles, each with its fix, in source order.
+pub fn check(file: &str, source: &str, tokens: &[Token], table: &SymbolTable) -> Vec<Diagnostic> {
+    let mut diagnostics = assignments_in_conditions(file, source, tokens);
+    diagnostics.extend(undeclared_variables(file, source, tokens, table));
+    diagnostics.extend(null_in_arithmetic(file, source, tokens, table));
+    diagnostics.extend(integer_truncations(file, source, tokens));
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.span.line, diagnostic.span.column));
+    for diagnostic in &mut diagnostics {
+        diagnostic.render(source);
+    }
+    diagnostics
+}
+
+// `if (x = 1)`: valid, since an assignment has the assigned value, but `==`
+// was almost certainly meant. As in C compilers, an assignment in extra
+// parentheses, e.g. `while ((x = next()))`, is taken as intended.
+fn assignments_in_conditions(file: &str, source: &str, tokens: &[Token]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if !matches!(token.token_type, TokenType::If | TokenType::While) {
+            continue;
+        }
+        let mut depth = 0usize;
+        for token in &tokens[index + 1..] {
+            match token.token_type {
+                TokenType::LeftParen => depth += 1,
+                TokenType::RightParen => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                TokenType::Assign if depth == 1 => {
+                    let span = TextSpan {
+                        length: 1,
+                        ..start_of(source, token)
+                    };
+                    diagnostics.push(diagnostic(
+                        file,
+                        span,
+                        Severity::Warning,
+                        "assignment_in_condition",
+                        "Assignment used as a condition; did you mean '=='?".to_string(),
+                        vec![QuickFix {
+                            title: "Replace '=' with '=='".to_string(),
+                            edits: vec![TextEdit {
+                                span,
+                                new_text: "==".to_string(),
+                            }],
+                        }],
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+    diagnostics
+}
+
+// Globals that are never declared: reading one before it is assigned fails at
+// runtime. Imported files may declare globals, so programs with imports are skipped.
+fn undeclared_variables(file: &str, source: &str, tokens: &[Token], table: &SymbolTable) -> Vec<Diagnostic> {
+    if tokens.iter().any(|token| token.token_type == TokenType::Import) {
+        return Vec::new();
+    }
+    let statements = split_statements(tokens);
+    let mut diagnostics = Vec::new();
+    for (index, symbol) in table.symbols.iter().enumerate() {
+        if symbol.kind != SymbolKind::Global || !symbol.declarations.is_empty() {
+            continue;
+        }
+        let Some(first_use) = table.occurrences_of(index).next() else {
+            continue;
+        };
+        let Some(token) = tokens
+            .iter()
+            .find(|token| token.line == first_use.span.line && token.column == first_use.span.column)
+        else {
+            continue;
+        };
+        let Some(statement) = statements
+            .iter()
+            .find(|statement| statement.last().is_some_and(|last| last.offset >= token.offset))
+        else {
+            continue;
+        };
+
+        // `y = 1;` becomes the declaration; otherwise one is added before the
+        // top-level statement of the first use, so it declares a global
+        let is_named = |other: &&Token| other.token_type == TokenType::Identifier(symbol.name.as_str().into());
+        let assigned_first = statement[0].offset == token.offset
+            && statement.get(1).is_some_and(|next| next.token_type == TokenType::Assign)
+            && statement.iter().filter(is_named).count() == 1;
+        let fix = if assigned_first {
+            let var_type = if statement.iter().any(|token| matches!(token.token_type, TokenType::FloatLiteral(_))) {
+                "float"
+            } else {
+                "int"
+            };
+            QuickFix {
+                title: format!("Declare '{}' as {}", symbol.name, var_type),
+                edits: vec![insert(start_of(source, token), &format!("{} ", var_type))],
+            }
+        } else {
+            let start = &statement[0];
+            let line_start = source[..start.offset].rfind('\n').map_or(0, |index| index + 1);
+            let indent = &source[line_start..start.offset];
+            let declaration = if indent.trim().is_empty() {
+                format!("int {} = 0;\n{}", symbol.name, indent)
+            } else {
+                format!("int {} = 0; ", symbol.name)
+            };
+            QuickFix {
+                title: format!("Declare '{}' as int", symbol.name),
+                edits: vec![insert(start_of(source, start), &declaration)],
+            }
+        };
+
+        diagnostics.push(diagnostic(
+            file,
+            first_use.span,
+            Severity::Warning,
+            "undeclared_variable",
+            format!("Variable '{}' is used without being declared", symbol.name),
+            vec![fix],
+        ));
+    }
+    diagnostics
+}
+
+// `null` as an operand of arithmetic or of `<` and `>`, which fails at runtime
+// with a type error. Variables that are ever assigned `null` are reported
+// where they are used as such an operand, since they may hold it then; `null`
+// itself gets a fix replacing it with 0.
+fn null_in_arithmetic(file: &str, source: &str, tokens: &[Token], table: &SymbolTable) -> Vec<Diagnostic> {
+    let symbol_of = |token: &Token| {
+        table
+            .occurrences
+            .iter()
+            .find(|occurrence| occurrence.span.line == token.line && occurrence.span.column == token.column)
+            .map(|occurrence| occurrence.symbol)
+    };
+    let is_operator = |token: Option<&Token>| {
+        token.is_some_and(|token| {
+            matches!(
+                token.token_type,
+                TokenType::Plus
+                    | TokenType::Minus
+                    | TokenType::Multiply
+                    | TokenType::Divide
+                    | TokenType::LessThan
+                    | TokenType::GreaterThan
+                    | TokenType::LessEqual
+                    | TokenType::GreaterEqual
+            )
+        })
+    };
+
+    // `x = null` and `int x = null`, ending the expression
+    let nullable: Vec<usize> = tokens
+        .windows(4)
+        .filter(|window| {
+            window[1].token_type == TokenType::Assign
+                && window[2].token_type == TokenType::Null
+                && !is_operator(Some(&window[3]))
+        })
+        .filter_map(|window| symbol_of(&window[0]))
+        .collect();
+
+    let mut diagnostics = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let in_arithmetic = is_operator(index.checked_sub(1).map(|previous| &tokens[previous])) || is_operator(tokens.get(index + 1));
+        if !in_arithmetic {
+            continue;
+        }
+        let span = TextSpan {
+            length: source[token.offset..token.offset + token.length].chars().count(),
+            ..start_of(source, token)
+        };
+        match &token.token_type {
+            TokenType::Null => diagnostics.push(diagnostic(
+                file,
+                span,
+                Severity::Warning,
+                "null_in_arithmetic",
+                "'null' used in arithmetic; this fails at runtime".to_string(),
+                vec![QuickFix {
+                    title: "Replace 'null' with 0".to_string(),
+                    edits: vec![TextEdit {
+                        span,
+                        new_text: "0".to_string(),
+                    }],
+                }],
+            )),
+            TokenType::Identifier(name) if symbol_of(token).is_some_and(|symbol| nullable.contains(&symbol)) => {
+                diagnostics.push(diagnostic(
+                    file,
+                    span,
+                    Severity::Warning,
+                    "null_in_arithmetic",
+                    format!("Variable '{}' may be null here, and arithmetic on null fails at runtime", name),
+                    Vec::new(),
+                ))
+            }
+            _ => {}
+        }
+    }
+    diagnostics
+}
+
+// Warning or error found by `check`, filed under the registered code of the check
+// Int divisions whose fraction is lost without the program saying so, as the
+// semantic checks find them. A dividend that is an int literal gets a fix
+// making it a float, so the division keeps the fraction.
+fn integer_truncations(file: &str, source: &str, tokens: &[Token]) -> Vec<Diagnostic> {
+    let Ok(program) = Parser::new(tokens.to_vec()).parse() else {
+        return Vec::new();
+    };
+    let unit = Unit { tokens, program: &program };
+    let mut diagnostics = Vec::new();
+    for (_, warning) in semantic::truncations(&[unit]) {
+        let Some(index) = tokens
+            .iter()
+            .position(|token| token.line == warning.line() && token.column == warning.column())
+        else {
+            continue;
+        };
+        let span = TextSpan {
+            length: 1,
+            ..start_of(source, &tokens[index])
+        };
+        let fixes = match index.checked_sub(1).map(|previous| &tokens[previous]) {
+            Some(dividend @ Token { token_type: TokenType::IntLiteral(value), .. }) => vec![QuickFix {
+                title: format!("Write {}.0 to divide as floats", value),
+                edits: vec![insert(end_of(source, dividend), ".0")],
+            }],
+            _ => Vec::new(),
+        };
+        diagnostics.push(diagnostic(
+            file,
+            span,
+            Severity::Warning,
+            "integer_truncation",
+            warning.message().to_string(),
+            fixes,
+        ));
+    }
+    diagnostics
+}
+
+fn diagnostic(file: &str, span: TextSpan, severity: Severity, check: &str, message: String, fixes: Vec<QuickFix>) -> Diagnostic {
+    Diagnostic {
+        file: file.to_string(),
+        span: Span {
+            line: Some(span.line),
+            column: Some(span.column),
+        },
+        severity,
+        code: registry::warning(check).map_or(check, |entry| entry.code).to_string(),
+        message,
+        suggestion: None,
+        fixes,
+        rendered: None,
+    }
+}
+
+fn insert(at: TextSpan, text: &str) -> TextEdit {
+    TextEdit {
+        span: at,
+        new_text: text.to_string(),
+    }
+}
+
+// Empty spans at the start and just after the end of a token
+fn start_of(source: &str, token: &Token) -> TextSpan {
+    TextSpan {
+        line: token.line,
+        column: token.column,
+        offset: source[..token.offset].chars().count(),
+        length: 0,
+    }
+}
+
+fn end_of(source: &str, token: &Token) -> TextSpan {
+    let start = start_of(source, token);
+    let length = source[token.offset..token.offset + token.length].chars().count();
+    TextSpan {
+        column: start.column + length,
+        offset: start.offset + length,
+        ..start
+    }
+}
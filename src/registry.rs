@@ -0,0 +1,639 @@
+// Registry of error codes: every diagnostic of a program carries a stable code,
+// such as E0001 or R0101, that stays the same when the wording of its message
+// changes. Each code comes with a longer explanation and an example, served by
+// GET /explain/{code} and `compiler explain CODE`. Codes starting with E are
+// reported before the program runs (except type mismatches, which the VM
+// detects), R while it runs, and W are warnings.
+
+#[cfg(feature = "server")]
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::diagnostics::Severity;
+use crate::{ErrorDetails, ErrorKind};
+
+/// A registered error code.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub kind: Option<ErrorKind>, // Kind of the errors filed under the code; None for warnings
+    pub title: &'static str,
+    #[serde(skip)]
+    matches: &'static [&'static str], // Texts of the messages filed under the code, or the name of the check for warnings
+    pub explanation: &'static str,
+    pub example: &'static str, // A program that raises the error
+}
+
+// Errors of a kind are filed under its first entry with a text that appears in
+// their message, or without texts, so such an entry comes after the others of
+// its kind
+static CODES: &[ErrorCode] = &[
+    ErrorCode {
+        code: "E0002",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Missing semicolon",
+        matches: &["Expected ';'"],
+        explanation: "Declarations, expression statements, `return` and `import` end with `;`. \
+            The error points at the token after the statement; the `;` is usually missing at the \
+            end of the line before it.",
+        example: "int x = 1\nx + 1;",
+    },
+    ErrorCode {
+        code: "E0003",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Unclosed parenthesis or brace",
+        matches: &["Expected ')'"],
+        explanation: "A `(` is not closed by a matching `)`. Conditions of `if` and `while`, calls \
+            and parenthesized expressions all need one.",
+        example: "if (1 > 0 { 1; }",
+    },
+    ErrorCode {
+        code: "E0004",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Unclosed block",
+        matches: &["Expected '}'"],
+        explanation: "A block opened with `{` reaches the end of the program without its `}`.",
+        example: "while (1 < 0) {\n  1;",
+    },
+    ErrorCode {
+        code: "E0005",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Invalid assignment target",
+        matches: &["Invalid assignment target"],
+        explanation: "Only variables can be assigned to. The left side of `=` is an expression \
+            such as a literal or a sum; in a condition, `==` was probably meant.",
+        example: "int x = 1;\nx + 1 = 2;",
+    },
+    ErrorCode {
+        code: "E0006",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Missing variable name",
+        matches: &["Expected identifier", "Expected type name"],
+        explanation: "A declaration names its variable after the type: `int count = 0;`.",
+        example: "int = 5;",
+    },
+    ErrorCode {
+        code: "E0007",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Program too deeply nested",
+        matches: &["too deeply nested"],
+        explanation: "Parentheses, blocks, branches and other constructs are nested in one another \
+            more deeply than the compiler accepts: 100 levels, unless COMPILER_MAX_NESTING sets \
+            another cap. A chain of calls or indices nests too, as `a[1][2]` indexes `a[1]`. Move the \
+            inner parts into variables or functions of their own.",
+        example: "",
+    },
+    ErrorCode {
+        code: "E0008",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Chain of operators too long",
+        matches: &["operators is too long"],
+        explanation: "More binary operators are applied one after another, as the two of `a + b * c` \
+            are, than the compiler accepts: 1000, unless COMPILER_MAX_CHAIN sets another cap. \
+            Operators in parentheses count with those around them. The message gives the length \
+            of the whole chain. Split it over variables holding partial results.",
+        example: "",
+    },
+    ErrorCode {
+        code: "E0001",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Syntax),
+        title: "Unexpected token",
+        matches: &[],
+        explanation: "The parser found a token that cannot appear at this point of the program, \
+            such as an operator where an expression should start. The message names the token \
+            that was expected and the one that was found.",
+        example: "int x = * 2;",
+    },
+    ErrorCode {
+        code: "E0101",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "Unexpected character",
+        matches: &["Unexpected character"],
+        explanation: "The source contains a character that is not part of the language, such as \
+            `@` or `$`. A single `&` or `|` is also rejected: the operators are `&&` and `||`.",
+        example: "int x = 1 @ 2;",
+    },
+    ErrorCode {
+        code: "E0102",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "Unterminated string literal",
+        matches: &["Unterminated string"],
+        explanation: "A string literal is not closed by a `\"` before the end of its line or of \
+            the program.",
+        example: "int x = \"hello;",
+    },
+    ErrorCode {
+        code: "E0103",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "Unterminated block comment",
+        matches: &["Unterminated block comment"],
+        explanation: "A comment opened with `/*` is not closed by `*/` before the end of the \
+            program.",
+        example: "int x = 1; /* the answer",
+    },
+    ErrorCode {
+        code: "E0105",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "Source too long",
+        matches: &["Source too long"],
+        explanation: "The program is made of more tokens, such as names, literals and operators, \
+            than the compiler accepts: a million, unless COMPILER_MAX_TOKENS sets another cap. The \
+            error points at the first token past the cap.",
+        example: "",
+    },
+    ErrorCode {
+        code: "E0106",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "String literal too long",
+        matches: &["String literal longer"],
+        explanation: "A string literal holds more bytes than the compiler accepts: 1 MiB, unless \
+            COMPILER_MAX_STRING_BYTES sets another cap. Read long texts from a file or from the \
+            input instead.",
+        example: "",
+    },
+    ErrorCode {
+        code: "E0107",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "Unknown escape sequence",
+        matches: &["Unknown escape sequence"],
+        explanation: "A backslash in a string literal starts an escape sequence, and only `\\n` \
+            (newline), `\\t` (tab), `\\\\` (backslash), `\\\"` (double quote) and `\\0` (NUL) \
+            are known. Write `\\\\` for a backslash of its own.",
+        example: "int path = \"C:\\data\";",
+    },
+    ErrorCode {
+        code: "E0104",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Lexical),
+        title: "Invalid number literal",
+        matches: &[],
+        explanation: "A number cannot be read, usually because an integer literal is too large \
+            for a 64-bit integer.",
+        example: "int x = 99999999999999999999;",
+    },
+    ErrorCode {
+        code: "E0201",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Variable declared twice",
+        matches: &["already declared"],
+        explanation: "A variable is declared twice in the same block. Assign to the existing \
+            variable instead, or give the new one another name. Top-level variables may be \
+            declared again, which assigns them.",
+        example: "{\n  int x = 1;\n  int x = 2;\n}",
+    },
+    ErrorCode {
+        code: "E0202",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Wrong number of arguments",
+        matches: &["argument(s)"],
+        explanation: "A builtin function is called with more or fewer arguments than it takes. \
+            The signatures of the builtins are listed in the README and by editor completions.",
+        example: "rand_int(1);",
+    },
+    ErrorCode {
+        code: "E0203",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Type mismatch",
+        matches: &["Type error"],
+        explanation: "An operator is applied to values it does not work on, such as subtracting \
+            strings or adding `null`. Types are checked when the operation runs, so the error is \
+            raised while running the program.",
+        example: "int x = null;\nx + 1;",
+    },
+    ErrorCode {
+        code: "E0204",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Import outside a project",
+        matches: &["Cannot import"],
+        explanation: "Imports are resolved when compiling files from the command line, as with \
+            `compiler run FILE` or `compiler build`. A program sent to the server is a single \
+            source and cannot import.",
+        example: "import \"lib.src\";",
+    },
+    ErrorCode {
+        code: "E0205",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Unsupported construct",
+        matches: &["Unexpected node type", "Unsupported"],
+        explanation: "The program parses but bytecode generation does not support one of its \
+            constructs. This is a limitation of the compiler rather than a mistake in the \
+            program.",
+        example: "",
+    },
+    ErrorCode {
+        code: "E0206",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Imported file not found",
+        matches: &["Cannot find imported file"],
+        explanation: "An `import` names a file that does not exist. Paths are relative to the \
+            importing file, then to the source directories of the project.",
+        example: "import \"missing.src\";",
+    },
+    ErrorCode {
+        code: "E0207",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Import cycle",
+        matches: &["Import cycle"],
+        explanation: "A file imports itself, directly or through the files it imports. Move what \
+            both files need into a third file that they import.",
+        example: "",
+    },
+    ErrorCode {
+        code: "E0208",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Implicit conversion in strict mode",
+        matches: &["without a conversion"],
+        explanation: "With strict `conversions`, a value must have the type of the variable it is \
+            stored in, and the operands of an operator must not mix int and float. Write the \
+            literal with the right type, e.g. `2.0` for a float, or declare the variable with the \
+            type of its value.",
+        example: "int x = 2.5;",
+    },
+    ErrorCode {
+        code: "E0209",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Condition is not bool in strict mode",
+        matches: &["must be bool"],
+        explanation: "With strict `conditions`, the condition of an `if` or `while` must be a \
+            comparison or another bool value. Without strict mode any value except `false` counts \
+            as true, so `if (x)` holds even when `x` is 0; write `if (x != 0)` instead.",
+        example: "int x = 0;\nif (x) { x = 1; }",
+    },
+    ErrorCode {
+        code: "E0210",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Declaration without initializer in strict mode",
+        matches: &["must be initialized"],
+        explanation: "With strict `initializers`, every variable gets its value where it is \
+            declared. Without strict mode such a variable holds `null` until it is assigned.",
+        example: "int x;\nx = 1;",
+    },
+    ErrorCode {
+        code: "E0211",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Unused variable in strict mode",
+        matches: &["never read"],
+        explanation: "With strict `unused`, every declared variable must be read somewhere; \
+            assigning to it does not count. Remove the variable, or start its name with `_` to \
+            keep it.",
+        example: "int x = 1;\nint y = 2;\nx;",
+    },
+    ErrorCode {
+        code: "E0212",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Invalid format string",
+        matches: &["Invalid format string"],
+        explanation: "The format string given to `format` or `printf` has a `{` without its `}`, \
+            a `}` without its `{`, or a placeholder other than `{}`, `{:d}`, `{:f}`, `{:.Nf}` \
+            and `{:s}`. Write `{{` and `}}` for literal braces.",
+        example: "format(\"{x}\", 1);",
+    },
+    ErrorCode {
+        code: "E0213",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Placeholder type mismatch in strict mode",
+        matches: &["Placeholder"],
+        explanation: "With strict `formats`, each value given to `format` or `printf` after a \
+            literal format string must suit its placeholder: a whole number for `{:d}`, a number \
+            for `{:f}` and a string for `{:s}`. `{}` takes any value.",
+        example: "format(\"{:d}\", \"x\");",
+    },
+    ErrorCode {
+        code: "E0214",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Undefined function",
+        matches: &["Undefined function"],
+        explanation: "A call names neither a builtin nor a function of the program. Calls are \
+            resolved when the program compiles, so only the builtins listed in the README can \
+            be called.",
+        example: "int f = 1;\nf(2);",
+    },
+    ErrorCode {
+        code: "E0200",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Compile),
+        title: "Compile error",
+        matches: &[],
+        explanation: "The program or its project could not be compiled for a reason without its \
+            own code, such as an unreadable file.",
+        example: "",
+    },
+    ErrorCode {
+        code: "R0101",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Division by zero",
+        matches: &["Division by zero"],
+        explanation: "A number is divided by zero. Check the divisor before dividing when it may \
+            be zero.",
+        example: "int x = 0;\n10 / x;",
+    },
+    ErrorCode {
+        code: "R0102",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Undefined variable",
+        matches: &["Undefined variable"],
+        explanation: "A variable is read before any declaration of it has run. Declare it with \
+            its type before using it; `check` warns about such variables with a fix.",
+        example: "y + 1;",
+    },
+    ErrorCode {
+        code: "R0103",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Undefined function",
+        matches: &["Undefined function"],
+        explanation: "Bytecode from another source calls a function missing from its function \
+            table. Compiled programs resolve their calls before they run; see E0214.",
+        example: "",
+    },
+    ErrorCode {
+        code: "R0104",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Permission denied",
+        matches: &["Permission denied"],
+        explanation: "A builtin needs a capability, such as `time` or `random`, that the sandbox \
+            does not grant to this program. The server grants those listed in \
+            COMPILER_CAPABILITIES, and `exec --json` those in the `capabilities` of the job. \
+            A list with `constant_time` also refuses the builtins whose running time may \
+            reveal their arguments, such as `regex_match`.",
+        example: "random();",
+    },
+    ErrorCode {
+        code: "R0105",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Invalid argument",
+        matches: &["expects", "lower bound", "json_", "regex_", "format string"],
+        explanation: "A builtin function is given an argument of the wrong type or out of its \
+            range, such as a negative duration to `sleep`, text that is not JSON to \
+            `json_parse` or an invalid pattern to `regex_match`.",
+        example: "rand_int(\"a\", 2);",
+    },
+    ErrorCode {
+        code: "R0106",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Instruction limit exceeded",
+        matches: &["Instruction limit exceeded"],
+        explanation: "The program ran more instructions than allowed, usually because a loop \
+            never ends. Check that the condition of each `while` eventually becomes false.",
+        example: "int i = 0;\nwhile (i < 10) { i - 1; }",
+    },
+    ErrorCode {
+        code: "R0107",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Memory limit exceeded",
+        matches: &["Memory limit exceeded"],
+        explanation: "The values held by the program take more memory than allowed, usually \
+            because a string grows in a loop.",
+        example: "int s = \"x\";\nwhile (1 > 0) { s = s + s; }",
+    },
+    ErrorCode {
+        code: "R0108",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Sleep budget exceeded",
+        matches: &["sleep budget"],
+        explanation: "Calls to `sleep` together ask for more time than a program may spend \
+            sleeping.",
+        example: "sleep(100000000);",
+    },
+    ErrorCode {
+        code: "R0109",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Execution cancelled",
+        matches: &["Execution cancelled"],
+        explanation: "The program was stopped from outside while running, e.g. through \
+            POST /admin/executions/{id}/cancel.",
+        example: "",
+    },
+    ErrorCode {
+        code: "R0110",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Replay diverged",
+        matches: &["Replay diverged"],
+        explanation: "A recorded execution is replayed, but the program reads the clock in a \
+            different order or more often than during the recording, so it is not the program \
+            that was recorded.",
+        example: "",
+    },
+    ErrorCode {
+        code: "R0111",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Timeout),
+        title: "Time limit exceeded",
+        matches: &[],
+        explanation: "The program ran longer than its timeout and was stopped. As with the \
+            instruction limit, a loop that never ends is the usual cause.",
+        example: "while (1 > 0) { 1; }",
+    },
+    ErrorCode {
+        code: "R0112",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Output limit exceeded",
+        matches: &["Output limit exceeded"],
+        explanation: "The program printed more than the request allows, usually because a \
+            `printf` sits in a loop that runs longer than intended.",
+        example: "while (1 > 0) { printf(\"x\"); }",
+    },
+    ErrorCode {
+        code: "R0113",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Call depth limit exceeded",
+        matches: &["Call depth limit exceeded"],
+        explanation: "Calls are nested deeper than the request allows, usually because a \
+            recursive function never reaches its base case.",
+        example: "",
+    },
+    ErrorCode {
+        code: "R0114",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Assertion failed",
+        matches: &["Assertion failed"],
+        explanation: "The condition given to `assert` was false. In a test block this is how the \
+            test reports that the program does not do what it checks; the message given to \
+            `assert`, if any, follows the error.",
+        example: "int total = 2 + 2;\nassert(total == 5, \"2 + 2 should be 5\");",
+    },
+    ErrorCode {
+        code: "R0115",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Invalid array index",
+        matches: &["out of bounds", "Array index"],
+        explanation: "An array is indexed with a position it does not have. Positions are whole \
+            numbers from 0 to one less than the length the array has, which `len` returns.",
+        example: "int a = [1, 2, 3];\na[3];",
+    },
+    ErrorCode {
+        code: "R0100",
+        severity: Severity::Error,
+        kind: Some(ErrorKind::Runtime),
+        title: "Runtime error",
+        matches: &[],
+        explanation: "The program failed while running for a reason without its own code.",
+        example: "",
+    },
+    ErrorCode {
+        code: "W0001",
+        severity: Severity::Warning,
+        kind: None,
+        title: "Assignment used as a condition",
+        matches: &["assignment_in_condition"],
+        explanation: "The condition of an `if` or `while` is an assignment, which is valid but \
+            almost always a typo for `==`. Wrap the assignment in extra parentheses, \
+            `if ((x = 1))`, when it is intended.",
+        example: "int x = 1;\nif (x = 2) { x; }",
+    },
+    ErrorCode {
+        code: "W0002",
+        severity: Severity::Warning,
+        kind: None,
+        title: "Undeclared variable",
+        matches: &["undeclared_variable"],
+        explanation: "A variable is used but never declared, so reading it fails at runtime with \
+            R0102. Its fix declares it.",
+        example: "total = 1;",
+    },
+    ErrorCode {
+        code: "W0003",
+        severity: Severity::Warning,
+        kind: None,
+        title: "Null in arithmetic",
+        matches: &["null_in_arithmetic"],
+        explanation: "`null`, or a variable that is assigned `null`, is an operand of `+`, `-`, \
+            `*`, `/`, `<`, `>`, `<=` or `>=`, which fails at runtime with E0203.",
+        example: "int x = null + 1;",
+    },
+    ErrorCode {
+        code: "W0004",
+        severity: Severity::Warning,
+        kind: None,
+        title: "Integer division truncates",
+        matches: &["integer_truncation"],
+        explanation: "`/` on two ints divides as integers and drops the remainder, so `7 / 2` is 3. \
+            The warning points at such divisions whose quotient is then stored in a float \
+            variable or combined with a float, where the fraction was most likely wanted, \
+            and at divisions of two int literals that leave a remainder. Make one operand a \
+            float, e.g. `7.0 / 2`, to keep the fraction. It also points at divisions by a \
+            variable declared `int` that was given a float or the result of a builtin such as \
+            `json_parse()`: whether they truncate depends on the value it holds when they run.",
+        example: "int total = 7;\nfloat half = total / 2;",
+    },
+];
+
+/// The entry of `code`, e.g. "E0203"; case does not matter.
+pub fn lookup(code: &str) -> Option<&'static ErrorCode> {
+    CODES.iter().find(|entry| entry.code.eq_ignore_ascii_case(code))
+}
+
+/// The code an error of `kind` with `message` is filed under, if any. Only
+/// errors of programs have codes, not those of requests.
+pub fn classify(kind: ErrorKind, message: &str) -> Option<&'static ErrorCode> {
+    CODES
+        .iter()
+        .find(|entry| {
+            entry.kind == Some(kind)
+                && (entry.matches.is_empty() || entry.matches.iter().any(|text| message.contains(text)))
+        })
+}
+
+/// The code of the warnings of the check named `check`.
+pub fn warning(check: &str) -> Option<&'static ErrorCode> {
+    CODES.iter().find(|entry| entry.kind.is_none() && entry.matches.contains(&check))
+}
+
+/// Whether `text` has the shape of an error code, like "E0001", as opposed to
+/// a file name.
+pub fn is_code(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some('E' | 'R' | 'W' | 'e' | 'r' | 'w'))
+        && text.len() == 5
+        && chars.all(|c| c.is_ascii_digit())
+}
+
+/// Explanation of an entry as text, for the command line.
+pub fn to_text(entry: &ErrorCode) -> String {
+    let mut text = format!("{} ({}): {}\n\n{}\n", entry.code, entry.severity.name(), entry.title, entry.explanation);
+    if !entry.example.is_empty() {
+        text.push_str("\nFor example:\n\n");
+        for line in entry.example.lines() {
+            text.push_str(&format!("    {}\n", line));
+        }
+    }
+    text
+}
+
+// Response of GET /explain/{code}
+#[derive(Serialize, ToSchema)]
+pub struct ExplainOutput {
+    #[serde(flatten)]
+    entry: Option<ErrorCode>, // Absent when the code is unknown
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for GET /explain/{code}
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    params(("code" = String, Path, description = "Error code, e.g. E0203, from the `code` of error_details or a diagnostic")),
+    responses(
+        (status = 200, description = "Title, explanation and example of the code", body = ExplainOutput),
+        (status = 404, description = "No such code", body = ExplainOutput),
+    )
+)]
+#[get("/explain/{code}")]
+pub async fn explain(code: web::Path<String>) -> impl Responder {
+    match lookup(&code) {
+        Some(entry) => HttpResponse::Ok().json(ExplainOutput {
+            entry: Some(entry.clone()),
+            error_details: None,
+        }),
+        None => {
+            let details = ErrorDetails::new(ErrorKind::NotFound, format!("Unknown error code '{}'", code));
+            HttpResponse::build(details.kind.status()).json(ExplainOutput {
+                entry: None,
+                error_details: Some(details),
+            })
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// Rename refactoring: the edits that rename a variable everywhere it is used.
+// A rename is refused when it would change what any name refers to, e.g. when
+// the new name is already declared in the same scope, or when a use of the
+// variable would end up shadowed by, or shadowing, another variable.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::builtins;
+use crate::lexer::{Lexer, TokenType};
+use crate::resolver::{SymbolKind, SymbolTable, TextSpan};
+use crate::ErrorDetails;
+
+// Request body of POST /rename
+#[derive(Deserialize, ToSchema)]
+pub struct RenameInput {
+    source: String,
+    cursor: usize, // Character offset of any part of a name of the variable
+    new_name: String,
+}
+
+/// Replacement of one span of the source.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TextEdit {
+    pub span: TextSpan,
+    pub new_text: String,
+}
+
+// Response of POST /rename
+#[derive(Serialize, ToSchema)]
+pub struct RenameOutput {
+    edits: Vec<TextEdit>, // In source order
+    error_details: Option<ErrorDetails>,
+}
+
+/// Error for renames that cannot be done.
+#[derive(Debug)]
+pub struct RenameError {
+    message: String,
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot rename: {}", self.message)
+    }
+}
+
+impl Error for RenameError {}
+
+fn refuse(message: String) -> Box<dyn Error> {
+    Box::new(RenameError { message })
+}
+
+/// Computes the edits renaming the variable under `cursor` to `new_name`.
+pub fn rename(source: &str, cursor: usize, new_name: &str) -> Result<Vec<TextEdit>, Box<dyn Error>> {
+    let table = SymbolTable::build(source)?;
+    let occurrence = table
+        .occurrence_at(cursor)
+        .ok_or_else(|| refuse("there is no variable at the cursor".to_string()))?;
+    let symbol = &table.symbols[occurrence.symbol];
+    if symbol.kind == SymbolKind::Builtin {
+        return Err(refuse(format!("'{}' is a builtin function", symbol.name)));
+    }
+
+    let is_identifier = matches!(
+        Lexer::new(new_name).tokenize().as_deref(),
+        Ok([token, _eof]) if token.token_type == TokenType::Identifier(new_name.into())
+    );
+    if !is_identifier {
+        return Err(refuse(format!("'{}' is not a valid variable name", new_name)));
+    }
+    if builtins::lookup(new_name).is_some() {
+        return Err(refuse(format!("'{}' is the name of a builtin function", new_name)));
+    }
+    if new_name == symbol.name {
+        return Ok(Vec::new());
+    }
+
+    let edits: Vec<TextEdit> = table
+        .occurrences_of(occurrence.symbol)
+        .map(|occurrence| TextEdit {
+            span: occurrence.span,
+            new_text: new_name.to_string(),
+        })
+        .collect();
+
+    // Resolve the renamed program: every name must refer to the same variable as before
+    let renamed = SymbolTable::build(&apply(source, &edits)).map_err(|e| {
+        refuse(format!("renaming '{}' to '{}' makes the program invalid: {}", symbol.name, new_name, e))
+    })?;
+    let mut before_to_after = HashMap::new();
+    let mut after_to_before = HashMap::new();
+    for (before, after) in table.occurrences.iter().zip(&renamed.occurrences) {
+        let consistent = *before_to_after.entry(before.symbol).or_insert(after.symbol) == after.symbol
+            && *after_to_before.entry(after.symbol).or_insert(before.symbol) == before.symbol;
+        if !consistent {
+            return Err(refuse(format!(
+                "renaming '{}' to '{}' would change what the name at {}:{} refers to",
+                symbol.name, new_name, before.span.line, before.span.column
+            )));
+        }
+    }
+
+    Ok(edits)
+}
+
+// Applies non-overlapping edits, sorted by offset, to the source
+fn apply(source: &str, edits: &[TextEdit]) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut result = String::with_capacity(source.len());
+    let mut position = 0;
+    for edit in edits {
+        result.extend(&chars[position..edit.span.offset]);
+        result.push_str(&edit.new_text);
+        position = edit.span.offset + edit.span.length;
+    }
+    result.extend(&chars[position..]);
+    result
+}
+
+// Route handler for POST /rename
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = RenameInput,
+    responses(
+        (status = 200, description = "Edits that rename the variable at the cursor", body = RenameOutput),
+        (status = 400, description = "The source does not compile", body = RenameOutput),
+        (status = 422, description = "No variable at the cursor, invalid new name, or the rename would change the program's meaning", body = RenameOutput),
+    )
+)]
+#[post("/rename")]
+pub async fn rename_symbol(input: web::Json<RenameInput>) -> impl Responder {
+    match rename(&input.source, input.cursor, &input.new_name) {
+        Ok(edits) => HttpResponse::Ok().json(RenameOutput {
+            edits,
+            error_details: None,
+        }),
+        Err(e) => {
+            let details = ErrorDetails::from_error(e.as_ref());
+            HttpResponse::build(details.kind.status()).json(RenameOutput {
+                edits: Vec::new(),
+                error_details: Some(details),
+            })
+        }
+    }
+}
@@ -0,0 +1,264 @@
+// Incremental parsing for editors that send small edits as the user types.
+//
+// After an edit only the lines around it are lexed again. The other tokens are
+// kept, moved by the size of the edit, once the lexer is known to be in the
+// same state on both sides of the re-lexed text as before (not inside a block
+// comment), so the text that follows is lexed exactly as it was.
+//
+// The tokens are then split into top-level statements, and only statements
+// whose text changed are parsed again. The syntax tree holds no positions, so
+// a statement's tree depends on its text alone and can be cached by it.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use utoipa::ToSchema;
+
+use crate::lexer::{scan, LexState, Lexer, Token, TokenType};
+use crate::parser::{ASTNode, Parser};
+
+/// Replacement of the bytes `start..end` of a source by `text`.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SourceEdit {
+    pub start: usize, // Byte offset
+    pub end: usize,   // Byte offset, exclusive
+    #[serde(default)]
+    pub text: String,
+}
+
+/// What the last parse had to do.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseReport {
+    pub items: usize,    // Top-level statements in the program
+    pub reparsed: usize, // Statements parsed again instead of taken from the cache
+    pub relexed: usize,  // Bytes lexed since the previous successful parse
+}
+
+/// Parser state kept for one document between edits.
+#[derive(Default)]
+pub struct IncrementalParser {
+    source: String,
+    tokens: Option<Vec<Token<'static>>>, // Owning their text, to outlive edits; None when the source does not lex
+    cache: HashMap<String, Vec<ASTNode>>, // Statement text -> its syntax tree
+    relexed: usize,
+}
+
+impl IncrementalParser {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Tokens of the source, or None when it does not lex.
+    pub fn tokens(&self) -> Option<&[Token<'static>]> {
+        self.tokens.as_deref()
+    }
+
+    /// Replaces the whole source. Statements that did not change are still
+    /// taken from the cache by the next parse.
+    pub fn set_source(&mut self, source: &str) {
+        if self.tokens.is_some() && self.source == source {
+            return;
+        }
+        self.source = source.to_string();
+        self.tokens = lex(source);
+        self.relexed += source.len();
+    }
+
+    /// Applies an edit, lexing again only the lines around it.
+    pub fn edit(&mut self, edit: &SourceEdit) -> Result<(), String> {
+        let old = &self.source;
+        if edit.start > edit.end || edit.end > old.len() {
+            return Err(format!(
+                "Edit {}..{} is outside the document ({} bytes)",
+                edit.start,
+                edit.end,
+                old.len()
+            ));
+        }
+        if !old.is_char_boundary(edit.start) || !old.is_char_boundary(edit.end) {
+            return Err(format!("Edit {}..{} splits a character", edit.start, edit.end));
+        }
+
+        let source = format!("{}{}{}", &old[..edit.start], edit.text, &old[edit.end..]);
+        self.tokens = match self.tokens.take() {
+            Some(tokens) => self.relex(tokens, &source, edit),
+            None => None,
+        };
+        if self.tokens.is_none() {
+            self.tokens = lex(&source);
+            self.relexed += source.len();
+        }
+        self.source = source;
+        Ok(())
+    }
+
+    // Tokens of `source`, the old source with the edit applied, from the old
+    // tokens and a re-lexed window around the edit
+    fn relex(&mut self, tokens: Vec<Token<'static>>, source: &str, edit: &SourceEdit) -> Option<Vec<Token<'static>>> {
+        let old = self.source.as_bytes();
+        let new = source.as_bytes();
+        let moved = |offset: usize| offset - edit.end + edit.start + edit.text.len();
+
+        // The window starts at the start of the edited line, or of a block
+        // comment still open there. The lexer reads code right after a token.
+        let line_start = old[..edit.start].iter().rposition(|byte| *byte == b'\n').map_or(0, |index| index + 1);
+        let before = tokens.partition_point(|token| {
+            token.token_type != TokenType::EOF && token.offset + token.length <= line_start
+        });
+        let (from, line, column) = match before.checked_sub(1).map(|index| &tokens[index]) {
+            Some(token) => {
+                let end = token.offset + token.length;
+                (end, token.line, token.column + self.source[token.offset..end].chars().count())
+            }
+            None => (0, 1, 1),
+        };
+        let (window_start, line, column) = match scan(old, from, line_start, LexState::Code, line, column) {
+            (LexState::BlockComment { offset, line, column }, ..) => (offset, line, column),
+            (_, line, column) => (line_start, line, column),
+        };
+
+        // The window ends at the start of a line after the edit where neither
+        // the old nor the new text is inside a block comment
+        let mut old_state = (window_start, LexState::Code);
+        let mut new_state = (window_start, LexState::Code);
+        let mut old_end = edit.end;
+        loop {
+            old_end = old[old_end..].iter().position(|byte| *byte == b'\n').map_or(old.len(), |index| old_end + index + 1);
+            if old_end == old.len() {
+                break;
+            }
+            old_state = (old_end, scan(old, old_state.0, old_end, old_state.1, 1, 1).0);
+            new_state = (moved(old_end), scan(new, new_state.0, moved(old_end), new_state.1, 1, 1).0);
+            if !matches!(old_state.1, LexState::BlockComment { .. }) && !matches!(new_state.1, LexState::BlockComment { .. }) {
+                break;
+            }
+        }
+        let new_end = if old_end == old.len() { new.len() } else { moved(old_end) };
+
+        let mut fresh = Lexer::starting_at(&source[window_start..new_end], line, column, window_start)
+            .tokenize()
+            .ok()?;
+        self.relexed += new_end - window_start;
+        if new_end < new.len() {
+            fresh.pop(); // EOF of the window
+        }
+
+        let removed_lines = old[edit.start..edit.end].iter().filter(|byte| **byte == b'\n').count();
+        let added_lines = edit.text.matches('\n').count();
+        let mut spliced: Vec<Token> = tokens.iter().take_while(|token| token.offset < window_start).cloned().collect();
+        spliced.retain(|token| token.token_type != TokenType::EOF);
+        spliced.extend(fresh.into_iter().map(Token::into_owned));
+        if old_end < old.len() {
+            spliced.extend(tokens.into_iter().filter(|token| token.offset >= old_end).map(|mut token| {
+                token.offset = moved(token.offset);
+                token.line = token.line - removed_lines + added_lines;
+                token
+            }));
+        }
+        Some(spliced)
+    }
+
+    /// Parses the current source, reusing the trees of unchanged statements.
+    pub fn parse(&mut self) -> Result<(ASTNode, ParseReport), Box<dyn Error>> {
+        if self.tokens.is_none() {
+            // Fails with the lexer error
+            self.tokens = Some(Lexer::new(&self.source).tokenize()?.into_iter().map(Token::into_owned).collect());
+        }
+        let Some(tokens) = &self.tokens else {
+            return Err("Source does not lex".into());
+        };
+
+        let mut report = ParseReport::default();
+        let mut cache = HashMap::new();
+        let mut program = Vec::new();
+        for statement in split_statements(tokens) {
+            let (Some(first), Some(last)) = (statement.first(), statement.last()) else {
+                continue;
+            };
+            let text = self.source[first.offset..last.offset + last.length].to_string();
+            let items = match self.cache.remove(&text).or_else(|| cache.get(&text).cloned()) {
+                Some(items) => items,
+                None => {
+                    report.reparsed += 1;
+                    match Parser::new(statement.to_vec()).parse()? {
+                        ASTNode::Program(items) => items,
+                        item => vec![item],
+                    }
+                }
+            };
+            report.items += 1;
+            program.extend(items.iter().cloned());
+            cache.insert(text, items);
+        }
+
+        self.cache = cache;
+        report.relexed = std::mem::take(&mut self.relexed);
+        Ok((ASTNode::Program(program), report))
+    }
+}
+
+// Tokens of `source` owning their text, or None when it does not lex
+fn lex(source: &str) -> Option<Vec<Token<'static>>> {
+    let tokens = Lexer::new(source).tokenize().ok()?;
+    Some(tokens.into_iter().map(Token::into_owned).collect())
+}
+
+/// Splits tokens into top-level statements: a statement ends with a `;` or
+/// `}` outside braces, unless an `else` follows or the `}` closes a block
+/// expression that the statement continues after, as in `int x = { 1 } + 2;`.
+pub fn split_statements<'t, 'a>(tokens: &'t [Token<'a>]) -> Vec<&'t [Token<'a>]> {
+    let mut statements = Vec::new();
+    let mut depth = 0usize;
+    let mut first = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token.token_type {
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth = depth.saturating_sub(1),
+            TokenType::EOF => break,
+            _ => {}
+        }
+        let ends = depth == 0
+            && match token.token_type {
+                TokenType::Semicolon => true,
+                TokenType::RightBrace => !tokens.get(index + 1).is_some_and(|next| continues(&next.token_type)),
+                _ => false,
+            };
+        if ends {
+            statements.push(&tokens[first..=index]);
+            first = index + 1;
+        }
+    }
+
+    let end = tokens.iter().position(|token| token.token_type == TokenType::EOF).unwrap_or(tokens.len());
+    if first < end {
+        statements.push(&tokens[first..end]);
+    }
+    statements
+}
+
+// Whether a token after a `}` carries on the statement. Splitting a little
+// too late only parses two statements together, so `-` counts, though a
+// block statement may be followed by a statement starting with it
+fn continues(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::Else
+            | TokenType::Semicolon
+            | TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::Comma
+            | TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Multiply
+            | TokenType::Divide
+            | TokenType::Assign
+            | TokenType::Equal
+            | TokenType::NotEqual
+            | TokenType::LessThan
+            | TokenType::GreaterThan
+            | TokenType::LessEqual
+            | TokenType::GreaterEqual
+            | TokenType::And
+            | TokenType::Or
+    )
+}
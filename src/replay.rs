@@ -0,0 +1,181 @@
+// Replays: a run made with `"record": true` returns a bundle holding the
+// program, its input and its nondeterministic inputs (the seed of random()
+// and every clock reading). POST /replay runs the bundle again with the same
+// inputs, so a failure seen on the server can be repeated and debugged.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[cfg(feature = "server")]
+use crate::executions::ExecutionRegistry;
+#[cfg(feature = "server")]
+use crate::limits::ExecutionLimits;
+use crate::pipeline::Mode;
+use crate::semantic::StrictMode;
+#[cfg(feature = "server")]
+use crate::sessions::SessionStore;
+use crate::vm::{ClockRead, Recording};
+#[cfg(feature = "server")]
+use crate::worker::WorkerJob;
+use crate::{CodeInput, CodeOutput};
+#[cfg(feature = "server")]
+use crate::{client_of, dispatch_request, ErrorKind, ServerConfig};
+
+/// Version of the bundle format; bundles of other versions are refused.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// A recorded run: what it ran, with which inputs, and what came out.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ReplayBundle {
+    pub version: u32,
+    #[serde(default)]
+    pub source: String,           // Empty when an artifact ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<String>, // Base64 artifact, for runs of /execute
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub language: String,         // "beginner" for the beginner dialect
+    #[serde(default, skip_serializing_if = "Mode::is_program")]
+    pub mode: Mode,
+    #[serde(default)]
+    pub opt_level: u8,
+    #[serde(default, skip_serializing_if = "StrictMode::is_off")]
+    pub strict: StrictMode,
+    #[serde(default)]
+    pub stdin: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>, // Files given to the run, not those it wrote
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub globals: BTreeMap<String, serde_json::Value>, // Globals defined before the run
+    pub seed: u64,                // Seed of random(), also when none was requested
+    #[serde(default)]
+    pub clock_reads: Vec<ClockRead>, // Values returned by clock_ms() and now(), in order
+    pub result: String,           // Outcome of the recorded run
+    pub error: Option<String>,
+}
+
+impl ReplayBundle {
+    /// Bundle of a finished run of `input`; the artifact is added by the caller.
+    pub fn new(input: &CodeInput, recording: &Recording, output: &CodeOutput) -> Self {
+        ReplayBundle {
+            version: REPLAY_VERSION,
+            source: input.source.clone(),
+            artifact: None,
+            language: input.language.clone(),
+            mode: input.mode,
+            opt_level: input.opt_level,
+            strict: input.strict,
+            stdin: input.stdin.clone(),
+            env: input.env.clone(),
+            files: input.files.clone(),
+            globals: input.globals.clone(),
+            seed: recording.seed,
+            clock_reads: recording.clock_reads.clone(),
+            result: output.result.clone(),
+            error: output.error.clone(),
+        }
+    }
+}
+
+// Response of POST /replay
+#[cfg(feature = "server")]
+#[derive(Serialize, ToSchema)]
+pub struct ReplayOutput {
+    #[serde(flatten)]
+    output: CodeOutput,       // The replayed run, as /compile reports it
+    reproduced: bool,         // Whether it ended as the recorded run did
+    differences: Vec<String>, // How it differs from the recorded run
+}
+
+// Route handler for POST /replay
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = ReplayBundle,
+    responses(
+        (status = 200, description = "Bundle replayed", body = ReplayOutput),
+        (status = 400, description = "The replayed program failed", body = ReplayOutput),
+        (status = 422, description = "Malformed bundle, or a bundle of another version", body = ReplayOutput),
+    )
+)]
+#[post("/replay")]
+pub async fn replay(
+    bundle: web::Json<ReplayBundle>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+    request: HttpRequest,
+) -> impl Responder {
+    let bundle = bundle.into_inner();
+    if bundle.version != REPLAY_VERSION {
+        let output = CodeOutput::failure(
+            ErrorKind::InvalidInput,
+            format!("Replay bundle version {} is not supported (expected {})", bundle.version, REPLAY_VERSION),
+        );
+        return HttpResponse::build(output.status()).json(ReplayOutput {
+            output,
+            reproduced: false,
+            differences: Vec::new(),
+        });
+    }
+
+    let input = CodeInput {
+        source: bundle.source.clone(),
+        language: bundle.language.clone(),
+        mode: bundle.mode,
+        inspect_variables: false,
+        seed: Some(bundle.seed),
+        stdin: bundle.stdin.clone(),
+        env: bundle.env.clone(),
+        files: bundle.files.clone(),
+        globals: bundle.globals.clone(),
+        return_variables: Vec::new(),
+        document: None,
+        opt_level: bundle.opt_level,
+        strict: bundle.strict,
+        verbose: false,
+        session: None,
+        limits: ExecutionLimits::default(),
+        profile: None,
+        record: true,
+        replay_clock: Some(bundle.clock_reads.clone()),
+        locale: None,
+    };
+    let job = WorkerJob {
+        artifact: bundle.artifact.clone(),
+        ..WorkerJob::new(input, config.policy.clone())
+    };
+    let output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+
+    let differences = differences(&bundle, &output);
+    HttpResponse::build(output.status()).json(ReplayOutput {
+        reproduced: differences.is_empty(),
+        differences,
+        output,
+    })
+}
+
+// Ways in which a replayed run differs from the recorded one
+#[cfg(feature = "server")]
+fn differences(bundle: &ReplayBundle, output: &CodeOutput) -> Vec<String> {
+    let mut differences = Vec::new();
+    if output.result != bundle.result {
+        differences.push(format!("result: recorded {:?}, replayed {:?}", bundle.result, output.result));
+    }
+    if output.error != bundle.error {
+        differences.push(format!("error: recorded {:?}, replayed {:?}", bundle.error, output.error));
+    }
+    let reads = output.replay.as_ref().map_or(0, |replayed| replayed.clock_reads.len());
+    if reads < bundle.clock_reads.len() {
+        differences.push(format!(
+            "clock: read {} time(s), {} recorded",
+            reads,
+            bundle.clock_reads.len()
+        ));
+    }
+    differences
+}
@@ -0,0 +1,393 @@
+// Name resolution for editor features: links every identifier in a program to
+// the variable, function or builtin it names, with the position of each
+// occurrence.
+//
+// It follows the scoping rules of the bytecode generator. Blocks open a scope,
+// a declaration takes effect after its initializer, locals shadow outer
+// variables, and globals are shared by name, so all top-level declarations of
+// a name are one variable. A call to a builtin's name always calls the builtin,
+// and a call to a declared function's name the function, wherever it is
+// declared. The parameters of a function are in a scope of their own, around
+// its body. The program must parse; resolution then only needs its tokens.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::builtins;
+use crate::lexer::{Lexer, Token, TokenType};
+use crate::parser::Parser;
+
+/// Position of a name in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct TextSpan {
+    pub line: usize,   // 1-based
+    pub column: usize, // 1-based, in characters
+    pub offset: usize, // Characters from the start of the source
+    pub length: usize, // In characters
+}
+
+impl TextSpan {
+    /// Whether a cursor at `offset` touches the span, including just after its end.
+    pub fn contains(&self, offset: usize) -> bool {
+        (self.offset..=self.offset + self.length).contains(&offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolKind {
+    Global,
+    Local,
+    Parameter,
+    Function,
+    Builtin,
+}
+
+/// A variable, function or builtin function named in the program.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub detail: String,              // Declared type of a variable, signature of a function or builtin
+    pub declarations: Vec<TextSpan>, // Empty for builtins and globals that are never declared
+}
+
+/// One appearance of a name.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    pub span: TextSpan,
+    pub symbol: usize, // Index into SymbolTable::symbols
+}
+
+/// Every name in a program, resolved.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>,
+    pub occurrences: Vec<Occurrence>, // In source order
+}
+
+/// Error for programs whose names cannot be resolved.
+#[derive(Debug)]
+pub struct ResolveError {
+    message: String,
+    line: usize,
+    column: usize,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Resolve error at {}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for ResolveError {}
+
+impl ResolveError {
+    /// Line (1-based) of the offending name.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column (1-based) of the offending name.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl SymbolTable {
+    /// Parses the source, to reject invalid programs, and resolves its names.
+    pub fn build(source: &str) -> Result<Self, Box<dyn Error>> {
+        let tokens = Lexer::new(source).tokenize()?;
+        Parser::new(tokens.clone()).parse()?;
+        Ok(resolve(source, &tokens)?)
+    }
+
+    /// The occurrence under a cursor, given as a character offset.
+    pub fn occurrence_at(&self, offset: usize) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| occurrence.span.contains(offset))
+    }
+
+    /// All occurrences of a symbol, in source order.
+    pub fn occurrences_of(&self, symbol: usize) -> impl Iterator<Item = &Occurrence> {
+        self.occurrences.iter().filter(move |occurrence| occurrence.symbol == symbol)
+    }
+}
+
+/// Resolves the names in the tokens of a program that parses.
+pub fn resolve(source: &str, tokens: &[Token]) -> Result<SymbolTable, ResolveError> {
+    let mut resolver = Resolver {
+        line_starts: line_starts(source),
+        table: SymbolTable::default(),
+        globals: HashMap::new(),
+        functions: HashMap::new(),
+        builtins: HashMap::new(),
+        scopes: Vec::new(),
+        pending: None,
+        parameters: None,
+        in_parameters: false,
+    };
+    resolver.declare_functions(tokens);
+
+    for (index, token) in tokens.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|i| &tokens[i].token_type);
+        match &token.token_type {
+            // The parameters of a function, in their own scope
+            TokenType::LeftParen if index >= 2 && tokens[index - 2].token_type == TokenType::Fn => {
+                resolver.scopes.push(Vec::new());
+                resolver.parameters = Some(resolver.scopes.len());
+                resolver.in_parameters = true;
+            }
+            TokenType::RightParen => resolver.in_parameters = false,
+            TokenType::LeftBrace => resolver.scopes.push(Vec::new()),
+            TokenType::RightBrace => {
+                resolver.scopes.pop();
+                // The body of a function ends with the scope of its parameters
+                if resolver.parameters == Some(resolver.scopes.len()) {
+                    resolver.scopes.pop();
+                    resolver.parameters = None;
+                }
+            }
+            TokenType::Semicolon => resolver.finish_declaration()?,
+            TokenType::Identifier(name) if resolver.in_parameters => {
+                let span = resolver.span(token, name);
+                resolver.declare_parameter(name, span);
+            }
+            TokenType::Identifier(name) => {
+                let span = resolver.span(token, name);
+                let next = tokens.get(index + 1).map(|t| &t.token_type);
+                // Loop labels and the `test` starting a test block are not variables
+                let test_block = name == "test" && matches!(next, Some(TokenType::StringLiteral(_)));
+                if test_block || next == Some(&TokenType::Colon) || matches!(previous, Some(TokenType::Break | TokenType::Continue)) {
+                    continue;
+                }
+                match previous {
+                    Some(TokenType::Int) => resolver.start_declaration(name, "int", span),
+                    Some(TokenType::Float) => resolver.start_declaration(name, "float", span),
+                    _ if next == Some(&TokenType::LeftParen) && builtins::lookup(name).is_some() => {
+                        let symbol = resolver.builtin(name);
+                        resolver.occur(span, symbol);
+                    }
+                    // Declarations of functions were recorded up front
+                    Some(TokenType::Fn) => {}
+                    _ if next == Some(&TokenType::LeftParen) && resolver.functions.contains_key(name.as_ref()) => {
+                        let symbol = resolver.functions[name.as_ref()];
+                        resolver.occur(span, symbol);
+                    }
+                    _ => {
+                        let symbol = resolver.lookup(name);
+                        resolver.occur(span, symbol);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Declarations are recorded at the end of their initializer
+    resolver.table.occurrences.sort_by_key(|occurrence| occurrence.span.offset);
+    Ok(resolver.table)
+}
+
+struct Resolver {
+    line_starts: Vec<usize>, // Character offset of the start of each line
+    table: SymbolTable,
+    globals: HashMap<String, usize>,
+    functions: HashMap<String, usize>,
+    builtins: HashMap<String, usize>,
+    scopes: Vec<Vec<(String, usize)>>, // Locals of each enclosing block, or parameters of a function
+    pending: Option<(String, String, TextSpan)>, // Declaration waiting for the end of its initializer
+    parameters: Option<usize>, // Depth of the scope of the parameters of the function being resolved
+    in_parameters: bool,       // Between the parentheses of a function declaration
+}
+
+impl Resolver {
+    fn span(&self, token: &Token, name: &str) -> TextSpan {
+        TextSpan {
+            line: token.line,
+            column: token.column,
+            offset: self.line_starts.get(token.line - 1).copied().unwrap_or(0) + token.column - 1,
+            length: name.chars().count(),
+        }
+    }
+
+    // Records the functions declared at the top level, as they can be called
+    // before their declaration
+    fn declare_functions(&mut self, tokens: &[Token]) {
+        let mut depth = 0usize;
+        for (index, token) in tokens.iter().enumerate() {
+            match &token.token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth = depth.saturating_sub(1),
+                TokenType::Fn if depth == 0 => {
+                    let Some(Token { token_type: TokenType::Identifier(name), .. }) = tokens.get(index + 1) else {
+                        continue;
+                    };
+                    let params: Vec<&str> = tokens[index + 2..]
+                        .iter()
+                        .take_while(|token| token.token_type != TokenType::RightParen)
+                        .filter_map(|token| match &token.token_type {
+                            TokenType::Identifier(param) => Some(param.as_ref()),
+                            _ => None,
+                        })
+                        .collect();
+                    let span = self.span(&tokens[index + 1], name);
+                    let symbol = match self.functions.get(name.as_ref()) {
+                        Some(&symbol) => symbol,
+                        None => {
+                            let signature = format!("fn {}({})", name, params.join(", "));
+                            let symbol = self.add_symbol(name, SymbolKind::Function, &signature);
+                            self.functions.insert(name.to_string(), symbol);
+                            symbol
+                        }
+                    };
+                    self.table.symbols[symbol].declarations.push(span);
+                    self.occur(span, symbol);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn declare_parameter(&mut self, name: &str, span: TextSpan) {
+        let symbol = self.add_symbol(name, SymbolKind::Parameter, "");
+        self.table.symbols[symbol].declarations.push(span);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.to_string(), symbol));
+        }
+        self.occur(span, symbol);
+    }
+
+    fn start_declaration(&mut self, name: &str, var_type: &str, span: TextSpan) {
+        self.pending = Some((name.to_string(), var_type.to_string(), span));
+    }
+
+    fn finish_declaration(&mut self) -> Result<(), ResolveError> {
+        let Some((name, var_type, span)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        let symbol = match self.scopes.last() {
+            None => {
+                let symbol = self.global(&name, &var_type);
+                self.table.symbols[symbol].declarations.push(span);
+                symbol
+            }
+            Some(scope) => {
+                if scope.iter().any(|(local, _)| *local == name) {
+                    return Err(ResolveError {
+                        message: format!("Variable '{}' already declared in this scope", name),
+                        line: span.line,
+                        column: span.column,
+                    });
+                }
+                let symbol = self.add_symbol(&name, SymbolKind::Local, &var_type);
+                self.table.symbols[symbol].declarations.push(span);
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.push((name, symbol));
+                }
+                symbol
+            }
+        };
+        self.occur(span, symbol);
+        Ok(())
+    }
+
+    fn lookup(&mut self, name: &str) -> usize {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(local, _)| local == name)
+            .map(|(_, symbol)| *symbol);
+        local.unwrap_or_else(|| self.global(name, ""))
+    }
+
+    fn global(&mut self, name: &str, var_type: &str) -> usize {
+        if let Some(&symbol) = self.globals.get(name) {
+            if self.table.symbols[symbol].detail.is_empty() {
+                self.table.symbols[symbol].detail = var_type.to_string();
+            }
+            return symbol;
+        }
+        let symbol = self.add_symbol(name, SymbolKind::Global, var_type);
+        self.globals.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    fn builtin(&mut self, name: &str) -> usize {
+        if let Some(&symbol) = self.builtins.get(name) {
+            return symbol;
+        }
+        let signature = builtins::lookup(name).map_or("", |builtin| builtin.signature);
+        let symbol = self.add_symbol(name, SymbolKind::Builtin, signature);
+        self.builtins.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    fn add_symbol(&mut self, name: &str, kind: SymbolKind, detail: &str) -> usize {
+        self.table.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            detail: detail.to_string(),
+            declarations: Vec::new(),
+        });
+        self.table.symbols.len() - 1
+    }
+
+    fn occur(&mut self, span: TextSpan, symbol: usize) {
+        self.table.occurrences.push(Occurrence { span, symbol });
+    }
+}
+
+// Character offset at which each line starts
+fn line_starts(source: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(
+        source
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '\n')
+            .map(|(index, _)| index + 1),
+    );
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "fn add(a, b) { return a + b; }\nfn twice(a) { int t = add(a, a); return t; }\nint x = twice(1);\n";
+
+    fn symbol_at(table: &SymbolTable, offset: usize) -> &Symbol {
+        &table.symbols[table.occurrence_at(offset).expect("a name at the offset").symbol]
+    }
+
+    #[test]
+    fn functions_and_parameters_are_their_own_symbols() {
+        let table = SymbolTable::build(PROGRAM).unwrap();
+
+        let add = symbol_at(&table, PROGRAM.find("add").unwrap());
+        assert_eq!(add.kind, SymbolKind::Function);
+        assert_eq!(add.detail, "fn add(a, b)");
+        assert_eq!(add.declarations.len(), 1);
+
+        // The call in `twice` is the same symbol as the declaration
+        let call = table.occurrence_at(PROGRAM.find("add(a, a)").unwrap()).unwrap().symbol;
+        assert_eq!(table.occurrence_at(PROGRAM.find("add").unwrap()).unwrap().symbol, call);
+
+        // Each function has its own `a`, declared in its parameter list
+        let first = table.occurrence_at(PROGRAM.find("a + b").unwrap()).unwrap().symbol;
+        let second = table.occurrence_at(PROGRAM.find("a, a)").unwrap()).unwrap().symbol;
+        assert_ne!(first, second);
+        assert_eq!(table.symbols[first].kind, SymbolKind::Parameter);
+        assert_eq!(table.occurrences_of(first).count(), 2);
+        assert_eq!(table.occurrences_of(second).count(), 3);
+
+        // Parameters are out of scope after the body
+        assert!(table.symbols.iter().all(|symbol| symbol.kind != SymbolKind::Global || symbol.name == "x"));
+    }
+}
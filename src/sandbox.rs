@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::builtins::Builtin;
+
+/// Access to the outside world that a native builtin may need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Time,
+    Random,
+    Stdin,
+    Env,
+    FileSystem,
+    Network,
+}
+
+impl Capability {
+    const ALL: [Capability; 6] = [
+        Capability::Time,
+        Capability::Random,
+        Capability::Stdin,
+        Capability::Env,
+        Capability::FileSystem,
+        Capability::Network,
+    ];
+
+    /// Name used in configuration and error messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Time => "time",
+            Capability::Random => "random",
+            Capability::Stdin => "stdin",
+            Capability::Env => "env",
+            Capability::FileSystem => "fs",
+            Capability::Network => "network",
+        }
+    }
+}
+
+/// Name of the restriction to constant-time builtins in capability lists.
+const CONSTANT_TIME: &str = "constant_time";
+
+/// Permissions granted to a program. Builtins that need a capability
+/// are refused at call time unless the policy allows it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SandboxPolicy {
+    pub allow_time: bool,
+    pub allow_random: bool,
+    pub allow_stdin: bool,
+    pub allow_env: bool,     // getenv(), which only reads the variables of the request
+    pub allow_fs: bool,      // read_file(), write_file() and append_file()
+    pub allow_network: bool, // Reserved for network builtins
+    // Only builtins whose running time cannot reveal their arguments, for
+    // code handling secrets; see `Builtin::constant_time`
+    #[serde(default)]
+    pub constant_time: bool,
+}
+
+impl Default for SandboxPolicy {
+    /// Safe for untrusted code: no network access, and the file builtins
+    /// only see the in-memory files of the run (see `vfs`).
+    fn default() -> Self {
+        SandboxPolicy {
+            allow_time: true,
+            allow_random: true,
+            allow_stdin: true,
+            allow_env: true,
+            allow_fs: true,
+            allow_network: false,
+            constant_time: false,
+        }
+    }
+}
+
+impl SandboxPolicy {
+    /// Policy granting nothing.
+    pub fn deny_all() -> Self {
+        SandboxPolicy {
+            allow_time: false,
+            allow_random: false,
+            allow_stdin: false,
+            allow_env: false,
+            allow_fs: false,
+            allow_network: false,
+            constant_time: false,
+        }
+    }
+
+    /// Parses a comma-separated list of capability names, e.g. `"time,random"`.
+    /// Capabilities that are not listed are denied. `constant_time` in the
+    /// list restricts the policy to the constant-time builtins.
+    pub fn from_list(list: &str) -> Result<Self, String> {
+        let mut policy = SandboxPolicy::deny_all();
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if name == CONSTANT_TIME {
+                policy.constant_time = true;
+                continue;
+            }
+            let capability = Capability::ALL
+                .iter()
+                .copied()
+                .find(|capability| capability.name() == name)
+                .ok_or_else(|| format!("Unknown capability: {}", name))?;
+            policy.set(capability, true);
+        }
+        Ok(policy)
+    }
+
+    /// Whether the policy lets a program call `builtin`, or the reason it
+    /// doesn't.
+    pub fn check(&self, builtin: &Builtin) -> Result<(), String> {
+        if let Some(capability) = builtin.capability.filter(|capability| !self.allows(*capability)) {
+            return Err(format!(
+                "Permission denied: {}() requires the '{}' capability",
+                builtin.name,
+                capability.name()
+            ));
+        }
+        if self.constant_time && !builtin.constant_time {
+            return Err(format!(
+                "Permission denied: {}() may reveal its arguments through its running time, and only constant-time builtins are allowed",
+                builtin.name
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Time => self.allow_time,
+            Capability::Random => self.allow_random,
+            Capability::Stdin => self.allow_stdin,
+            Capability::Env => self.allow_env,
+            Capability::FileSystem => self.allow_fs,
+            Capability::Network => self.allow_network,
+        }
+    }
+
+    fn set(&mut self, capability: Capability, allowed: bool) {
+        match capability {
+            Capability::Time => self.allow_time = allowed,
+            Capability::Random => self.allow_random = allowed,
+            Capability::Stdin => self.allow_stdin = allowed,
+            Capability::Env => self.allow_env = allowed,
+            Capability::FileSystem => self.allow_fs = allowed,
+            Capability::Network => self.allow_network = allowed,
+        }
+    }
+}
+
+impl fmt::Display for SandboxPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut granted: Vec<&str> = Capability::ALL
+            .iter()
+            .filter(|capability| self.allows(**capability))
+            .map(|capability| capability.name())
+            .collect();
+        if self.constant_time {
+            granted.push("constant-time builtins only");
+        }
+
+        if granted.is_empty() {
+            write!(f, "(none)")
+        } else {
+            write!(f, "{}", granted.join(", "))
+        }
+    }
+}
@@ -0,0 +1,263 @@
+// Scheduled runs of stored programs. A program uploaded with a `schedule`, a
+// cron expression, is run by the server at every minute the expression
+// matches, as an invocation without arguments would run it, and the outcome
+// of each run is kept for GET /programs/{name}/runs. Runs of a program never
+// overlap: a minute that comes while its previous run is still going is
+// skipped. Times are in UTC.
+
+use actix_web::web;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use crate::limits::ExecutionLimits;
+use crate::pipeline::Mode;
+use crate::programs::ProgramStore;
+use crate::semantic::StrictMode;
+use crate::worker::WorkerJob;
+use crate::{dispatch_request, CodeInput, CodeOutput, ErrorDetails, ExecutionRegistry, ServerConfig, SessionStore};
+
+/// Days searched for the next run of a schedule; covers a leap day.
+const SEARCH_DAYS: u64 = 5 * 366;
+
+/// Time limit of a scheduled run, unless the server's is lower; without it a
+/// program stuck in a loop would never run on its schedule again.
+const RUN_TIMEOUT_MS: u64 = 60_000;
+
+/// Characters of a run's output kept in its record.
+const MAX_RUN_OUTPUT: usize = 4096;
+
+/// When a program runs: a cron expression of five fields, minute, hour, day
+/// of the month, month and day of the week (0 or 7 being Sunday), each a
+/// list of `*`, `n` or `a-b`, optionally with a step `/s`. `@hourly`,
+/// `@daily`, `@weekly`, `@monthly` and `@yearly` are shorthands. As in cron,
+/// when both days are restricted a day matching either of them matches.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    expression: String,
+    minutes: u64, // Bit n is set when n matches
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,     // The day of the month is `*`
+    any_weekday: bool, // The day of the week is `*`
+}
+
+// Day of the month, month and day of the week of a day since 1970-01-01
+struct Date {
+    day: u64,
+    month: u64,
+    weekday: u64,
+}
+
+impl Date {
+    // Civil date of `days` after the epoch, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    fn from_days(days: u64) -> Self {
+        let days = days + 719_468;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        Date {
+            day: day_of_year - (153 * month_from_march + 2) / 5 + 1,
+            month: if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 },
+            weekday: (days - 719_468 + 4) % 7, // The epoch was a Thursday
+        }
+    }
+}
+
+impl Schedule {
+    /// Parses a cron expression, refusing one that never matches, such as
+    /// the 30th of February.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let expression = expression.trim();
+        let expanded = match expression {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Schedule '{}' must have 5 fields: minute, hour, day, month and weekday",
+                expression
+            ));
+        }
+        let field = |index: usize, name: &str, min: u64, max: u64| {
+            parse_field(fields[index], min, max).map_err(|e| format!("Schedule '{}': {} {}", expression, name, e))
+        };
+        let weekdays = field(4, "weekday", 0, 7)?;
+        let schedule = Schedule {
+            expression: expression.to_string(),
+            minutes: field(0, "minute", 0, 59)?,
+            hours: field(1, "hour", 0, 23)?,
+            days: field(2, "day", 1, 31)?,
+            months: field(3, "month", 1, 12)?,
+            weekdays: (weekdays | weekdays >> 7) & 0x7f, // 7 is Sunday as well
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        };
+        match schedule.next_after(0) {
+            Some(_) => Ok(schedule),
+            None => Err(format!("Schedule '{}' never runs", expression)),
+        }
+    }
+
+    /// The expression, as given.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// Whether the program runs at `time`, the start of a minute in Unix time.
+    pub fn matches(&self, time: u64) -> bool {
+        let minute = time / 60 % 60;
+        let hour = time / 3_600 % 24;
+        has(self.minutes, minute) && has(self.hours, hour) && self.matches_day(&Date::from_days(time / 86_400))
+    }
+
+    /// The first minute after `time` at which the program runs, in Unix time.
+    pub fn next_after(&self, time: u64) -> Option<u64> {
+        let first_day = time / 86_400;
+        (first_day..first_day + SEARCH_DAYS)
+            .filter(|&day| self.matches_day(&Date::from_days(day)))
+            .find_map(|day| {
+                let hours = (0..24).filter(|&hour| has(self.hours, hour));
+                hours
+                    .flat_map(|hour| (0..60).filter(|&minute| has(self.minutes, minute)).map(move |minute| hour * 60 + minute))
+                    .map(|minute| day * 86_400 + minute * 60)
+                    .find(|&start| start > time)
+            })
+    }
+
+    fn matches_day(&self, date: &Date) -> bool {
+        let day = has(self.days, date.day);
+        let weekday = has(self.weekdays, date.weekday);
+        let day_matches = if self.any_day || self.any_weekday { day && weekday } else { day || weekday };
+        has(self.months, date.month) && day_matches
+    }
+}
+
+fn has(bits: u64, value: u64) -> bool {
+    bits & (1 << value) != 0
+}
+
+// Values matched by one field of a cron expression, as bits
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let number = |text: &str| text.parse::<u64>().map_err(|_| format!("'{}' is not a number", text));
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, number(step)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // "n/s" runs from n to the end of the field
+            None if part.contains('/') => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if step == 0 {
+            return Err(format!("'{}' has a step of 0", part));
+        }
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Outcome of a scheduled run, as listed by GET /programs/{name}/runs.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RunRecord {
+    version: u64,        // Version of the program that ran
+    scheduled_for: u64,  // Unix time of the minute the run was due
+    duration_ms: u64,
+    result: String,      // Output of the program, cut to MAX_RUN_OUTPUT characters
+    truncated: bool,     // Whether `result` was cut
+    exit_code: Option<i32>, // As in CodeOutput; null when the run failed
+    error_details: Option<ErrorDetails>,
+}
+
+impl RunRecord {
+    fn new(version: u64, scheduled_for: u64, duration: Duration, output: CodeOutput) -> Self {
+        let truncated = output.result.chars().count() > MAX_RUN_OUTPUT;
+        RunRecord {
+            version,
+            scheduled_for,
+            duration_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+            result: if truncated {
+                output.result.chars().take(MAX_RUN_OUTPUT).collect::<String>() + "..."
+            } else {
+                output.result
+            },
+            truncated,
+            exit_code: output.exit_code,
+            error_details: output.error_details,
+        }
+    }
+}
+
+/// Runs the scheduled programs of `programs` at the start of every minute,
+/// under the server's limits, until the server stops.
+pub async fn run(
+    programs: web::Data<ProgramStore>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+) {
+    loop {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let minute = now.as_secs() / 60 * 60 + 60;
+        actix_web::rt::time::sleep(Duration::from_secs(minute).saturating_sub(now)).await;
+
+        for due in programs.due(minute) {
+            let (programs, config, sessions, executions) =
+                (programs.clone(), config.clone(), sessions.clone(), executions.clone());
+            actix_web::rt::spawn(async move {
+                let input = CodeInput {
+                    source: String::new(),
+                    language: String::new(),
+                    mode: Mode::Program,
+                    inspect_variables: false,
+                    seed: None,
+                    stdin: String::new(),
+                    env: BTreeMap::new(),
+                    files: BTreeMap::new(),
+                    globals: BTreeMap::new(),
+                    return_variables: Vec::new(),
+                    document: None,
+                    opt_level: 0,
+                    strict: StrictMode::default(),
+                    verbose: false,
+                    session: None,
+                    limits: ExecutionLimits {
+                        timeout_ms: Some(RUN_TIMEOUT_MS),
+                        ..ExecutionLimits::default()
+                    },
+                    profile: None,
+                    record: false,
+                    replay_clock: None,
+                    locale: None,
+                };
+                let job = WorkerJob {
+                    artifact: Some(due.artifact),
+                    ..WorkerJob::new(input, config.policy.clone())
+                };
+                let started = Instant::now();
+                let output = dispatch_request(job, None, &config, &sessions, &executions).await;
+                let record = RunRecord::new(due.version, minute, started.elapsed(), output);
+                programs.record(&due.namespace, &due.name, record);
+            });
+        }
+    }
+}
@@ -0,0 +1,742 @@
+// Semantic checks of strict mode, run between parsing and bytecode generation.
+// The language itself lets int and float values mix, tests any value in a
+// condition, defaults declarations without an initializer to null and
+// ignores variables that are never read, and checks the values given to
+// format() and printf() only as they run; each check of strict mode turns
+// one of these into a compile error. All checks are off by default.
+//
+// The same walk finds int divisions whose fraction is silently lost, and
+// divisions by variables declared int that may not hold one, which are
+// reported as warnings rather than errors, see `truncations`.
+//
+// Types are inferred from literals, declarations and builtin signatures. An
+// expression whose type cannot be known, such as a call to a user function
+// or the result of `||` on numbers, passes every check. The syntax tree has
+// no positions, so errors are placed by walking the tokens in step with it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::builtins::{self, Placeholder};
+use crate::lexer::{Token, TokenType};
+use crate::parser::ASTNode;
+
+/// The checks of strict mode, each enabled separately.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Strictness {
+    pub conversions: bool,  // int and float values never mix, in assignments or operators
+    pub conditions: bool,   // Conditions of `if` and `while` must be bool
+    pub initializers: bool, // Every declaration needs an initializer
+    pub unused: bool,       // A variable that is never read is an error, unless its name starts with `_`
+    pub formats: bool,      // Values given to format() and printf() match the placeholders of a literal format string
+}
+
+impl Strictness {
+    /// Every check enabled.
+    pub const ALL: Strictness = Strictness {
+        conversions: true,
+        conditions: true,
+        initializers: true,
+        unused: true,
+        formats: true,
+    };
+
+    /// Whether any check is enabled.
+    pub fn any(&self) -> bool {
+        *self != Strictness::default()
+    }
+}
+
+/// `strict` as written in requests and manifests: `true` for every check, or
+/// an object naming the checks to enable, e.g. `{"conditions": true}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum StrictMode {
+    All(bool),
+    Checks(Strictness),
+}
+
+impl Default for StrictMode {
+    fn default() -> Self {
+        StrictMode::All(false)
+    }
+}
+
+impl StrictMode {
+    /// The checks this setting enables.
+    pub fn checks(self) -> Strictness {
+        match self {
+            StrictMode::All(true) => Strictness::ALL,
+            StrictMode::All(false) => Strictness::default(),
+            StrictMode::Checks(checks) => checks,
+        }
+    }
+
+    /// Whether no check is enabled.
+    pub fn is_off(&self) -> bool {
+        !self.checks().any()
+    }
+}
+
+/// Error for programs rejected by a check of strict mode.
+#[derive(Debug)]
+pub struct SemanticError {
+    message: String,
+    line: usize,
+    column: usize,
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Semantic error at {}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for SemanticError {}
+
+impl SemanticError {
+    /// Line (1-based) of the offending code.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Column (1-based) of the offending code.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// What is wrong, without the position.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// A parsed source file with the tokens it was parsed from.
+pub struct Unit<'a> {
+    pub tokens: &'a [Token<'a>],
+    pub program: &'a ASTNode,
+}
+
+/// Checks a program made of one or more files, which share their globals.
+/// On failure, returns the index of the unit at fault with the first error in it.
+pub fn check(units: &[Unit], strictness: Strictness) -> Result<(), (usize, SemanticError)> {
+    if !strictness.any() {
+        return Ok(());
+    }
+
+    let mut checker = Checker::walk(units, strictness);
+    let unused: Vec<(String, (usize, usize, usize))> = checker
+        .globals
+        .iter()
+        .filter(|(name, global)| !global.read && !name.starts_with('_'))
+        .filter_map(|(name, global)| Some((name.clone(), global.declared?)))
+        .collect();
+    for (name, (unit, line, column)) in unused {
+        checker.unused(&name, unit, (line, column));
+    }
+
+    checker.errors.sort_by_key(|(unit, error)| (*unit, error.line, error.column));
+    match checker.errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Int divisions whose fraction is lost without the program saying so: `7 / 2`
+/// with a remainder, and quotients of two ints stored in a float variable or
+/// combined with a float. Reported as warnings, whether strict mode is on or not.
+pub fn truncations(units: &[Unit]) -> Vec<(usize, SemanticError)> {
+    let mut truncations = Checker::walk(units, Strictness::default()).truncations;
+    truncations.sort_by_key(|(unit, warning)| (*unit, warning.line, warning.column));
+    truncations
+}
+
+fn statements(program: &ASTNode) -> &[ASTNode] {
+    match program {
+        ASTNode::Program(statements) => statements,
+        other => std::slice::from_ref(other),
+    }
+}
+
+// Static type of an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    Float,
+    Bool,
+    String,
+    Null,
+    Unknown,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Type {
+        match name {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            "string" => Type::String,
+            "null" => Type::Null,
+            _ => Type::Unknown,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::Bool => "bool",
+            Type::String => "string",
+            Type::Null => "null",
+            Type::Unknown => "unknown",
+        }
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+}
+
+struct Variable {
+    var_type: Type,
+    read: bool,
+    not_int: bool, // Declared int but given a value that may not be one, so far in the walk
+    declared: Option<(usize, usize, usize)>, // Unit, line and column of the first declaration checked
+}
+
+impl Variable {
+    fn new(var_type: &str) -> Self {
+        Variable {
+            var_type: Type::from_name(var_type),
+            read: false,
+            not_int: false,
+            declared: None,
+        }
+    }
+}
+
+// Positions of the tokens the checker reports errors at, in source order.
+// The checker takes them in the order it meets the matching nodes.
+#[derive(Default)]
+struct Marks {
+    declarations: std::vec::IntoIter<(usize, usize)>, // Name after `int` or `float`
+    assignments: std::vec::IntoIter<(usize, usize)>,  // Name before the `=` of an assignment
+    operators: std::vec::IntoIter<(usize, usize)>,    // Binary operators
+    conditions: std::vec::IntoIter<(usize, usize)>,   // First token inside the parentheses of `if` or `while`
+    formats: std::vec::IntoIter<(usize, usize)>,      // Name of a call to a builtin taking a format string
+}
+
+impl Marks {
+    fn new(tokens: &[Token]) -> Self {
+        let mut marks: [Vec<(usize, usize)>; 5] = Default::default();
+        let kind = |index: Option<usize>| index.and_then(|i| tokens.get(i)).map(|token| &token.token_type);
+        for (index, token) in tokens.iter().enumerate() {
+            let before = kind(index.checked_sub(1));
+            let slot = match &token.token_type {
+                TokenType::Identifier(_) if matches!(before, Some(TokenType::Int | TokenType::Float)) => Some((0, index)),
+                TokenType::Assign if !matches!(kind(index.checked_sub(2)), Some(TokenType::Int | TokenType::Float)) => {
+                    index.checked_sub(1).map(|name| (1, name))
+                }
+                TokenType::Minus => {
+                    let binary = matches!(
+                        before,
+                        Some(
+                            TokenType::Identifier(_)
+                                | TokenType::IntLiteral(_)
+                                | TokenType::FloatLiteral(_)
+                                | TokenType::StringLiteral(_)
+                                | TokenType::Null
+                                | TokenType::RightParen
+                                | TokenType::RightBracket
+                        )
+                    );
+                    binary.then_some((2, index))
+                }
+                TokenType::Plus
+                | TokenType::Multiply
+                | TokenType::Divide
+                | TokenType::Equal
+                | TokenType::NotEqual
+                | TokenType::LessThan
+                | TokenType::GreaterThan
+                | TokenType::LessEqual
+                | TokenType::GreaterEqual
+                | TokenType::And
+                | TokenType::Or => Some((2, index)),
+                TokenType::If | TokenType::While => Some((3, index + 2)),
+                TokenType::Identifier(name)
+                    if kind(Some(index + 1)) == Some(&TokenType::LeftParen)
+                        && builtins::lookup(name).is_some_and(|builtin| builtin.variadic) =>
+                {
+                    Some((4, index))
+                }
+                _ => None,
+            };
+            if let Some((slot, at)) = slot {
+                let at = tokens.get(at).unwrap_or(token);
+                marks[slot].push((at.line, at.column));
+            }
+        }
+
+        let [declarations, assignments, operators, conditions, formats] = marks;
+        Marks {
+            declarations: declarations.into_iter(),
+            assignments: assignments.into_iter(),
+            operators: operators.into_iter(),
+            conditions: conditions.into_iter(),
+            formats: formats.into_iter(),
+        }
+    }
+}
+
+struct Checker {
+    strictness: Strictness,
+    globals: HashMap<String, Variable>,
+    scopes: Vec<Vec<(String, Variable)>>, // Locals of each enclosing block
+    marks: Marks,                         // Of the unit being checked
+    unit: usize,
+    errors: Vec<(usize, SemanticError)>,
+    quotient: Option<(usize, usize)>, // Operator of the expression just checked, when it is a division of two ints
+    truncations: Vec<(usize, SemanticError)>,
+}
+
+impl Checker {
+    // Checks every statement of a program made of `units`
+    fn walk(units: &[Unit], strictness: Strictness) -> Checker {
+        let mut checker = Checker {
+            strictness,
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            marks: Marks::default(),
+            unit: 0,
+            errors: Vec::new(),
+            quotient: None,
+            truncations: Vec::new(),
+        };
+        // Globals are shared by name across files; the first declaration gives the type
+        for unit in units {
+            for statement in statements(unit.program) {
+                if let ASTNode::VarDeclaration { var_type, name, .. } = statement {
+                    checker.globals.entry(name.clone()).or_insert(Variable::new(var_type));
+                }
+            }
+        }
+
+        for (index, unit) in units.iter().enumerate() {
+            checker.unit = index;
+            checker.marks = Marks::new(unit.tokens);
+            for statement in statements(unit.program) {
+                checker.statement(statement);
+            }
+        }
+        checker
+    }
+
+    fn statement(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::VarDeclaration { var_type, name, initializer, .. } => {
+                let at = self.marks.declarations.next().unwrap_or_default();
+                match initializer {
+                    Some(initializer) => {
+                        let value = self.expression(initializer);
+                        self.assign(Type::from_name(var_type), value, name, at);
+                        self.stored_quotient(initializer, Type::from_name(var_type), name);
+                        let not_int = var_type == "int" && Self::may_not_be_int(initializer, value);
+                        self.declare(var_type, name, at);
+                        if let Some(variable) = self.variable(name) {
+                            variable.not_int |= not_int;
+                        }
+                        return;
+                    }
+                    None if self.strictness.initializers => {
+                        self.error(at, format!("Variable '{}' must be initialized where it is declared", name));
+                    }
+                    None => {}
+                }
+                self.declare(var_type, name, at);
+            }
+            ASTNode::Block(statements) => {
+                self.block(statements, None);
+            }
+            ASTNode::IfStatement { condition, then_branch, else_branch } => {
+                self.condition("if", condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            ASTNode::WhileStatement { condition, body, .. } => {
+                self.condition("while", condition);
+                self.statement(body);
+            }
+            ASTNode::ExpressionStatement(expression) | ASTNode::PrintStatement(expression) => {
+                self.expression(expression);
+            }
+            ASTNode::ReturnStatement(Some(value)) => {
+                self.expression(value);
+            }
+            ASTNode::DeferStatement(statement) | ASTNode::TestBlock { body: statement, .. } => self.statement(statement),
+            ASTNode::FunctionDeclaration { params, body, .. } => {
+                // Parameters have no declared type, and are not reported as unused
+                self.scopes.push(params.iter().map(|param| (param.clone(), Variable::new(""))).collect());
+                self.expression(body);
+                self.scopes.pop();
+            }
+            _ => {}
+        }
+    }
+
+    // Checks the statements of a block in a scope of their own, then the
+    // expression ending it, whose type is that of the block
+    fn block(&mut self, statements: &[ASTNode], value: Option<&ASTNode>) -> Type {
+        self.scopes.push(Vec::new());
+        for statement in statements {
+            self.statement(statement);
+        }
+        let value = value.map_or(Type::Null, |value| self.expression(value));
+        let locals = self.scopes.pop().unwrap_or_default();
+        for (name, local) in locals {
+            if let (false, Some((unit, line, column))) = (local.read || name.starts_with('_'), local.declared) {
+                self.unused(&name, unit, (line, column));
+            }
+        }
+        value
+    }
+
+    fn expression(&mut self, node: &ASTNode) -> Type {
+        match node {
+            ASTNode::IntLiteral(_) => Type::Int,
+            ASTNode::FloatLiteral(_) => Type::Float,
+            ASTNode::StringLiteral(_) => Type::String,
+            ASTNode::NullLiteral => Type::Null,
+            ASTNode::Identifier(name) => match self.variable(name) {
+                Some(variable) => {
+                    variable.read = true;
+                    variable.var_type
+                }
+                None => Type::Unknown,
+            },
+            ASTNode::AssignmentExpression { name, value } => {
+                let at = self.marks.assignments.next().unwrap_or_default();
+                let value_type = self.expression(value);
+                let target = self.variable(name).map_or(Type::Unknown, |variable| variable.var_type);
+                self.assign(target, value_type, name, at);
+                self.stored_quotient(value, target, name);
+                if let (Type::Int, Some(variable)) = (target, self.variable(name)) {
+                    variable.not_int |= Self::may_not_be_int(value, value_type);
+                }
+                value_type
+            }
+            // Elements have no declared type, so only their subtrees are checked
+            ASTNode::IndexAssignment { name, indices, value } => {
+                self.marks.assignments.next();
+                if let Some(variable) = self.variable(name) {
+                    variable.read = true;
+                }
+                for index in indices {
+                    self.expression(index);
+                }
+                self.expression(value)
+            }
+            ASTNode::IndexExpression { array, index } => {
+                self.expression(array);
+                self.expression(index);
+                Type::Unknown
+            }
+            ASTNode::ArrayLiteral(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                Type::Unknown
+            }
+            // A chain of operators, as in `a + b - c`, is checked from its first
+            // operand up with a loop, so a long one doesn't exhaust the stack
+            ASTNode::BinaryExpression { .. } => {
+                let mut operations = Vec::new();
+                let mut first = node;
+                while let ASTNode::BinaryExpression { left, operator, right } = first {
+                    operations.push((first, operator, right.as_ref()));
+                    first = left;
+                }
+                let mut left = self.expression(first);
+                let mut left_node = first;
+                for (node, operator, right_node) in operations.into_iter().rev() {
+                    left = self.operation(left_node, left, operator, right_node);
+                    left_node = node;
+                }
+                left
+            }
+            ASTNode::UnaryExpression { operator: TokenType::Not, operand } => {
+                self.expression(operand);
+                Type::Bool
+            }
+            ASTNode::UnaryExpression { operand, .. } => match self.expression(operand) {
+                operand if operand.is_number() => operand,
+                _ => Type::Unknown,
+            },
+            ASTNode::CallExpression { callee, arguments } => {
+                let format_call = match callee.as_ref() {
+                    ASTNode::Identifier(name) if builtins::lookup(name).is_some_and(|builtin| builtin.variadic) => {
+                        Some((name, self.marks.formats.next().unwrap_or_default()))
+                    }
+                    _ => None,
+                };
+                let types: Vec<Type> = arguments.iter().map(|argument| self.expression(argument)).collect();
+                if let (Some((name, at)), Some(ASTNode::StringLiteral(format))) = (format_call, arguments.first()) {
+                    self.format(name, format, &types[1..], at);
+                }
+                match callee.as_ref() {
+                    ASTNode::Identifier(name) => builtins::lookup(name)
+                        .and_then(|builtin| builtin.signature.split("-> ").nth(1))
+                        .map_or(Type::Unknown, Type::from_name),
+                    _ => Type::Unknown,
+                }
+            }
+            ASTNode::BlockExpression { statements, value } => self.block(statements, value.as_deref()),
+            ASTNode::IfExpression { condition, then_branch, else_branch } => {
+                self.condition("if", condition);
+                match (self.expression(then_branch), self.expression(else_branch)) {
+                    (then_type, else_type) if then_type == else_type => then_type,
+                    _ => Type::Unknown,
+                }
+            }
+            _ => Type::Unknown,
+        }
+    }
+
+    // Checks the operation of `operator` on `left_node`, just checked and of
+    // type `left`, and `right_node`
+    fn operation(&mut self, left_node: &ASTNode, left: Type, operator: &TokenType, right_node: &ASTNode) -> Type {
+        let left_quotient = self.quotient_of(left_node);
+        let at = self.marks.operators.next().unwrap_or_default();
+        let right = self.expression(right_node);
+        let right_quotient = self.quotient_of(right_node);
+        let result = self.binary(left, operator, right, at);
+
+        let arithmetic = matches!(operator, TokenType::Plus | TokenType::Minus | TokenType::Multiply | TokenType::Divide);
+        for (quotient, other) in [(left_quotient, right), (right_quotient, left)] {
+            if let (true, Some(quotient), Type::Float) = (arithmetic, quotient, other) {
+                self.truncation(quotient, "Dividing two ints truncates the quotient before it is used with a float".to_string());
+            }
+        }
+        let ints = *operator == TokenType::Divide && left == Type::Int && right == Type::Int;
+        if let (true, ASTNode::IntLiteral(a), ASTNode::IntLiteral(b)) = (ints, left_node, right_node) {
+            if a.checked_rem(*b).is_some_and(|remainder| remainder != 0) {
+                let message = format!("{} / {} divides two ints, so it is {}, not {}", a, b, a / b, *a as f64 / *b as f64);
+                self.truncation(at, message);
+            }
+        }
+        if ints {
+            for operand in [left_node, right_node] {
+                let ASTNode::Identifier(name) = operand else { continue };
+                if self.variable(name).is_some_and(|variable| variable.not_int) {
+                    let message = format!(
+                        "'{}' is declared int but may hold a float, so whether this division truncates depends on its value",
+                        name
+                    );
+                    self.truncation(at, message);
+                    break;
+                }
+            }
+        }
+        self.quotient = ints.then_some(at);
+        result
+    }
+
+    fn binary(&mut self, left: Type, operator: &TokenType, right: Type, at: (usize, usize)) -> Type {
+        let symbol = match operator {
+            TokenType::And | TokenType::Or => {
+                // `a && b` yields `b` or false, `a || b` yields true or `b`
+                return if right == Type::Bool { Type::Bool } else { Type::Unknown };
+            }
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Multiply => "*",
+            TokenType::Divide => "/",
+            TokenType::Equal => "==",
+            TokenType::NotEqual => "!=",
+            TokenType::LessThan => "<",
+            TokenType::GreaterThan => ">",
+            TokenType::LessEqual => "<=",
+            TokenType::GreaterEqual => ">=",
+            _ => return Type::Unknown,
+        };
+        if self.strictness.conversions && left.is_number() && right.is_number() && left != right {
+            self.error(
+                at,
+                format!("Cannot mix {} and {} in '{}' without a conversion", left.name(), right.name(), symbol),
+            );
+        }
+
+        match operator {
+            TokenType::Equal
+            | TokenType::NotEqual
+            | TokenType::LessThan
+            | TokenType::GreaterThan
+            | TokenType::LessEqual
+            | TokenType::GreaterEqual => Type::Bool,
+            _ if left == right && (left.is_number() || (left == Type::String && symbol == "+")) => left,
+            _ => Type::Unknown,
+        }
+    }
+
+    // The operator of `node` when it is a division of two ints that may have
+    // a remainder, right after `node` is checked
+    fn quotient_of(&self, node: &ASTNode) -> Option<(usize, usize)> {
+        match node {
+            ASTNode::BinaryExpression { left, operator: TokenType::Divide, right } => match (left.as_ref(), right.as_ref()) {
+                (ASTNode::IntLiteral(a), ASTNode::IntLiteral(b)) if a.checked_rem(*b) == Some(0) => None,
+                _ => self.quotient,
+            },
+            _ => None,
+        }
+    }
+
+    // Whether `value`, of type `value_type`, may be something other than an
+    // int: a value of another known type, or a builtin's result not declared
+    // an int. Calls to user functions are given the benefit of the doubt
+    fn may_not_be_int(value: &ASTNode, value_type: Type) -> bool {
+        match value_type {
+            Type::Int | Type::Null => false,
+            Type::Unknown => matches!(
+                value,
+                ASTNode::CallExpression { callee, .. }
+                    if matches!(callee.as_ref(), ASTNode::Identifier(name) if builtins::lookup(name).is_some())
+            ),
+            _ => true,
+        }
+    }
+
+    // Warns when `value`, just checked, is a division of two ints stored into
+    // a float variable
+    fn stored_quotient(&mut self, value: &ASTNode, target: Type, name: &str) {
+        if let (Some(quotient), Type::Float) = (self.quotient_of(value), target) {
+            let message = format!("Dividing two ints truncates the quotient before it is stored in float variable '{}'", name);
+            self.truncation(quotient, message);
+        }
+    }
+
+    fn truncation(&mut self, (line, column): (usize, usize), message: String) {
+        self.truncations.push((self.unit, SemanticError { message, line, column }));
+    }
+
+    // Checks a value stored into a variable of type `target`
+    fn assign(&mut self, target: Type, value: Type, name: &str, at: (usize, usize)) {
+        let known = |t: Type| !matches!(t, Type::Unknown | Type::Null);
+        if self.strictness.conversions && known(target) && known(value) && target != value {
+            self.error(
+                at,
+                format!("Cannot assign {} to {} variable '{}' without a conversion", value.name(), target.name(), name),
+            );
+        }
+    }
+
+    // Values after a literal format string must suit its placeholders; errors
+    // in the format string itself are left to the code generator
+    fn format(&mut self, function: &str, format: &str, values: &[Type], at: (usize, usize)) {
+        if !self.strictness.formats {
+            return;
+        }
+        let Ok(placeholders) = builtins::placeholders(format) else { return };
+        for (placeholder, value) in placeholders.into_iter().zip(values) {
+            let (fits, needed) = match placeholder {
+                Placeholder::Any => (true, ""),
+                Placeholder::Integer => (*value == Type::Int, "int"),
+                Placeholder::Number(_) => (value.is_number(), "int or float"),
+                Placeholder::Text => (*value == Type::String, "string"),
+            };
+            if !fits && *value != Type::Unknown {
+                self.error(
+                    at,
+                    format!("Placeholder {} of {}() needs {}, found {}", placeholder.spec(), function, needed, value.name()),
+                );
+            }
+        }
+    }
+
+    fn condition(&mut self, statement: &str, condition: &ASTNode) {
+        let at = self.marks.conditions.next().unwrap_or_default();
+        let found = self.expression(condition);
+        if self.strictness.conditions && !matches!(found, Type::Bool | Type::Unknown) {
+            self.error(at, format!("Condition of {} must be bool, found {}", statement, found.name()));
+        }
+    }
+
+    // A declaration takes effect after its initializer
+    fn declare(&mut self, var_type: &str, name: &str, (line, column): (usize, usize)) {
+        let declared = Some((self.unit, line, column));
+        match self.scopes.last_mut() {
+            Some(scope) => scope.push((name.to_string(), Variable { declared, ..Variable::new(var_type) })),
+            None => {
+                let global = self.globals.entry(name.to_string()).or_insert(Variable::new(var_type));
+                global.declared = global.declared.or(declared);
+            }
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Option<&mut Variable> {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|(local, _)| local == name);
+        match local {
+            Some((_, variable)) => Some(variable),
+            None => self.globals.get_mut(name),
+        }
+    }
+
+    fn unused(&mut self, name: &str, unit: usize, (line, column): (usize, usize)) {
+        if self.strictness.unused {
+            let message = format!("Variable '{}' is declared but never read", name);
+            self.errors.push((unit, SemanticError { message, line, column }));
+        }
+    }
+
+    fn error(&mut self, (line, column): (usize, usize), message: String) {
+        self.errors.push((self.unit, SemanticError { message, line, column }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens.clone()).parse().unwrap();
+        truncations(&[Unit { tokens: &tokens, program: &program }])
+            .into_iter()
+            .map(|(_, warning)| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn a_division_by_an_int_variable_holding_a_float_is_reported() {
+        for source in [
+            r#"int r = json_parse("7.5"); r / 2;"#,
+            "int r = random(); r / 2;",
+            "int x = 7.5; x / 2;",
+            "int x = 1; x = 0.5; 2 / x;",
+        ] {
+            let found = warnings(source);
+            assert_eq!(found.len(), 1, "{}: {:?}", source, found);
+            assert!(found[0].contains("may hold a float"), "{}", found[0]);
+        }
+    }
+
+    #[test]
+    fn divisions_of_ints_are_reported_only_where_the_fraction_is_lost() {
+        assert!(warnings("int x = 7; int n = len([1]); x / 2 + n / 2;").is_empty());
+        assert!(warnings("6 / 2;").is_empty());
+        assert_eq!(warnings("float m = 7 / 2;").len(), 2);
+        assert_eq!(warnings("int a = 7; int b = 2; float m = a / b;").len(), 1);
+    }
+}
@@ -0,0 +1,208 @@
+// Sessions group the runs of one client, e.g. a tab of the web editor or a
+// tenant of a shared server, so that limits apply to the client rather than to
+// each request: every run of a session may hold at most the session's memory
+// ceiling, and the instructions of all its runs are counted against one
+// budget. A session is created by the first run that names it and dropped,
+// with its usage, once it has been idle for too long.
+//
+// Session ids are chosen by clients, so a client can always start a new
+// session: the budget bounds the work done under one id, not by one client.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::vm::ExecutionStats;
+
+/// Sessions kept at most; the least recently active is dropped beyond this.
+const MAX_SESSIONS: usize = 10_000;
+
+/// Limits applied to every session.
+#[derive(Debug, Clone)]
+pub struct SessionLimits {
+    pub max_heap_bytes: usize,   // Memory ceiling of each run
+    pub instruction_budget: u64, // Instructions all runs of a session may execute together
+    pub idle_timeout: Duration,  // Sessions without a run for this long are dropped
+}
+
+/// Limits of a single run, passed to the VM (and to worker processes).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunQuota {
+    #[serde(default)]
+    pub max_instructions: Option<u64>,
+    #[serde(default)]
+    pub max_heap_bytes: Option<usize>,
+}
+
+/// Resources used by a session, as listed by GET /admin/sessions.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionUsage {
+    pub id: String,
+    pub runs: u64,                    // Finished runs
+    pub running: u64,                 // Runs in progress
+    pub instructions_used: u64,       // By the finished runs
+    pub instruction_budget: u64,
+    pub peak_heap_bytes: usize,       // Largest peak of a single run
+    pub memory_ceiling_bytes: usize,
+    pub idle_ms: u64,                 // Since the last run started or finished
+    pub age_ms: u64,                  // Since the session was created
+}
+
+/// The sessions of all clients, by id.
+pub struct SessionStore {
+    limits: SessionLimits,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    created_at: Instant,
+    last_active: Instant,
+    runs: u64,
+    running: u64,
+    instructions_used: u64,
+    instructions_reserved: u64, // Granted to runs in progress
+    peak_heap_bytes: usize,
+}
+
+impl Session {
+    fn new(now: Instant) -> Self {
+        Session {
+            created_at: now,
+            last_active: now,
+            runs: 0,
+            running: 0,
+            instructions_used: 0,
+            instructions_reserved: 0,
+            peak_heap_bytes: 0,
+        }
+    }
+}
+
+/// A run started in a session. Its usage is charged to the session when it
+/// is dropped, e.g. after `finish`, or when the request is abandoned.
+pub struct SessionRun<'a> {
+    store: &'a SessionStore,
+    id: String,
+    granted: u64, // Instructions reserved for the run
+    stats: ExecutionStats,
+    pub quota: RunQuota,
+}
+
+impl SessionRun<'_> {
+    /// Records the statistics of the finished run.
+    pub fn finish(mut self, stats: &ExecutionStats) {
+        self.stats = stats.clone();
+    }
+}
+
+impl Drop for SessionRun<'_> {
+    // Charges the run and releases the part of the reservation it did not use
+    fn drop(&mut self) {
+        let mut sessions = self.store.lock();
+        if let Some(session) = sessions.get_mut(&self.id) {
+            session.instructions_reserved -= self.granted;
+            session.instructions_used += self.stats.instructions_executed.min(self.granted);
+            session.peak_heap_bytes = session.peak_heap_bytes.max(self.stats.peak_heap_bytes);
+            session.running -= 1;
+            session.runs += 1;
+            session.last_active = Instant::now();
+        }
+    }
+}
+
+impl SessionStore {
+    pub fn new(limits: SessionLimits) -> Self {
+        SessionStore {
+            limits,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts a run in a session, creating the session if needed. The run may
+    /// use the whole budget the session has left, which is reserved for it
+    /// until it finishes, so runs started at the same time cannot overspend.
+    pub fn start(&self, id: &str) -> Result<SessionRun<'_>, String> {
+        let now = Instant::now();
+        let mut sessions = self.lock();
+        self.evict_idle(&mut sessions, now);
+        if !sessions.contains_key(id) && sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions
+                .iter()
+                .filter(|(_, session)| session.running == 0)
+                .min_by_key(|(_, session)| session.last_active)
+                .map(|(other, _)| other.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+
+        let session = sessions.entry(id.to_string()).or_insert_with(|| Session::new(now));
+        let spent = session.instructions_used + session.instructions_reserved;
+        let available = self.limits.instruction_budget.saturating_sub(spent);
+        if available == 0 {
+            return Err(if session.instructions_reserved > 0 {
+                format!("Session '{}' is busy: the rest of its instruction budget is reserved by a running program", id)
+            } else {
+                format!(
+                    "Session '{}' has used its budget of {} instructions",
+                    id, self.limits.instruction_budget
+                )
+            });
+        }
+
+        session.instructions_reserved += available;
+        session.running += 1;
+        session.last_active = now;
+        Ok(SessionRun {
+            store: self,
+            id: id.to_string(),
+            granted: available,
+            stats: ExecutionStats::default(),
+            quota: RunQuota {
+                max_instructions: Some(available),
+                max_heap_bytes: Some(self.limits.max_heap_bytes),
+            },
+        })
+    }
+
+    /// Usage of the sessions that are still active, by id.
+    pub fn usage(&self) -> Vec<SessionUsage> {
+        let now = Instant::now();
+        let mut sessions = self.lock();
+        self.evict_idle(&mut sessions, now);
+        let mut usage: Vec<SessionUsage> = sessions
+            .iter()
+            .map(|(id, session)| SessionUsage {
+                id: id.clone(),
+                runs: session.runs,
+                running: session.running,
+                instructions_used: session.instructions_used,
+                instruction_budget: self.limits.instruction_budget,
+                peak_heap_bytes: session.peak_heap_bytes,
+                memory_ceiling_bytes: self.limits.max_heap_bytes,
+                idle_ms: millis(now - session.last_active),
+                age_ms: millis(now - session.created_at),
+            })
+            .collect();
+        usage.sort_by(|a, b| a.id.cmp(&b.id));
+        usage
+    }
+
+    // Drops the sessions idle for longer than the limit. A session with a run
+    // in progress is never idle.
+    fn evict_idle(&self, sessions: &mut HashMap<String, Session>, now: Instant) {
+        sessions.retain(|_, session| {
+            session.running > 0 || now - session.last_active < self.limits.idle_timeout
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
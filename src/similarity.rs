@@ -0,0 +1,294 @@
+// Structural similarity of two programs, for instructors comparing
+// submissions. Both sources are parsed and their syntax trees normalized:
+// variable names and literal values are dropped unless asked for, so renaming
+// variables or changing constants does not hide a copy. Identical subtrees are
+// then matched, largest first, and the score is the share of both trees
+// covered by matched subtrees.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use utoipa::ToSchema;
+
+use crate::builtins;
+use crate::lexer::Lexer;
+use crate::parser::{ASTNode, Parser};
+use crate::ErrorDetails;
+
+// Smallest subtree that is matched, so that single names and literals, which
+// every program shares, don't count as similarity
+const MIN_SUBTREE_SIZE: usize = 3;
+
+/// What counts as a difference between two programs.
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+pub struct SimilarityOptions {
+    #[serde(default)]
+    pub compare_names: bool,    // Subtrees using different variable names don't match
+    #[serde(default)]
+    pub compare_literals: bool, // Subtrees with different literal values don't match
+}
+
+/// A subtree of the left program matched with an identical one of the right.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SubtreeMatch {
+    pub kind: String,      // Kind of the root node, e.g. "WhileStatement"
+    pub size: usize,       // Nodes in the subtree
+    pub left: Vec<usize>,  // Path of the subtree in the left program: child indexes from the root
+    pub right: Vec<usize>, // Path in the right program
+}
+
+/// Outcome of a comparison.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Similarity {
+    pub score: f64, // From 0 (nothing in common) to 1 (same structure)
+    pub left_nodes: usize,
+    pub right_nodes: usize,
+    pub matches: Vec<SubtreeMatch>, // Largest first
+}
+
+/// Compares the structure of two programs.
+pub fn compare(left: &str, right: &str, options: SimilarityOptions) -> Result<Similarity, Box<dyn Error>> {
+    let left = Tree::build(&parse(left)?, options);
+    let right = Tree::build(&parse(right)?, options);
+    Ok(left.compare(&right))
+}
+
+fn parse(source: &str) -> Result<ASTNode, Box<dyn Error>> {
+    let tokens = Lexer::new(source).tokenize()?;
+    Parser::new(tokens).parse()
+}
+
+// A node of a normalized tree
+struct Node {
+    kind: &'static str,
+    hash: u64,       // Equal for identical subtrees
+    size: usize,     // Nodes in the subtree; they follow this one in pre-order
+    parent: Option<(usize, usize)>, // Index of the parent, and of this node among its children
+}
+
+// Nodes of a normalized tree, in pre-order
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    // Numbers the nodes in pre-order, then hashes them children first. Both
+    // walks keep a stack of their own rather than recursing, so a deep tree,
+    // such as that of a long chain of operators, doesn't exhaust the stack
+    fn build(program: &ASTNode, options: SimilarityOptions) -> Self {
+        let mut tree = Tree { nodes: Vec::new() };
+        let mut labels = Vec::new();
+        let mut pending = vec![(program, None)];
+        while let Some((node, parent)) = pending.pop() {
+            let (kind, label, children) = describe(node, options);
+            let index = tree.nodes.len();
+            tree.nodes.push(Node { kind, hash: 0, size: 1, parent });
+            labels.push(label);
+            pending.extend(children.into_iter().enumerate().rev().map(|(position, child)| (child, Some((index, position)))));
+        }
+
+        // Children follow their parent, in order
+        let mut children = vec![Vec::new(); tree.nodes.len()];
+        for (index, node) in tree.nodes.iter().enumerate() {
+            if let Some((parent, _)) = node.parent {
+                children[parent].push(index);
+            }
+        }
+        for index in (0..tree.nodes.len()).rev() {
+            let mut hasher = DefaultHasher::new();
+            labels[index].hash(&mut hasher);
+            for &child in &children[index] {
+                tree.nodes[child].hash.hash(&mut hasher);
+                tree.nodes[index].size += tree.nodes[child].size;
+            }
+            tree.nodes[index].hash = hasher.finish();
+        }
+        tree
+    }
+
+    // Child indexes from the root down to the node at `index`
+    fn path(&self, mut index: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        while let Some((parent, position)) = self.nodes[index].parent {
+            path.push(position);
+            index = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    // Matches subtrees largest first; a subtree inside a matched one is not
+    // matched again
+    fn compare(&self, other: &Tree) -> Similarity {
+        let mut candidates: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (index, node) in other.nodes.iter().enumerate() {
+            if node.size >= MIN_SUBTREE_SIZE {
+                candidates.entry(node.hash).or_default().push(index);
+            }
+        }
+        let mut order: Vec<usize> = (0..self.nodes.len())
+            .filter(|&index| self.nodes[index].size >= MIN_SUBTREE_SIZE)
+            .collect();
+        order.sort_by_key(|&index| std::cmp::Reverse(self.nodes[index].size));
+
+        let mut covered = vec![false; self.nodes.len()];
+        let mut other_covered = vec![false; other.nodes.len()];
+        let mut matches = Vec::new();
+        for index in order {
+            let node = &self.nodes[index];
+            if covered[index] {
+                continue;
+            }
+            let Some(&other_index) = candidates
+                .get(&node.hash)
+                .and_then(|found| found.iter().find(|&&candidate| !other_covered[candidate]))
+            else {
+                continue;
+            };
+            covered[index..index + node.size].fill(true);
+            other_covered[other_index..other_index + node.size].fill(true);
+            matches.push(SubtreeMatch {
+                kind: node.kind.to_string(),
+                size: node.size,
+                left: self.path(index),
+                right: other.path(other_index),
+            });
+        }
+
+        let total = self.nodes.len() + other.nodes.len();
+        let matched = 2 * matches.iter().map(|found| found.size).sum::<usize>();
+        Similarity {
+            score: if total == 0 { 1.0 } else { matched as f64 / total as f64 },
+            left_nodes: self.nodes.len(),
+            right_nodes: other.nodes.len(),
+            matches,
+        }
+    }
+}
+
+// Kind, normalized label and children of a node. Operators and the names of
+// called builtins are always part of the label; functions of the program are
+// names like variables.
+fn describe(node: &ASTNode, options: SimilarityOptions) -> (&'static str, String, Vec<&ASTNode>) {
+    let name = |name: &str| if options.compare_names { name.to_string() } else { String::new() };
+    let literal = |value: String| if options.compare_literals { value } else { String::new() };
+    match node {
+        ASTNode::Program(statements) => ("Program", String::new(), statements.iter().collect()),
+        ASTNode::VarDeclaration { var_type, name: declared, initializer, .. } => (
+            "VarDeclaration",
+            format!("{} {}", var_type, name(declared)),
+            initializer.iter().map(|value| value.as_ref()).collect(),
+        ),
+        ASTNode::Block(statements) => ("Block", String::new(), statements.iter().collect()),
+        ASTNode::ExpressionStatement(expression) => ("ExpressionStatement", String::new(), vec![expression]),
+        ASTNode::IfStatement { condition, then_branch, else_branch } => {
+            let mut children = vec![condition.as_ref(), then_branch.as_ref()];
+            children.extend(else_branch.as_deref());
+            ("IfStatement", children.len().to_string(), children)
+        }
+        ASTNode::WhileStatement { condition, body, .. } => ("WhileStatement", String::new(), vec![condition, body]),
+        ASTNode::BreakStatement(_) => ("BreakStatement", String::new(), Vec::new()),
+        ASTNode::ContinueStatement(_) => ("ContinueStatement", String::new(), Vec::new()),
+        ASTNode::ReturnStatement(value) => ("ReturnStatement", String::new(), value.iter().map(|value| value.as_ref()).collect()),
+        ASTNode::PrintStatement(value) => ("PrintStatement", String::new(), vec![value]),
+        ASTNode::DeferStatement(statement) => ("DeferStatement", String::new(), vec![statement]),
+        ASTNode::Import(path) => ("Import", literal(path.clone()), Vec::new()),
+        ASTNode::FunctionDeclaration { name: declared, params, body, .. } => {
+            ("FunctionDeclaration", format!("{} {}", name(declared), params.len()), vec![body])
+        }
+        ASTNode::TestBlock { name: declared, body } => ("TestBlock", literal(declared.clone()), vec![body]),
+        ASTNode::BinaryExpression { left, operator, right } => {
+            ("BinaryExpression", format!("{:?}", operator), vec![left, right])
+        }
+        ASTNode::UnaryExpression { operator, operand } => ("UnaryExpression", format!("{:?}", operator), vec![operand]),
+        ASTNode::CallExpression { callee, arguments } => match callee.as_ref() {
+            ASTNode::Identifier(builtin) if builtins::lookup(builtin).is_some() => {
+                ("CallExpression", builtin.clone(), arguments.iter().collect())
+            }
+            ASTNode::Identifier(function) => ("CallExpression", name(function), arguments.iter().collect()),
+            _ => {
+                let mut children = vec![callee.as_ref()];
+                children.extend(arguments);
+                ("CallExpression", String::new(), children)
+            }
+        },
+        ASTNode::AssignmentExpression { name: assigned, value } => ("AssignmentExpression", name(assigned), vec![value]),
+        ASTNode::IndexExpression { array, index } => ("IndexExpression", String::new(), vec![array, index]),
+        ASTNode::IndexAssignment { name: assigned, indices, value } => {
+            let mut children: Vec<&ASTNode> = indices.iter().collect();
+            children.push(value);
+            ("IndexAssignment", format!("{} {}", name(assigned), indices.len()), children)
+        }
+        ASTNode::BlockExpression { statements, value } => {
+            let mut children: Vec<&ASTNode> = statements.iter().collect();
+            children.extend(value.as_deref());
+            ("BlockExpression", String::new(), children)
+        }
+        ASTNode::IfExpression { condition, then_branch, else_branch } => {
+            ("IfExpression", String::new(), vec![condition, then_branch, else_branch])
+        }
+        ASTNode::IntLiteral(value) => ("IntLiteral", literal(value.to_string()), Vec::new()),
+        ASTNode::FloatLiteral(value) => ("FloatLiteral", literal(value.to_string()), Vec::new()),
+        ASTNode::StringLiteral(value) => ("StringLiteral", literal(value.clone()), Vec::new()),
+        ASTNode::NullLiteral => ("NullLiteral", String::new(), Vec::new()),
+        ASTNode::ArrayLiteral(elements) => ("ArrayLiteral", elements.len().to_string(), elements.iter().collect()),
+        ASTNode::Identifier(identifier) => ("Identifier", name(identifier), Vec::new()),
+    }
+}
+
+// Request body of POST /similarity
+#[derive(Deserialize, ToSchema)]
+pub struct SimilarityInput {
+    left: String,
+    right: String,
+    #[serde(flatten)]
+    options: SimilarityOptions,
+}
+
+// Response of POST /similarity
+#[derive(Serialize, ToSchema)]
+pub struct SimilarityOutput {
+    #[serde(flatten)]
+    similarity: Similarity, // Empty when a source does not parse
+    error_details: Option<ErrorDetails>,
+}
+
+// Route handler for POST /similarity
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = SimilarityInput,
+    responses(
+        (status = 200, description = "Similarity score and matched subtrees of the two programs", body = SimilarityOutput),
+        (status = 400, description = "A source does not parse", body = SimilarityOutput),
+    )
+)]
+#[post("/similarity")]
+pub async fn similarity(input: web::Json<SimilarityInput>) -> impl Responder {
+    let parsed = parse(&input.left)
+        .map_err(|e| ("left", e))
+        .and_then(|left| parse(&input.right).map(|right| (left, right)).map_err(|e| ("right", e)));
+    match parsed {
+        Ok((left, right)) => HttpResponse::Ok().json(SimilarityOutput {
+            similarity: Tree::build(&left, input.options).compare(&Tree::build(&right, input.options)),
+            error_details: None,
+        }),
+        Err((side, e)) => {
+            let mut details = ErrorDetails::from_error(e.as_ref());
+            details.message = format!("In the {} source: {}", side, details.message);
+            HttpResponse::build(details.kind.status()).json(SimilarityOutput {
+                similarity: Similarity {
+                    score: 0.0,
+                    left_nodes: 0,
+                    right_nodes: 0,
+                    matches: Vec::new(),
+                },
+                error_details: Some(details),
+            })
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Table of the strings of compiled code: string constants and the names of
+// variables, builtins and functions, each stored once and referred to by
+// index. Artifacts and the listing returned by /compile use it, so a long
+// string a program repeats is written out once.
+
+use std::collections::HashMap;
+
+use crate::vm::{Instruction, Value};
+
+/// Distinct strings, in order of first use.
+#[derive(Debug, Clone, Default)]
+pub struct StringTable {
+    strings: Vec<String>,
+    indexes: HashMap<String, usize>, // Index of each string in `strings`
+}
+
+impl StringTable {
+    /// Table of the strings of `instructions`.
+    pub fn of(instructions: &[Instruction]) -> Self {
+        let mut table = StringTable::default();
+        for string in instructions.iter().filter_map(string_operand) {
+            table.intern(string);
+        }
+        table
+    }
+
+    /// Index of `string`, adding it unless it is already in the table.
+    pub fn intern(&mut self, string: &str) -> usize {
+        if let Some(&index) = self.indexes.get(string) {
+            return index;
+        }
+        self.strings.push(string.to_string());
+        self.indexes.insert(string.to_string(), self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+
+    pub fn into_strings(self) -> Vec<String> {
+        self.strings
+    }
+}
+
+/// The string an instruction holds, if any.
+pub fn string_operand(instruction: &Instruction) -> Option<&str> {
+    match instruction {
+        Instruction::Push(Value::String(string))
+        | Instruction::StoreVariable(string)
+        | Instruction::LoadVariable(string)
+        | Instruction::CallNative(string, _)
+        | Instruction::Function(string) => Some(string),
+        _ => None,
+    }
+}
+
+/// Listing of `instructions`, one per line, with each string written as
+/// `#N`, its index in the returned table, e.g. `StoreVariable(#0)`.
+pub fn listing(instructions: &[Instruction]) -> (Vec<String>, Vec<String>) {
+    let mut table = StringTable::default();
+    let lines = instructions
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::Push(Value::String(string)) => format!("Push(String(#{}))", table.intern(string)),
+            Instruction::StoreVariable(name) => format!("StoreVariable(#{})", table.intern(name)),
+            Instruction::LoadVariable(name) => format!("LoadVariable(#{})", table.intern(name)),
+            Instruction::CallNative(name, arg_count) => format!("CallNative(#{}, {})", table.intern(name), arg_count),
+            Instruction::Function(name) => format!("Function(#{})", table.intern(name)),
+            instruction => format!("{:?}", instruction),
+        })
+        .collect();
+    (lines, table.into_strings())
+}
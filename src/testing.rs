@@ -0,0 +1,260 @@
+// Tests written in the language itself, so an exercise can ship with checks
+// of its solution: a `test "name" { ... }` block at the top level of a
+// program is skipped when the program runs, and run by `compiler test FILE`
+// and POST /test. Each test runs on its own, in a fresh VM, as the whole
+// program followed by the body of the test, so the body can check what the
+// program computed and call its functions. A test passes when its run ends
+// without an error, which a false `assert` causes, and with exit code 0.
+
+#[cfg(feature = "server")]
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+#[cfg(feature = "server")]
+use base64::Engine;
+#[cfg(feature = "server")]
+use serde::Deserialize;
+use serde::Serialize;
+#[cfg(feature = "server")]
+use std::collections::BTreeMap;
+use std::error::Error;
+use utoipa::ToSchema;
+
+use crate::artifact::{Artifact, Limits};
+#[cfg(feature = "server")]
+use crate::executions::ExecutionRegistry;
+#[cfg(feature = "server")]
+use crate::lexer::Dialect;
+#[cfg(feature = "server")]
+use crate::limits::ExecutionLimits;
+use crate::parser::ASTNode;
+#[cfg(feature = "server")]
+use crate::pipeline::{Compilation, Mode, PassManager};
+#[cfg(feature = "server")]
+use crate::semantic::StrictMode;
+#[cfg(feature = "server")]
+use crate::sessions::SessionStore;
+#[cfg(feature = "server")]
+use crate::worker::WorkerJob;
+use crate::{optimizer, verifier, CodeOutput, ErrorDetails};
+#[cfg(feature = "server")]
+use crate::{catch_panics, client_of, dispatch_request, CodeInput, ErrorKind, ServerConfig};
+
+/// A test of a program, compiled into a program of its own.
+pub struct Test {
+    pub name: String,
+    pub artifact: Artifact,
+}
+
+/// Outcome of a test, in the order the tests are declared.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    pub output: String,          // What the program and the test printed
+    pub exit_code: Option<i32>,
+    pub instructions: u64,       // Instructions executed
+    pub error_details: Option<ErrorDetails>, // Why the test failed, e.g. a false assert
+}
+
+impl TestResult {
+    pub fn new(name: String, output: CodeOutput) -> Self {
+        TestResult {
+            name,
+            passed: output.error_details.is_none() && output.exit_code == Some(0),
+            output: output.result,
+            exit_code: output.exit_code,
+            instructions: output.stats.instructions_executed,
+            error_details: output.error_details,
+        }
+    }
+}
+
+/// Compiles every test of `program`, optimized at `opt_level`. A program
+/// without tests is still compiled, so that its errors are reported.
+pub fn compile(program: ASTNode, opt_level: u8) -> Result<Vec<Test>, Box<dyn Error>> {
+    let items = match program {
+        ASTNode::Program(items) => items,
+        item => vec![item],
+    };
+    let (tests, items): (Vec<ASTNode>, Vec<ASTNode>) =
+        items.into_iter().partition(|item| matches!(item, ASTNode::TestBlock { .. }));
+    if tests.is_empty() {
+        crate::compile_ast(ASTNode::Program(items))?;
+        return Ok(Vec::new());
+    }
+
+    let mut compiled = Vec::with_capacity(tests.len());
+    for test in tests {
+        let ASTNode::TestBlock { name, body } = test else {
+            continue;
+        };
+        // Ending with null leaves only what was printed as the output
+        let program = items.iter().cloned().chain([*body, ASTNode::ExpressionStatement(Box::new(ASTNode::NullLiteral))]).collect();
+        let instructions = optimizer::optimize(crate::compile_ast(ASTNode::Program(program))?, opt_level);
+        compiled.push(Test {
+            name,
+            artifact: Artifact {
+                opt_level,
+                limits: Limits::default(),
+                max_stack: verifier::max_stack_depth(&instructions).unwrap_or(None),
+                instructions,
+                debug: None,
+            },
+        });
+    }
+    Ok(compiled)
+}
+
+// Request body of POST /test
+#[cfg(feature = "server")]
+#[derive(Deserialize, ToSchema)]
+pub struct TestInput {
+    source: String,
+    #[serde(default)]
+    language: String,        // "beginner" for the beginner dialect, as for /compile
+    #[serde(default)]
+    opt_level: u8,
+    #[serde(default)]
+    strict: StrictMode,
+    #[serde(default)]
+    stdin: String,           // Input of read_line(), the same for every test
+    #[serde(default)]
+    seed: Option<u64>,       // Seed of random builtins, the same for every test
+    #[serde(default)]
+    limits: ExecutionLimits, // Limits of each test, as for /compile
+    #[serde(default)]
+    session: Option<String>, // Session charged for every test, as for /compile
+    #[serde(default)]
+    profile: Option<String>, // Profile whose settings apply, as for /compile
+}
+
+// Response of POST /test
+#[cfg(feature = "server")]
+#[derive(Serialize, ToSchema)]
+pub struct TestOutput {
+    passed: usize,           // Tests that passed
+    total: usize,
+    tests: Vec<TestResult>,
+    error_details: Option<ErrorDetails>,
+}
+
+#[cfg(feature = "server")]
+impl TestOutput {
+    fn failure(details: ErrorDetails) -> HttpResponse {
+        HttpResponse::build(details.kind.status()).json(TestOutput {
+            passed: 0,
+            total: 0,
+            tests: Vec::new(),
+            error_details: Some(details),
+        })
+    }
+}
+
+// Compiles the tests of a request
+#[cfg(feature = "server")]
+fn compile_input(input: &TestInput) -> Result<Vec<Test>, ErrorDetails> {
+    if input.opt_level > optimizer::MAX_OPT_LEVEL {
+        return Err(ErrorDetails::new(
+            ErrorKind::InvalidInput,
+            format!("opt_level must be between 0 and {}", optimizer::MAX_OPT_LEVEL),
+        ));
+    }
+    let mut compilation = Compilation::new(
+        &input.source,
+        Dialect::from_language(&input.language),
+        input.strict.checks(),
+        input.opt_level,
+    );
+    let details = |e: Box<dyn Error>| ErrorDetails::from_error(e.as_ref()).rendered_in(&input.source);
+    let mut passes = PassManager::unoptimized();
+    passes.set_enabled("codegen", false).map_err(|message| details(message.into()))?;
+    passes.run(&mut compilation).map_err(details)?;
+    let program = compilation.ast.take().map_or(ASTNode::Program(Vec::new()), |(ast, root)| ast.to_tree(root));
+    compile(program, input.opt_level).map_err(details)
+}
+
+// Route handler for POST /test
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "compiler",
+    request_body = TestInput,
+    responses(
+        (status = 200, description = "Every test ran; failing tests are reported in `tests`", body = TestOutput),
+        (status = 400, description = "The program does not compile", body = TestOutput),
+        (status = 422, description = "Malformed request body", body = TestOutput),
+        (status = 429, description = "The session has used its instruction budget", body = TestOutput),
+        (status = 500, description = "Internal compiler error", body = TestOutput),
+    )
+)]
+#[post("/test")]
+pub async fn run_tests(
+    input: web::Json<TestInput>,
+    config: web::Data<ServerConfig>,
+    sessions: web::Data<SessionStore>,
+    executions: web::Data<ExecutionRegistry>,
+    request: HttpRequest,
+) -> impl Responder {
+    let input = input.into_inner();
+    let (input, compiled) = match web::block(move || {
+        let compiled = catch_panics(|| compile_input(&input));
+        (input, compiled)
+    })
+    .await
+    {
+        Ok((input, Ok(Ok(tests)))) => (input, tests),
+        Ok((_, Ok(Err(details)))) => return TestOutput::failure(details),
+        Ok((_, Err(message))) => {
+            let message = format!("Internal compiler error: {}", message);
+            return TestOutput::failure(ErrorDetails::new(ErrorKind::Internal, message));
+        }
+        Err(e) => {
+            let message = format!("Internal compiler error: {}", e);
+            return TestOutput::failure(ErrorDetails::new(ErrorKind::Internal, message));
+        }
+    };
+
+    let mut tests = Vec::with_capacity(compiled.len());
+    for test in compiled {
+        let code_input = CodeInput {
+            source: String::new(),
+            language: String::new(),
+            mode: Mode::Program,
+            inspect_variables: false,
+            seed: input.seed,
+            stdin: input.stdin.clone(),
+            env: BTreeMap::new(),
+            files: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            return_variables: Vec::new(),
+            document: None,
+            opt_level: 0, // The artifact is already optimized
+            strict: StrictMode::default(),
+            verbose: false,
+            session: input.session.clone(),
+            limits: input.limits.clone(),
+            profile: input.profile.clone(),
+            record: false,
+            replay_clock: None,
+            locale: None,
+        };
+        let job = WorkerJob {
+            artifact: Some(base64::engine::general_purpose::STANDARD.encode(test.artifact.encode())),
+            ..WorkerJob::new(code_input, config.policy.clone())
+        };
+        let output = dispatch_request(job, client_of(&request), &config, &sessions, &executions).await;
+        // Only the program's own failures fail a test; others end the run
+        let failed_run = output.error_details.as_ref().filter(|details| {
+            !matches!(details.kind, ErrorKind::Runtime | ErrorKind::Timeout)
+        });
+        if let Some(details) = failed_run {
+            return TestOutput::failure(details.clone());
+        }
+        tests.push(TestResult::new(test.name, output));
+    }
+
+    HttpResponse::Ok().json(TestOutput {
+        passed: tests.iter().filter(|test| test.passed).count(),
+        total: tests.len(),
+        tests,
+        error_details: None,
+    })
+}
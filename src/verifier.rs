@@ -0,0 +1,310 @@
+// Static checks on bytecode that did not come straight from the code
+// generator, such as uploaded artifacts. Rejecting malformed code up front
+// gives a clear error instead of undefined behaviour halfway through a run.
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use crate::builtins;
+use crate::vm::Instruction;
+
+/// Error for bytecode that failed verification.
+#[derive(Debug)]
+pub struct VerifyError {
+    index: usize, // Position of the offending instruction
+    message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bytecode verification failed at instruction {}: {}", self.index, self.message)
+    }
+}
+
+impl Error for VerifyError {}
+
+/// Checks that every jump stays inside the program and inside the function
+/// it belongs to, that every call names a function of the function table and
+/// every native call a known builtin with the right number of arguments, and
+/// that no path pops from an empty stack.
+/// Jump targets are instruction indexes, never byte offsets, so a target
+/// that passes always begins an instruction.
+pub fn verify(instructions: &[Instruction]) -> Result<(), VerifyError> {
+    let chunks = chunks(instructions);
+    let functions = instructions.iter().filter(|instruction| matches!(instruction, Instruction::Function(_))).count();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let error = |message: String| VerifyError { index, message };
+
+        match instruction {
+            // Jumping to the end of the program is how the generator leaves it
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) if *target > instructions.len() => {
+                return Err(error(format!(
+                    "jump target {} is outside the program ({} instructions)",
+                    target,
+                    instructions.len()
+                )));
+            }
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => {
+                let chunk = chunks.iter().find(|chunk| chunk.contains(&index)).cloned().unwrap_or(0..0);
+                let leaves_program = *target == instructions.len() && chunk.end == instructions.len();
+                if !chunk.contains(target) && !leaves_program {
+                    let from = match chunk.start {
+                        0 => "the top-level code".to_string(),
+                        start => format!("the function starting at instruction {}", start),
+                    };
+                    return Err(error(format!("jump target {} is outside {}", target, from)));
+                }
+            }
+            Instruction::Call(function, _) if *function >= functions => {
+                return Err(error(format!("call to function {} of a function table of {}", function, functions)));
+            }
+            Instruction::CallNative(name, arg_count) => {
+                let builtin = builtins::lookup(name).ok_or_else(|| error(format!("unknown builtin {}()", name)))?;
+                if !builtin.accepts(*arg_count) {
+                    return Err(error(format!(
+                        "{}() takes {} argument(s) but is called with {}",
+                        name, builtin.arity_text(), arg_count
+                    )));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    max_stack_depth(instructions)?;
+    Ok(())
+}
+
+// Instructions of the top-level code, from the start to the first
+// function, then of each function of the function table, from its entry to
+// the next one. A function is only left by returning
+fn chunks(instructions: &[Instruction]) -> Vec<Range<usize>> {
+    let mut starts: Vec<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| matches!(instruction, Instruction::Function(_)))
+        .map(|(index, _)| index)
+        .filter(|&index| index > 0)
+        .collect();
+    starts.insert(0, 0);
+    let ends = starts.iter().skip(1).copied().chain([instructions.len()]);
+    starts.iter().zip(ends).map(|(&start, end)| start..end).collect()
+}
+
+/// Largest number of values on the operand stack on any path through the
+/// program, or None when there is no static bound: when a loop leaves values
+/// on the stack, e.g. a `return` inside it in artifacts of bytecode versions
+/// before `return` ended the program, or when a function can call itself,
+/// directly or not. Each function is analysed from its entry, with the
+/// arguments its calls pass on the stack; a call then needs as much room as
+/// the deepest its callee gets. Fails when a path pops from an empty stack.
+pub fn max_stack_depth(instructions: &[Instruction]) -> Result<Option<usize>, VerifyError> {
+    let (top_level, functions): (Vec<Range<usize>>, Vec<Range<usize>>) = chunks(instructions)
+        .into_iter()
+        .partition(|chunk| !matches!(instructions.get(chunk.start), Some(Instruction::Function(_))));
+
+    // A function is entered with the arguments its calls pass, which must agree;
+    // one never called is never run
+    let mut arities = vec![None; functions.len()];
+    for (index, instruction) in instructions.iter().enumerate() {
+        if let Instruction::Call(function, arg_count) = instruction {
+            match arities.get_mut(*function) {
+                Some(Some(arity)) if arity != arg_count => {
+                    return Err(VerifyError {
+                        index,
+                        message: format!("passes {} argument(s) to function {}, called elsewhere with {}", arg_count, function, arity),
+                    });
+                }
+                Some(arity) => *arity = Some(*arg_count),
+                None => {}
+            }
+        }
+    }
+
+    let program = match top_level.first() {
+        Some(chunk) => chunk_depth(instructions, chunk.clone(), chunk.start, 0, false)?,
+        None => ChunkDepth { deepest: Some(0), calls: Vec::new() },
+    };
+    let mut callees = Vec::with_capacity(functions.len());
+    for (chunk, arity) in functions.iter().zip(&arities) {
+        callees.push(match arity {
+            Some(arity) => chunk_depth(instructions, chunk.clone(), chunk.start + 1, *arity, true)?,
+            None => ChunkDepth { deepest: Some(0), calls: Vec::new() },
+        });
+    }
+    Ok(call_depth(&program, &callees, &mut vec![None; callees.len()], &mut Vec::new()))
+}
+
+// What the analysis of one chunk found: the largest depth on its paths,
+// counted from below the arguments of a function, and its calls, each with the
+// largest depth below the arguments it passes
+struct ChunkDepth {
+    deepest: Option<usize>,
+    calls: Vec<(usize, Option<usize>)>,
+}
+
+// Largest depth of the stack while `chunk` runs, its callees included, or
+// None when unbounded. `known` holds the depths of the functions worked out so
+// far and `active` those whose calls are being followed, which a call back
+// into makes the stack unbounded
+fn call_depth(
+    chunk: &ChunkDepth,
+    functions: &[ChunkDepth],
+    known: &mut Vec<Option<Option<usize>>>,
+    active: &mut Vec<usize>,
+) -> Option<usize> {
+    let mut deepest = chunk.deepest;
+    for &(function, below) in &chunk.calls {
+        let Some(callee) = functions.get(function) else {
+            continue; // Verification rejects the call
+        };
+        let depth = match known[function] {
+            Some(depth) => depth,
+            None if active.contains(&function) => None,
+            None => {
+                active.push(function);
+                let depth = call_depth(callee, functions, known, active);
+                active.pop();
+                known[function] = Some(depth);
+                depth
+            }
+        };
+        deepest = deepest.zip(below.zip(depth)).map(|(deepest, (below, depth))| deepest.max(below + depth));
+    }
+    deepest
+}
+
+// Analyses the paths of `chunk` from `entry`, which is reached with `arity`
+// values on the stack. A function ends its paths by returning the value on
+// top of the stack, the only one left; the top-level code runs on past a return
+fn chunk_depth(
+    instructions: &[Instruction],
+    chunk: Range<usize>,
+    entry: usize,
+    arity: usize,
+    function: bool,
+) -> Result<ChunkDepth, VerifyError> {
+    // Smallest and largest depth before each instruction, over all paths so
+    // far; a largest depth of None is unbounded
+    let mut depths: Vec<Option<(usize, Option<usize>)>> = vec![None; chunk.len()];
+    let mut pending = vec![(entry, arity, Some(arity), entry)]; // Index, depth range, and where the edge comes from
+    let mut deepest = Some(arity);
+    let mut calls = Vec::new();
+    while let Some((index, min, max, from)) = pending.pop() {
+        if !chunk.contains(&index) {
+            continue; // Verification rejects jumps out of the chunk
+        }
+        let (min, max) = match depths[index - chunk.start] {
+            None => (min, max),
+            Some((old_min, old_max)) => {
+                let grown = match (old_max, max) {
+                    (Some(old), Some(new)) => new > old,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                if min >= old_min && !grown {
+                    continue;
+                }
+                // A loop that gets deeper on every iteration has no bound
+                let max = if grown && index <= from { None } else { old_max.zip(max).map(|(old, new)| old.max(new)) };
+                (min.min(old_min), max)
+            }
+        };
+        depths[index - chunk.start] = Some((min, max));
+
+        let instruction = &instructions[index];
+        let (pops, pushes) = match instruction {
+            Instruction::Return if function => (1, 0),
+            _ => stack_effect(instruction),
+        };
+        if min < pops {
+            return Err(VerifyError {
+                index,
+                message: format!(
+                    "pops {} value(s) from the stack, which can hold only {} here",
+                    pops, min
+                ),
+            });
+        }
+        if let Instruction::Call(callee, arg_count) = instruction {
+            calls.push((*callee, max.map(|max| max - arg_count)));
+        }
+        let (min, max) = (min - pops + pushes, max.map(|max| max - pops + pushes));
+        deepest = deepest.zip(max).map(|(deepest, max)| deepest.max(max));
+        match instruction {
+            Instruction::Jump(target) => pending.push((*target, min, max, index)),
+            Instruction::JumpIfFalse(target) => {
+                pending.push((*target, min, max, index));
+                pending.push((index + 1, min, max, index));
+            }
+            Instruction::Return if function => {
+                if max != Some(0) {
+                    return Err(VerifyError {
+                        index,
+                        message: "returns with values left on the stack besides the one returned".to_string(),
+                    });
+                }
+            }
+            Instruction::Halt => {}
+            _ => pending.push((index + 1, min, max, index)),
+        }
+    }
+
+    Ok(ChunkDepth { deepest, calls })
+}
+
+// Values an instruction pops, then pushes
+fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Push(_) | Instruction::LoadVariable(_) => (0, 1),
+        Instruction::Pop | Instruction::StoreVariable(_) | Instruction::JumpIfFalse(_) | Instruction::Print => (1, 0),
+        Instruction::Duplicate => (1, 2),
+        Instruction::Negate => (1, 1),
+        Instruction::Add
+        | Instruction::Subtract
+        | Instruction::Multiply
+        | Instruction::Divide
+        | Instruction::IntDivide
+        | Instruction::Equal
+        | Instruction::NotEqual
+        | Instruction::GreaterThan
+        | Instruction::LessThan
+        | Instruction::GreaterEqual
+        | Instruction::LessEqual => (2, 1),
+        Instruction::CallNative(_, arg_count) | Instruction::Call(_, arg_count) => (*arg_count, 1),
+        Instruction::MakeArray(count) => (*count, 1),
+        Instruction::IndexGet => (2, 1),
+        Instruction::IndexSet(count) => (count + 2, 2),
+        // At the top level a return continues with the next instruction
+        Instruction::Jump(_) | Instruction::Return | Instruction::Function(_) | Instruction::Halt => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Value;
+
+    #[test]
+    fn a_call_needs_the_room_of_its_callee_unless_it_recurses() {
+        let depth = |source: &str| max_stack_depth(&crate::compile_source(source).unwrap()).unwrap();
+        // f runs above 1 and 2, and g above f's 1 and the sum so far
+        let source = "fn g(x) { return x * 2; } fn f(n) { return 1 + g(n) + g(n + 1); } 1 + (2 + f(3));";
+        assert_eq!(depth(source), Some(5));
+        assert_eq!(depth("fn f(n) { if (n < 1) { return 0; } return f(n - 1); } f(3);"), None);
+
+        // f pops one value more than its argument
+        let code = vec![
+            Instruction::Push(Value::Int(1)),
+            Instruction::Call(0, 1),
+            Instruction::Halt,
+            Instruction::Function("f".to_string()),
+            Instruction::Pop,
+            Instruction::Pop,
+            Instruction::Return,
+        ];
+        let error = max_stack_depth(&code).unwrap_err();
+        assert_eq!(error.index, 5, "{}", error);
+    }
+}
@@ -0,0 +1,93 @@
+// File system behind read_file(), write_file() and append_file(). The server
+// gives every run its own in-memory file system, so programs never touch the
+// host's files; the CLI maps the builtins onto the real file system when the
+// user permits it with `run --allow-fs`.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Files a program can read and write, keyed by path.
+#[derive(Debug, Clone)]
+pub enum FileSystem {
+    Memory(BTreeMap<String, String>), // Contents by path, discarded after the run
+    Host,                            // The real file system, relative to the working directory
+}
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        FileSystem::Memory(BTreeMap::new())
+    }
+}
+
+impl FileSystem {
+    /// Contents of the file at `path`, or None when there is no such file.
+    pub fn read(&self, path: &str) -> Result<Option<String>, String> {
+        check_path(path)?;
+        match self {
+            FileSystem::Memory(files) => Ok(files.get(path).cloned()),
+            FileSystem::Host => match std::fs::read_to_string(path) {
+                Ok(contents) => Ok(Some(contents)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(format!("Cannot read '{}': {}", path, e)),
+            },
+        }
+    }
+
+    /// Replaces the contents of the file at `path`, or adds to its end with
+    /// `append`. The file is created when it does not exist.
+    pub fn write(&mut self, path: &str, contents: &str, append: bool) -> Result<(), String> {
+        check_path(path)?;
+        match self {
+            FileSystem::Memory(files) => {
+                let file = files.entry(path.to_string()).or_default();
+                if !append {
+                    file.clear();
+                }
+                file.push_str(contents);
+                Ok(())
+            }
+            FileSystem::Host => {
+                let written = if append {
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .and_then(|mut file| io::Write::write_all(&mut file, contents.as_bytes()))
+                } else {
+                    std::fs::write(path, contents)
+                };
+                written.map_err(|e| format!("Cannot write '{}': {}", path, e))
+            }
+        }
+    }
+
+    /// Adds the files of a request to an in-memory file system. The host's
+    /// file system is left as it is.
+    pub fn with_files(self, files: &BTreeMap<String, String>) -> FileSystem {
+        match self {
+            FileSystem::Memory(mut own) => {
+                own.extend(files.iter().map(|(path, contents)| (path.clone(), contents.clone())));
+                FileSystem::Memory(own)
+            }
+            FileSystem::Host => FileSystem::Host,
+        }
+    }
+
+    /// Bytes held in memory by the files, counted against the heap limit of
+    /// a run. Files on the host hold none.
+    pub fn held_bytes(&self) -> usize {
+        match self {
+            FileSystem::Memory(files) => files.iter().map(|(path, contents)| path.len() + contents.len()).sum(),
+            FileSystem::Host => 0,
+        }
+    }
+}
+
+// Rejects paths that cannot name a file
+fn check_path(path: &str) -> Result<(), String> {
+    if path.is_empty() || path.contains('\0') || Path::new(path).file_name().is_none() {
+        return Err(format!("Invalid file path: '{}'", path));
+    }
+    Ok(())
+}
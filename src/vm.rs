@@ -1,26 +1,189 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error;
 use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+use crate::builtins::{self, RegexCache, Rng};
+use crate::pipeline::Observer;
+use crate::sandbox::SandboxPolicy;
+use crate::vfs::FileSystem;
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    Number(f64),
+    Int(i64),
+    Number(f64), // A float
     String(String),
     Boolean(bool),
     Null,
+    // Shared between the variables and stack slots holding it; changing an
+    // element copies the elements unless the array is held once
+    Array(Arc<Array>),
+}
+
+/// Elements of an array value. The heap bytes they hold are kept with them,
+/// so the memory limit counts an array without walking its elements. An
+/// array held by several elements is charged once.
+#[derive(Debug, Clone, Default)]
+pub struct Array {
+    elements: Vec<Value>,
+    heap_bytes: usize,
+    nested: HashMap<usize, usize>, // Elements holding each array element, by address
+}
+
+impl Array {
+    pub fn new(elements: Vec<Value>) -> Self {
+        let mut array = Array { elements: Vec::with_capacity(elements.len()), ..Array::default() };
+        for value in elements {
+            array.push(value);
+        }
+        array
+    }
+
+    /// Adds `value` after the last element.
+    pub fn push(&mut self, value: Value) {
+        self.charge(&value);
+        self.elements.push(value);
+    }
+
+    /// Puts `value` at `index`, returning the element it replaces.
+    pub fn replace(&mut self, index: usize, value: Value) -> Value {
+        self.charge(&value);
+        let old = std::mem::replace(&mut self.elements[index], value);
+        self.release(&old);
+        old
+    }
+
+    // Counts an element about to be added: its slot, and what it holds
+    // unless it is an array another element already holds
+    fn charge(&mut self, value: &Value) {
+        let mut bytes = std::mem::size_of::<Value>();
+        match value {
+            Value::Array(elements) => {
+                let holders = self.nested.entry(Arc::as_ptr(elements) as usize).or_default();
+                *holders += 1;
+                if *holders == 1 {
+                    bytes = bytes.saturating_add(elements.heap_bytes);
+                }
+            }
+            value => bytes += heap_size(value),
+        }
+        charge(&mut self.heap_bytes, bytes);
+    }
+
+    // Uncounts an element taken out, the reverse of `charge`
+    fn release(&mut self, value: &Value) {
+        let mut bytes = std::mem::size_of::<Value>();
+        match value {
+            Value::Array(elements) => {
+                let address = Arc::as_ptr(elements) as usize;
+                if let Some(holders) = self.nested.get_mut(&address) {
+                    *holders -= 1;
+                    if *holders == 0 {
+                        self.nested.remove(&address);
+                        bytes = bytes.saturating_add(elements.heap_bytes);
+                    }
+                }
+            }
+            value => bytes += heap_size(value),
+        }
+        release(&mut self.heap_bytes, bytes);
+    }
+}
+
+impl PartialEq for Array {
+    fn eq(&self, other: &Self) -> bool {
+        self.elements == other.elements
+    }
+}
+
+impl std::ops::Deref for Array {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        &self.elements
+    }
+}
+
+impl FromIterator<Value> for Array {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Self {
+        Array::new(iter.into_iter().collect())
+    }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Value::Int(i) => write!(f, "{}", i),
             Value::Number(n) => write!(f, "{}", n),
             Value::String(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Null => write!(f, "null"),
+            // Strings are quoted inside arrays, so ["a, b"] is told from ["a", "b"]
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match element {
+                        Value::String(s) => write!(f, "{:?}", s)?,
+                        element => write!(f, "{}", element)?,
+                    }
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+// An int equals a float of the same value, as 2 == 2.0
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Int(a), Value::Number(b)) | (Value::Number(b), Value::Int(a)) => *a as f64 == *b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            _ => false,
         }
     }
 }
 
+impl Value {
+    /// Name of the runtime type, as shown to users.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Number(_) => "float",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "bool",
+            Value::Null => "null",
+            Value::Array(_) => "array",
+        }
+    }
+
+    /// The value of an int or a float as a float, None for other values.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Whether a condition with this value holds: everything but `false` does.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Boolean(false))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Instruction {
     // Stack operations
@@ -33,6 +196,7 @@ pub enum Instruction {
     Subtract,
     Multiply,
     Divide,
+    IntDivide, // Quotient truncated toward zero; only in artifacts of versions 3 to 8
     Negate,
     
     // Comparison operations
@@ -40,7 +204,14 @@ pub enum Instruction {
     NotEqual,
     GreaterThan,
     LessThan,
-    
+    GreaterEqual,
+    LessEqual,
+
+    // Array operations
+    MakeArray(usize), // Element count
+    IndexGet,
+    IndexSet(usize),  // Index count: sets the element, pushing the value then the updated array
+
     // Variable operations
     StoreVariable(String),
     LoadVariable(String),
@@ -48,8 +219,12 @@ pub enum Instruction {
     // Control flow
     Jump(usize),
     JumpIfFalse(usize),
-    Call(String, usize),
+    Call(usize, usize), // Index in the function table, argument count
+    CallNative(String, usize), // Builtin name, argument count
     Return,
+    // Entry of a function; the function table lists them in order. The
+    // name only appears in listings and errors
+    Function(String),
     
     // I/O operations
     Print,
@@ -58,256 +233,1024 @@ pub enum Instruction {
     Halt,
 }
 
-#[derive(Debug)]
+/// Shared flag used to stop a running program from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests that the program using this token stops at the next check.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Withdraws a cancellation, so the token can stop the next program.
+    #[cfg(feature = "jupyter")]
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Number of instructions a program has run so far, readable from other
+/// threads while it runs.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressCounter(Arc<AtomicU64>);
+
+impl ProgressCounter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, instructions: u64) {
+        self.0.store(instructions, Ordering::Relaxed);
+    }
+}
+
+/// A reading of the clock by a program, recorded so the run can be replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockRead {
+    ElapsedMs(f64), // clock_ms(): milliseconds since the run started
+    UnixSecs(u64),  // now(): seconds since the Unix epoch
+}
+
+/// The nondeterministic inputs of a run besides its stdin: replaying them
+/// with the same program and input repeats the run exactly.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Recording {
+    pub seed: u64, // Seed of random(), also when it was taken from the clock
+    pub clock_reads: Vec<ClockRead>,
+}
+
+/// Settings that bound what a program may do while it runs.
+#[derive(Debug, Clone)]
+pub struct VmConfig {
+    pub max_sleep_ms: u64,              // Total time a program may spend in sleep()
+    pub cancel_token: CancellationToken, // Checked between instructions and while sleeping
+    pub progress: ProgressCounter,       // Updated after every instruction
+    pub seed: Option<u64>,              // Seed for random(); taken from the clock when absent
+    pub policy: SandboxPolicy,          // Capabilities granted to native builtins
+    pub stdin: String,                  // Input consumed by read_line()
+    pub env: BTreeMap<String, String>,   // Variables returned by getenv()
+    pub globals: BTreeMap<String, Value>, // Global variables defined before the program runs
+    pub files: FileSystem,              // Files of the file builtins as a run starts
+    pub max_instructions: Option<u64>,  // Instructions a run may execute; unbounded when absent
+    pub max_heap_bytes: Option<usize>,  // Bytes a run may hold in strings at once; unbounded when absent
+    pub max_output_bytes: Option<usize>, // Bytes a run may print; unbounded when absent
+    pub max_call_depth: Option<usize>,  // Calls a run may nest; unbounded when absent
+    pub echo: bool,                     // Whether the final value of the program is added to its output
+    pub replay_clock: Option<Vec<ClockRead>>, // Readings returned instead of the clock's, in order
+    pub observers: Option<InstructionObservers>, // Shown the instructions the run executes
+}
+
+/// Observers shown one in every `interval` of the instructions a run
+/// executes, so that watching a long run does not slow it down much.
+#[derive(Clone)]
+pub struct InstructionObservers {
+    pub observers: Vec<Arc<dyn Observer>>,
+    pub interval: u64, // 1 for every instruction
+}
+
+impl fmt::Debug for InstructionObservers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InstructionObservers({} every {})", self.observers.len(), self.interval)
+    }
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            max_sleep_ms: 1_000,
+            cancel_token: CancellationToken::new(),
+            progress: ProgressCounter::default(),
+            seed: None,
+            policy: SandboxPolicy::default(),
+            stdin: String::new(),
+            env: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            files: FileSystem::default(),
+            max_instructions: None,
+            max_heap_bytes: None,
+            max_output_bytes: None,
+            max_call_depth: None,
+            echo: true,
+            replay_clock: None,
+            observers: None,
+        }
+    }
+}
+
+/// Final state of a global variable, reported after a run.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct VariableSnapshot {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub value: String,
+    pub truncated: bool, // Whether `value` was cut to the requested length
+}
+
+impl VariableSnapshot {
+    /// The snapshot of `value` as the variable `name`, rendered and cut to
+    /// `max_value_len` characters.
+    pub fn new(name: String, value: &Value, max_value_len: usize) -> Self {
+        let rendered = value.to_string();
+        let truncated = rendered.chars().count() > max_value_len;
+        VariableSnapshot {
+            name,
+            type_name: value.type_name().to_string(),
+            value: if truncated {
+                rendered.chars().take(max_value_len).collect::<String>() + "..."
+            } else {
+                rendered
+            },
+            truncated,
+        }
+    }
+}
+
+/// Counters collected while executing a program.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, ToSchema)]
+pub struct ExecutionStats {
+    pub instructions_executed: u64,
+    pub peak_stack_depth: usize,
+    pub peak_heap_bytes: usize,
+    pub function_calls: u64,
+}
+
+// A call in progress: where it returns to, and the values the variables
+// internal to the callee, its locals and temporaries, had before the call,
+// which a recursive call would otherwise overwrite
+#[derive(Debug, Clone)]
+struct Frame {
+    return_address: usize,
+    saved: Vec<(String, Option<Value>)>,
+}
+
+// Cloning a VM snapshots its whole state, as the debugger does to step back
+#[derive(Debug, Clone)]
 pub struct VirtualMachine {
     stack: Vec<Value>,
     variables: HashMap<String, Value>,
     output_buffer: String,
-    call_stack: Vec<usize>,
-    functions: HashMap<String, usize>,
+    call_stack: Vec<Frame>,
+    functions: Vec<(usize, Vec<String>)>, // Entry and internal variables of each function, by index
     last_popped_value: Option<Value>, // Track the last popped value
+    stats: ExecutionStats,
+    heap_bytes: usize, // Bytes currently held by strings on the stack, in variables and in output
+    config: VmConfig,
+    started_at: Instant,
+    pub(crate) slept_ms: u64, // Time spent in sleep() during the current run
+    pub(crate) rng: Rng,
+    pub(crate) regexes: RegexCache, // Patterns compiled by the regex builtins
+    recording: Recording, // Seed and clock readings of the current run
+    stdin_pos: usize, // Byte offset of the next unread input line
+    files: FileSystem, // Files as the current run left them
+    written: BTreeSet<String>, // Paths of the files the current run wrote
+    ip: usize,        // Index of the next instruction to run
+    exit_code: Option<i32>, // Set by exit(), which ends the run
 }
 
-impl VirtualMachine {    pub fn new() -> Self {
+impl VirtualMachine {
+    pub fn with_config(config: VmConfig) -> Self {
         VirtualMachine {
             stack: Vec::new(),
             variables: HashMap::new(),
             output_buffer: String::new(),
             call_stack: Vec::new(),
-            functions: HashMap::new(),
+            functions: Vec::new(),
             last_popped_value: None,
+            stats: ExecutionStats::default(),
+            heap_bytes: 0,
+            config,
+            started_at: Instant::now(),
+            slept_ms: 0,
+            rng: Rng::new(0),
+            regexes: RegexCache::default(),
+            recording: Recording::default(),
+            stdin_pos: 0,
+            files: FileSystem::default(),
+            written: BTreeSet::new(),
+            ip: 0,
+            exit_code: None,
+        }
+    }
+
+    /// Makes the VM as `with_config(config)` would, but keeps the memory it
+    /// allocated and the regexes it compiled, for a pool to hand it out again.
+    pub fn reset(&mut self, config: VmConfig) {
+        self.stack.clear();
+        self.variables.clear();
+        self.output_buffer.clear();
+        self.call_stack.clear();
+        self.functions.clear();
+        self.last_popped_value = None;
+        self.stats = ExecutionStats::default();
+        self.heap_bytes = 0;
+        self.config = config;
+        self.started_at = Instant::now();
+        self.slept_ms = 0;
+        self.rng = Rng::new(0);
+        self.recording = Recording::default();
+        self.stdin_pos = 0;
+        self.files = FileSystem::default();
+        self.written.clear();
+        self.ip = 0;
+        self.exit_code = None;
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    /// Reads the next line of the configured input, without its line ending.
+    pub fn next_stdin_line(&mut self) -> Option<String> {
+        let rest = &self.config.stdin[self.stdin_pos..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (line, consumed) = match rest.find('\n') {
+            Some(end) => (&rest[..end], end + 1),
+            None => (rest, rest.len()),
+        };
+        let line = line.strip_suffix('\r').unwrap_or(line).to_string();
+        self.stdin_pos += consumed;
+        Some(line)
+    }
+
+    /// Contents of a file of the run, or None when it has no such file.
+    pub fn read_file(&self, path: &str) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self.files.read(path)?)
+    }
+
+    /// Writes or appends to a file of the run. Files kept in memory count
+    /// against the heap limit.
+    pub fn write_file(&mut self, path: &str, contents: &str, append: bool) -> Result<(), Box<dyn Error>> {
+        let held = self.files.held_bytes();
+        self.files.write(path, contents, append)?;
+        self.written.insert(path.to_string());
+        charge(&mut self.heap_bytes, self.files.held_bytes());
+        release(&mut self.heap_bytes, held);
+        self.track_heap();
+        Ok(())
+    }
+
+    /// Final contents of the files the run wrote, by path. Only files kept
+    /// in memory are returned; those on the host stay where they are.
+    pub fn written_files(&self) -> BTreeMap<String, String> {
+        match &self.files {
+            FileSystem::Memory(files) => self.written.iter()
+                .filter_map(|path| Some((path.clone(), files.get(path)?.clone())))
+                .collect(),
+            FileSystem::Host => BTreeMap::new(),
+        }
+    }
+
+    /// Adds `text` and a line break to the output of the run.
+    pub(crate) fn print_line(&mut self, text: &str) {
+        charge(&mut self.heap_bytes, text.len().saturating_add(1));
+        self.output_buffer.push_str(text);
+        self.output_buffer.push('\n');
+        self.track_heap();
+    }
+
+    /// Time elapsed since the current run started.
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// The value the last run ended with: the one left on the stack, or else
+    /// the last one popped, e.g. that of the final expression statement.
+    pub fn last_value(&self) -> Option<&Value> {
+        self.stack.last().or(self.last_popped_value.as_ref())
+    }
+
+    /// The value of the global `name` left by the last run, if it has one.
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name).filter(|_| !name.starts_with('<'))
+    }
+
+    /// Returns the global variables left by the last run, sorted by name.
+    /// Rendered values longer than `max_value_len` characters are truncated.
+    pub fn snapshot_variables(&self, max_value_len: usize) -> Vec<VariableSnapshot> {
+        let mut snapshot: Vec<VariableSnapshot> = self.variables.iter()
+            .filter(|(name, _)| !name.starts_with('<')) // Skip internal placeholder slots
+            .map(|(name, value)| VariableSnapshot::new(name.clone(), value, max_value_len))
+            .collect();
+
+        snapshot.sort_by(|a, b| a.name.cmp(&b.name));
+        snapshot
+    }
+
+    /// The value of the VM variable `name`, internal ones included.
+    pub fn variable(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    /// The internal variables of the code at the instruction pointer that
+    /// have a value: those of the function running, or at the top level
+    /// those of no function.
+    pub fn variables_in_scope(&self) -> Vec<&String> {
+        let running = self.call_stack.last().and_then(|_| self.functions.iter().rev().find(|(entry, _)| *entry <= self.ip));
+        match running {
+            Some((_, internal)) => internal.iter().filter(|name| self.variables.contains_key(*name)).collect(),
+            None => {
+                let internal: BTreeSet<&String> = self.functions.iter().flat_map(|(_, internal)| internal).collect();
+                self.variables.keys().filter(|name| name.starts_with('<') && !internal.contains(name)).collect()
+            }
         }
     }
+
+    /// Records a reading of the clock and returns it, or, when replaying, the
+    /// recorded reading in its place.
+    pub fn read_clock(&mut self, reading: ClockRead) -> Result<ClockRead, Box<dyn Error>> {
+        let reading = match &self.config.replay_clock {
+            None => reading,
+            Some(recorded) => *recorded.get(self.recording.clock_reads.len()).ok_or_else(|| {
+                format!("Replay diverged: the clock is read more than the {} time(s) recorded", recorded.len())
+            })?,
+        };
+        self.recording.clock_reads.push(reading);
+        Ok(reading)
+    }
+
+    /// Whether the clock readings of the run are replayed.
+    pub fn is_replaying(&self) -> bool {
+        self.config.replay_clock.is_some()
+    }
+
+    /// Seed and clock readings of the last run.
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Makes the clock builtins return `readings`, or read the clock again
+    /// with None. Readings already taken by the run are skipped.
+    pub fn set_replay_clock(&mut self, readings: Option<Vec<ClockRead>>) {
+        self.config.replay_clock = readings;
+    }
+
+    /// Index of the next instruction to run.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// The operand stack, from bottom to top.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// What the program has printed so far.
+    pub fn printed(&self) -> &str {
+        &self.output_buffer
+    }
+
+    /// Allocates room for `slots` values on the operand stack up front, so a
+    /// program whose depth is known never grows it while running.
+    pub fn reserve_stack(&mut self, slots: usize) {
+        self.stack.reserve_exact(slots);
+    }
+
+    /// The code the program passed to exit(), if it called it.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Ends the run after the current instruction, as exit(code) does.
+    pub(crate) fn exit(&mut self, code: i32) {
+        self.exit_code = Some(code);
+    }
+
+    /// Returns the statistics gathered by the most recent call to `execute`.
+    pub fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
       pub fn execute(&mut self, bytecode: &[Instruction]) -> Result<String, Box<dyn Error>> {
+        self.start(bytecode);
+        while self.step(bytecode)? {}
+        self.finish()
+    }
+
+    /// Resets the VM to run `bytecode` from its first instruction.
+    pub fn start(&mut self, bytecode: &[Instruction]) {
         self.stack.clear();
         self.variables.clear();
         self.output_buffer.clear();
         self.call_stack.clear();
         self.last_popped_value = None;
-        
-        // First pass: register function addresses
-        for (i, instruction) in bytecode.iter().enumerate() {
-            if let Instruction::StoreVariable(name) = instruction {
-                if name.starts_with("fn_") {
-                    self.functions.insert(name[3..].to_string(), i);
+        self.stats = ExecutionStats::default();
+        self.started_at = Instant::now();
+        self.slept_ms = 0;
+        self.stdin_pos = 0;
+        self.files = self.config.files.clone();
+        self.written.clear();
+        self.variables.extend(self.config.globals.iter().map(|(name, value)| (name.clone(), value.clone())));
+        self.heap_bytes = total_size(self.config.globals.values());
+        charge(&mut self.heap_bytes, self.files.held_bytes());
+        let seed = self.config.seed.unwrap_or_else(Rng::time_seed);
+        self.rng = Rng::new(seed);
+        self.recording = Recording {
+            seed,
+            clock_reads: Vec::new(),
+        };
+        self.register_functions(bytecode);
+        self.ip = 0;
+        self.exit_code = None;
+    }
+
+    /// Runs `bytecode` as the next cell of a notebook: like `execute`, but the
+    /// variables, the random() sequence and the input left by the previous
+    /// cells are kept. Returns the value the cell leaves on the stack or pops
+    /// last; what it printed is in `printed`.
+    #[cfg(feature = "jupyter")]
+    pub fn execute_cell(&mut self, bytecode: &[Instruction]) -> Result<Option<Value>, Box<dyn Error>> {
+        self.stack.clear();
+        self.output_buffer.clear();
+        self.call_stack.clear();
+        self.last_popped_value = None;
+        self.stats = ExecutionStats::default();
+        self.heap_bytes = total_size(self.variables.values());
+        charge(&mut self.heap_bytes, self.files.held_bytes());
+        self.started_at = Instant::now();
+        self.slept_ms = 0;
+        self.register_functions(bytecode);
+        self.ip = 0;
+        self.exit_code = None;
+        while self.step(bytecode)? {}
+        self.check_heap()?;
+        Ok(self.stack.pop().or(self.last_popped_value.take()))
+    }
+
+    // Builds the function table of `bytecode` from the entries of its
+    // functions, with the internal variables each one stores, up to the next
+    fn register_functions(&mut self, bytecode: &[Instruction]) {
+        let entries: Vec<usize> = bytecode
+            .iter()
+            .enumerate()
+            .filter(|(_, instruction)| matches!(instruction, Instruction::Function(_)))
+            .map(|(address, _)| address)
+            .collect();
+        let ends = entries.iter().skip(1).copied().chain([bytecode.len()]);
+        self.functions = entries
+            .iter()
+            .zip(ends)
+            .map(|(&entry, end)| {
+                let mut internal: Vec<String> = bytecode[entry..end]
+                    .iter()
+                    .filter_map(|instruction| match instruction {
+                        Instruction::StoreVariable(name) if name.starts_with('<') => Some(name.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                internal.sort();
+                internal.dedup();
+                (entry, internal)
+            })
+            .collect();
+    }
+
+    /// Runs `bytecode` against the current variables on a copy of the VM,
+    /// leaving this one as it is, and returns the value it leaves on the stack
+    /// or pops last.
+    pub fn evaluate(&self, bytecode: &[Instruction]) -> Result<Option<Value>, Box<dyn Error>> {
+        let mut vm = self.clone();
+        release(&mut vm.heap_bytes, total_size(vm.stack.iter()));
+        vm.stack.clear();
+        vm.call_stack.clear();
+        vm.last_popped_value = None;
+        vm.ip = 0;
+        while vm.step(bytecode)? {}
+        Ok(vm.stack.pop().or(vm.last_popped_value))
+    }
+
+    /// Runs the instruction at the instruction pointer. Returns false, without
+    /// running anything, once the program has ended.
+    pub fn step(&mut self, bytecode: &[Instruction]) -> Result<bool, Box<dyn Error>> {
+        let mut ip = self.ip; // Instruction pointer
+        if ip >= bytecode.len() {
+            return Ok(false);
+        }
+
+        if self.config.cancel_token.is_cancelled() {
+            return Err("Execution cancelled".into());
+        }
+        if self.config.max_instructions.is_some_and(|max| self.stats.instructions_executed >= max) {
+            return Err(format!("Instruction limit exceeded: the program ran {} instructions",
+                self.stats.instructions_executed).into());
+        }
+        self.check_heap()?;
+        self.check_output()?;
+        self.stats.instructions_executed += 1;
+        self.config.progress.set(self.stats.instructions_executed);
+        if let Some(watch) = &self.config.observers {
+            if self.stats.instructions_executed.is_multiple_of(watch.interval.max(1)) {
+                for observer in &watch.observers {
+                    observer.on_instruction_executed(ip, &bytecode[ip], self.stats.instructions_executed);
                 }
             }
         }
-        
-        let mut ip = 0; // Instruction pointer
-        
-        while ip < bytecode.len() {
-            match &bytecode[ip] {
-                Instruction::Push(value) => {
-                    self.stack.push(value.clone());
-                    ip += 1;
-                }                Instruction::Pop => {
-                    // Pop the value off the stack but capture it first
-                    let value = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    // Save the value in case it's from the last expression
-                    self.last_popped_value = Some(value);
-                    
-                    ip += 1;
-                }
-                Instruction::Duplicate => {
-                    if let Some(value) = self.stack.last() {
-                        self.stack.push(value.clone());
-                    } else {
-                        return Err("Cannot duplicate from empty stack".into());
-                    }
-                    ip += 1;
+
+        match &bytecode[ip] {
+            Instruction::Push(value) => {
+                self.push(value.clone());
+                ip += 1;
+            }                Instruction::Pop => {
+                // Pop the value off the stack but capture it first
+                let value = self.pop()?;
+                
+                // Save the value in case it's from the last expression
+                self.last_popped_value = Some(value);
+                
+                ip += 1;
+            }
+            Instruction::Duplicate => {
+                if let Some(value) = self.stack.last().cloned() {
+                    self.push(value);
+                } else {
+                    return Err("Cannot duplicate from empty stack".into());
                 }
-                Instruction::Add => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (a, b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Number(a_val + b_val));
-                        }
-                        (Value::String(a_val), Value::String(b_val)) => {
-                            self.stack.push(Value::String(a_val + &b_val));
-                        }
-                        _ => return Err("Type error in addition".into()),
-                    }
-                    ip += 1;
+                ip += 1;
+            }
+            operation @ (Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::IntDivide
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::GreaterThan
+            | Instruction::LessThan
+            | Instruction::GreaterEqual
+            | Instruction::LessEqual) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let result = binary(operation, a, b)?;
+                self.push(result);
+                ip += 1;
+            }
+            Instruction::Negate => {
+                let value = self.pop()?;
+                let result = negate(value)?;
+                self.push(result);
+                ip += 1;
+            }
+            Instruction::MakeArray(count) => {
+                if self.stack.len() < *count {
+                    return Err("Stack underflow".into());
                 }
-                Instruction::Subtract => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (a, b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Number(a_val - b_val));
-                        }
-                        _ => return Err("Type error in subtraction".into()),
-                    }
-                    ip += 1;
+                let elements = self.stack.split_off(self.stack.len() - count);
+                release(&mut self.heap_bytes, total_size(elements.iter()));
+                self.push(Value::Array(Arc::new(Array::new(elements))));
+                ip += 1;
+            }
+            Instruction::IndexGet => {
+                let index = self.pop()?;
+                let array = self.pop()?;
+
+                let (elements, position) = element_position(&array, &index)?;
+                let element = elements[position].clone();
+                self.push(element);
+                ip += 1;
+            }
+            Instruction::IndexSet(count) => {
+                let value = self.pop()?;
+                let mut indices = Vec::with_capacity(*count);
+                for _ in 0..*count {
+                    indices.push(self.pop()?);
                 }
-                Instruction::Multiply => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (a, b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Number(a_val * b_val));
-                        }
-                        _ => return Err("Type error in multiplication".into()),
-                    }
-                    ip += 1;
+                indices.reverse();
+                let array = self.pop()?;
+
+                // Every index is checked first, so the array only changes when all are valid
+                let mut element = &array;
+                for index in &indices {
+                    let (elements, position) = element_position(element, index)?;
+                    element = &elements[position];
                 }
-                Instruction::Divide => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (a, b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            if b_val == 0.0 {
-                                return Err("Division by zero".into());
-                            }
-                            self.stack.push(Value::Number(a_val / b_val));
+                // The array is stored back into the variable it came from, which
+                // lets go of it, so the elements change in place instead of being copied
+                if let (Value::Array(elements), Some(Instruction::StoreVariable(name))) = (&array, bytecode.get(ip + 1)) {
+                    if matches!(self.variables.get(name), Some(Value::Array(held)) if Arc::ptr_eq(held, elements)) {
+                        if let Some(held) = self.variables.remove(name) {
+                            release(&mut self.heap_bytes, heap_size(&held));
                         }
-                        _ => return Err("Type error in division".into()),
                     }
-                    ip += 1;
                 }
-                Instruction::Negate => {
-                    let value = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match value {
-                        Value::Number(val) => {
-                            self.stack.push(Value::Number(-val));
-                        }
-                        _ => return Err("Type error in negation".into()),
-                    }
-                    ip += 1;
+                let updated = set_element(array, &indices, value.clone());
+                self.push(value);
+                self.push(updated);
+                ip += 1;
+            }
+            Instruction::StoreVariable(name) => {
+                let value = self.pop()?;
+                charge(&mut self.heap_bytes, heap_size(&value));
+                if let Some(old) = self.variables.insert(name.clone(), value) {
+                    release(&mut self.heap_bytes, heap_size(&old));
                 }
-                Instruction::Equal => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (&a, &b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val == b_val));
-                        }
-                        (Value::String(a_val), Value::String(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val == b_val));
-                        }
-                        (Value::Boolean(a_val), Value::Boolean(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val == b_val));
-                        }
-                        _ => self.stack.push(Value::Boolean(false)),
-                    }
-                    ip += 1;
+                self.track_heap();
+                ip += 1;
+            }
+            Instruction::LoadVariable(name) => {
+                if let Some(value) = self.variables.get(name).cloned() {
+                    self.push(value);
+                } else {
+                    return Err(format!("Undefined variable: {}", name).into());
                 }
-                Instruction::NotEqual => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (&a, &b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val != b_val));
-                        }
-                        (Value::String(a_val), Value::String(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val != b_val));
-                        }
-                        (Value::Boolean(a_val), Value::Boolean(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val != b_val));
-                        }
-                        _ => self.stack.push(Value::Boolean(true)),
-                    }
+                ip += 1;
+            }
+            Instruction::Jump(address) => {
+                ip = *address;
+            }
+            Instruction::JumpIfFalse(address) => {
+                let condition = self.pop()?;
+                
+                if condition.is_truthy() {
                     ip += 1;
+                } else {
+                    ip = *address;
                 }
-                Instruction::GreaterThan => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (a, b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val > b_val));
-                        }
-                        _ => return Err("Type error in greater than comparison".into()),
+            }
+            Instruction::Call(index, _arg_count) => {
+                if let Some((func_address, internal)) = self.functions.get(*index) {
+                    if let Some(max) = self.config.max_call_depth.filter(|&max| self.call_stack.len() >= max) {
+                        return Err(format!("Call depth limit exceeded: calls are nested more than {} deep", max).into());
                     }
-                    ip += 1;
+                    self.stats.function_calls += 1;
+                    let saved = internal.iter().map(|name| (name.clone(), self.variables.remove(name))).collect();
+                    self.call_stack.push(Frame { return_address: ip + 1, saved });
+                    ip = func_address + 1;
+                } else {
+                    return Err(format!("Undefined function: #{}", index).into());
                 }
-                Instruction::LessThan => {
-                    let b = self.stack.pop().ok_or("Stack underflow")?;
-                    let a = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match (a, b) {
-                        (Value::Number(a_val), Value::Number(b_val)) => {
-                            self.stack.push(Value::Boolean(a_val < b_val));
-                        }
-                        _ => return Err("Type error in less than comparison".into()),
-                    }
-                    ip += 1;
+            }
+            Instruction::CallNative(name, arg_count) => {
+                let builtin = builtins::lookup(name)
+                    .ok_or_else(|| format!("Undefined function: {}", name))?;
+                self.config.policy.check(builtin)?;
+                if self.stack.len() < *arg_count {
+                    return Err("Stack underflow".into());
                 }
-                Instruction::StoreVariable(name) => {
-                    let value = self.stack.pop().ok_or("Stack underflow")?;
-                    self.variables.insert(name.clone(), value);
-                    ip += 1;
+
+                let mut args = Vec::with_capacity(*arg_count);
+                for _ in 0..*arg_count {
+                    args.push(self.pop()?);
                 }
-                Instruction::LoadVariable(name) => {
-                    if let Some(value) = self.variables.get(name) {
-                        self.stack.push(value.clone());
-                    } else {
-                        return Err(format!("Undefined variable: {}", name).into());
-                    }
+                args.reverse();
+
+                self.stats.function_calls += 1;
+                let result = (builtin.function)(self, &args)?;
+                if self.exit_code.is_some() {
+                    ip = bytecode.len();
+                } else {
+                    self.push(result);
                     ip += 1;
                 }
-                Instruction::Jump(address) => {
-                    ip = *address;
-                }
-                Instruction::JumpIfFalse(address) => {
-                    let condition = self.stack.pop().ok_or("Stack underflow")?;
-                    
-                    match condition {
-                        Value::Boolean(false) => ip = *address,
-                        _ => ip += 1,
-                    }
-                }
-                Instruction::Call(func_name, _arg_count) => {
-                    if let Some(&func_address) = self.functions.get(func_name) {
-                        self.call_stack.push(ip + 1);
-                        ip = func_address;
-                    } else {
-                        return Err(format!("Undefined function: {}", func_name).into());
-                    }
-                }
-                Instruction::Return => {
-                    if let Some(return_address) = self.call_stack.pop() {
-                        ip = return_address;
-                    } else {
-                        ip += 1;
+            }
+            Instruction::Return => {
+                if let Some(frame) = self.call_stack.pop() {
+                    for (name, value) in frame.saved {
+                        if let Some(internal) = self.variables.remove(&name) {
+                            release(&mut self.heap_bytes, heap_size(&internal));
+                        }
+                        if let Some(value) = value {
+                            self.variables.insert(name, value);
+                        }
                     }
-                }
-                Instruction::Print => {
-                    let value = self.stack.pop().ok_or("Stack underflow")?;
-                    self.output_buffer.push_str(&format!("{}\n", value));
+                    ip = frame.return_address;
+                } else {
                     ip += 1;
                 }
-                Instruction::Halt => {
-                    break;
-                }            }
+            }
+            // Only entered by calls, which skip it
+            Instruction::Function(_) => {
+                ip += 1;
+            }
+            Instruction::Print => {
+                let value = self.pop()?;
+                self.print_line(&value.to_string());
+                ip += 1;
+            }
+            Instruction::Halt => {
+                ip = bytecode.len();
+            }        }
+        self.ip = ip;
+        Ok(true)
+    }
+
+    /// Ends the run, returning the output of the program: what it printed,
+    /// then the value it left on the stack or popped last, unless that is a
+    /// null following printed output or echoing is turned off.
+    pub fn finish(&mut self) -> Result<String, Box<dyn Error>> {
+        self.check_heap()?;
+        if !self.config.echo {
+            return Ok(self.output_buffer.clone());
         }
-          // Add the final value on the stack to the output if there is one
-        if let Some(final_value) = self.stack.last() {
+          // Add the final value on the stack to the output if there is one,
+          // e.g. the value of a `return`
+        if let Some(final_value) = self.stack.last()
+            .filter(|value| self.output_buffer.is_empty() || !matches!(value, Value::Null))
+        {
             // Only add a newline if we already have output and don't have a trailing one
             if !self.output_buffer.is_empty() && !self.output_buffer.ends_with('\n') {
                 self.output_buffer.push('\n');
             }
             self.output_buffer.push_str(&format!("{}", final_value));
         } 
-        // If nothing on the stack but we had a last popped value (likely from the last expression)
-        else if let Some(last_value) = &self.last_popped_value {
+        // If nothing on the stack but we had a last popped value (likely from the last expression).
+        // A null after printed output, as left by printf(), is not shown
+        else if let Some(last_value) = self.last_popped_value.as_ref()
+            .filter(|value| self.output_buffer.is_empty() || !matches!(value, Value::Null))
+        {
             // Only add a newline if we already have output and don't have a trailing one
             if !self.output_buffer.is_empty() && !self.output_buffer.ends_with('\n') {
                 self.output_buffer.push('\n');
             }
             self.output_buffer.push_str(&format!("{}", last_value));
         }
-        
+        self.check_output()?;
+
         Ok(self.output_buffer.clone())
     }
-}
\ No newline at end of file
+
+    /// Pushes a value onto the operand stack, updating the peak statistics.
+    fn push(&mut self, value: Value) {
+        charge(&mut self.heap_bytes, heap_size(&value));
+        self.stack.push(value);
+        self.stats.peak_stack_depth = self.stats.peak_stack_depth.max(self.stack.len());
+        self.track_heap();
+    }
+
+    /// Pops a value off the operand stack.
+    fn pop(&mut self) -> Result<Value, Box<dyn Error>> {
+        let value = self.stack.pop().ok_or("Stack underflow")?;
+        release(&mut self.heap_bytes, heap_size(&value));
+        Ok(value)
+    }
+
+    /// Fails when the program holds more memory than its configuration allows.
+    fn check_heap(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(max) = self.config.max_heap_bytes {
+            if self.heap_bytes > max {
+                return Err(format!("Memory limit exceeded: the program holds more than {} bytes", max).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails when the program printed more than its configuration allows.
+    fn check_output(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(max) = self.config.max_output_bytes {
+            if self.output_buffer.len() > max {
+                return Err(format!("Output limit exceeded: the program printed more than {} bytes", max).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn track_heap(&mut self) {
+        self.stats.peak_heap_bytes = self.stats.peak_heap_bytes.max(self.heap_bytes);
+    }
+}
+
+/// Result of the arithmetic or comparison `operation` on `a` and `b`. The
+/// optimizer folds constants with it, so folded code computes the same.
+///
+/// Arithmetic on two ints gives an int, and `/` on two ints truncates toward
+/// zero; a result too large for an int is given as a float. An int with a
+/// float is promoted to a float.
+pub fn binary(operation: &Instruction, a: Value, b: Value) -> Result<Value, Box<dyn Error>> {
+    let name = match operation {
+        Instruction::Equal => return Ok(Value::Boolean(a == b)),
+        Instruction::NotEqual => return Ok(Value::Boolean(a != b)),
+        Instruction::Add => "addition",
+        Instruction::Subtract => "subtraction",
+        Instruction::Multiply => "multiplication",
+        Instruction::Divide | Instruction::IntDivide => "division",
+        Instruction::GreaterThan => "greater than comparison",
+        Instruction::LessThan => "less than comparison",
+        Instruction::GreaterEqual => "greater or equal comparison",
+        Instruction::LessEqual => "less or equal comparison",
+        other => return Err(format!("{:?} is not a binary operation", other).into()),
+    };
+    if let Some(order) = numeric_order(&a, &b) {
+        let holds = match operation {
+            Instruction::GreaterThan => order.is_some_and(|order| order.is_gt()),
+            Instruction::LessThan => order.is_some_and(|order| order.is_lt()),
+            Instruction::GreaterEqual => order.is_some_and(|order| order.is_ge()),
+            Instruction::LessEqual => order.is_some_and(|order| order.is_le()),
+            _ => return arithmetic(operation, name, a, b),
+        };
+        return Ok(Value::Boolean(holds));
+    }
+    match (operation, a, b) {
+        (Instruction::Add, Value::String(a), Value::String(b)) => Ok(Value::String(a + &b)),
+        (_, a, b) => Err(type_error(name, &[&a, &b])),
+    }
+}
+
+// Arithmetic `operation`, named `name`, on two numbers
+fn arithmetic(operation: &Instruction, name: &str, a: Value, b: Value) -> Result<Value, Box<dyn Error>> {
+    let (x, y) = (a.as_number().unwrap_or_default(), b.as_number().unwrap_or_default());
+    if matches!(operation, Instruction::Divide | Instruction::IntDivide) && y == 0.0 {
+        return Err("Division by zero".into());
+    }
+    if let (Value::Int(a), Value::Int(b)) = (&a, &b) {
+        let exact = match operation {
+            Instruction::Add => a.checked_add(*b),
+            Instruction::Subtract => a.checked_sub(*b),
+            Instruction::Multiply => a.checked_mul(*b),
+            _ => a.checked_div(*b),
+        };
+        if let Some(exact) = exact {
+            return Ok(Value::Int(exact));
+        }
+    }
+    Ok(Value::Number(match operation {
+        Instruction::Add => x + y,
+        Instruction::Subtract => x - y,
+        Instruction::Multiply => x * y,
+        Instruction::Divide if !matches!((&a, &b), (Value::Int(_), Value::Int(_))) => x / y,
+        Instruction::Divide | Instruction::IntDivide => (x / y).trunc(),
+        _ => return Err(type_error(name, &[&a, &b])),
+    }))
+}
+
+/// `-value`: an int stays an int unless its negation is too large for one.
+pub fn negate(value: Value) -> Result<Value, Box<dyn Error>> {
+    match value {
+        Value::Int(i) => Ok(i.checked_neg().map_or(Value::Number(-(i as f64)), Value::Int)),
+        Value::Number(n) => Ok(Value::Number(-n)),
+        value => Err(type_error("negation", &[&value])),
+    }
+}
+
+// How two numbers compare, None inside when one is NaN; None for other
+// values. Ints are compared exactly
+fn numeric_order(a: &Value, b: &Value) -> Option<Option<std::cmp::Ordering>> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(Some(a.cmp(b))),
+        _ => Some(a.as_number()?.partial_cmp(&b.as_number()?)),
+    }
+}
+
+// Error for an operation on operands it does not apply to. A null operand,
+// the usual cause, is named, since the bare type error is hard to trace back.
+fn type_error(operation: &str, operands: &[&Value]) -> Box<dyn Error> {
+    if operands.iter().any(|operand| matches!(operand, Value::Null)) {
+        format!("Type error in {}: an operand is null", operation).into()
+    } else {
+        format!("Type error in {}", operation).into()
+    }
+}
+
+// The elements of `array` and the position of the one `index` names, which
+// must be a whole number below their count
+fn element_position<'a>(array: &'a Value, index: &Value) -> Result<(&'a Array, usize), Box<dyn Error>> {
+    let (Value::Array(elements), Some(position)) = (array, index.as_number()) else {
+        return Err(type_error("indexing", &[array, index]));
+    };
+    let position = &position;
+    if position.fract() != 0.0 {
+        return Err(format!("Array index must be a whole number, got {}", position).into());
+    }
+    if *position < 0.0 || *position >= elements.len() as f64 {
+        return Err(format!("Index {} is out of bounds for an array of length {}", position, elements.len()).into());
+    }
+    Ok((elements, *position as usize))
+}
+
+// `array` with the element at `indices`, one per level of nesting, set to
+// `value`. The indices are those `element_position` accepted
+fn set_element(array: Value, indices: &[Value], value: Value) -> Value {
+    match (array, indices.split_first()) {
+        (Value::Array(mut elements), Some((position, rest))) => {
+            let position = position.as_number().unwrap_or_default() as usize;
+            let elements_mut = Arc::make_mut(&mut elements);
+            let element = elements_mut.replace(position, Value::Null);
+            elements_mut.replace(position, set_element(element, rest, value));
+            Value::Array(elements)
+        }
+        _ => value,
+    }
+}
+
+/// Approximate number of heap bytes owned by a value.
+fn heap_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.len(),
+        Value::Array(elements) => elements.heap_bytes,
+        _ => 0,
+    }
+}
+
+// Heap bytes owned by `values` together
+fn total_size<'a>(values: impl Iterator<Item = &'a Value>) -> usize {
+    values.fold(0, |total, value| total.saturating_add(heap_size(value)))
+}
+
+// Counts of heap bytes saturate instead of overflowing, and stay saturated:
+// the limit is then exceeded whatever is released afterwards
+fn charge(total: &mut usize, bytes: usize) {
+    *total = total.saturating_add(bytes);
+}
+
+fn release(total: &mut usize, bytes: usize) {
+    if *total != usize::MAX {
+        *total -= bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, config: VmConfig) -> Result<String, Box<dyn Error>> {
+        let instructions = crate::compile_source(source)?;
+        VirtualMachine::with_config(config).execute(&instructions)
+    }
+
+    #[test]
+    fn an_array_held_twice_is_charged_once() {
+        let source = "int a = [1]; int i = 0; while (i < 70) { a = [a, a]; i = i + 1; } 1;";
+        assert_eq!(run(source, VmConfig::default()).unwrap(), "1");
+
+        let mut vm = VirtualMachine::with_config(VmConfig::default());
+        vm.execute(&crate::compile_source(source).unwrap()).unwrap();
+        let slots = 2 * 70 + 1;
+        assert!(vm.stats().peak_heap_bytes <= 4 * slots * std::mem::size_of::<Value>());
+    }
+
+    #[test]
+    fn shared_arrays_past_the_limit_fail_with_the_memory_error() {
+        // Every level holds two distinct arrays sharing all their elements,
+        // so the bytes counted double until they saturate
+        let source = "int a = [1]; int b = [1]; int i = 0; \
+            while (i < 70) { int c = [a, b]; b = [b, a]; a = c; i = i + 1; } 1;";
+        let config = VmConfig { max_heap_bytes: Some(1 << 20), ..VmConfig::default() };
+        let error = run(source, config).unwrap_err();
+        assert!(error.to_string().starts_with("Memory limit exceeded"), "{}", error);
+
+        // Without a limit the count saturates instead of overflowing
+        assert_eq!(run(source, VmConfig::default()).unwrap(), "1");
+    }
+
+    #[test]
+    fn dividing_two_ints_truncates_and_a_float_keeps_the_fraction() {
+        let result = |source: &str| run(source, VmConfig::default()).unwrap();
+        assert_eq!(result("7 / 2;"), "3");
+        assert_eq!(result("-7 / 2;"), "-3");
+        assert_eq!(result("7.0 / 2;"), "3.5");
+        assert_eq!(result("int x = 7.5; x / 2;"), "3.75");
+        assert_eq!(result(r#"int r = json_parse("7.5"); r / 2;"#), "3.75");
+        assert_eq!(result(r#"int r = json_parse("7"); r / 2;"#), "3");
+        assert_eq!(result("float r = random(); r / 2 < 0.5;"), "true");
+        assert_eq!(result("int r = random(); r / 2 < 0.5;"), "true");
+        assert!(run("1 / 0;", VmConfig::default()).unwrap_err().to_string().contains("Division by zero"));
+    }
+
+    #[test]
+    fn a_runtime_error_is_located_at_its_statement_until_optimized() {
+        let source = "int a = 1;\nfn div(x, y) {\n    return x / y;\n}\nint b = 0;\ndiv(a, b);\na / b;";
+        let line_of = |source: &str, opt_level| {
+            let mut compilation = crate::pipeline::Compilation::new(source, Default::default(), Default::default(), opt_level);
+            let mut vm = VirtualMachine::with_config(VmConfig::default());
+            let error = crate::process_code(&mut compilation, &mut Vec::new(), &mut vm).unwrap_err();
+            let details = crate::ErrorDetails::from_error(error.as_ref());
+            (details.line, details.rendered_in(source).rendered.is_some())
+        };
+        // An error in a function is located at its declaration
+        assert_eq!(line_of(source, 0), (Some(2), true));
+        assert_eq!(line_of(&source.replace("div(a, b);", ""), 0), (Some(7), true));
+        assert_eq!(line_of(source, 1), (None, false));
+    }
+
+    #[test]
+    fn int_arithmetic_too_large_for_an_int_gives_a_float() {
+        let max = Value::Int(i64::MAX);
+        assert_eq!(binary(&Instruction::Add, max.clone(), Value::Int(1)).unwrap(), Value::Number(i64::MAX as f64 + 1.0));
+        assert!(matches!(binary(&Instruction::Divide, Value::Int(i64::MIN), Value::Int(-1)).unwrap(), Value::Number(_)));
+        assert!(matches!(negate(Value::Int(i64::MIN)).unwrap(), Value::Number(_)));
+        assert_eq!(binary(&Instruction::Equal, Value::Int(2), Value::Number(2.0)).unwrap(), Value::Boolean(true));
+        assert_eq!(binary(&Instruction::LessThan, max, Value::Int(i64::MAX - 1)).unwrap(), Value::Boolean(false));
+    }
+
+    #[test]
+    fn replacing_a_shared_element_keeps_the_other_charged() {
+        let mut array = Array::new(vec![Value::Array(Arc::new(Array::new(vec![Value::String("x".repeat(100))])))]);
+        let held = array[0].clone();
+        array.push(held);
+        let both = array.heap_bytes;
+        array.replace(0, Value::Null);
+        assert_eq!(array.heap_bytes, both);
+        array.replace(1, Value::Null);
+        assert_eq!(array.heap_bytes, 2 * std::mem::size_of::<Value>());
+    }
+}
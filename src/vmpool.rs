@@ -0,0 +1,146 @@
+// Pool of virtual machines kept warm between runs. A run checks a VM out
+// instead of building one, so the stack, variable map, output buffer and
+// regex cache allocated by earlier runs are reused, and gives it back when
+// it is done. A VM is reset as it is checked out, so nothing of one run is
+// visible to the next; compiled regexes are kept, as they only depend on
+// their pattern.
+//
+// The pool is shared by the whole process. Worker processes run a single
+// program each, so in worker mode nothing is reused.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+#[cfg(feature = "server")]
+use actix_web::{get, HttpResponse, Responder};
+
+use crate::vm::{VirtualMachine, VmConfig};
+
+/// Idle VMs kept at most; more are dropped when they are given back.
+const MAX_IDLE: usize = 32;
+
+/// VMs whose run held more memory than this are dropped rather than pooled,
+/// so the pool does not keep the buffers of the largest runs alive.
+const MAX_POOLED_HEAP_BYTES: usize = 1024 * 1024;
+
+/// The pool of this process.
+pub static POOL: VmPool = VmPool::new();
+
+/// Idle VMs, and counters of how they were handed out.
+pub struct VmPool {
+    idle: Mutex<Vec<VirtualMachine>>,
+    reused: AtomicU64,    // Checkouts served by an idle VM
+    created: AtomicU64,   // Checkouts that had to build a VM
+    discarded: AtomicU64, // VMs dropped when given back: too large, or the pool was full
+}
+
+/// Counters of a pool, as reported by GET /metrics.
+#[derive(Debug, Clone, Default)]
+pub struct PoolStats {
+    pub idle: usize,
+    pub reused: u64,
+    pub created: u64,
+    pub discarded: u64,
+}
+
+/// A VM checked out of a pool; it goes back when dropped.
+pub struct PooledVm<'a> {
+    pool: &'a VmPool,
+    vm: Option<VirtualMachine>, // Only None while being given back
+}
+
+impl VmPool {
+    const fn new() -> Self {
+        VmPool {
+            idle: Mutex::new(Vec::new()),
+            reused: AtomicU64::new(0),
+            created: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        }
+    }
+
+    /// A VM with `config` and nothing of any earlier run.
+    pub fn checkout(&self, config: VmConfig) -> PooledVm<'_> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let vm = match idle {
+            Some(mut vm) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                vm.reset(config);
+                vm
+            }
+            None => {
+                self.created.fetch_add(1, Ordering::Relaxed);
+                VirtualMachine::with_config(config)
+            }
+        };
+        PooledVm { pool: self, vm: Some(vm) }
+    }
+
+    /// Current counters of the pool.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            idle: self.idle.lock().unwrap_or_else(|e| e.into_inner()).len(),
+            reused: self.reused.load(Ordering::Relaxed),
+            created: self.created.load(Ordering::Relaxed),
+            discarded: self.discarded.load(Ordering::Relaxed),
+        }
+    }
+
+    fn give_back(&self, vm: VirtualMachine) {
+        if vm.stats().peak_heap_bytes <= MAX_POOLED_HEAP_BYTES {
+            let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+            if idle.len() < MAX_IDLE {
+                idle.push(vm);
+                return;
+            }
+        }
+        self.discarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Deref for PooledVm<'_> {
+    type Target = VirtualMachine;
+
+    fn deref(&self) -> &VirtualMachine {
+        self.vm.as_ref().expect("pooled VM already given back")
+    }
+}
+
+impl DerefMut for PooledVm<'_> {
+    fn deref_mut(&mut self) -> &mut VirtualMachine {
+        self.vm.as_mut().expect("pooled VM already given back")
+    }
+}
+
+impl Drop for PooledVm<'_> {
+    fn drop(&mut self) {
+        if let Some(vm) = self.vm.take() {
+            self.pool.give_back(vm);
+        }
+    }
+}
+
+// Route handler for GET /metrics, in the Prometheus text format
+#[cfg(feature = "server")]
+#[utoipa::path(
+    tag = "metrics",
+    responses(
+        (status = 200, description = "Counters of the VM pool, in the Prometheus text format", body = String, content_type = "text/plain"),
+    )
+)]
+#[get("/metrics")]
+pub async fn metrics() -> impl Responder {
+    let stats = POOL.stats();
+    let metrics = [
+        ("compiler_vm_pool_idle", "gauge", "Virtual machines waiting in the pool", stats.idle as u64),
+        ("compiler_vm_pool_reused_total", "counter", "Runs that reused a pooled virtual machine", stats.reused),
+        ("compiler_vm_pool_created_total", "counter", "Runs that had to create a virtual machine", stats.created),
+        ("compiler_vm_pool_discarded_total", "counter", "Virtual machines dropped instead of pooled", stats.discarded),
+    ];
+    let body: String = metrics
+        .iter()
+        .map(|(name, kind, help, value)| format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"))
+        .collect();
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(body)
+}
@@ -0,0 +1,295 @@
+// Process-level isolation: each program runs in a short-lived child process
+// (this same binary started with `--worker`) that receives the job as JSON on
+// stdin and writes the CodeOutput as JSON on stdout. A crash, runaway loop or
+// memory blow-up in the child cannot take down the web server.
+
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::artifact::Artifact;
+use crate::lexer::SourceLimits;
+use crate::limits::tightest;
+use crate::sandbox::SandboxPolicy;
+use crate::sessions::RunQuota;
+use crate::vm::{CancellationToken, ProgressCounter, VmConfig};
+use crate::{run_job, CodeInput, CodeOutput};
+
+/// Command-line flag that starts the binary in worker mode.
+pub const WORKER_FLAG: &str = "--worker";
+
+// How often the parent checks whether the worker has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Everything a worker needs to run one program.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WorkerJob {
+    pub input: CodeInput,
+    pub policy: SandboxPolicy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<String>, // Base64 artifact to run instead of `input.source`
+    #[serde(default)]
+    pub quota: RunQuota, // Limits of the session the program runs in
+    #[serde(default = "default_echo")]
+    pub echo: bool, // Whether the final value of the program is printed, see `profiles`
+    #[serde(default)]
+    pub timed_by_parent: bool, // Whether the timeout of its artifact is left to the process that started the worker
+    #[serde(skip)]
+    pub cancel_token: CancellationToken, // Stops the run; a worker process is killed
+    #[serde(skip)]
+    pub progress: ProgressCounter, // Instructions run so far; not updated by worker processes
+}
+
+impl WorkerJob {
+    /// Job running `input` under `policy`, without session limits.
+    pub fn new(input: CodeInput, policy: SandboxPolicy) -> Self {
+        WorkerJob {
+            input,
+            policy,
+            artifact: None,
+            quota: RunQuota::default(),
+            echo: true,
+            timed_by_parent: false,
+            cancel_token: CancellationToken::new(),
+            progress: ProgressCounter::default(),
+        }
+    }
+
+    /// Settings of the VM that runs the job, before those of the request
+    /// other than its limits, which apply with those of the session.
+    pub fn vm_config(&self) -> VmConfig {
+        let limits = &self.input.limits;
+        VmConfig {
+            policy: self.policy.clone(),
+            cancel_token: self.cancel_token.clone(),
+            progress: self.progress.clone(),
+            max_instructions: tightest(self.quota.max_instructions, limits.max_instructions),
+            max_heap_bytes: self.quota.max_heap_bytes,
+            max_output_bytes: limits.max_output_bytes,
+            max_call_depth: limits.max_call_depth,
+            echo: self.echo,
+            ..VmConfig::default()
+        }
+    }
+
+    /// Time the job may run: the sooner of its own timeout and that of its
+    /// artifact, if it has one.
+    pub fn timeout_ms(&self) -> Option<u64> {
+        let artifact = self.artifact.as_deref().and_then(|encoded| Artifact::load_base64(encoded).ok());
+        tightest(self.input.limits.timeout_ms, artifact.and_then(|artifact| artifact.limits.timeout_ms))
+    }
+
+    // The job as a worker receives it: without a timeout, since the worker
+    // may not start the thread that would enforce it
+    fn forwarded(&self) -> WorkerJob {
+        let mut job = self.clone();
+        job.input.limits.timeout_ms = None;
+        job.timed_by_parent = true;
+        job
+    }
+}
+
+fn default_echo() -> bool {
+    true
+}
+
+/// Resource limits applied to each worker process.
+#[derive(Debug, Clone)]
+pub struct WorkerLimits {
+    pub timeout_ms: u64,
+    pub memory_mb: u64,
+}
+
+/// Why a worker did not produce a result.
+#[derive(Debug)]
+pub enum WorkerError {
+    Timeout(u64),   // Killed after running for this many milliseconds
+    Cancelled,      // Killed because its job was cancelled
+    Failed(String), // Crashed, could not be started, or returned garbage
+}
+
+impl From<String> for WorkerError {
+    fn from(message: String) -> Self {
+        WorkerError::Failed(message)
+    }
+}
+
+impl From<&str> for WorkerError {
+    fn from(message: &str) -> Self {
+        WorkerError::Failed(message.to_string())
+    }
+}
+
+/// Runs a job in a constrained child process and returns its output. The
+/// worker is killed once `limits.timeout_ms` or the job's own timeout passes.
+pub fn run_isolated(job: &WorkerJob, limits: &WorkerLimits) -> Result<CodeOutput, WorkerError> {
+    let limits = &WorkerLimits {
+        timeout_ms: job.timeout_ms().map_or(limits.timeout_ms, |ms| ms.min(limits.timeout_ms)),
+        ..limits.clone()
+    };
+    let payload = serde_json::to_vec(&job.forwarded()).map_err(|e| format!("Failed to encode job: {}", e))?;
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate worker binary: {}", e))?;
+
+    let mut command = Command::new(exe);
+    command
+        .arg(WORKER_FLAG)
+        .env_clear()
+        .envs(SourceLimits::default().to_env())
+        .current_dir("/")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    apply_limits(&mut command, limits);
+
+    let mut child = command.spawn().map_err(|e| format!("Failed to start worker: {}", e))?;
+
+    // Feed the job and collect the output on separate threads so a worker
+    // that never reads its input or floods its output cannot block us.
+    let mut stdin = child.stdin.take().ok_or("Worker stdin unavailable")?;
+    let writer = thread::spawn(move || stdin.write_all(&payload));
+    let mut stdout = child.stdout.take().ok_or("Worker stdout unavailable")?;
+    let reader = thread::spawn(move || {
+        let mut buffer = Vec::new();
+        stdout.read_to_end(&mut buffer).map(|_| buffer)
+    });
+
+    let deadline = Instant::now() + Duration::from_millis(limits.timeout_ms);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(WorkerError::Timeout(limits.timeout_ms));
+            }
+            Ok(None) if job.cancel_token.is_cancelled() => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(WorkerError::Cancelled);
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(format!("Failed to wait for worker: {}", e).into()),
+        }
+    };
+
+    // A write error just means the worker exited before reading everything
+    let _ = writer.join();
+    let output = reader
+        .join()
+        .map_err(|_| "Worker output reader panicked".to_string())?
+        .map_err(|e| format!("Failed to read worker output: {}", e))?;
+
+    if !status.success() {
+        return Err(format!("Worker terminated abnormally ({})", status).into());
+    }
+
+    Ok(serde_json::from_slice(&output).map_err(|e| format!("Invalid worker output: {}", e))?)
+}
+
+/// Entry point of a worker process: reads one job from stdin, runs it and
+/// writes the result to stdout.
+pub fn worker_main() -> std::io::Result<()> {
+    let mut payload = Vec::new();
+    std::io::stdin().read_to_end(&mut payload)?;
+    let job: WorkerJob = serde_json::from_slice(&payload)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    let output = run_job(&job).map_err(std::io::Error::other)?;
+
+    let mut stdout = std::io::stdout();
+    serde_json::to_writer(&mut stdout, &output)?;
+    stdout.flush()
+}
+
+#[cfg(unix)]
+fn apply_limits(command: &mut Command, limits: &WorkerLimits) {
+    use std::os::unix::process::CommandExt;
+
+    let memory_bytes = limits.memory_mb.saturating_mul(1024 * 1024);
+    // CPU time backstop in case the wall-clock kill is delayed
+    let cpu_seconds = limits.timeout_ms / 1000 + 1;
+
+    // SAFETY: the closure runs between fork and exec and only calls
+    // setrlimit, which is async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            set_limit(libc::RLIMIT_AS, memory_bytes)?;
+            set_limit(libc::RLIMIT_CPU, cpu_seconds)?;
+            set_limit(libc::RLIMIT_FSIZE, 0)?; // No writing to files
+            set_limit(libc::RLIMIT_NOFILE, 4)?; // stdio plus one descriptor for the loader
+            set_limit(libc::RLIMIT_NPROC, 0)?; // No spawning further processes
+            Ok(())
+        });
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn set_limit(resource: Resource, value: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit for the duration of the call.
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn apply_limits(_command: &mut Command, _limits: &WorkerLimits) {
+    // Only the timeout is enforced on platforms without rlimits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artifact::Limits;
+    use base64::Engine;
+
+    fn job(input: &str) -> WorkerJob {
+        WorkerJob::new(serde_json::from_str(input).unwrap(), SandboxPolicy::default())
+    }
+
+    fn artifact(timeout_ms: Option<u64>) -> String {
+        let instructions = crate::compile_source("6 * 7;").unwrap();
+        let artifact = Artifact {
+            opt_level: 0,
+            limits: Limits { timeout_ms, max_sleep_ms: None },
+            max_stack: crate::verifier::max_stack_depth(&instructions).unwrap(),
+            instructions,
+            debug: None,
+        };
+        base64::engine::general_purpose::STANDARD.encode(artifact.encode())
+    }
+
+    #[test]
+    fn the_timeout_of_a_request_is_left_to_the_parent() {
+        let job = job(r#"{"source": "6 * 7;", "language": "", "limits": {"timeout_ms": 50}}"#);
+        assert_eq!(job.timeout_ms(), Some(50));
+
+        let forwarded = job.forwarded();
+        assert_eq!(forwarded.input.limits.timeout_ms, None);
+        assert!(forwarded.timed_by_parent);
+        // What a worker does with it: no watchdog thread is started
+        assert_eq!(run_job(&forwarded).unwrap().result, "42");
+    }
+
+    #[test]
+    fn the_timeout_of_an_artifact_is_left_to_the_parent() {
+        let mut job = job(r#"{"source": "", "language": "", "limits": {"timeout_ms": 500}}"#);
+        job.artifact = Some(artifact(Some(20)));
+        assert_eq!(job.timeout_ms(), Some(20));
+
+        job.artifact = Some(artifact(None));
+        assert_eq!(job.timeout_ms(), Some(500));
+        assert_eq!(run_job(&job.forwarded()).unwrap().result, "42");
+    }
+}